@@ -0,0 +1,48 @@
+//! Compares matching a file name against every rule pattern one after the other with the
+//! single pass over all patterns that wurmloch uses. Run with `cargo bench`.
+
+use std::ffi::OsStr;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use globset::{Glob, GlobMatcher};
+use wurmloch::matching::Patterns;
+
+/// Patterns like in a large rule file: extensions, prefixes and directories
+fn globs(count: usize) -> Vec<Glob> {
+    (0..count)
+        .map(|i| match i % 3 {
+            0 => format!("*.ext{}", i),
+            1 => format!("invoice_{}_*.pdf", i),
+            _ => format!("**/project{}/*.{{jpg,png}}", i),
+        })
+        .map(|pattern| Glob::new(&pattern).unwrap())
+        .collect()
+}
+
+fn matching(c: &mut Criterion) {
+    let mut group = c.benchmark_group("matching");
+    let name = OsStr::new("holiday_photo_2024.jpg");
+    for count in [10, 100, 500] {
+        let globs = globs(count);
+        let matchers: Vec<GlobMatcher> = globs.iter().map(Glob::compile_matcher).collect();
+        let patterns = Patterns::new(globs.iter()).unwrap();
+
+        group.bench_with_input(BenchmarkId::new("each_pattern", count), &name, |b, name| {
+            b.iter(|| {
+                matchers
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, matcher)| matcher.is_match(black_box(name)))
+                    .map(|(index, _)| index)
+                    .collect::<Vec<_>>()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("glob_set", count), &name, |b, name| {
+            b.iter(|| patterns.matching(black_box(name)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, matching);
+criterion_main!(benches);
@@ -0,0 +1,985 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::process::{self, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{Context as _, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::attributes::{self, Attributes};
+use crate::journal::{self, Event};
+use crate::ownership::Ownership;
+use crate::quarantine::{Blocked, Check};
+use crate::rclone::RcloneSettings;
+use crate::s3::{self, S3Settings};
+use crate::shard::Layout;
+use crate::template::Template;
+use crate::throttle::{self, Limit, Throttle};
+use crate::transfer::Checksum;
+use crate::trash::Trash;
+use crate::webdav::{self, WebDavSettings};
+use crate::{archive, logging, transfer};
+
+/// How long the program of a `run` action may take unless the rule says otherwise
+const DEFAULT_RUN_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// What a rule does with a matching file. Custom actions are made available in the rule file
+/// with [`Registry::action`](crate::plugin::Registry::action).
+pub trait Action: Debug + Send + Sync {
+    /// Used in the history, e.g. `move`
+    fn name(&self) -> &str;
+
+    /// What the action would do with the file
+    fn describe(&self, path: &Path) -> Result<String>;
+
+    fn apply(&self, path: &Path, context: &Context) -> Result<Outcome>;
+
+    /// Where files end up, if they are moved or copied somewhere. Used to check the rules for
+    /// targets inside the watch directory.
+    fn target(&self) -> Option<&Template> {
+        None
+    }
+
+    /// Whether the action deletes the file once it has done its work, e.g. after unpacking it.
+    /// Safe mode keeps the file instead.
+    fn deletes_original(&self) -> bool {
+        false
+    }
+}
+
+/// Everything besides the file that an action may need
+pub struct Context<'a> {
+    /// Name of the rule the action belongs to
+    pub rule: &'a str,
+    pub trash: &'a Trash,
+    pub throttle: &'a Throttle,
+    /// Files must not replace existing ones, they get a free name instead
+    pub safe: bool,
+}
+
+impl Context<'_> {
+    /// Limits the bytes written to the device of the destination
+    pub fn limit(&self, destination: &Path) -> Option<Limit<'_>> {
+        self.throttle
+            .limit(&throttle::device(&destination.to_string_lossy()))
+    }
+}
+
+/// What an action did
+#[derive(Debug, Default)]
+pub struct Outcome {
+    /// Where the file is now, if it still exists and is not in the trash
+    pub destination: Option<PathBuf>,
+    /// Whether an existing file at the destination was replaced
+    pub replaced: bool,
+    /// Recorded in the journal and the index
+    pub event: Option<Event>,
+}
+
+/// What happens to a file whose name is taken in the target
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnConflict {
+    /// Overwrite the existing file. The default of WebDAV targets.
+    #[default]
+    Replace,
+    /// Leave the file in the wormhole
+    Skip,
+    /// Save it as `name (1).ext`, `name (2).ext` and so on
+    Rename,
+    /// Move the file to the quarantine directory. The default of local targets.
+    Quarantine,
+}
+
+/// Settings of a rule that an action is created from
+pub struct ActionSettings {
+    /// Already checked to be a valid target
+    pub target: Option<Template>,
+    pub create_target: bool,
+    /// Program and arguments, one template per word
+    pub command: Option<Vec<Template>>,
+    /// How long the program of `run` actions may take
+    pub timeout: Option<Duration>,
+    /// Unpack archives into a new directory named after the archive
+    pub subfolder: bool,
+    /// Delete the original after it has been unpacked or packed
+    pub delete_original: bool,
+    /// Format of new archives
+    pub format: Option<String>,
+    /// Name of new archives without the extension
+    pub archive_name: Option<Template>,
+    /// New name of moved or copied files
+    pub rename: Option<Template>,
+    /// What happens if the name of a moved or copied file is taken in the target
+    pub on_conflict: Option<OnConflict>,
+    /// Compare copies with the original before it is deleted
+    pub verify: Option<Checksum>,
+    /// Mode and owner of moved or copied files
+    pub ownership: Ownership,
+    /// Extended attributes of moved or copied files
+    pub attributes: Attributes,
+    /// Subdirectories of the target for moved or copied files
+    pub layout: Layout,
+    /// Used for `s3://` targets
+    pub s3: S3Settings,
+    /// Used for `davs://` and `dav://` targets
+    pub webdav: WebDavSettings,
+    /// Used by `rclone` actions
+    pub rclone: RcloneSettings,
+}
+
+/// Creates the action of a rule
+pub trait ActionFactory: Send + Sync {
+    fn create(&self, settings: ActionSettings) -> Result<Box<dyn Action>>;
+}
+
+impl<F> ActionFactory for F
+where
+    F: Fn(ActionSettings) -> Result<Box<dyn Action>> + Send + Sync,
+{
+    fn create(&self, settings: ActionSettings) -> Result<Box<dyn Action>> {
+        self(settings)
+    }
+}
+
+#[derive(Debug)]
+pub struct Move {
+    pub target: Template,
+    pub create_target: bool,
+    /// New name of the file
+    pub rename: Option<Template>,
+    /// Quarantine if the name is taken, unless the rule says otherwise
+    pub on_conflict: Option<OnConflict>,
+    /// Used when the file has to be copied to another filesystem
+    pub verify: Option<Checksum>,
+    pub ownership: Ownership,
+    pub attributes: Attributes,
+    pub layout: Layout,
+}
+
+impl Move {
+    pub fn create(settings: ActionSettings) -> Result<Box<dyn Action>> {
+        if is_remote(&settings, s3::is_url) {
+            return s3::Upload::create(settings, true);
+        }
+        if is_remote(&settings, webdav::is_url) {
+            return webdav::Upload::create(settings, true);
+        }
+        Ok(Box::new(Move {
+            target: settings.target.context("A target is required.")?,
+            create_target: settings.create_target,
+            rename: settings.rename,
+            on_conflict: settings.on_conflict,
+            verify: settings.verify,
+            ownership: settings.ownership,
+            attributes: settings.attributes,
+            layout: settings.layout,
+        }))
+    }
+}
+
+impl Action for Move {
+    fn name(&self) -> &str {
+        "move"
+    }
+
+    fn describe(&self, path: &Path) -> Result<String> {
+        Ok(format!(
+            "move to {:?}",
+            describe_target(&self.target, self.rename.as_ref(), &self.layout, path)?
+        ))
+    }
+
+    fn apply(&self, path: &Path, context: &Context) -> Result<Outcome> {
+        let destination = prepare_target(
+            &self.target,
+            self.create_target,
+            self.rename.as_ref(),
+            &self.layout,
+            path,
+        )?;
+        let destination = match keep_existing(destination, self.on_conflict, context)? {
+            Some(destination) => destination,
+            None => return Ok(Outcome::default()),
+        };
+        let attributes = self.attributes.resolve(path)?;
+        let replaced = stage_replaced(&destination, context)?;
+        let limit = context.limit(&destination);
+        let placed = transfer::move_path(path, &destination, self.verify, limit.as_ref());
+        put_back_replaced(placed, replaced.as_deref(), context)?;
+        finish_placed(&destination, &self.ownership, &attributes);
+        debug!("Moved {:?} to {:?}.", path, &destination);
+        Ok(Outcome {
+            destination: Some(destination.clone()),
+            replaced: replaced.is_some(),
+            event: Some(Event::Move {
+                rule: context.rule.to_string(),
+                from: path.to_path_buf(),
+                to: destination,
+                replaced,
+            }),
+        })
+    }
+
+    fn target(&self) -> Option<&Template> {
+        Some(&self.target)
+    }
+}
+
+/// Leaves the original in the wormhole
+#[derive(Debug)]
+pub struct Copy {
+    pub target: Template,
+    pub create_target: bool,
+    /// New name of the copy
+    pub rename: Option<Template>,
+    /// Quarantine if the name is taken, unless the rule says otherwise
+    pub on_conflict: Option<OnConflict>,
+    pub verify: Option<Checksum>,
+    pub ownership: Ownership,
+    pub attributes: Attributes,
+    pub layout: Layout,
+}
+
+impl Copy {
+    pub fn create(settings: ActionSettings) -> Result<Box<dyn Action>> {
+        if is_remote(&settings, s3::is_url) {
+            return s3::Upload::create(settings, false);
+        }
+        if is_remote(&settings, webdav::is_url) {
+            return webdav::Upload::create(settings, false);
+        }
+        Ok(Box::new(Copy {
+            target: settings.target.context("A target is required.")?,
+            create_target: settings.create_target,
+            rename: settings.rename,
+            on_conflict: settings.on_conflict,
+            verify: settings.verify,
+            ownership: settings.ownership,
+            attributes: settings.attributes,
+            layout: settings.layout,
+        }))
+    }
+}
+
+impl Action for Copy {
+    fn name(&self) -> &str {
+        "copy"
+    }
+
+    fn describe(&self, path: &Path) -> Result<String> {
+        Ok(format!(
+            "copy to {:?}",
+            describe_target(&self.target, self.rename.as_ref(), &self.layout, path)?
+        ))
+    }
+
+    fn apply(&self, path: &Path, context: &Context) -> Result<Outcome> {
+        let destination = prepare_target(
+            &self.target,
+            self.create_target,
+            self.rename.as_ref(),
+            &self.layout,
+            path,
+        )?;
+        let destination = match keep_existing(destination, self.on_conflict, context)? {
+            Some(destination) => destination,
+            None => return Ok(Outcome::default()),
+        };
+        let attributes = self.attributes.resolve(path)?;
+        let replaced = stage_replaced(&destination, context)?;
+        let limit = context.limit(&destination);
+        let placed = transfer::copy_path(path, &destination, self.verify, limit.as_ref());
+        put_back_replaced(placed, replaced.as_deref(), context)?;
+        finish_placed(&destination, &self.ownership, &attributes);
+        debug!("Copied {:?} to {:?}.", path, &destination);
+        let (size, modified) = journal::stamp(&destination)?;
+        Ok(Outcome {
+            destination: Some(destination.clone()),
+            replaced: replaced.is_some(),
+            event: Some(Event::Copy {
+                rule: context.rule.to_string(),
+                from: path.to_path_buf(),
+                to: destination,
+                replaced,
+                size: Some(size),
+                modified: Some(modified),
+            }),
+        })
+    }
+
+    fn target(&self) -> Option<&Template> {
+        Some(&self.target)
+    }
+}
+
+/// Files are staged in the trash before they are deleted permanently
+#[derive(Debug)]
+pub struct Delete;
+
+impl Delete {
+    pub fn create(_settings: ActionSettings) -> Result<Box<dyn Action>> {
+        Ok(Box::new(Delete))
+    }
+}
+
+impl Action for Delete {
+    fn name(&self) -> &str {
+        "delete"
+    }
+
+    fn describe(&self, _path: &Path) -> Result<String> {
+        Ok(String::from("delete"))
+    }
+
+    fn apply(&self, path: &Path, context: &Context) -> Result<Outcome> {
+        Ok(Outcome {
+            event: Some(delete(path, context)?),
+            ..Outcome::default()
+        })
+    }
+}
+
+/// Moves the file that is about to be replaced to the trash, so that undo can bring it back
+fn stage_replaced(destination: &Path, context: &Context) -> Result<Option<String>> {
+    if !destination.exists() {
+        return Ok(None);
+    }
+    let entry = context.trash.stage(destination)?;
+    debug!("Moved {:?} to the trash as {}.", destination, &entry.id);
+    Ok(Some(entry.id))
+}
+
+/// Restores the replaced file if the new one could not be placed
+fn put_back_replaced(
+    placed: io::Result<()>,
+    replaced: Option<&str>,
+    context: &Context,
+) -> io::Result<()> {
+    if placed.is_err() {
+        if let Err(e) = journal::put_back(context.trash, replaced) {
+            warn!("{:#}", e);
+        }
+    }
+    placed
+}
+
+/// Moves the file to the trash. Files that are too large for it are left in place.
+fn delete(path: &Path, context: &Context) -> Result<Event> {
+    let entry = context.trash.stage(path)?;
+    debug!("Moved {:?} to the trash as {}.", path, &entry.id);
+    Ok(Event::Delete {
+        rule: context.rule.to_string(),
+        from: path.to_path_buf(),
+        trash_id: Some(entry.id),
+    })
+}
+
+/// Runs a program with the file, e.g. to convert it. A failing program is retried like a
+/// failed move. If it succeeds, the file is moved to the target, if there is one.
+#[derive(Debug)]
+pub struct Run {
+    pub command: Vec<Template>,
+    /// The program is killed if it takes longer
+    pub timeout: Duration,
+    pub then: Option<Move>,
+    /// Modification times of files that stayed where they are after the program ran, so that
+    /// the changes it made do not start it again
+    handled: Mutex<HashMap<PathBuf, SystemTime>>,
+}
+
+impl Run {
+    pub fn create(settings: ActionSettings) -> Result<Box<dyn Action>> {
+        let create_target = settings.create_target;
+        let rename = settings.rename;
+        let on_conflict = settings.on_conflict;
+        let verify = settings.verify;
+        let ownership = settings.ownership;
+        let attributes = settings.attributes;
+        let layout = settings.layout;
+        Ok(Box::new(Run {
+            command: settings.command.context("A command is required.")?,
+            timeout: settings.timeout.unwrap_or(DEFAULT_RUN_TIMEOUT),
+            then: settings.target.map(|target| Move {
+                target,
+                create_target,
+                rename,
+                on_conflict,
+                verify,
+                ownership,
+                attributes,
+                layout,
+            }),
+            handled: Mutex::default(),
+        }))
+    }
+}
+
+impl Action for Run {
+    fn name(&self) -> &str {
+        "run"
+    }
+
+    fn describe(&self, path: &Path) -> Result<String> {
+        let command = self
+            .command
+            .iter()
+            .map(|word| word.expand(path))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(match &self.then {
+            Some(then) => format!("run {:?}, then {}", command.join(" "), then.describe(path)?),
+            None => format!("run {:?}", command.join(" ")),
+        })
+    }
+
+    fn apply(&self, path: &Path, context: &Context) -> Result<Outcome> {
+        let unchanged = modified(path);
+        // Only locked for the lookup, so that a slow program does not hold up other files
+        if unchanged.is_some()
+            && self
+                .handled
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .get(path)
+                == unchanged.as_ref()
+        {
+            debug!("{:?} has not changed since the command ran. Skipped.", path);
+            return Ok(Outcome::default());
+        }
+
+        let command = self
+            .command
+            .iter()
+            .map(|word| word.expand(path))
+            .collect::<Result<Vec<_>>>()?;
+        let program = command.first().context("The command is empty.")?;
+        let output = run_program(program, &command[1..], self.timeout)?;
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            debug!("{}: {}", program, line);
+        }
+        if !output.status.success() {
+            let mut message = format!("{} failed ({}).", program, output.status);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if !stderr.trim().is_empty() {
+                message = format!("{} {}", message, stderr.trim());
+            }
+            return Err(anyhow!(message));
+        }
+        debug!("Ran {} for {:?}.", program, path);
+
+        match &self.then {
+            Some(then) => then.apply(path, context),
+            None => {
+                if let Some(modified) = modified(path) {
+                    self.handled
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .insert(path.to_path_buf(), modified);
+                }
+                Ok(Outcome::default())
+            }
+        }
+    }
+
+    fn target(&self) -> Option<&Template> {
+        self.then.as_ref().map(|then| &then.target)
+    }
+}
+
+/// Runs the program with the arguments and kills it if it has not finished after `timeout`
+fn run_program(program: &str, args: &[String], timeout: Duration) -> Result<process::Output> {
+    let mut child = process::Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context(format!("Could not run {:?}.", program))?;
+    // Read in the background, so that the program does not block on a full pipe
+    let stdout = read_all(child.stdout.take());
+    let stderr = read_all(child.stderr.take());
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child
+            .try_wait()
+            .context(format!("Could not wait for {:?}.", program))?
+        {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            child.kill().ok();
+            child.wait().ok();
+            return Err(anyhow!(
+                "{} did not finish within {} and was killed.",
+                program,
+                humantime::format_duration(timeout)
+            ));
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+    Ok(process::Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+fn read_all<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut content = Vec::new();
+        if let Some(mut pipe) = pipe {
+            pipe.read_to_end(&mut content).ok();
+        }
+        content
+    })
+}
+
+/// Unpacks zip, tar and 7z archives into the target or, without one, next to the archive
+#[derive(Debug)]
+pub struct Extract {
+    pub target: Option<Template>,
+    pub create_target: bool,
+    /// Unpack into a new directory named after the archive
+    pub subfolder: bool,
+    /// Stage the archive in the trash after it has been unpacked
+    pub delete_original: bool,
+    /// Modification times of archives that stayed where they are after they were unpacked, so
+    /// that they are not unpacked again
+    handled: Mutex<HashMap<PathBuf, SystemTime>>,
+}
+
+impl Extract {
+    pub fn create(settings: ActionSettings) -> Result<Box<dyn Action>> {
+        Ok(Box::new(Extract {
+            target: settings.target,
+            create_target: settings.create_target,
+            subfolder: settings.subfolder,
+            delete_original: settings.delete_original,
+            handled: Mutex::default(),
+        }))
+    }
+
+    /// Where the content of the archive goes
+    fn dir(&self, path: &Path) -> Result<PathBuf> {
+        let mut dir = match &self.target {
+            Some(target) => PathBuf::from(target.expand(path)?),
+            None => path.parent().unwrap_or_else(|| Path::new("")).to_path_buf(),
+        };
+        if self.subfolder {
+            dir.push(archive::stem(path).context(format!("{:?} is not an archive.", path))?);
+        }
+        Ok(dir)
+    }
+}
+
+impl Action for Extract {
+    fn name(&self) -> &str {
+        "extract"
+    }
+
+    fn describe(&self, path: &Path) -> Result<String> {
+        Ok(format!("extract to {:?}", self.dir(path)?))
+    }
+
+    fn apply(&self, path: &Path, context: &Context) -> Result<Outcome> {
+        let unchanged = modified(path);
+        let handled = self.handled.lock().unwrap_or_else(|e| e.into_inner());
+        if unchanged.is_some() && handled.get(path) == unchanged.as_ref() {
+            debug!("{:?} has been unpacked already. Skipped.", path);
+            return Ok(Outcome::default());
+        }
+        drop(handled);
+
+        if let Some(target) = &self.target {
+            create_target_dir(target, self.create_target, path)?;
+        }
+        let mut dir = self.dir(path)?;
+        if self.subfolder {
+            // Another archive of the same name does not mix with the content of this one
+            dir = free_name(dir);
+        }
+        logging::target(&dir);
+        let delete_original = self.delete_original && !context.safe;
+        // Checked first, so that the archive is not extracted again and again
+        if delete_original {
+            context.trash.fits(path)?;
+        }
+        let count = archive::extract(path, &dir)?;
+        debug!("Extracted {} files from {:?} to {:?}.", count, path, &dir);
+
+        let event = if delete_original {
+            Some(delete(path, context)?)
+        } else {
+            if let Some(modified) = unchanged {
+                let mut handled = self.handled.lock().unwrap_or_else(|e| e.into_inner());
+                handled.insert(path.to_path_buf(), modified);
+            }
+            None
+        };
+        Ok(Outcome {
+            destination: Some(dir),
+            replaced: false,
+            event,
+        })
+    }
+
+    fn target(&self) -> Option<&Template> {
+        self.target.as_ref()
+    }
+
+    fn deletes_original(&self) -> bool {
+        self.delete_original
+    }
+}
+
+/// Packs files or directories into a zip or tar.zst archive in the target
+#[derive(Debug)]
+pub struct Compress {
+    pub target: Template,
+    pub create_target: bool,
+    pub format: archive::Format,
+    /// Name of the archive without the extension. Defaults to the name of the file.
+    pub archive_name: Option<Template>,
+    /// Stage the original in the trash once the archive has been verified
+    pub delete_original: bool,
+}
+
+impl Compress {
+    pub fn create(settings: ActionSettings) -> Result<Box<dyn Action>> {
+        let format = settings.format.as_deref().unwrap_or("zip");
+        Ok(Box::new(Compress {
+            target: settings.target.context("A target is required.")?,
+            create_target: settings.create_target,
+            format: archive::Format::for_compression(format)
+                .context(format!("Unknown format {}. Use zip or tar.zst.", format))?,
+            archive_name: settings.archive_name,
+            delete_original: settings.delete_original,
+        }))
+    }
+
+    /// Path of the archive for the file
+    fn archive(&self, path: &Path) -> Result<PathBuf> {
+        let name = match &self.archive_name {
+            Some(name) => name.expand(path)?,
+            None => path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+        };
+        Ok(PathBuf::from(self.target.expand(path)?).join(format!(
+            "{}{}",
+            name,
+            self.format.extension()
+        )))
+    }
+}
+
+impl Action for Compress {
+    fn name(&self) -> &str {
+        "compress"
+    }
+
+    fn describe(&self, path: &Path) -> Result<String> {
+        Ok(format!("compress to {:?}", self.archive(path)?))
+    }
+
+    fn apply(&self, path: &Path, context: &Context) -> Result<Outcome> {
+        let delete_original = self.delete_original && !context.safe;
+        if delete_original {
+            context.trash.fits(path)?;
+        }
+        create_target_dir(&self.target, self.create_target, path)?;
+        // An earlier archive of a file with the same name may be the only copy left of it
+        let archive = free_name(self.archive(path)?);
+        logging::target(&archive);
+        archive::compress(path, &archive, self.format)?;
+        debug!("Compressed {:?} to {:?}.", path, &archive);
+
+        let trash_id = if delete_original {
+            archive::verify(path, &archive, self.format).context(format!(
+                "Could not verify {:?}. The original was kept.",
+                &archive
+            ))?;
+            Some(context.trash.stage(path)?.id)
+        } else {
+            None
+        };
+        Ok(Outcome {
+            destination: Some(archive.clone()),
+            replaced: false,
+            event: Some(Event::Compress {
+                rule: context.rule.to_string(),
+                from: path.to_path_buf(),
+                to: archive,
+                trash_id,
+            }),
+        })
+    }
+
+    fn target(&self) -> Option<&Template> {
+        Some(&self.target)
+    }
+
+    fn deletes_original(&self) -> bool {
+        self.delete_original
+    }
+}
+
+/// Splits a command into words at whitespace. Single and double quotes keep words together.
+pub(crate) fn split_command(command: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    for c in command.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err(anyhow!("Unclosed quote in command {:?}.", command));
+    }
+    words.extend(word);
+    if words.is_empty() {
+        return Err(anyhow!("The command is empty."));
+    }
+    Ok(words)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Expands the target for the file and creates it if necessary. Returns the new path of the
+/// file.
+fn prepare_target(
+    target: &Template,
+    create_target: bool,
+    rename: Option<&Template>,
+    layout: &Layout,
+    path: &Path,
+) -> Result<PathBuf> {
+    let target = create_target_dir(target, create_target, path)?;
+    let name = new_name(rename, path)?;
+    let destination = layout.create_dir(&target, &name)?.join(name);
+    logging::target(&destination);
+    Ok(destination)
+}
+
+/// The destination to use if it is taken, as `on_conflict` says: quarantine by default, a free
+/// name in safe mode. `None` leaves the file alone.
+fn keep_existing(
+    destination: PathBuf,
+    on_conflict: Option<OnConflict>,
+    context: &Context,
+) -> Result<Option<PathBuf>> {
+    if !destination.exists() {
+        return Ok(Some(destination));
+    }
+    let on_conflict = match (on_conflict, context.safe) {
+        (None, true) | (Some(OnConflict::Replace), true) => OnConflict::Rename,
+        (None, false) => OnConflict::Quarantine,
+        (Some(on_conflict), _) => on_conflict,
+    };
+    match on_conflict {
+        OnConflict::Replace => Ok(Some(destination)),
+        OnConflict::Rename => Ok(Some(free_name(destination))),
+        OnConflict::Skip => {
+            info!("{:?} exists already. The file is left alone.", &destination);
+            Ok(None)
+        }
+        OnConflict::Quarantine => Err(Blocked::new(
+            Check::Overwrite,
+            format!(
+                "{:?} exists already. Set on_conflict to replace it.",
+                &destination
+            ),
+        )
+        .into()),
+    }
+}
+
+/// The destination, or `name (1).ext` and so on if it is taken
+fn free_name(destination: PathBuf) -> PathBuf {
+    if !destination.exists() {
+        return destination;
+    }
+    let name = destination
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let free = (1..)
+        .map(|number| destination.with_file_name(numbered_name(&name, number)))
+        .find(|path| !path.exists())
+        .unwrap_or_default();
+    info!(
+        "{:?} exists already. Using {:?} instead.",
+        &destination, &free
+    );
+    logging::target(&free);
+    free
+}
+
+/// `name (1).ext`, `name (2).ext` and so on
+pub(crate) fn numbered_name(name: &str, number: usize) -> String {
+    let path = Path::new(name);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    format!("{} ({}){}", stem, number, extension)
+}
+
+/// Applies mode, owner and extended attributes to a moved or copied file. The file is already in
+/// place, so failures are only reported.
+fn finish_placed(destination: &Path, ownership: &Ownership, attributes: &[(String, Vec<u8>)]) {
+    if let Err(e) = ownership.apply(destination) {
+        error!(
+            "Could not change the mode or owner of {:?}: {}",
+            destination, e
+        );
+    }
+    if let Err(e) = attributes::write(destination, attributes) {
+        error!("Could not set the attributes of {:?}: {}", destination, e);
+    }
+}
+
+/// The target directory or, if the file is renamed, its new path
+fn describe_target(
+    target: &Template,
+    rename: Option<&Template>,
+    layout: &Layout,
+    path: &Path,
+) -> Result<PathBuf> {
+    let target = PathBuf::from(target.expand(path)?);
+    let name = new_name(rename, path)?;
+    let dir = layout.dir(&target, &name);
+    Ok(match rename {
+        Some(_) => dir.join(name),
+        None => dir,
+    })
+}
+
+/// Whether the target is a URL of the given kind instead of a directory
+fn is_remote(settings: &ActionSettings, is_url: fn(&str) -> bool) -> bool {
+    settings
+        .target
+        .as_ref()
+        .is_some_and(|target| is_url(target.source()))
+}
+
+/// The name of the file in the target. A new name must not contain a directory.
+pub(crate) fn new_name(rename: Option<&Template>, path: &Path) -> Result<PathBuf> {
+    let name = match rename {
+        Some(rename) => PathBuf::from(rename.expand(path)?),
+        None => return Ok(PathBuf::from(path.file_name().unwrap_or_default())),
+    };
+    let mut components = name.components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(name),
+        _ => Err(anyhow!("{:?} is not a valid file name.", name)),
+    }
+}
+
+/// Expands the target for the file and creates it if necessary
+fn create_target_dir(target: &Template, create_target: bool, path: &Path) -> Result<PathBuf> {
+    let target = PathBuf::from(target.expand(path)?);
+    if create_target && !target.exists() {
+        fs::create_dir_all(&target)?;
+        info!("Created target directory {:?}.", &target);
+    }
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::plugin::Registry;
+
+    use super::*;
+
+    fn check(dir: &Path, safe: bool, action: impl FnOnce(&Context)) {
+        let trash = Trash::open(dir.join("trash"), Default::default()).unwrap();
+        let throttle = Throttle::new(None, None);
+        action(&Context {
+            rule: "*.pdf",
+            trash: &trash,
+            throttle: &throttle,
+            safe,
+        });
+    }
+
+    #[test]
+    fn taken_names_are_numbered() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.pdf");
+        assert_eq!(free_name(path.clone()), path);
+        fs::write(&path, "").unwrap();
+        fs::write(dir.path().join("report (1).pdf"), "").unwrap();
+        assert_eq!(free_name(path), dir.path().join("report (2).pdf"));
+        assert_eq!(numbered_name("archive", 3), "archive (3)");
+    }
+
+    #[test]
+    fn safe_mode_never_replaces() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.pdf");
+        fs::write(&path, "").unwrap();
+        let renamed = Some(dir.path().join("report (1).pdf"));
+        check(dir.path(), true, |context| {
+            let keep = |on_conflict| keep_existing(path.clone(), on_conflict, context).unwrap();
+            assert_eq!(keep(None), renamed);
+            assert_eq!(keep(Some(OnConflict::Replace)), renamed);
+            assert_eq!(keep(Some(OnConflict::Skip)), None);
+        });
+        check(dir.path(), false, |context| {
+            let error = keep_existing(path.clone(), None, context).unwrap_err();
+            assert_eq!(
+                Blocked::of(&error).map(|blocked| blocked.check),
+                Some(Check::Overwrite)
+            );
+            let replaced = keep_existing(path.clone(), Some(OnConflict::Replace), context);
+            assert_eq!(replaced.unwrap(), Some(path.clone()));
+        });
+    }
+
+    #[test]
+    fn safe_mode_keeps_originals() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.pdf");
+        fs::write(&path, "report").unwrap();
+        let compress = Compress {
+            target: Template::parse(
+                &dir.path().join("archives").to_string_lossy(),
+                &Registry::new(),
+            )
+            .unwrap(),
+            create_target: true,
+            format: archive::Format::Zip,
+            archive_name: None,
+            delete_original: true,
+        };
+        check(dir.path(), true, |context| {
+            let outcome = compress.apply(&path, context).unwrap();
+            assert!(outcome.destination.unwrap().exists());
+            assert!(matches!(
+                outcome.event,
+                Some(Event::Compress { trash_id: None, .. })
+            ));
+            assert!(context.trash.entries().unwrap().is_empty());
+        });
+        assert!(path.exists());
+    }
+}
@@ -0,0 +1,59 @@
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::Sorter;
+
+/// Finds rules that would move files in circles or not at all. Outside of permissive mode,
+/// such rules are refused.
+pub fn check(sorter: &Sorter, watch_dir: &Path, permissive: bool) -> Result<()> {
+    let problems = analyze(sorter, watch_dir);
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    for problem in problems.iter() {
+        if permissive {
+            warn!("{}", problem);
+        } else {
+            error!("{}", problem);
+        }
+    }
+    if permissive {
+        warn!("Permissive mode, the rules are used anyway.");
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Found {} problems in the rules. Fix them or start wurmloch with --permissive.",
+            problems.len()
+        ))
+    }
+}
+
+fn analyze(sorter: &Sorter, watch_dir: &Path) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (name, target) in sorter.move_targets() {
+        let dir = target.static_dir();
+        if dir != watch_dir {
+            // Targets outside of the watch directory are never seen again, nested ones are
+            // excluded from sorting
+            continue;
+        }
+
+        if target.is_static() {
+            problems.push(format!(
+                "Rule {} moves files to the watch directory they come from and does nothing.",
+                name
+            ));
+        } else if sorter.max_depth != Some(0) {
+            // The file lands in a subdirectory that cannot be excluded in advance. There, the
+            // same file name is matched by the same rule again.
+            problems.push(format!(
+                "Rule {} moves files to {} inside the watch directory, where they are sorted \
+                 again in an endless loop.",
+                name, target
+            ));
+        }
+    }
+    problems
+}
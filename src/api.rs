@@ -0,0 +1,129 @@
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::control::State;
+use crate::http::{self, Request, Response};
+
+/// Recent actions returned by `/api/actions` unless `last` says otherwise
+const DEFAULT_ACTIONS: usize = 20;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiSettings {
+    /// Address and port to listen on. Defaults to `127.0.0.1:9185`, only reachable from this
+    /// machine.
+    #[serde(default = "default_addr")]
+    pub addr: SocketAddr,
+    /// Clients send it as `Authorization: Bearer <token>`
+    pub token: String,
+}
+
+fn default_addr() -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::LOCALHOST, 9185))
+}
+
+impl ApiSettings {
+    pub fn validate(&self) -> Result<()> {
+        if self.token.trim().is_empty() {
+            return Err(anyhow!(
+                "The API needs a token. Set api.token, e.g. to a secret."
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Serves a JSON API to manage the running instance from other machines
+pub fn serve(settings: &ApiSettings, state: Arc<State>) -> Result<()> {
+    let listener = TcpListener::bind(settings.addr)
+        .context(format!("Could not serve the API on {}.", settings.addr))?;
+    info!("Serving the API on http://{}/api.", settings.addr);
+    let token = settings.token.clone();
+    http::serve(listener, "API", move |request| {
+        respond(request, &token, &state)
+    });
+    Ok(())
+}
+
+fn respond(request: &Request, token: &str, state: &State) -> Result<Response> {
+    let authorized = request
+        .header("authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|sent| same(sent.trim(), token));
+    let (status, body) = if authorized {
+        handle(&request.method, &request.path, &request.query, state)
+    } else {
+        (
+            "401 Unauthorized",
+            json!({"error": "Invalid or missing token."}),
+        )
+    };
+    Ok(Response {
+        status,
+        content_type: "application/json",
+        body: serde_json::to_string_pretty(&body)? + "\n",
+    })
+}
+
+fn handle(method: &str, path: &str, query: &str, state: &State) -> (&'static str, Value) {
+    let message = |text: &str| json!({ "message": text, "paused": state.is_paused() });
+    match (method, path.trim_end_matches('/')) {
+        ("GET", "/api/status") => {
+            let status = state.status();
+            (
+                "200 OK",
+                json!({
+                    "started": status.started,
+                    "paused": state.is_paused(),
+                    "watched": status.watched,
+                    "rules": status.rules.len(),
+                    "moved": status.moved,
+                    "deleted": status.deleted,
+                    "errors": status.errors,
+                    "given_up": status.given_up,
+                    "quarantined": status.quarantined,
+                    "bytes_moved": status.bytes_moved,
+                    "queued": status.queued + status.work_queue + status.in_progress,
+                }),
+            )
+        }
+        ("GET", "/api/rules") => ("200 OK", json!(state.status().rules)),
+        ("GET", "/api/actions") => {
+            let last = query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("last="))
+                .and_then(|last| last.parse().ok())
+                .unwrap_or(DEFAULT_ACTIONS);
+            let status = state.status();
+            let skip = status.recent.len().saturating_sub(last);
+            let actions: Vec<_> = status.recent.iter().skip(skip).collect();
+            ("200 OK", json!(actions))
+        }
+        ("POST", "/api/pause") => ("200 OK", message(state.pause())),
+        ("POST", "/api/resume") => ("200 OK", message(state.resume())),
+        ("POST", "/api/rescan") => ("200 OK", message(state.rescan())),
+        ("POST", "/api/reload") => ("200 OK", message(state.reload())),
+        (
+            _,
+            "/api/status" | "/api/rules" | "/api/actions" | "/api/pause" | "/api/resume"
+            | "/api/rescan" | "/api/reload",
+        ) => (
+            "405 Method Not Allowed",
+            json!({"error": "Method not allowed."}),
+        ),
+        _ => ("404 Not Found", json!({"error": "Not found."})),
+    }
+}
+
+/// Compares the whole token, so that the time taken does not tell how much of it was right
+fn same(sent: &str, token: &str) -> bool {
+    sent.len() == token.len()
+        && sent
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
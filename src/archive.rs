@@ -0,0 +1,507 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Local, Timelike};
+use flate2::read::GzDecoder;
+
+use crate::quarantine::{Blocked, Check};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+    SevenZ,
+}
+
+impl Format {
+    /// Formats that archives can be created in, as named in the rule file
+    pub fn for_compression(name: &str) -> Option<Format> {
+        match name {
+            "zip" => Some(Format::Zip),
+            "tar.zst" => Some(Format::TarZst),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        SUFFIXES
+            .iter()
+            .find(|(_, format)| *format == self)
+            .map_or("", |(suffix, _)| suffix)
+    }
+}
+
+/// Recognized file name endings, longest first. The first one of a format is used for new
+/// archives.
+const SUFFIXES: [(&str, Format); 7] = [
+    (".tar.gz", Format::TarGz),
+    (".tar.zst", Format::TarZst),
+    (".tgz", Format::TarGz),
+    (".tzst", Format::TarZst),
+    (".tar", Format::Tar),
+    (".zip", Format::Zip),
+    (".7z", Format::SevenZ),
+];
+
+fn split(path: &Path) -> Option<(String, Format)> {
+    let name = path.file_name()?.to_string_lossy().into_owned();
+    SUFFIXES.iter().find_map(|(suffix, format)| {
+        let stem_len = name.len().checked_sub(suffix.len())?;
+        if stem_len > 0 && name.to_lowercase().ends_with(suffix) {
+            Some((String::from(&name[..stem_len]), *format))
+        } else {
+            None
+        }
+    })
+}
+
+/// Name of the archive without its extension, e.g. `photos` for `photos.tar.gz`
+pub fn stem(path: &Path) -> Option<String> {
+    split(path).map(|(stem, _)| stem)
+}
+
+/// Unpacks a zip, tar, tar.gz, tar.zst or 7z archive into `dir` and returns the number of unpacked
+/// files. Archives with entries or links that would end up outside of `dir`, or that would
+/// replace existing files, are refused.
+pub fn extract(path: &Path, dir: &Path) -> Result<usize> {
+    let (_, format) = split(path).context(format!("{:?} is not a known archive format.", path))?;
+    // Nothing is written if a single entry is refused
+    for (name, link) in entries(path, format).context(format!("Could not read {:?}.", path))? {
+        let destination =
+            enclosed(dir, &name).context(format!("Refused to extract {:?}.", path))?;
+        if let Some(link) = link {
+            linked(&name, &link).context(format!("Refused to extract {:?}.", path))?;
+        }
+        // Directories are merged, everything else would be replaced
+        if fs::symlink_metadata(&destination).is_ok_and(|m| !m.is_dir()) {
+            return Err(Blocked::new(
+                Check::Overwrite,
+                format!(
+                    "Extracting {:?} would replace {:?}.",
+                    path.file_name().unwrap_or_default(),
+                    destination
+                ),
+            )
+            .into());
+        }
+    }
+
+    fs::create_dir_all(dir).context(format!("Could not create {:?}.", dir))?;
+    let file = File::open(path).context(format!("Could not open {:?}.", path))?;
+    match format {
+        Format::Zip => extract_zip(file, dir),
+        Format::Tar => extract_tar(file, dir),
+        Format::TarGz => extract_tar(GzDecoder::new(file), dir),
+        Format::TarZst => extract_tar(zstd::Decoder::new(file)?, dir),
+        Format::SevenZ => extract_7z(path, dir),
+    }
+    .context(format!("Could not extract {:?}.", path))
+}
+
+/// Names of the entries and, for links, what they point to
+fn entries(path: &Path, format: Format) -> Result<Vec<(PathBuf, Option<Link>)>> {
+    let file = File::open(path)?;
+    Ok(match format {
+        Format::Zip => zip::ZipArchive::new(file)?
+            .file_names()
+            .map(|name| (PathBuf::from(name), None))
+            .collect(),
+        Format::Tar => tar_entries(file)?,
+        Format::TarGz => tar_entries(GzDecoder::new(file))?,
+        Format::TarZst => tar_entries(zstd::Decoder::new(file)?)?,
+        Format::SevenZ => sevenz_rust::SevenZReader::open(path, sevenz_rust::Password::empty())?
+            .archive()
+            .files
+            .iter()
+            .map(|entry| (PathBuf::from(entry.name()), None))
+            .collect(),
+    })
+}
+
+fn tar_entries(reader: impl Read) -> Result<Vec<(PathBuf, Option<Link>)>> {
+    let mut entries = Vec::new();
+    for entry in tar::Archive::new(reader).entries()? {
+        let entry = entry?;
+        let kind = entry.header().entry_type();
+        let link = match entry.link_name()? {
+            Some(target) if kind.is_symlink() => Some(Link::Symbolic(target.into_owned())),
+            Some(target) if kind.is_hard_link() => Some(Link::Hard(target.into_owned())),
+            _ => None,
+        };
+        entries.push((entry.path()?.into_owned(), link));
+    }
+    Ok(entries)
+}
+
+/// What a link in an archive points to
+#[derive(Debug)]
+enum Link {
+    /// Relative to the directory of the link
+    Symbolic(PathBuf),
+    /// Relative to the root of the archive
+    Hard(PathBuf),
+}
+
+fn extract_zip(file: File, dir: &Path) -> Result<usize> {
+    let mut archive = zip::ZipArchive::new(file)?;
+    let mut count = 0;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        let destination = enclosed(dir, Path::new(entry.name()))?;
+        if entry.is_dir() {
+            fs::create_dir_all(&destination)?;
+        } else {
+            write_file(&destination, &mut entry)?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+fn extract_tar(reader: impl Read, dir: &Path) -> Result<usize> {
+    let mut archive = tar::Archive::new(reader);
+    let mut count = 0;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        enclosed(dir, &entry.path()?)?;
+        // Also refuses links that point outside of the directory
+        if entry.unpack_in(dir)? && entry.header().entry_type().is_file() {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+fn extract_7z(path: &Path, dir: &Path) -> Result<usize> {
+    let mut archive = sevenz_rust::SevenZReader::open(path, sevenz_rust::Password::empty())?;
+    let mut count = 0;
+    archive.for_each_entries(|entry, reader| {
+        let destination = enclosed(dir, Path::new(entry.name()))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:#}", e)))?;
+        if entry.is_directory() {
+            fs::create_dir_all(&destination)?;
+        } else {
+            write_file(&destination, reader)?;
+            count += 1;
+        }
+        Ok(true)
+    })?;
+    Ok(count)
+}
+
+/// Packs a file or a directory with all its content into a new zip or tar.zst archive. The
+/// archive only appears once it is complete and never replaces an existing one.
+pub fn compress(path: &Path, archive: &Path, format: Format) -> Result<()> {
+    let mut partial = archive.as_os_str().to_os_string();
+    partial.push(".part");
+    let partial = PathBuf::from(partial);
+    let result = match format {
+        Format::Zip => compress_zip(path, &partial),
+        Format::TarZst => compress_tar_zst(path, &partial),
+        _ => Err(anyhow!("Archives cannot be created as {:?}.", format)),
+    };
+    if let Err(e) = result {
+        let _ = fs::remove_file(&partial);
+        return Err(e.context(format!("Could not compress {:?}.", path)));
+    }
+    if archive.exists() {
+        let _ = fs::remove_file(&partial);
+        return Err(anyhow!("{:?} exists already.", archive));
+    }
+    fs::rename(&partial, archive).context(format!("Could not create {:?}.", archive))
+}
+
+/// Makes sure that an archive created by [`compress`] is readable and contains every file of
+/// the original with the same size
+pub fn verify(path: &Path, archive: &Path, format: Format) -> Result<()> {
+    let mut expected = BTreeMap::new();
+    for (name, path) in members(path)? {
+        if path.is_file() {
+            expected.insert(name, fs::metadata(&path)?.len());
+        }
+    }
+
+    let mut found = BTreeMap::new();
+    let file = File::open(archive).context(format!("Could not open {:?}.", archive))?;
+    match format {
+        Format::Zip => {
+            let mut archive = zip::ZipArchive::new(file)?;
+            for index in 0..archive.len() {
+                let mut entry = archive.by_index(index)?;
+                if !entry.is_dir() {
+                    // Reading the whole entry checks its checksum
+                    let size = io::copy(&mut entry, &mut io::sink())?;
+                    found.insert(entry.name().to_string(), size);
+                }
+            }
+        }
+        _ => {
+            let mut archive = tar::Archive::new(zstd::Decoder::new(file)?);
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                if entry.header().entry_type().is_file() {
+                    let name = archive_name(&entry.path()?);
+                    found.insert(name, io::copy(&mut entry, &mut io::sink())?);
+                }
+            }
+        }
+    }
+
+    if expected != found {
+        return Err(anyhow!(
+            "The archive {:?} does not match {:?}.",
+            archive,
+            path
+        ));
+    }
+    Ok(())
+}
+
+/// Everything that goes into an archive of `path`, with the names inside the archive
+fn members(path: &Path) -> io::Result<Vec<(String, PathBuf)>> {
+    let mut members = Vec::new();
+    let mut pending = vec![PathBuf::from(path.file_name().unwrap_or_default())];
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    while let Some(relative) = pending.pop() {
+        let path = base.join(&relative);
+        if path.is_dir() {
+            for entry in fs::read_dir(&path)? {
+                pending.push(relative.join(entry?.file_name()));
+            }
+        }
+        members.push((archive_name(&relative), path));
+    }
+    members.sort();
+    Ok(members)
+}
+
+/// Entry names in archives always use `/`
+fn archive_name(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn compress_zip(path: &Path, archive: &Path) -> Result<()> {
+    let mut writer = zip::ZipWriter::new(File::create(archive)?);
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .large_file(true);
+    for (name, path) in members(path)? {
+        let options = options.last_modified_time(modified(&path));
+        if path.is_dir() {
+            writer.add_directory(name, options)?;
+        } else {
+            writer.start_file(name, options)?;
+            io::copy(&mut File::open(&path)?, &mut writer)?;
+        }
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// Modification time in the resolution of zip archives
+fn modified(path: &Path) -> zip::DateTime {
+    let modified = match fs::metadata(path).and_then(|m| m.modified()) {
+        Ok(modified) => DateTime::<Local>::from(modified),
+        Err(_) => return zip::DateTime::default(),
+    };
+    zip::DateTime::from_date_and_time(
+        modified.year() as u16,
+        modified.month() as u8,
+        modified.day() as u8,
+        modified.hour() as u8,
+        modified.minute() as u8,
+        modified.second() as u8,
+    )
+    .unwrap_or_default()
+}
+
+fn compress_tar_zst(path: &Path, archive: &Path) -> Result<()> {
+    let encoder = zstd::Encoder::new(File::create(archive)?, 0)?;
+    let mut builder = tar::Builder::new(encoder);
+    let name = path.file_name().unwrap_or_default();
+    if path.is_dir() {
+        builder.append_dir_all(name, path)?;
+    } else {
+        builder.append_path_with_name(path, name)?;
+    }
+    builder.into_inner()?.finish()?;
+    Ok(())
+}
+
+fn write_file(destination: &Path, reader: &mut dyn Read) -> io::Result<()> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // Existing files were refused before, this also keeps from writing through links
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(destination)?;
+    io::copy(reader, &mut file)?;
+    Ok(())
+}
+
+/// Refuses links that point outside of the directory the archive is unpacked to, so that later
+/// entries cannot be written through them
+fn linked(name: &Path, link: &Link) -> Result<()> {
+    let (mut depth, target) = match link {
+        Link::Symbolic(target) => {
+            let parents = name
+                .components()
+                .filter(|c| matches!(c, Component::Normal(_)))
+                .count();
+            (parents.saturating_sub(1), target)
+        }
+        Link::Hard(target) => (0, target),
+    };
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => depth -= 1,
+            _ => {
+                return Err(Blocked::new(
+                    Check::ZipSlip,
+                    format!(
+                        "Link {:?} points outside of the target directory ({:?}).",
+                        name, target
+                    ),
+                )
+                .into())
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Where an entry of an archive is unpacked to. Absolute paths and `..` are refused, so that
+/// an archive cannot write anywhere else (zip slip).
+fn enclosed(dir: &Path, name: &Path) -> Result<PathBuf> {
+    let mut destination = dir.to_path_buf();
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => destination.push(part),
+            Component::CurDir => {}
+            _ => {
+                return Err(Blocked::new(
+                    Check::ZipSlip,
+                    format!("Entry {:?} points outside of the target directory.", name),
+                )
+                .into())
+            }
+        }
+    }
+    Ok(destination)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn zip(path: &Path, names: &[&str]) {
+        let mut writer = zip::ZipWriter::new(File::create(path).unwrap());
+        for name in names {
+            writer
+                .start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(b"new").unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    fn check(error: &anyhow::Error) -> Option<Check> {
+        Blocked::of(error).map(|blocked| blocked.check)
+    }
+
+    #[test]
+    fn entries_stay_inside() {
+        let dir = Path::new("/target");
+        assert_eq!(
+            enclosed(dir, Path::new("a/./b.txt")).unwrap(),
+            dir.join("a/b.txt")
+        );
+        assert!(enclosed(dir, Path::new("../x")).is_err());
+        assert!(enclosed(dir, Path::new("a/../../x")).is_err());
+        assert!(enclosed(dir, Path::new("a/../b")).is_err());
+        assert!(enclosed(dir, Path::new("/etc/passwd")).is_err());
+    }
+
+    #[test]
+    fn links_stay_inside() {
+        let symbolic = |target: &str| Link::Symbolic(PathBuf::from(target));
+        assert!(linked(Path::new("a/link"), &symbolic("../b")).is_ok());
+        assert!(linked(Path::new("a/link"), &symbolic("c/d")).is_ok());
+        assert!(linked(Path::new("link"), &symbolic("../x")).is_err());
+        assert!(linked(Path::new("./a/link"), &symbolic("../../x")).is_err());
+        assert!(linked(Path::new("a/link"), &symbolic("/etc")).is_err());
+        assert!(linked(Path::new("link"), &Link::Hard(PathBuf::from("a/b"))).is_ok());
+        assert!(linked(Path::new("a/link"), &Link::Hard(PathBuf::from("../x"))).is_err());
+    }
+
+    #[test]
+    fn refuses_entries_outside() {
+        let temp = tempfile::tempdir().unwrap();
+        let archive = temp.path().join("evil.zip");
+        zip(&archive, &["ok.txt", "../evil.txt"]);
+        let dir = temp.path().join("out");
+        let error = extract(&archive, &dir).unwrap_err();
+        assert_eq!(check(&error), Some(Check::ZipSlip));
+        assert!(!dir.exists());
+        assert!(!temp.path().join("evil.txt").exists());
+    }
+
+    #[test]
+    fn refuses_symlinks_outside() {
+        let temp = tempfile::tempdir().unwrap();
+        let outside = temp.path().join("outside");
+        fs::create_dir(&outside).unwrap();
+        let archive = temp.path().join("evil.tar");
+        let mut builder = tar::Builder::new(File::create(&archive).unwrap());
+        let mut link = tar::Header::new_gnu();
+        link.set_entry_type(tar::EntryType::Symlink);
+        link.set_size(0);
+        link.set_mode(0o777);
+        builder.append_link(&mut link, "evil", &outside).unwrap();
+        let mut file = tar::Header::new_gnu();
+        file.set_size(3);
+        file.set_mode(0o644);
+        file.set_cksum();
+        builder
+            .append_data(&mut file, "evil/x.txt", &b"new"[..])
+            .unwrap();
+        builder.into_inner().unwrap();
+
+        let dir = temp.path().join("out");
+        let error = extract(&archive, &dir).unwrap_err();
+        assert_eq!(check(&error), Some(Check::ZipSlip));
+        assert!(!dir.exists());
+        assert!(!outside.join("x.txt").exists());
+    }
+
+    #[test]
+    fn refuses_to_replace_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let archive = temp.path().join("files.zip");
+        zip(&archive, &["a.txt"]);
+        let dir = temp.path().join("out");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "old").unwrap();
+        let error = extract(&archive, &dir).unwrap_err();
+        assert_eq!(check(&error), Some(Check::Overwrite));
+        assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "old");
+
+        fs::remove_file(dir.join("a.txt")).unwrap();
+        assert_eq!(extract(&archive, &dir).unwrap(), 1);
+        assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "new");
+    }
+}
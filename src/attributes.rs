@@ -0,0 +1,150 @@
+use std::io;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::template::Template;
+
+/// Where the original path of a file is recorded
+pub const ORIGIN_ATTRIBUTE: &str = "user.wurmloch.origin";
+
+/// Finder tags on macOS
+const FINDER_TAGS_ATTRIBUTE: &str = "com.apple.metadata:_kMDItemUserTags";
+
+/// Tags on other systems, as used by KDE Dolphin
+const XDG_TAGS_ATTRIBUTE: &str = "user.xdg.tags";
+
+/// Extended attributes that moved and copied files get
+#[derive(Debug, Default)]
+pub struct Attributes {
+    pub tags: Vec<Template>,
+    /// Names and values of further attributes
+    pub xattrs: Vec<(String, Template)>,
+    /// Record the original path in [`ORIGIN_ATTRIBUTE`]
+    pub record_origin: bool,
+}
+
+impl Attributes {
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.xattrs.is_empty() && !self.record_origin
+    }
+
+    /// Names and values of the attributes for a file. Resolved before the file is moved, as
+    /// placeholders may read it.
+    pub fn resolve(&self, path: &Path) -> Result<Vec<(String, Vec<u8>)>> {
+        let mut attributes = Vec::new();
+        if !self.tags.is_empty() {
+            let mut tags = Vec::new();
+            for tag in &self.tags {
+                let tag = tag.expand(path)?;
+                if !tag.is_empty() && !tags.contains(&tag) {
+                    tags.push(tag);
+                }
+            }
+            attributes.push(if cfg!(target_os = "macos") {
+                (String::from(FINDER_TAGS_ATTRIBUTE), property_list(&tags))
+            } else {
+                (
+                    String::from(XDG_TAGS_ATTRIBUTE),
+                    tags.join(",").into_bytes(),
+                )
+            });
+        }
+        for (name, value) in &self.xattrs {
+            attributes.push((name.clone(), value.expand(path)?.into_bytes()));
+        }
+        if self.record_origin {
+            attributes.push((
+                String::from(ORIGIN_ATTRIBUTE),
+                path.to_string_lossy().into_owned().into_bytes(),
+            ));
+        }
+        Ok(attributes)
+    }
+}
+
+/// Sets attributes as resolved by [`Attributes::resolve`]
+#[cfg(unix)]
+pub fn write(path: &Path, attributes: &[(String, Vec<u8>)]) -> io::Result<()> {
+    for (name, value) in attributes {
+        xattr::set(path, name, value)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn write(_path: &Path, attributes: &[(String, Vec<u8>)]) -> io::Result<()> {
+    if attributes.is_empty() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Extended attributes are only supported on Unix.",
+        ))
+    }
+}
+
+/// A binary property list with an array of strings, the format of Finder tags
+fn property_list(strings: &[String]) -> Vec<u8> {
+    let reference_size = if strings.len() < 255 { 1 } else { 2 };
+    let mut objects = Vec::new();
+
+    // The array is the first object and refers to the strings that follow it
+    let mut array = Vec::new();
+    push_marker(&mut array, 0xa0, strings.len());
+    for index in 1..=strings.len() {
+        array.extend_from_slice(&index.to_be_bytes()[8 - reference_size..]);
+    }
+    objects.push(array);
+    for string in strings {
+        let mut object = Vec::new();
+        if string.is_ascii() {
+            push_marker(&mut object, 0x50, string.len());
+            object.extend_from_slice(string.as_bytes());
+        } else {
+            let units: Vec<u16> = string.encode_utf16().collect();
+            push_marker(&mut object, 0x60, units.len());
+            units
+                .iter()
+                .for_each(|unit| object.extend_from_slice(&unit.to_be_bytes()));
+        }
+        objects.push(object);
+    }
+
+    let mut plist = b"bplist00".to_vec();
+    let mut offsets = Vec::new();
+    for object in objects {
+        offsets.push(plist.len());
+        plist.extend(object);
+    }
+    let offset_table = plist.len();
+    let offset_size = if offset_table < 1 << 8 {
+        1
+    } else if offset_table < 1 << 16 {
+        2
+    } else {
+        4
+    };
+    for offset in &offsets {
+        plist.extend_from_slice(&offset.to_be_bytes()[8 - offset_size..]);
+    }
+    plist.extend_from_slice(&[0; 6]);
+    plist.push(offset_size as u8);
+    plist.push(reference_size as u8);
+    plist.extend_from_slice(&(offsets.len() as u64).to_be_bytes());
+    plist.extend_from_slice(&0u64.to_be_bytes());
+    plist.extend_from_slice(&(offset_table as u64).to_be_bytes());
+    plist
+}
+
+/// Object type and length. Lengths of 15 and more follow as an integer object.
+fn push_marker(object: &mut Vec<u8>, kind: u8, len: usize) {
+    if len < 15 {
+        object.push(kind | len as u8);
+    } else if len < 1 << 8 {
+        object.extend_from_slice(&[kind | 0xf, 0x10, len as u8]);
+    } else {
+        object.extend_from_slice(&[kind | 0xf, 0x11]);
+        object.extend_from_slice(&(len as u16).to_be_bytes());
+    }
+}
@@ -0,0 +1,115 @@
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, StandardTagKey};
+use symphonia::core::probe::Hint;
+
+use crate::plugin::{self, LastRead, Plugin, Registry};
+
+/// Placeholders from the tags of MP3 (ID3), FLAC and Ogg Vorbis files
+pub struct AudioTags;
+
+impl Plugin for AudioTags {
+    fn name(&self) -> &str {
+        "audio"
+    }
+
+    fn register(&self, registry: &mut Registry) {
+        registry.variable("audio.artist", |path: &Path| {
+            tag(path, |tags| {
+                tags.artist
+                    .or(tags.album_artist)
+                    .unwrap_or_else(|| String::from("Unknown Artist"))
+            })
+        });
+        registry.variable("audio.album", |path: &Path| {
+            tag(path, |tags| {
+                tags.album.unwrap_or_else(|| String::from("Unknown Album"))
+            })
+        });
+        registry.variable("audio.title", |path: &Path| {
+            tag(path, |tags| {
+                tags.title.unwrap_or_else(|| {
+                    path.file_stem()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned()
+                })
+            })
+        });
+        registry.variable("audio.track", |path: &Path| {
+            tag(path, |tags| tags.track.unwrap_or_else(|| String::from("0")))
+        });
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Tags {
+    artist: Option<String>,
+    album_artist: Option<String>,
+    album: Option<String>,
+    title: Option<String>,
+    /// Without the number of tracks, e.g. `3` for `3/12`
+    track: Option<String>,
+}
+
+static LAST_READ: LastRead<Tags> = LastRead::new();
+
+/// A tag value that can be used as a file or directory name
+fn tag(path: &Path, value: impl Fn(Tags) -> String) -> Result<String> {
+    let tags = LAST_READ
+        .get(path, read)
+        .context(format!("Could not read tags of {:?}.", path))?;
+    Ok(plugin::sanitize(&value(tags)))
+}
+
+fn read(path: &Path) -> Result<Tags> {
+    let source = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+    let mut probed = symphonia::default::get_probe().format(
+        &hint,
+        source,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+
+    let mut tags = Tags::default();
+    // ID3 tags are found while probing, the tags of FLAC and Ogg files are part of the format
+    let mut revisions = Vec::new();
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+        revisions.push(revision.clone());
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        revisions.push(revision.clone());
+    }
+    for tag in revisions.iter().flat_map(|r| r.tags()) {
+        let value = tag.value.to_string().trim().to_string();
+        if value.is_empty() {
+            continue;
+        }
+        let field = match tag.std_key {
+            Some(StandardTagKey::Artist) => &mut tags.artist,
+            Some(StandardTagKey::AlbumArtist) => &mut tags.album_artist,
+            Some(StandardTagKey::Album) => &mut tags.album,
+            Some(StandardTagKey::TrackTitle) => &mut tags.title,
+            Some(StandardTagKey::TrackNumber) => &mut tags.track,
+            _ => continue,
+        };
+        field.get_or_insert(value);
+    }
+    if let Some(track) = tags.track.as_mut() {
+        *track = track
+            .split('/')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+    }
+    Ok(tags)
+}
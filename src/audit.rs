@@ -0,0 +1,218 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::config::RuleType;
+use crate::{data_dir, scan, Rule, Sorter};
+
+const MAX_LISTED_FILES: usize = 50;
+
+/// Fingerprints of the cleanup rules that have been confirmed, one per line
+const CONFIRMED_CLEANUPS: &str = "confirmed-cleanups";
+/// Written when the rules are confirmed on the first start, with the fingerprint of the rules
+const CONFIRMED_RULES: &str = "confirmed-rules";
+
+/// Shows what freshly created rules would do with the files that are currently in the wormhole
+/// and asks for confirmation. Returns whether sorting may start.
+pub fn confirm_first_run(sorter: &Sorter, watch_dir: &Path, yes: bool) -> Result<bool> {
+    let files =
+        scan::files_in(sorter, watch_dir, 0).context(format!("Could not read {:?}.", watch_dir))?;
+    println!("This is the first start of wurmloch. Nothing has been changed so far.");
+    println!(
+        "The rules would treat the {} files in {:?} like this:\n",
+        files.len(),
+        watch_dir
+    );
+
+    let (mut moved, mut deleted, mut unmatched) = (0, 0, 0);
+    for (index, path) in files.iter().enumerate() {
+        let filename = path.file_name().unwrap_or_default();
+        if sorter.quirks.matching(filename).is_some() {
+            continue;
+        }
+        let kind = if path.is_dir() {
+            RuleType::Dir
+        } else {
+            RuleType::File
+        };
+        let outcome = match sorter.find_rule(path, kind) {
+            Some(rule) => {
+                match rule.action.name() {
+                    "delete" => deleted += 1,
+                    _ => moved += 1,
+                }
+                rule.action
+                    .describe(path)
+                    .unwrap_or_else(|e| format!("fail ({:#})", e))
+            }
+            None => match &sorter.fallback {
+                Some(fallback) => {
+                    moved += 1;
+                    fallback
+                        .action
+                        .describe(path)
+                        .map(|d| format!("{}, no rule matches", d))
+                        .unwrap_or_else(|e| format!("fail ({:#})", e))
+                }
+                None => {
+                    unmatched += 1;
+                    String::from("stay, no rule matches")
+                }
+            },
+        };
+        if index < MAX_LISTED_FILES {
+            println!(
+                "  {}  ->  {}",
+                path.strip_prefix(watch_dir)?.display(),
+                outcome
+            );
+        }
+    }
+    if files.len() > MAX_LISTED_FILES {
+        println!("  ... and {} more", files.len() - MAX_LISTED_FILES);
+    }
+    println!(
+        "\n{} files would be moved, {} deleted and {} would stay.",
+        moved, deleted, unmatched
+    );
+    println!("Files that are already in the wormhole are left alone, new files are sorted.");
+
+    let confirmed = ask(
+        "Start sorting?",
+        yes,
+        "The rules have not been confirmed yet. Start wurmloch with --yes to confirm them.",
+    )?;
+    if confirmed {
+        let path = data_dir()?.join(CONFIRMED_RULES);
+        fs::write(&path, format!("{}\n", sorter.fingerprint))
+            .context(format!("Could not write {:?}.", path))?;
+        info!("Rules confirmed.");
+    } else {
+        println!("Nothing was changed. Adjust the rules and start wurmloch again.");
+    }
+    Ok(confirmed)
+}
+
+/// Shows what the rules that delete files by age would delete in the directory now and
+/// confirms them. Until then, they only log what they would delete.
+pub fn confirm_cleanup(sorter: &Sorter, dir: &Path, yes: bool) -> Result<()> {
+    let unconfirmed: Vec<&Rule> = sorter
+        .rules
+        .iter()
+        .filter(|rule| {
+            rule.cleanup
+                .as_ref()
+                .is_some_and(|fingerprint| !is_cleanup_confirmed(fingerprint))
+        })
+        .collect();
+    if unconfirmed.is_empty() {
+        println!("There are no cleanup rules to confirm.");
+        return Ok(());
+    }
+
+    let files = scan::files_in(sorter, dir, 0).context(format!("Could not read {:?}.", dir))?;
+    for rule in unconfirmed.iter() {
+        let expired: Vec<&PathBuf> = files
+            .iter()
+            .filter(|path| {
+                let kind = if path.is_dir() {
+                    RuleType::Dir
+                } else {
+                    RuleType::File
+                };
+                sorter
+                    .find_rule(path, kind)
+                    .is_some_and(|found| std::ptr::eq(found, *rule))
+            })
+            .collect();
+        println!(
+            "Rule {} would delete {} files in {:?} now:\n",
+            &rule.name,
+            expired.len(),
+            dir
+        );
+        for path in expired.iter().take(MAX_LISTED_FILES) {
+            let modified = fs::symlink_metadata(path).and_then(|m| m.modified());
+            println!(
+                "  {}  (last modified {})",
+                path.strip_prefix(dir)?.display(),
+                match modified {
+                    Ok(modified) => DateTime::<Local>::from(modified)
+                        .format("%Y-%m-%d")
+                        .to_string(),
+                    Err(_) => String::from("unknown"),
+                }
+            );
+        }
+        if expired.len() > MAX_LISTED_FILES {
+            println!("  ... and {} more", expired.len() - MAX_LISTED_FILES);
+        }
+        println!();
+    }
+
+    let confirmed = ask(
+        "Confirm these rules?",
+        yes,
+        "The cleanup rules have not been confirmed. Run confirm-cleanup with --yes to confirm them.",
+    )?;
+    if !confirmed {
+        println!("Nothing was confirmed. The rules keep logging what they would delete.");
+        return Ok(());
+    }
+    let path = data_dir()?.join(CONFIRMED_CLEANUPS);
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .context(format!("Could not open {:?}.", path))?;
+    for rule in unconfirmed.iter() {
+        writeln!(file, "{}", rule.cleanup.as_deref().unwrap_or_default())
+            .context(format!("Could not write {:?}.", path))?;
+    }
+    println!(
+        "Confirmed {} cleanup rules. They delete files from now on.",
+        unconfirmed.len()
+    );
+    Ok(())
+}
+
+/// Identifies the settings of a rule, or of all rules, so that a confirmation is void once they
+/// change
+pub fn fingerprint<T: Serialize + ?Sized>(rules: &T) -> String {
+    let settings = serde_yaml::to_string(rules).unwrap_or_default();
+    format!("{:x}", Sha256::digest(settings.as_bytes()))
+}
+
+/// Whether the rules have been confirmed on the first start
+pub fn rules_confirmed() -> bool {
+    data_dir().is_ok_and(|dir| dir.join(CONFIRMED_RULES).exists())
+}
+
+/// Whether the cleanup rule has been confirmed with `wurmloch confirm-cleanup`
+pub fn is_cleanup_confirmed(fingerprint: &str) -> bool {
+    let confirmed = data_dir()
+        .and_then(|dir| Ok(fs::read_to_string(dir.join(CONFIRMED_CLEANUPS))?))
+        .unwrap_or_default();
+    confirmed.lines().any(|line| line == fingerprint)
+}
+
+/// Asks a yes/no question, unless `yes` already answered it. Fails without a terminal to ask
+/// in.
+fn ask(question: &str, yes: bool, unanswered: &str) -> Result<bool> {
+    if yes {
+        return Ok(true);
+    }
+    if !io::stdin().is_terminal() {
+        return Err(anyhow!("{}", unanswered));
+    }
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
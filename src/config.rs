@@ -0,0 +1,910 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::prelude::Write;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use globset::Glob;
+use serde::{Deserialize, Serialize};
+
+use crate::action::OnConflict;
+use crate::api::ApiSettings;
+use crate::counter::CounterSettings;
+use crate::errors::OnError;
+use crate::history::HistorySettings;
+use crate::index::OnDuplicate;
+use crate::normalize::Normalization;
+use crate::notifications::NotificationSettings;
+use crate::provenance::Provenance;
+use crate::rclone::RcloneSettings;
+use crate::retry::RetrySettings;
+use crate::s3::S3Settings;
+use crate::schedule::Schedule;
+use crate::secrets::{Secret, Secrets};
+use crate::shard::Shard;
+use crate::space::OnFull;
+use crate::transfer::Checksum;
+use crate::trash::TrashSettings;
+use crate::video::VideoConditions;
+use crate::volumes::VolumeSettings;
+use crate::webdav::WebDavSettings;
+use crate::window::TimeWindow;
+use crate::{audit, presets, APP_NAME, RULES_FILE_NAME};
+
+/// Rule configuration, either a plain list of rules or a map with global settings
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Config {
+    /// Create missing target directories instead of ignoring the rule
+    #[serde(default)]
+    pub create_target: bool,
+
+    /// Never delete or replace files: rules that delete files are ignored and files whose name
+    /// is taken in the target get a number instead
+    #[serde(default)]
+    pub safe: bool,
+
+    /// Checksum that copies are verified with, including moves to another filesystem
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verify: Option<Checksum>,
+
+    /// Record the original path of moved and copied files in the extended attribute
+    /// `user.wurmloch.origin`
+    #[serde(default)]
+    pub record_origin: bool,
+
+    /// Record where sorted files came from in a sidecar file next to them or in a manifest in
+    /// their target directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+
+    /// What happens to files with the same content as a file that has been placed before
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_duplicate: Option<OnDuplicate>,
+
+    /// Bytes that have to stay free on the filesystem of a target after a file has been
+    /// written to it, e.g. `10 GB`. Not checked by default.
+    #[serde(
+        default,
+        with = "crate::units::optional_size",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub min_free_space: Option<u64>,
+
+    /// What happens to files whose target has less than `min_free_space` left: `skip`
+    /// (default), `alert` or another target directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_full: Option<OnFull>,
+
+    /// Staging area for deleted files
+    #[serde(default)]
+    pub trash: TrashSettings,
+
+    /// Endpoint and upload settings of `s3://` targets
+    #[serde(default)]
+    pub s3: S3Settings,
+
+    /// Conflict handling and chunk size of `davs://` and `dav://` targets
+    #[serde(default)]
+    pub webdav: WebDavSettings,
+
+    /// How `rclone` actions start rclone
+    #[serde(default)]
+    pub rclone: RcloneSettings,
+
+    /// How often failed actions are retried
+    #[serde(default)]
+    pub retry: RetrySettings,
+
+    /// What happens to files after all attempts to sort them failed, by the class of the
+    /// error: `continue` (default), `halt` or `divert: <directory>`
+    #[serde(default)]
+    pub on_error: OnError,
+
+    /// How the user is told about sorted files and failures
+    #[serde(default)]
+    pub notifications: NotificationSettings,
+
+    /// HTTP API to manage the running instance remotely. Disabled without this section.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api: Option<ApiSettings>,
+
+    /// Machine-readable log of all actions. Disabled without this section.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history: Option<HistorySettings>,
+
+    /// Built-in handling of temporary files that should be turned off, or `all`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disabled_quirks: Vec<String>,
+
+    /// Subdirectory levels below the watch directory whose files are sorted. `0` only sorts the
+    /// top level. Unlimited by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<usize>,
+
+    /// How long repeated events for a file that has just been sorted are ignored. Defaults to
+    /// 10 seconds.
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub cooldown: Option<Duration>,
+
+    /// Files sorted per minute to each disk or server. Unlimited by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_files_per_minute: Option<u32>,
+
+    /// Bytes written per second to each disk or server, e.g. `5 MB`. Unlimited by default.
+    #[serde(
+        default,
+        with = "crate::units::optional_size",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_bytes_per_second: Option<u64>,
+
+    /// Files that match no rule are moved here instead of staying in the wormhole
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback_target: Option<PathBuf>,
+
+    /// Record next to every file moved to the fallback target where it came from
+    #[serde(default)]
+    pub quarantine: bool,
+
+    /// Files that a safety check refused, e.g. because their copy did not match, are moved here
+    /// with a record of the reason. `quarantine` in the data directory by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quarantine_dir: Option<PathBuf>,
+
+    /// Days and hours during which files are sorted, e.g. `["Mon-Fri 18:00-08:00", "Sat,Sun"]`.
+    /// Files that arrive at other times wait. Always by default.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub active_during: Vec<TimeWindow>,
+
+    /// When the whole wormhole is looked through for files that were missed, e.g. `1h` or
+    /// `*/30 * * * *`. Never by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rescan: Option<Schedule>,
+
+    /// Removable media whose files are sorted whenever they are mounted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub volumes: Vec<VolumeSettings>,
+
+    /// Values that can be referenced as `{vars.<name>}` in patterns and targets
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
+
+    /// Credentials that settings can reference as `{secrets.<name>}` instead of containing them
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub secrets: BTreeMap<String, Secret>,
+
+    /// File with `NAME=value` lines that `dotenv` secrets are read from, relative to the rule
+    /// file. Defaults to `.env` next to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dotenv: Option<PathBuf>,
+
+    /// Further rule files, relative to the including file. File names may contain wildcards.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+
+    /// Built-in rule sets that are added after the own rules, e.g. `[downloads]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include_preset: Vec<String>,
+
+    #[serde(default)]
+    pub rules: Vec<ConfigRule>,
+
+    /// Rules that share settings. They come after the rules of the same file.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<RuleGroup>,
+
+    /// All files the configuration was loaded from
+    #[serde(skip)]
+    pub files: Vec<PathBuf>,
+
+    /// Directories that are searched for included files
+    #[serde(skip)]
+    pub include_dirs: Vec<PathBuf>,
+}
+
+/// What a rule matches
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleType {
+    #[default]
+    File,
+    Dir,
+}
+
+/// Where a rule puts files: one directory, or several that are tried in order until one of them
+/// is available
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "OneOrMore", into = "OneOrMore")]
+pub struct Targets(Vec<PathBuf>);
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum OneOrMore {
+    One(PathBuf),
+    More(Vec<PathBuf>),
+}
+
+impl Targets {
+    /// The preferred target, empty if there is none
+    pub fn first(&self) -> &Path {
+        self.0.first().map_or(Path::new(""), PathBuf::as_path)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Used in order if the first target is unavailable
+    pub fn failover(&self) -> &[PathBuf] {
+        self.0.get(1..).unwrap_or_default()
+    }
+}
+
+impl From<PathBuf> for Targets {
+    fn from(target: PathBuf) -> Targets {
+        Targets(vec![target])
+    }
+}
+
+impl From<OneOrMore> for Targets {
+    fn from(targets: OneOrMore) -> Targets {
+        match targets {
+            OneOrMore::One(target) => Targets(vec![target]),
+            OneOrMore::More(targets) => Targets(targets),
+        }
+    }
+}
+
+impl From<Targets> for OneOrMore {
+    fn from(mut targets: Targets) -> OneOrMore {
+        match targets.0.len() {
+            1 => OneOrMore::One(targets.0.remove(0)),
+            _ => OneOrMore::More(targets.0),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConfigRule {
+    pub pattern: String,
+
+    /// Shown instead of the pattern in logs, metrics and the journal
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    /// `false` switches the rule off without removing it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+
+    /// Defaults to `file`. `dir` rules move or delete whole directories.
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<RuleType>,
+
+    /// `move` (default), `copy`, `delete`, `run`, `extract`, `compress`, `rclone` or an action
+    /// registered by a plugin
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
+
+    /// Program and arguments of `run` actions, e.g. `ocrmypdf {path} {path}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+
+    /// How long the program of a `run` action may take before it is killed, e.g. `10m`.
+    /// Defaults to one hour.
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub timeout: Option<Duration>,
+
+    /// A directory or a list of directories that are tried in order
+    #[serde(default, skip_serializing_if = "Targets::is_empty")]
+    pub target: Targets,
+
+    /// New name of moved or copied files, e.g. `{audio.track:02} - {audio.title}.{ext}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rename: Option<String>,
+
+    /// What happens if the name of a moved or copied file is taken in the target: `quarantine`
+    /// (default), `replace`, `rename` to `name (1).ext` or `skip`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_conflict: Option<OnConflict>,
+
+    /// Start, padding and reset of the `{counter}` in `rename`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counter: Option<CounterSettings>,
+
+    /// Clean-ups of the names of moved and copied files, applied in order after `rename`:
+    /// `lowercase`, `underscores`, `strip_diacritics` and `nfc`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub normalize: Vec<Normalization>,
+
+    /// `extract` actions unpack into a new directory named after the archive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subfolder: Option<bool>,
+
+    /// `extract` actions delete the archive after it has been unpacked, `compress` actions
+    /// delete the file after it has been packed, `rclone` actions move instead of copy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delete_original: Option<bool>,
+
+    /// `zip` (default) or `tar.zst` for `compress` actions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+
+    /// Name of archives created by `compress` actions without the extension, e.g.
+    /// `{stem}-{year}{month}`. Defaults to the name of the file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive_name: Option<String>,
+
+    /// Only videos with these properties match
+    #[serde(flatten)]
+    pub video: VideoConditions,
+
+    /// Regular expression that the text of a PDF or Office document has to contain, e.g.
+    /// `Invoice\s+#\d+`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_matches: Option<String>,
+
+    /// Regular expression that the beginning of a plain-text file has to contain, e.g.
+    /// `^date,amount` for the header row of a CSV export
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contents_match: Option<String>,
+
+    /// How much of a file `contents_match` searches, 64 KiB by default
+    #[serde(
+        default,
+        with = "crate::units::optional_size",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub contents_max_bytes: Option<u64>,
+
+    /// Mode of moved and copied files, e.g. `0644`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chmod: Option<String>,
+
+    /// Owner of moved and copied files, e.g. `user:group`. Requires root.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chown: Option<String>,
+
+    /// Finder tags on macOS, `user.xdg.tags` elsewhere, e.g. `[receipts, "{year}"]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+
+    /// Extended attributes of moved and copied files by name, e.g. `user.project: apollo`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub xattrs: BTreeMap<String, String>,
+
+    /// Subdirectories that spread moved and copied files over the target: `first_letter`,
+    /// `hash` or `hash/<levels>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shard: Option<Shard>,
+
+    /// Moved and copied files go to numbered subdirectories like `0001` with at most this many
+    /// files each
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_files_per_dir: Option<usize>,
+
+    /// Overrides the global `record_origin` setting for this rule
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub record_origin: Option<bool>,
+
+    /// Overrides the global `provenance` setting for this rule
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+
+    /// Overrides the global `min_free_space` setting for this rule
+    #[serde(
+        default,
+        with = "crate::units::optional_size",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub min_free_space: Option<u64>,
+
+    /// Overrides the global `on_full` setting for this rule
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_full: Option<OnFull>,
+
+    /// Overrides the global `on_duplicate` setting for this rule
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_duplicate: Option<OnDuplicate>,
+
+    /// Overrides the global `verify` setting for this rule
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verify: Option<Checksum>,
+
+    /// Overrides the global `create_target` setting for this rule
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub create_target: Option<bool>,
+
+    /// Maximum number of files the rule accepts per batch, i.e. until the watch directory
+    /// has been quiet for the watch delay
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_per_batch: Option<usize>,
+
+    /// Target for files beyond `max_per_batch`. Without it, these files are deferred to the
+    /// next batch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overflow_target: Option<PathBuf>,
+
+    /// How long a matching file has to stay unchanged before it is sorted, e.g. `60s` for
+    /// long downloads. Defaults to the watch delay.
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub settle: Option<Duration>,
+
+    /// Only files that were last modified longer ago match, e.g. `30d`. Rules that delete files
+    /// by age need to be confirmed with `wurmloch confirm-cleanup` first.
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub older_than: Option<Duration>,
+
+    /// Days and hours during which the rule sorts files, e.g. `["22:00-06:00"]`. Matching
+    /// files that arrive at other times wait. Always by default.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub active_during: Vec<TimeWindow>,
+
+    /// Only log what the rule would do, e.g. to try out a new rule while the others keep
+    /// sorting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dry_run: Option<bool>,
+
+    /// Overrides the global `notifications.desktop` setting for this rule
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notify: Option<bool>,
+
+    /// Host names of the machines the rule applies to. Empty means all machines.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<String>,
+
+    /// Operating systems the rule applies to (`linux`, `macos`, `windows`, ...). Empty means
+    /// all systems.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub os: Vec<String>,
+}
+
+/// Rules with shared defaults, e.g. all rules that sort into the media share of a NAS
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RuleGroup {
+    /// Shown in the log when the group is switched off
+    pub name: String,
+
+    /// `false` switches off all rules of the group
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+
+    /// Directory that relative targets of the rules are inside of. Rules that move or copy
+    /// files and have no target use it directly.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root: Option<PathBuf>,
+
+    /// Default `on_duplicate` of the rules
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_duplicate: Option<OnDuplicate>,
+
+    /// Default `settle` of the rules
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub settle: Option<Duration>,
+
+    /// Default `create_target` of the rules
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub create_target: Option<bool>,
+
+    /// Default `verify` of the rules
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verify: Option<Checksum>,
+
+    pub rules: Vec<ConfigRule>,
+}
+
+impl RuleGroup {
+    /// The rules of the group with its settings applied where they do not set their own
+    fn into_rules(self) -> Vec<ConfigRule> {
+        if self.enabled == Some(false) {
+            info!("Group {} is disabled. Skipped.", self.name);
+            return Vec::new();
+        }
+        let root = self.root;
+        let mut rules = self.rules;
+        for rule in rules.iter_mut() {
+            if let Some(root) = &root {
+                let moves = matches!(rule.action.as_deref(), None | Some("move") | Some("copy"));
+                if rule.target.is_empty() && moves {
+                    rule.target = root.clone().into();
+                } else {
+                    for target in rule.target.0.iter_mut() {
+                        // URLs of remote targets stay as they are
+                        if !target.to_string_lossy().contains("://") {
+                            *target = root.join(&target);
+                        }
+                    }
+                }
+                if let Some(overflow) = rule.overflow_target.as_mut() {
+                    *overflow = root.join(&overflow);
+                }
+            }
+            rule.on_duplicate = rule.on_duplicate.or(self.on_duplicate);
+            rule.settle = rule.settle.or(self.settle);
+            rule.create_target = rule.create_target.or(self.create_target);
+            rule.verify = rule.verify.or(self.verify);
+        }
+        rules
+    }
+}
+
+impl ConfigRule {
+    /// The name of the rule or its pattern if it has none
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.pattern)
+    }
+
+    fn examples() -> [ConfigRule; 3] {
+        [
+            ConfigRule {
+                pattern: String::from("*.jpg"),
+                target: dirs::picture_dir().unwrap_or_default().into(),
+                ..Default::default()
+            },
+            ConfigRule {
+                pattern: String::from("*.pdf"),
+                target: dirs::document_dir().unwrap_or_default().into(),
+                ..Default::default()
+            },
+            ConfigRule {
+                pattern: String::from("*.mp3"),
+                target: dirs::audio_dir().unwrap_or_default().into(),
+                ..Default::default()
+            },
+        ]
+    }
+}
+
+impl Config {
+    /// Whether a change of this file affects the configuration
+    pub fn is_source(&self, path: &Path) -> bool {
+        self.files.iter().any(|f| f == path)
+            || (path
+                .parent()
+                .is_some_and(|p| self.include_dirs.iter().any(|d| d == p))
+                && matches!(
+                    path.extension().and_then(|e| e.to_str()),
+                    Some("yaml") | Some("yml")
+                ))
+    }
+
+    /// Directories that need to be (recursively) watched to notice configuration changes
+    pub fn watch_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = self
+            .files
+            .iter()
+            .filter_map(|f| f.parent().map(Path::to_path_buf))
+            .chain(self.include_dirs.iter().cloned())
+            .collect();
+        dirs.sort();
+
+        let mut watch_dirs: Vec<PathBuf> = Vec::new();
+        for dir in dirs {
+            if !watch_dirs.iter().any(|d| dir.starts_with(d)) {
+                watch_dirs.push(dir);
+            }
+        }
+        watch_dirs
+    }
+}
+
+/// Logs which rules were added, removed or changed by a reload. Rules are identified by their
+/// name or pattern; rules sharing one are told apart by their order.
+pub fn log_changes(old: &Config, new: &Config) {
+    fn keyed(config: &Config) -> Vec<((&str, usize), &ConfigRule)> {
+        let mut seen: BTreeMap<&str, usize> = BTreeMap::new();
+        config
+            .rules
+            .iter()
+            .map(|rule| {
+                let count = seen.entry(rule.name()).or_default();
+                *count += 1;
+                ((rule.name(), *count), rule)
+            })
+            .collect()
+    }
+    let old_rules = keyed(old);
+    let new_rules = keyed(new);
+
+    let mut changes = 0;
+    for (key, rule) in new_rules.iter() {
+        match old_rules.iter().find(|(k, _)| k == key) {
+            None => info!("Rule {} added.", key.0),
+            Some((_, old_rule)) if old_rule != rule => info!("Rule {} changed.", key.0),
+            Some(_) => continue,
+        }
+        changes += 1;
+    }
+    for (key, _) in old_rules.iter() {
+        if !new_rules.iter().any(|(k, _)| k == key) {
+            info!("Rule {} removed.", key.0);
+            changes += 1;
+        }
+    }
+    if changes == 0 {
+        info!("No rules changed.");
+    }
+}
+
+/// Path of the main rule file, which may not exist yet
+pub fn rules_path() -> Result<PathBuf> {
+    let config_dir = dirs::config_dir().context("Could not determine configuration directory.")?;
+    Ok(config_dir.join(APP_NAME).join(RULES_FILE_NAME))
+}
+
+/// Writes the rules of a preset, or the example rules, to the rule file. An existing rule file
+/// is kept as `rules.yaml.bak` if `force` is set.
+pub fn init(preset: Option<&str>, force: bool) -> Result<PathBuf> {
+    let rule_path = rules_path()?;
+    if rule_path.exists() {
+        if !force {
+            return Err(anyhow!(
+                "{:?} exists already. Use --force to replace it.",
+                rule_path
+            ));
+        }
+        let backup = rule_path.with_extension("yaml.bak");
+        fs::rename(&rule_path, &backup)
+            .context(format!("Could not keep the old rules as {:?}.", backup))?;
+        info!("Kept the old rules as {:?}.", backup);
+    }
+    let app_dir = rule_path.parent().unwrap();
+    fs::create_dir_all(app_dir).context(format!(
+        "Could not create configuration directory {:?}.",
+        app_dir
+    ))?;
+    let rules = match preset {
+        Some(name) => format!(
+            "# Preset {}. Adjust the rules as you like.\n{}",
+            name,
+            serde_yaml::to_string(&presets::rules(name)?)?
+        ),
+        None => serde_yaml::to_string(&ConfigRule::examples())?,
+    };
+    fs::write(&rule_path, rules).context(format!(
+        "Could not create configuration file {:?}.",
+        &rule_path
+    ))?;
+    Ok(rule_path)
+}
+
+/// Services cannot ask for confirmation of freshly created example rules
+pub fn ensure_confirmed() -> Result<()> {
+    if rules_path()?.exists() && audit::rules_confirmed() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "There are no rules yet. Start wurmloch once in a terminal to create and confirm them."
+        ))
+    }
+}
+
+/// Returns the path of the rule file and whether it has just been created. An example rule
+/// file is created if there is none.
+pub fn create_if_missing() -> Result<(PathBuf, bool)> {
+    // ensure that the config directory exists
+    let rule_path = rules_path()?;
+    let app_dir = rule_path.parent().unwrap();
+    fs::create_dir_all(app_dir).context(format!(
+        "Could not create configuration directory {:?}.",
+        app_dir
+    ))?;
+
+    // ensure that a rule file exists
+    let created = !rule_path.exists();
+    if created {
+        // no config file, create an example
+        let mut file = fs::File::create(&rule_path).context(format!(
+            "Could not create configuration file {:?}.",
+            &rule_path
+        ))?;
+        let config = serde_yaml::to_string(&ConfigRule::examples()).unwrap();
+        file.write_all(config.as_bytes()).unwrap();
+        info!("Created example configuration {:?}.", &rule_path);
+    } else {
+        info!("Found existing configuration {:?}.", &rule_path);
+    }
+
+    Ok((rule_path, created))
+}
+
+/// Loads the rule file including all files it references
+pub fn load(path: &Path) -> Result<Config> {
+    let mut config = read(path)?;
+    config.files.push(path.to_path_buf());
+
+    let mut visited = vec![path.canonicalize().unwrap_or_else(|_| path.to_path_buf())];
+    let include = std::mem::take(&mut config.include);
+    resolve_includes(&mut config, path, &include, &mut visited)?;
+    for name in config.include_preset.clone() {
+        info!("Including preset {}.", name);
+        config.rules.append(&mut presets::rules(&name)?);
+    }
+    resolve_secrets(&mut config, path)?;
+    if let Some(api) = &config.api {
+        api.validate()?;
+    }
+    Ok(config)
+}
+
+/// Replaces `{secrets.<name>}` in credentials, webhooks, MQTT and email accounts, the API token
+/// and arguments of rclone
+fn resolve_secrets(config: &mut Config, path: &Path) -> Result<()> {
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    let dotenv = base.join(
+        config
+            .dotenv
+            .as_deref()
+            .unwrap_or_else(|| Path::new(".env")),
+    );
+    let mut secrets = Secrets::new(&config.secrets, dotenv);
+
+    let mut fields: Vec<&mut String> = vec![];
+    fields.extend(config.s3.access_key_id.as_mut());
+    fields.extend(config.s3.secret_access_key.as_mut());
+    fields.extend(config.s3.session_token.as_mut());
+    fields.extend(config.webdav.user.as_mut());
+    fields.extend(config.webdav.password.as_mut());
+    fields.extend(config.rclone.args.iter_mut());
+    if let Some(webhook) = config.notifications.webhook.as_mut() {
+        fields.push(&mut webhook.url);
+        fields.extend(webhook.headers.values_mut());
+    }
+    if let Some(api) = config.api.as_mut() {
+        fields.push(&mut api.token);
+    }
+    if let Some(mqtt) = config.notifications.mqtt.as_mut() {
+        fields.extend(mqtt.user.as_mut());
+        fields.extend(mqtt.password.as_mut());
+    }
+    if let Some(email) = config.notifications.email.as_mut() {
+        fields.extend(email.user.as_mut());
+        fields.extend(email.password.as_mut());
+    }
+    for field in fields {
+        *field = secrets.substitute(field)?;
+    }
+    Ok(())
+}
+
+/// Appends the rules of all included files in the order in which they are listed. Files
+/// matching the same wildcard are included in alphabetical order.
+fn resolve_includes(
+    config: &mut Config,
+    from: &Path,
+    include: &[String],
+    visited: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let base = from.parent().unwrap_or_else(|| Path::new(""));
+    for pattern in include.iter() {
+        let pattern = normalize(&base.join(pattern));
+        let dir = pattern.parent().unwrap_or(base).to_path_buf();
+        let matcher = Glob::new(&pattern.file_name().unwrap_or_default().to_string_lossy())
+            .context(format!("Invalid include {:?}.", &pattern))?
+            .compile_matcher();
+
+        let mut files: Vec<PathBuf> = fs::read_dir(&dir)
+            .context(format!("Could not read include directory {:?}.", &dir))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.is_file() && matcher.is_match(path.file_name().unwrap()))
+            .collect();
+        files.sort();
+        if files.is_empty() {
+            warn!("Include {:?} does not match any file.", &pattern);
+        }
+        config.include_dirs.push(dir);
+
+        for file in files {
+            let canonical = file.canonicalize().unwrap_or_else(|_| file.clone());
+            if visited.contains(&canonical) {
+                warn!("{:?} is included more than once. Skipped.", &file);
+                continue;
+            }
+            visited.push(canonical);
+
+            info!("Including {:?}.", &file);
+            let mut included = read(&file)?;
+            config.rules.append(&mut included.rules);
+            config.include_preset.append(&mut included.include_preset);
+            for (name, value) in included.vars {
+                config.vars.entry(name).or_insert(value);
+            }
+            config.files.push(file.clone());
+            resolve_includes(config, &file, &included.include, visited)?;
+        }
+    }
+    Ok(())
+}
+
+/// Resolves `.` and `..` without touching the filesystem, so that paths stay comparable to
+/// the paths reported by the watcher
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+fn read(path: &Path) -> Result<Config> {
+    let config = fs::read_to_string(path)
+        .context(format!("Could not read configuration file {:#?}.", path))?;
+    let mut config =
+        parse(&config).context(format!("Failed to parse rule configuration {:?}.", path))?;
+    for group in std::mem::take(&mut config.groups) {
+        config.rules.append(&mut group.into_rules());
+    }
+    Ok(config)
+}
+
+fn parse(config: &str) -> Result<Config> {
+    let yaml: serde_yaml::Value = serde_yaml::from_str(config)?;
+
+    // A plain list of rules is the original configuration format
+    if yaml.is_sequence() {
+        Ok(Config {
+            rules: serde_yaml::from_str(config)?,
+            ..Default::default()
+        })
+    } else {
+        Ok(serde_yaml::from_str(config)?)
+    }
+}
+
+/// Replaces all `{vars.<name>}` references with their values
+pub fn substitute_vars(text: &str, vars: &BTreeMap<String, String>) -> Result<String> {
+    substitute(text, "vars", |name| {
+        vars.get(name)
+            .cloned()
+            .context(format!("Unknown variable {{vars.{}}}.", name))
+    })
+}
+
+/// Replaces all `{<kind>.<name>}` references with the values `lookup` returns for the names
+pub(crate) fn substitute(
+    text: &str,
+    kind: &str,
+    mut lookup: impl FnMut(&str) -> Result<String>,
+) -> Result<String> {
+    let prefix = format!("{{{}.", kind);
+
+    let mut substituted = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(&prefix) {
+        substituted.push_str(&rest[..start]);
+        rest = &rest[start + prefix.len()..];
+        let end = rest
+            .find('}')
+            .context(format!("Unclosed {} reference in {:?}.", kind, text))?;
+        substituted.push_str(&lookup(&rest[..end])?);
+        rest = &rest[end + 1..];
+    }
+    substituted.push_str(rest);
+    Ok(substituted)
+}
@@ -0,0 +1,69 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use regex::Regex;
+
+/// How much of a file is searched if the rule does not say otherwise
+pub const DEFAULT_MAX_BYTES: u64 = 64 * 1024;
+
+/// Matches the beginning of plain-text files against a regular expression
+#[derive(Debug)]
+pub struct ContentsCondition {
+    pub regex: Regex,
+    pub max_bytes: u64,
+}
+
+impl ContentsCondition {
+    /// Binary files and directories never meet the condition
+    pub fn is_met(&self, path: &Path) -> bool {
+        match read_text(path, self.max_bytes) {
+            Ok(Some(text)) => self.regex.is_match(&text),
+            Ok(None) => {
+                debug!("{:?} is not a text file.", path);
+                false
+            }
+            Err(e) => {
+                debug!("{:#}", e);
+                false
+            }
+        }
+    }
+}
+
+/// The first `max_bytes` of a file as text. UTF-8 and UTF-16 with a byte order mark are decoded
+/// as such, anything else that is not valid UTF-8 as Latin-1. Files with null bytes are no text.
+fn read_text(path: &Path, max_bytes: u64) -> Result<Option<String>> {
+    let mut bytes = Vec::new();
+    File::open(path)
+        .and_then(|file| file.take(max_bytes).read_to_end(&mut bytes))
+        .context(format!("Could not read {:?}.", path))?;
+
+    if let Some(utf8) = bytes.strip_prefix(&[0xef, 0xbb, 0xbf]) {
+        return Ok(Some(String::from_utf8_lossy(utf8).into_owned()));
+    }
+    let utf16 = match bytes.get(..2) {
+        Some([0xff, 0xfe]) => Some(u16::from_le_bytes as fn([u8; 2]) -> u16),
+        Some([0xfe, 0xff]) => Some(u16::from_be_bytes as fn([u8; 2]) -> u16),
+        _ => None,
+    };
+    if let Some(decode) = utf16 {
+        let units: Vec<u16> = bytes[2..]
+            .chunks_exact(2)
+            .map(|pair| decode([pair[0], pair[1]]))
+            .collect();
+        return Ok(Some(String::from_utf16_lossy(&units)));
+    }
+    if bytes.contains(&0) {
+        return Ok(None);
+    }
+    match std::str::from_utf8(&bytes) {
+        Ok(text) => Ok(Some(text.to_string())),
+        // Reading stopped in the middle of a character
+        Err(e) if e.error_len().is_none() => Ok(Some(
+            String::from_utf8_lossy(&bytes[..e.valid_up_to()]).into_owned(),
+        )),
+        Err(_) => Ok(Some(bytes.iter().map(|&b| b as char).collect())),
+    }
+}
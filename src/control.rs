@@ -0,0 +1,482 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::lock;
+use crate::metrics::Histogram;
+use crate::Applied;
+
+/// Tells clients where the running instance listens. Followed by the key of the watch
+/// directory, so that every instance has its own.
+const CONTROL_FILE_PREFIX: &str = "control-";
+
+/// Number of recent actions and failures that are kept for `wurmloch status` and the TUI
+const RECENT_ACTIONS: usize = 100;
+
+/// State of the running instance that can be queried and changed from the outside
+#[derive(Default)]
+pub struct State {
+    pub paused: AtomicBool,
+    stopping: AtomicBool,
+    reload: AtomicBool,
+    rescan: AtomicBool,
+    status: Mutex<Status>,
+}
+
+/// Statistics of the running instance
+pub struct Status {
+    pub started: DateTime<Local>,
+    pub watched: Vec<PathBuf>,
+    pub rules: Vec<RuleInfo>,
+    pub moved: usize,
+    pub deleted: usize,
+    pub errors: usize,
+    pub given_up: usize,
+    /// Files that a safety check refused and that were moved to the quarantine directory
+    pub quarantined: usize,
+    pub bytes_moved: u64,
+    /// Files that wait for the next batch, another attempt or the end of a pause
+    pub queued: usize,
+    /// Files that wait for a worker
+    pub work_queue: usize,
+    /// Files that are being sorted
+    pub in_progress: usize,
+    /// Events for files that were already waiting for a worker
+    pub coalesced: u64,
+    /// Files whose events were dropped because the work queue was full
+    pub turned_away: u64,
+    pub per_rule: BTreeMap<String, usize>,
+    pub latency: Histogram,
+    pub recent: VecDeque<RecentAction>,
+    pub failures: VecDeque<RecentFailure>,
+}
+
+/// An active rule as it is listed by the API
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleInfo {
+    pub name: String,
+    pub pattern: String,
+    pub action: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecentAction {
+    pub time: DateTime<Local>,
+    pub rule: String,
+    pub file: PathBuf,
+    /// Where the file was moved, or what happened to it otherwise
+    pub outcome: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecentFailure {
+    pub time: DateTime<Local>,
+    pub rule: String,
+    pub file: PathBuf,
+    pub reason: String,
+}
+
+/// Everything `wurmloch tui` shows, as sent over the control port
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub started: DateTime<Local>,
+    pub paused: bool,
+    pub watched: Vec<PathBuf>,
+    pub moved: usize,
+    pub deleted: usize,
+    pub errors: usize,
+    pub given_up: usize,
+    #[serde(default)]
+    pub quarantined: usize,
+    pub queued: usize,
+    pub work_queue: usize,
+    pub in_progress: usize,
+    pub per_rule: BTreeMap<String, usize>,
+    pub recent: Vec<RecentAction>,
+    pub failures: Vec<RecentFailure>,
+}
+
+impl fmt::Display for RecentAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}  {}  {}  ->  {}",
+            self.time.format("%Y-%m-%d %H:%M:%S"),
+            &self.rule,
+            self.file.display(),
+            &self.outcome
+        )
+    }
+}
+
+impl Default for Status {
+    fn default() -> Self {
+        Status {
+            started: Local::now(),
+            watched: Vec::new(),
+            rules: Vec::new(),
+            moved: 0,
+            deleted: 0,
+            errors: 0,
+            given_up: 0,
+            quarantined: 0,
+            bytes_moved: 0,
+            queued: 0,
+            work_queue: 0,
+            in_progress: 0,
+            coalesced: 0,
+            turned_away: 0,
+            per_rule: BTreeMap::new(),
+            latency: Histogram::default(),
+            recent: VecDeque::new(),
+            failures: VecDeque::new(),
+        }
+    }
+}
+
+impl Status {
+    /// Counts an action that has been applied successfully
+    pub fn processed(&mut self, rule: &str, path: &Path, applied: &Applied) {
+        let destination = applied.moved_to.as_deref();
+        match destination {
+            Some(_) => {
+                self.moved += 1;
+                self.bytes_moved += applied.bytes;
+            }
+            None if applied.action == "delete" => self.deleted += 1,
+            None => {}
+        }
+        *self.per_rule.entry(rule.to_string()).or_default() += 1;
+        self.latency.observe(applied.duration);
+
+        if self.recent.len() == RECENT_ACTIONS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(RecentAction {
+            time: Local::now(),
+            rule: rule.to_string(),
+            file: path.to_path_buf(),
+            outcome: match destination {
+                Some(destination) => destination.display().to_string(),
+                None if applied.action == "delete" => String::from("deleted"),
+                None => applied.action.clone(),
+            },
+        });
+    }
+
+    /// Counts an action that failed
+    pub fn failed(&mut self, rule: &str, path: &Path, reason: &str) {
+        self.errors += 1;
+        if self.failures.len() == RECENT_ACTIONS {
+            self.failures.pop_front();
+        }
+        self.failures.push_back(RecentFailure {
+            time: Local::now(),
+            rule: rule.to_string(),
+            file: path.to_path_buf(),
+            reason: reason.to_string(),
+        });
+    }
+
+    fn snapshot(&self, paused: bool) -> Snapshot {
+        Snapshot {
+            started: self.started,
+            paused,
+            watched: self.watched.clone(),
+            moved: self.moved,
+            deleted: self.deleted,
+            errors: self.errors,
+            given_up: self.given_up,
+            quarantined: self.quarantined,
+            queued: self.queued,
+            work_queue: self.work_queue,
+            in_progress: self.in_progress,
+            per_rule: self.per_rule.clone(),
+            recent: self.recent.iter().cloned().collect(),
+            failures: self.failures.iter().cloned().collect(),
+        }
+    }
+
+    fn report(&self, paused: bool, last: usize) -> String {
+        let uptime = (Local::now() - self.started).to_std().unwrap_or_default();
+        let mut report = format!(
+            "Running since {} ({}){}\n",
+            self.started.format("%Y-%m-%d %H:%M:%S"),
+            humantime::format_duration(Duration::from_secs(uptime.as_secs())),
+            if paused { ", paused" } else { "" }
+        );
+        for dir in self.watched.iter() {
+            report.push_str(&format!("Watching {}\n", dir.display()));
+        }
+        report.push_str(&format!(
+            "{} active rules\n{} files moved, {} deleted, {} errors, gave up on {}, quarantined {}\n",
+            self.rules.len(),
+            self.moved,
+            self.deleted,
+            self.errors,
+            self.given_up,
+            self.quarantined
+        ));
+
+        if !self.per_rule.is_empty() {
+            report.push_str("\nFiles per rule:\n");
+            for (rule, count) in self.per_rule.iter() {
+                report.push_str(&format!("  {:>8}  {}\n", count, rule));
+            }
+        }
+        if last > 0 && !self.recent.is_empty() {
+            report.push_str("\nLast actions:\n");
+            let skip = self.recent.len().saturating_sub(last);
+            for action in self.recent.iter().skip(skip) {
+                report.push_str(&format!("  {}\n", action));
+            }
+        }
+        report
+    }
+}
+
+impl State {
+    pub fn status(&self) -> MutexGuard<'_, Status> {
+        self.status.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn pause(&self) -> &'static str {
+        if self.paused.swap(true, Ordering::SeqCst) {
+            "Already paused."
+        } else {
+            info!("Paused. New files stay in the wormhole until sorting is resumed.");
+            "Paused."
+        }
+    }
+
+    pub fn resume(&self) -> &'static str {
+        if self.paused.swap(false, Ordering::SeqCst) {
+            info!("Resumed.");
+            "Resumed."
+        } else {
+            "Not paused."
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Stops watching after the current file
+    pub fn stop(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    /// Reads the configuration again as if it had been changed
+    pub fn reload(&self) -> &'static str {
+        self.reload.store(true, Ordering::SeqCst);
+        "Reloading."
+    }
+
+    /// Whether a reload was asked for since the last call
+    pub fn take_reload(&self) -> bool {
+        self.reload.swap(false, Ordering::SeqCst)
+    }
+
+    /// Looks through the wormhole for files that were missed
+    pub fn rescan(&self) -> &'static str {
+        self.rescan.store(true, Ordering::SeqCst);
+        "Rescanning."
+    }
+
+    /// Whether a rescan was asked for since the last call
+    pub fn take_rescan(&self) -> bool {
+        self.rescan.swap(false, Ordering::SeqCst)
+    }
+
+    pub fn report(&self, last: usize) -> String {
+        self.status().report(self.is_paused(), last)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Endpoint {
+    port: u16,
+    token: String,
+    #[serde(default)]
+    watch_dir: PathBuf,
+}
+
+/// Accepts commands from `wurmloch pause` and friends on a local port. Only clients that
+/// can read the control file know the token that is required to talk to it.
+pub struct Server {
+    control_file: PathBuf,
+}
+
+impl Server {
+    pub fn start(data_dir: &Path, watch_dir: &Path, state: Arc<State>) -> Result<Server> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .context("Could not open the control port.")?;
+        let endpoint = Endpoint {
+            port: listener.local_addr()?.port(),
+            token: random_token()?,
+            watch_dir: watch_dir
+                .canonicalize()
+                .unwrap_or_else(|_| watch_dir.to_path_buf()),
+        };
+
+        let control_file = control_file(data_dir, &lock::key(watch_dir)?);
+        let content = serde_yaml::to_string(&endpoint)?;
+        // Created anew, so that nobody else can read the token at any time
+        fs::remove_file(&control_file).ok();
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options
+            .open(&control_file)
+            .and_then(|mut file| file.write_all(content.as_bytes()))
+            .context(format!("Could not write {:?}.", &control_file))?;
+        debug!("Listening for control commands on port {}.", endpoint.port);
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(e) = serve(stream, &endpoint.token, &state) {
+                    warn!("Control connection failed: {:#}", e);
+                }
+            }
+        });
+        Ok(Server { control_file })
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        fs::remove_file(&self.control_file).ok();
+    }
+}
+
+fn serve(mut stream: TcpStream, token: &str, state: &State) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+
+    let mut words = line.split_whitespace();
+    if words.next() != Some(token) {
+        writeln!(stream, "Invalid token.")?;
+        return Ok(());
+    }
+    let reply = match (words.next(), words.next()) {
+        (Some("pause"), _) => state.pause().to_string(),
+        (Some("resume"), _) => state.resume().to_string(),
+        (Some("reload"), _) => state.reload().to_string(),
+        (Some("rescan"), _) => state.rescan().to_string(),
+        (Some("snapshot"), _) => {
+            serde_json::to_string(&state.status().snapshot(state.is_paused()))?
+        }
+        (Some("status"), last) => state.report(last.and_then(|l| l.parse().ok()).unwrap_or(10)),
+        _ => String::from("Unknown command."),
+    };
+    write!(stream, "{}", reply.trim_end())?;
+    writeln!(stream)?;
+    Ok(())
+}
+
+/// Sends a command to the running instance that watches `instance`, or to the only one that
+/// is running, and returns its reply
+pub fn send(data_dir: &Path, instance: Option<&Path>, command: &str) -> Result<String> {
+    let endpoint = find(data_dir, instance)?;
+    let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, endpoint.port))
+        .context("Could not connect to the running wurmloch.")?;
+    writeln!(stream, "{} {}", endpoint.token, command)?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    Ok(reply)
+}
+
+/// The directory that the instance `send` talks to watches, to keep talking to the same one
+pub fn instance(data_dir: &Path, instance: Option<&Path>) -> Result<PathBuf> {
+    Ok(find(data_dir, instance)?.watch_dir)
+}
+
+fn find(data_dir: &Path, instance: Option<&Path>) -> Result<Endpoint> {
+    if let Some(watch_dir) = instance {
+        let path = control_file(data_dir, &lock::key(watch_dir)?);
+        let content = fs::read_to_string(&path)
+            .context(format!("wurmloch does not seem to watch {:?}.", watch_dir))?;
+        return serde_yaml::from_str(&content).context(format!("Could not read {:?}.", &path));
+    }
+
+    let mut endpoints = Vec::new();
+    for entry in fs::read_dir(data_dir).context(format!("Could not read {:?}.", data_dir))? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.starts_with(CONTROL_FILE_PREFIX) && name.ends_with(".yaml") {
+            let content = fs::read_to_string(&path)?;
+            endpoints.push(
+                serde_yaml::from_str::<Endpoint>(&content)
+                    .context(format!("Could not read {:?}.", &path))?,
+            );
+        }
+    }
+    match endpoints.len() {
+        0 => Err(anyhow!("wurmloch does not seem to be running.")),
+        1 => Ok(endpoints.remove(0)),
+        _ => {
+            let dirs: Vec<String> = endpoints
+                .iter()
+                .map(|endpoint| format!("{:?}", endpoint.watch_dir))
+                .collect();
+            Err(anyhow!(
+                "Several instances of wurmloch are running, for {}. Choose one with --instance.",
+                dirs.join(", ")
+            ))
+        }
+    }
+}
+
+fn control_file(data_dir: &Path, key: &str) -> PathBuf {
+    data_dir.join(format!("{}{}.yaml", CONTROL_FILE_PREFIX, key))
+}
+
+/// 128 random bits from the operating system
+fn random_token() -> Result<String> {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).context("Could not create a control token.")?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// `SIGUSR1` pauses sorting, `SIGUSR2` resumes it
+#[cfg(unix)]
+pub fn handle_signals(state: Arc<State>) -> Result<()> {
+    use signal_hook::consts::{SIGUSR1, SIGUSR2};
+    use signal_hook::iterator::Signals;
+
+    let mut signals =
+        Signals::new([SIGUSR1, SIGUSR2]).context("Could not install the signal handler.")?;
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            match signal {
+                SIGUSR1 => state.pause(),
+                _ => state.resume(),
+            };
+        }
+    });
+    Ok(())
+}
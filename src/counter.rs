@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use anyhow::Result;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+use crate::index::Index;
+
+/// Numbering of the `{counter}` placeholder of a rule
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CounterSettings {
+    /// First number, and the number after a reset. Defaults to 1.
+    #[serde(default = "default_start")]
+    pub start: u64,
+    /// Pads the number with zeros to this many digits unless the placeholder says otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub padding: Option<usize>,
+    /// When the numbering starts over
+    #[serde(default)]
+    pub reset: Reset,
+}
+
+impl Default for CounterSettings {
+    fn default() -> CounterSettings {
+        CounterSettings {
+            start: default_start(),
+            padding: None,
+            reset: Reset::default(),
+        }
+    }
+}
+
+fn default_start() -> u64 {
+    1
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Reset {
+    #[default]
+    Never,
+    Daily,
+    Monthly,
+    Yearly,
+}
+
+impl Reset {
+    /// The period that numbers are counted in
+    fn period(self) -> String {
+        let format = match self {
+            Reset::Never => return String::new(),
+            Reset::Daily => "%Y-%m-%d",
+            Reset::Monthly => "%Y-%m",
+            Reset::Yearly => "%Y",
+        };
+        Local::now().format(format).to_string()
+    }
+}
+
+/// Sequence numbers of a rule that survive restarts. A file gets its number as soon as its new
+/// name is expanded, so that describing and applying the action agree, but the number only
+/// counts as used once the file has been placed.
+#[derive(Debug)]
+pub struct Counter {
+    rule: String,
+    settings: CounterSettings,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    period: String,
+    /// Next number that has not been used
+    next: u64,
+    /// Numbers of files that are being placed
+    reserved: HashMap<PathBuf, u64>,
+}
+
+impl Counter {
+    pub fn new(rule: &str, settings: CounterSettings) -> Counter {
+        Counter {
+            rule: String::from(rule),
+            state: Mutex::new(State {
+                period: settings.reset.period(),
+                next: settings.start,
+                reserved: HashMap::new(),
+            }),
+            settings,
+        }
+    }
+
+    /// Continues with the numbers stored in the index
+    pub fn load(&self, index: &Index) -> Result<()> {
+        let mut state = self.state();
+        if let Some((period, next)) = index.counter(&self.rule)? {
+            if period == state.period {
+                state.next = next;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn padding(&self) -> Option<usize> {
+        self.settings.padding
+    }
+
+    /// The number of the file
+    pub fn reserve(&self, path: &Path) -> u64 {
+        let mut state = self.state();
+        self.roll_over(&mut state);
+        if let Some(number) = state.reserved.get(path) {
+            return *number;
+        }
+        let number = state
+            .reserved
+            .values()
+            .map(|number| number + 1)
+            .fold(state.next, u64::max);
+        state.reserved.insert(path.to_path_buf(), number);
+        number
+    }
+
+    /// Marks the number of the placed file as used and stores it
+    pub fn commit(&self, path: &Path, index: &Index) -> Result<()> {
+        let mut state = self.state();
+        if let Some(number) = state.reserved.remove(path) {
+            state.next = state.next.max(number + 1);
+            index.set_counter(&self.rule, &state.period, state.next)?;
+        }
+        Ok(())
+    }
+
+    /// Gives the number of a file that was not placed back
+    pub fn release(&self, path: &Path) {
+        self.state().reserved.remove(path);
+    }
+
+    fn roll_over(&self, state: &mut State) {
+        let period = self.settings.reset.period();
+        if period != state.period {
+            debug!(
+                "Counter of rule {} starts over for {}.",
+                &self.rule, &period
+            );
+            state.period = period;
+            state.next = self.settings.start;
+            state.reserved.clear();
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,124 @@
+use std::env;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use daemonize::Daemonize;
+
+use crate::{check_watch_directory, config, data_dir, lock};
+
+/// Followed by the key of the watch directory, so that every instance has its own
+const PID_FILE_PREFIX: &str = "wurmloch-";
+const LOG_FILE_NAME: &str = "wurmloch.log";
+
+/// Removes the pidfile when the daemon stops
+pub struct PidFile(PathBuf);
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        fs::remove_file(&self.0).ok();
+    }
+}
+
+/// Continues in a background process. The calling process exits as soon as the background
+/// process has been started.
+pub fn start(watch_dir: &Path, force: bool) -> Result<PidFile> {
+    // Problems are reported here while there is still a terminal
+    check_watch_directory(watch_dir)?;
+    config::ensure_confirmed()?;
+    let data_dir = data_dir()?;
+    let pid_path = pid_file(&data_dir, &lock::key(watch_dir)?);
+    if let Some(pid) = running(&pid_path) {
+        return Err(anyhow!(
+            "wurmloch is already running for {:?} (PID {}).",
+            watch_dir,
+            pid
+        ));
+    }
+    // Only checked here, the background process takes the lock itself
+    drop(lock::acquire(&data_dir, watch_dir, force)?);
+    let log_path = data_dir.join(LOG_FILE_NAME);
+    let log = File::options()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .context(format!("Could not open log file {:?}.", &log_path))?;
+
+    println!("wurmloch runs in the background now. Stop it with `wurmloch stop`.");
+    println!("Its log is written to {}", log_path.display());
+    Daemonize::new()
+        .pid_file(&pid_path)
+        .working_directory(env::current_dir()?)
+        .stderr(log)
+        .start()
+        .context("Could not start in the background.")?;
+    Ok(PidFile(pid_path))
+}
+
+/// Stops the daemon that was started with `--daemon` for `instance`, or the only one that is
+/// running, and waits until it has finished
+pub fn stop(instance: Option<&Path>) -> Result<()> {
+    let data_dir = data_dir()?;
+    let mut pids = match instance {
+        Some(watch_dir) => running(&pid_file(&data_dir, &lock::key(watch_dir)?))
+            .into_iter()
+            .collect(),
+        None => {
+            let mut pids = Vec::new();
+            for entry in
+                fs::read_dir(&data_dir).context(format!("Could not read {:?}.", data_dir))?
+            {
+                let path = entry?.path();
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                if name.starts_with(PID_FILE_PREFIX) && name.ends_with(".pid") {
+                    pids.extend(running(&path));
+                }
+            }
+            pids
+        }
+    };
+    let pid = match pids.len() {
+        0 => return Err(anyhow!("wurmloch is not running in the background.")),
+        1 => pids.remove(0),
+        _ => {
+            return Err(anyhow!(
+                "Several instances of wurmloch run in the background. Choose one with --instance."
+            ))
+        }
+    };
+
+    // The daemon stops gracefully on SIGTERM
+    if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+        return Err(anyhow!("Could not stop wurmloch (PID {}).", pid));
+    }
+    let started = Instant::now();
+    while unsafe { libc::kill(pid, 0) } == 0 {
+        if started.elapsed() > Duration::from_secs(30) {
+            return Err(anyhow!(
+                "wurmloch (PID {}) did not stop within 30 seconds.",
+                pid
+            ));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    println!("Stopped wurmloch (PID {}).", pid);
+    Ok(())
+}
+
+fn pid_file(data_dir: &Path, key: &str) -> PathBuf {
+    data_dir.join(format!("{}{}.pid", PID_FILE_PREFIX, key))
+}
+
+/// PID of the daemon, if it is still alive
+fn running(pid_path: &Path) -> Option<libc::pid_t> {
+    let pid = fs::read_to_string(pid_path).ok()?.trim().parse().ok()?;
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        Some(pid)
+    } else {
+        // Left behind by a daemon that was killed
+        fs::remove_file(pid_path).ok();
+        None
+    }
+}
@@ -0,0 +1,101 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use zbus::blocking::connection;
+use zbus::blocking::Connection;
+use zbus::interface;
+use zbus::object_server::SignalEmitter;
+
+use crate::control::State;
+use crate::webhook::WebhookEvent;
+
+const NAME: &str = "org.wurmloch.Sorter";
+const PATH: &str = "/org/wurmloch/Sorter";
+
+/// The `org.wurmloch.Sorter` service on the session bus
+pub struct Bus {
+    connection: Connection,
+}
+
+struct Sorter {
+    state: Arc<State>,
+}
+
+#[interface(name = "org.wurmloch.Sorter")]
+impl Sorter {
+    fn pause(&self) -> String {
+        self.state.pause().to_string()
+    }
+
+    fn resume(&self) -> String {
+        self.state.resume().to_string()
+    }
+
+    fn reload(&self) -> String {
+        self.state.reload().to_string()
+    }
+
+    /// Same as `wurmloch status --last <last>`
+    fn status(&self, last: u32) -> String {
+        self.state.report(last as usize)
+    }
+
+    #[zbus(signal)]
+    async fn file_sorted(
+        emitter: &SignalEmitter<'_>,
+        file: &str,
+        rule: &str,
+        destination: &str,
+        outcome: &str,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn action_failed(
+        emitter: &SignalEmitter<'_>,
+        file: &str,
+        rule: &str,
+        reason: &str,
+    ) -> zbus::Result<()>;
+}
+
+impl Bus {
+    pub fn start(state: Arc<State>) -> Result<Bus> {
+        let connection = connection::Builder::session()?
+            .name(NAME)?
+            .serve_at(PATH, Sorter { state })?
+            .build()
+            .context(format!("Could not register {} on the session bus.", NAME))?;
+        Ok(Bus { connection })
+    }
+
+    /// Emits `FileSorted` for sorted files and `ActionFailed` for files wurmloch gave up on
+    pub fn send(&self, event: &WebhookEvent) {
+        let sorter = match self.connection.object_server().interface::<_, Sorter>(PATH) {
+            Ok(sorter) => sorter,
+            Err(e) => {
+                warn!("Could not emit D-Bus signal: {}", e);
+                return;
+            }
+        };
+        let emitter = sorter.signal_emitter();
+        let sent = match event.event {
+            "sorted" => zbus::block_on(Sorter::file_sorted(
+                emitter,
+                &event.file,
+                &event.rule,
+                event.destination.as_deref().unwrap_or_default(),
+                &event.outcome,
+            )),
+            "failed" => zbus::block_on(Sorter::action_failed(
+                emitter,
+                &event.file,
+                &event.rule,
+                &event.outcome,
+            )),
+            _ => Ok(()),
+        };
+        if let Err(e) = sent {
+            warn!("Could not emit D-Bus signal: {}", e);
+        }
+    }
+}
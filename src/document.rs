@@ -0,0 +1,648 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{Context, Result};
+use chrono::{Datelike, NaiveDate};
+use regex::Regex;
+
+use crate::plugin::{self, LastRead, Plugin, Registry};
+
+/// Upper limit for documents and their parts that are read into memory
+const MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Metadata of a PDF or an Office document
+#[derive(Debug, Clone, Default)]
+pub struct DocumentInfo {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub created: Option<NaiveDate>,
+}
+
+/// Placeholders from the metadata of PDF and Office documents
+pub struct DocumentPlugin;
+
+impl Plugin for DocumentPlugin {
+    fn name(&self) -> &str {
+        "doc"
+    }
+
+    fn register(&self, registry: &mut Registry) {
+        registry.variable("doc.title", |path: &Path| {
+            let title = info(path)?.title.unwrap_or_else(|| {
+                path.file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned()
+            });
+            Ok(plugin::sanitize(&title))
+        });
+        registry.variable("doc.author", |path: &Path| {
+            let author = info(path)?.author;
+            Ok(plugin::sanitize(author.as_deref().unwrap_or("Unknown")))
+        });
+        registry.variable("doc.year", |path: &Path| created(path, "%Y"));
+        registry.variable("doc.month", |path: &Path| created(path, "%m"));
+        registry.variable("doc.day", |path: &Path| created(path, "%d"));
+    }
+}
+
+static LAST_READ: LastRead<DocumentInfo> = LastRead::new();
+static LAST_TEXT: LastRead<String> = LastRead::new();
+
+/// Reads title, author and creation date of a PDF, Word, Excel, PowerPoint or OpenDocument file
+pub fn info(path: &Path) -> Result<DocumentInfo> {
+    LAST_READ
+        .get(path, |path| match kind(path)? {
+            Kind::Pdf => Ok(pdf::info(&read(path)?)),
+            Kind::Office => office::info(path),
+        })
+        .context(format!("Could not read the metadata of {:?}.", path))
+}
+
+/// The text of the first page of a PDF or the beginning of the text of an Office document
+pub fn text(path: &Path) -> Result<String> {
+    LAST_TEXT
+        .get(path, |path| match kind(path)? {
+            Kind::Pdf => match pdftotext(path) {
+                Some(text) => Ok(text),
+                None => Ok(pdf::text(&read(path)?)),
+            },
+            Kind::Office => office::text(path),
+        })
+        .context(format!("Could not read the text of {:?}.", path))
+}
+
+/// Whether the text of a document matches. Files that are no documents never match.
+pub fn content_matches(path: &Path, regex: &Regex) -> bool {
+    match text(path) {
+        Ok(text) => regex.is_match(&text),
+        Err(e) => {
+            debug!("{:#}", e);
+            false
+        }
+    }
+}
+
+/// When the document was created. Falls back to the modification date for documents without
+/// a creation date.
+fn created(path: &Path, format: &str) -> Result<String> {
+    match info(path)?.created {
+        Some(created) if created.year() > 1970 => Ok(created.format(format).to_string()),
+        _ => plugin::modified(path, format),
+    }
+}
+
+enum Kind {
+    Pdf,
+    Office,
+}
+
+fn kind(path: &Path) -> Result<Kind> {
+    let mut magic = Vec::new();
+    File::open(path)?.take(1024).read_to_end(&mut magic)?;
+    if magic.starts_with(b"PK\x03\x04") {
+        Ok(Kind::Office)
+    } else if find(&magic, b"%PDF-").is_some() {
+        Ok(Kind::Pdf)
+    } else {
+        Err(anyhow!("{:?} is not a document.", path))
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    if fs::metadata(path)?.len() > MAX_SIZE {
+        return Err(anyhow!("{:?} is too large.", path));
+    }
+    Ok(fs::read(path)?)
+}
+
+/// The text as extracted by `pdftotext` from poppler, if it is installed. It also handles fonts
+/// that the built-in extraction cannot decode.
+fn pdftotext(path: &Path) -> Option<String> {
+    let output = Command::new("pdftotext")
+        .args(["-l", "1", "-enc", "UTF-8"])
+        .arg(path)
+        .arg("-")
+        .output()
+        .ok()?;
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        None
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Dates as found in PDFs (`D:20240131...`) and in XML metadata (`2024-01-31T...`)
+fn parse_date(date: &str) -> Option<NaiveDate> {
+    let date = date.trim().trim_start_matches("D:");
+    if date.get(4..5) == Some("-") {
+        NaiveDate::parse_from_str(date.get(..10)?, "%Y-%m-%d").ok()
+    } else {
+        NaiveDate::parse_from_str(date.get(..8)?, "%Y%m%d").ok()
+    }
+}
+
+mod pdf {
+    use std::io::Read;
+    use std::sync::OnceLock;
+
+    use flate2::read::ZlibDecoder;
+    use regex::bytes::Regex;
+
+    use super::{find, parse_date, xml, DocumentInfo, MAX_SIZE};
+
+    pub fn info(data: &[u8]) -> DocumentInfo {
+        let pdf = Pdf { data };
+        let mut info = DocumentInfo::default();
+        if let Some(dict) = pdf.info_dictionary() {
+            info.title = pdf.string(&dict, b"/Title");
+            info.author = pdf.string(&dict, b"/Author");
+            info.created = pdf
+                .string(&dict, b"/CreationDate")
+                .and_then(|d| parse_date(&d));
+        }
+        // Some writers only fill the XMP metadata
+        if info.title.is_none() || info.author.is_none() || info.created.is_none() {
+            if let Some(xmp) = pdf.xmp() {
+                let entry = |name| {
+                    xml::element(&xmp, name).and_then(|e| xml::element(&e, "rdf:li").or(Some(e)))
+                };
+                info.title = info
+                    .title
+                    .or_else(|| entry("dc:title").map(|e| xml::text(&e)));
+                info.author = info
+                    .author
+                    .or_else(|| entry("dc:creator").map(|e| xml::text(&e)));
+                info.created = info
+                    .created
+                    .or_else(|| xml::element(&xmp, "xmp:CreateDate").and_then(|d| parse_date(&d)));
+            }
+        }
+        info
+    }
+
+    /// Text from the first content stream that shows text. For most documents this is the first
+    /// page.
+    pub fn text(data: &[u8]) -> String {
+        Pdf { data }
+            .streams()
+            .filter(|(dict, _)| {
+                !(contains(dict, b"/ObjStm")
+                    || contains(dict, b"/XRef")
+                    || contains(dict, b"/Image")
+                    || contains(dict, b"/Length1"))
+            })
+            .map(|(_, content)| page_text(&content))
+            .find(|text| !text.trim().is_empty())
+            .unwrap_or_default()
+    }
+
+    struct Pdf<'a> {
+        data: &'a [u8],
+    }
+
+    impl<'a> Pdf<'a> {
+        fn info_dictionary(&self) -> Option<Vec<u8>> {
+            let at = rfind(self.data, b"/Info")?;
+            let number = reference(&self.data[at + 5..])?;
+            self.object(number)
+        }
+
+        /// An object by its number. Later definitions replace earlier ones, objects in object
+        /// streams are found as well.
+        fn object(&self, number: u32) -> Option<Vec<u8>> {
+            let header = Regex::new(&format!(r"(?-u)(?:^|[^0-9]){}\s+\d+\s+obj", number)).ok()?;
+            if let Some(found) = header.find_iter(self.data).last() {
+                let body = &self.data[found.end()..];
+                let end = find(body, b"endobj").unwrap_or(body.len());
+                return Some(body[..end].to_vec());
+            }
+            self.streams()
+                .filter(|(dict, _)| contains(dict, b"/ObjStm"))
+                .find_map(|(dict, content)| object_in_stream(&dict, &content, number))
+        }
+
+        /// The dictionaries and decoded content of all streams that can be decoded
+        fn streams(&self) -> impl Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a {
+            static START: OnceLock<Regex> = OnceLock::new();
+            let start =
+                START.get_or_init(|| Regex::new(r"(?-u)>>\s*stream(?:\r\n|\n|\r)").unwrap());
+            let data = self.data;
+            start.find_iter(data).filter_map(move |found| {
+                let before = &data[..found.start() + 2];
+                let dict_start = rfind(before, b"obj").map_or(0, |at| at + 3);
+                let dict = &before[dict_start..];
+                let content = &data[found.end()..];
+                let end = find(content, b"endstream")?;
+                Some((dict.to_vec(), decode(dict, &content[..end])?))
+            })
+        }
+
+        fn xmp(&self) -> Option<String> {
+            let raw = |data: &[u8]| {
+                let start = find(data, b"<x:xmpmeta")?;
+                let end = find(&data[start..], b"</x:xmpmeta>")?;
+                Some(String::from_utf8_lossy(&data[start..start + end]).into_owned())
+            };
+            raw(self.data).or_else(|| {
+                self.streams()
+                    .filter(|(dict, _)| contains(dict, b"/Metadata"))
+                    .find_map(|(_, content)| raw(&content))
+            })
+        }
+
+        /// A text entry of a dictionary, given directly or as a reference to a string object
+        fn string(&self, dict: &[u8], key: &[u8]) -> Option<String> {
+            let value = value(dict, key)?;
+            let text = match string(value) {
+                Some((bytes, _)) => decode_text(&bytes),
+                None => {
+                    let object = self.object(reference(value)?)?;
+                    let object = skip_whitespace(&object);
+                    decode_text(&string(object)?.0)
+                }
+            };
+            Some(text.trim().to_string()).filter(|t| !t.is_empty())
+        }
+    }
+
+    fn contains(data: &[u8], needle: &[u8]) -> bool {
+        find(data, needle).is_some()
+    }
+
+    fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        haystack.windows(needle.len()).rposition(|w| w == needle)
+    }
+
+    fn skip_whitespace(data: &[u8]) -> &[u8] {
+        let start = data
+            .iter()
+            .position(|b| !b.is_ascii_whitespace())
+            .unwrap_or(data.len());
+        &data[start..]
+    }
+
+    /// The value of a dictionary entry, up to the end of the dictionary
+    fn value<'a>(dict: &'a [u8], key: &[u8]) -> Option<&'a [u8]> {
+        let mut at = 0;
+        while let Some(found) = find(&dict[at..], key) {
+            let end = at + found + key.len();
+            if !dict.get(end).is_some_and(|b| b.is_ascii_alphanumeric()) {
+                return Some(skip_whitespace(&dict[end..]));
+            }
+            at = end;
+        }
+        None
+    }
+
+    fn integer(data: &[u8]) -> Option<(u32, &[u8])> {
+        let data = skip_whitespace(data);
+        let len = data.iter().take_while(|b| b.is_ascii_digit()).count();
+        let number = std::str::from_utf8(&data[..len]).ok()?.parse().ok()?;
+        Some((number, &data[len..]))
+    }
+
+    /// The object number of a reference like `12 0 R`
+    fn reference(data: &[u8]) -> Option<u32> {
+        let (number, rest) = integer(data)?;
+        let (_, rest) = integer(rest)?;
+        if skip_whitespace(rest).first() == Some(&b'R') {
+            Some(number)
+        } else {
+            None
+        }
+    }
+
+    fn object_in_stream(dict: &[u8], content: &[u8], number: u32) -> Option<Vec<u8>> {
+        let (count, _) = integer(value(dict, b"/N")?)?;
+        let (first, _) = integer(value(dict, b"/First")?)?;
+        let mut header = content.get(..first as usize)?;
+        let mut offsets = Vec::new();
+        for _ in 0..count {
+            let (object, rest) = integer(header)?;
+            let (offset, rest) = integer(rest)?;
+            offsets.push((object, first as usize + offset as usize));
+            header = rest;
+        }
+        let index = offsets.iter().position(|(object, _)| *object == number)?;
+        let start = offsets[index].1;
+        let end = offsets
+            .get(index + 1)
+            .map_or(content.len(), |(_, end)| *end);
+        Some(content.get(start..end)?.to_vec())
+    }
+
+    /// Stream content without compression. Streams with other filters, e.g. images, are skipped.
+    fn decode(dict: &[u8], content: &[u8]) -> Option<Vec<u8>> {
+        if !contains(dict, b"/Filter") {
+            return Some(content.to_vec());
+        }
+        if !contains(dict, b"/FlateDecode") || contains(dict, b"/DCTDecode") {
+            return None;
+        }
+        let mut decoded = Vec::new();
+        // Streams are often followed by an extra line break, so errors at the end are expected
+        let _ = ZlibDecoder::new(content)
+            .take(MAX_SIZE)
+            .read_to_end(&mut decoded);
+        Some(decoded).filter(|d| !d.is_empty())
+    }
+
+    /// A literal `(...)` or hex `<...>` string at the start of `data` and its length
+    fn string(data: &[u8]) -> Option<(Vec<u8>, usize)> {
+        match data.first()? {
+            b'(' => Some(literal(data)),
+            b'<' if data.get(1) != Some(&b'<') => {
+                let end = data.iter().position(|&b| b == b'>')?;
+                let digits: Vec<u8> = data[1..end]
+                    .iter()
+                    .filter_map(|&b| (b as char).to_digit(16).map(|d| d as u8))
+                    .collect();
+                let bytes = digits
+                    .chunks(2)
+                    .map(|pair| pair[0] << 4 | pair.get(1).copied().unwrap_or(0))
+                    .collect();
+                Some((bytes, end + 1))
+            }
+            _ => None,
+        }
+    }
+
+    fn literal(data: &[u8]) -> (Vec<u8>, usize) {
+        let mut bytes = Vec::new();
+        let mut depth = 0;
+        let mut i = 0;
+        while i < data.len() {
+            let b = data[i];
+            i += 1;
+            match b {
+                b'(' => {
+                    depth += 1;
+                    if depth == 1 {
+                        continue;
+                    }
+                }
+                b')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                b'\\' => {
+                    let escaped = match data.get(i) {
+                        Some(b'n') => Some(b'\n'),
+                        Some(b'r') => Some(b'\r'),
+                        Some(b't') => Some(b'\t'),
+                        Some(b'b') => Some(8),
+                        Some(b'f') => Some(12),
+                        Some(b'\r') | Some(b'\n') => None,
+                        Some(b'0'..=b'7') => {
+                            let digits = data[i..]
+                                .iter()
+                                .take(3)
+                                .take_while(|b| (b'0'..=b'7').contains(b))
+                                .count();
+                            let value = data[i..i + digits]
+                                .iter()
+                                .fold(0u32, |value, b| value * 8 + (b - b'0') as u32);
+                            i += digits - 1;
+                            Some(value as u8)
+                        }
+                        Some(&other) => Some(other),
+                        None => None,
+                    };
+                    i += 1;
+                    bytes.extend(escaped);
+                    continue;
+                }
+                _ => {}
+            }
+            bytes.push(b);
+        }
+        (bytes, i)
+    }
+
+    /// Strings are UTF-16 with a byte order mark or a superset of Latin-1
+    fn decode_text(bytes: &[u8]) -> String {
+        if let Some(utf16) = bytes.strip_prefix(&[0xfe, 0xff]) {
+            let units: Vec<u16> = utf16
+                .chunks(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        } else if let Some(utf8) = bytes.strip_prefix(&[0xef, 0xbb, 0xbf]) {
+            String::from_utf8_lossy(utf8).into_owned()
+        } else {
+            bytes.iter().map(|&b| b as char).collect()
+        }
+    }
+
+    /// The strings shown by the text operators of a content stream
+    fn page_text(content: &[u8]) -> String {
+        let mut text = String::new();
+        let mut shown = String::new();
+        let mut i = 0;
+        while i < content.len() {
+            let b = content[i];
+            if b == b'(' || (b == b'<' && content.get(i + 1) != Some(&b'<')) {
+                let (bytes, len) = string(&content[i..]).unwrap_or_default();
+                shown.push_str(&decode_text(&bytes));
+                i += len.max(1);
+            } else if b == b'%' {
+                i += content[i..]
+                    .iter()
+                    .position(|&b| b == b'\n' || b == b'\r')
+                    .unwrap_or(content.len() - i);
+            } else if b.is_ascii_alphabetic() || b == b'\'' || b == b'"' || b == b'*' {
+                let len = content[i..]
+                    .iter()
+                    .take_while(|b| b.is_ascii_alphabetic() || b"'\"*".contains(b))
+                    .count();
+                match &content[i..i + len] {
+                    b"Tj" | b"TJ" => text.push_str(&shown),
+                    b"'" | b"\"" | b"T*" => {
+                        text.push('\n');
+                        text.push_str(&shown);
+                    }
+                    b"Td" | b"TD" | b"Tm" => text.push(' '),
+                    b"ET" => text.push('\n'),
+                    _ => {}
+                }
+                shown.clear();
+                i += len;
+            } else if b == b'-' || b.is_ascii_digit() || b == b'.' {
+                // Large gaps between the strings of a TJ array separate words
+                let len = content[i..]
+                    .iter()
+                    .take_while(|b| b.is_ascii_digit() || b"-.".contains(b))
+                    .count();
+                let gap = std::str::from_utf8(&content[i..i + len])
+                    .ok()
+                    .and_then(|n| n.parse::<f32>().ok());
+                if !shown.is_empty() && gap.is_some_and(|gap| gap < -200.0) {
+                    shown.push(' ');
+                }
+                i += len;
+            } else {
+                i += 1;
+            }
+        }
+        text
+    }
+}
+
+/// Word, Excel and PowerPoint files as well as OpenDocument files are zip archives of XML files
+mod office {
+    use std::fs::File;
+    use std::io::Read;
+    use std::path::Path;
+
+    use anyhow::Result;
+
+    use super::{parse_date, xml, DocumentInfo, MAX_SIZE};
+
+    /// Office Open XML metadata first, then OpenDocument
+    const METADATA: [(&str, &str, &str, &str); 2] = [
+        (
+            "docProps/core.xml",
+            "dc:title",
+            "dc:creator",
+            "dcterms:created",
+        ),
+        (
+            "meta.xml",
+            "dc:title",
+            "meta:initial-creator",
+            "meta:creation-date",
+        ),
+    ];
+
+    /// Where the text of Word, PowerPoint, Excel and OpenDocument files is found
+    const CONTENT: [&str; 4] = [
+        "word/document.xml",
+        "ppt/slides/slide1.xml",
+        "xl/sharedStrings.xml",
+        "content.xml",
+    ];
+
+    pub fn info(path: &Path) -> Result<DocumentInfo> {
+        let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+        for (name, title, author, created) in METADATA.iter() {
+            if let Some(metadata) = entry(&mut archive, name) {
+                return Ok(DocumentInfo {
+                    title: xml::element(&metadata, title).map(|e| xml::text(&e)),
+                    author: xml::element(&metadata, author)
+                        .or_else(|| xml::element(&metadata, "dc:creator"))
+                        .map(|e| xml::text(&e)),
+                    created: xml::element(&metadata, created).and_then(|d| parse_date(&d)),
+                });
+            }
+        }
+        Err(anyhow!("{:?} is not an Office document.", path))
+    }
+
+    pub fn text(path: &Path) -> Result<String> {
+        let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+        CONTENT
+            .iter()
+            .find_map(|name| entry(&mut archive, name))
+            .map(|content| xml::text(&content))
+            .ok_or_else(|| anyhow!("{:?} is not an Office document.", path))
+    }
+
+    fn entry(archive: &mut zip::ZipArchive<File>, name: &str) -> Option<String> {
+        let mut content = String::new();
+        archive
+            .by_name(name)
+            .ok()?
+            .take(MAX_SIZE)
+            .read_to_string(&mut content)
+            .ok()?;
+        Some(content)
+    }
+}
+
+/// Just enough XML to read metadata and text
+mod xml {
+    /// The content of the first element with this name
+    pub fn element(xml: &str, name: &str) -> Option<String> {
+        let open = format!("<{}", name);
+        let mut at = 0;
+        while let Some(found) = xml[at..].find(&open) {
+            let start = at + found + open.len();
+            let rest = &xml[start..];
+            if rest.starts_with(|c: char| c == '>' || c.is_whitespace()) {
+                let content = &rest[rest.find('>')? + 1..];
+                let end = content.find(&format!("</{}>", name))?;
+                return Some(content[..end].to_string()).filter(|c| !c.trim().is_empty());
+            }
+            at = start;
+        }
+        None
+    }
+
+    /// The text without tags. Paragraphs end with a line break.
+    pub fn text(xml: &str) -> String {
+        let mut text = String::new();
+        let mut rest = xml;
+        while let Some(start) = rest.find('<') {
+            text.push_str(&unescape(&rest[..start]));
+            let end = rest[start..]
+                .find('>')
+                .map_or(rest.len(), |end| start + end + 1);
+            let tag = &rest[start..end];
+            if ["</w:p>", "</a:p>", "</text:p>", "</text:h>", "</si>"].contains(&tag) {
+                text.push('\n');
+            } else if tag.starts_with("<w:tab") || tag.starts_with("<text:tab") {
+                text.push('\t');
+            }
+            rest = &rest[end..];
+        }
+        text.push_str(&unescape(rest));
+        text.trim().to_string()
+    }
+
+    fn unescape(text: &str) -> String {
+        let mut unescaped = String::new();
+        let mut rest = text;
+        while let Some(start) = rest.find('&') {
+            unescaped.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let end = match rest.find(';') {
+                Some(end) => end,
+                None => break,
+            };
+            let entity = &rest[1..end];
+            let character = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ => entity
+                    .strip_prefix("#x")
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                    .and_then(char::from_u32),
+            };
+            match character {
+                Some(character) => {
+                    unescaped.push(character);
+                    rest = &rest[end + 1..];
+                }
+                None => {
+                    unescaped.push('&');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        unescaped.push_str(rest);
+        unescaped
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use base64::Engine;
+use chrono::Local;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use serde::{Deserialize, Serialize};
+
+/// Failures are only compared to `error_rate` once this many files were processed in the window
+const MIN_FILES_FOR_RATE: usize = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmailSettings {
+    /// SMTP server
+    pub host: String,
+    /// Defaults to 465 with `tls: implicit`, 587 with `starttls` and 25 without encryption
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: Tls,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+
+    /// Sends a digest once this many files could not be sorted within `window`
+    #[serde(default = "default_after_failures")]
+    pub after_failures: usize,
+    /// Sends a digest once this share of the files within `window` could not be sorted, e.g.
+    /// `0.2`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_rate: Option<f64>,
+    #[serde(default = "default_window", with = "humantime_serde")]
+    pub window: Duration,
+    /// At most one digest is sent within this time. Later failures wait for the next one.
+    #[serde(default = "default_min_interval", with = "humantime_serde")]
+    pub min_interval: Duration,
+}
+
+fn default_after_failures() -> usize {
+    3
+}
+
+fn default_window() -> Duration {
+    Duration::from_secs(60 * 60)
+}
+
+fn default_min_interval() -> Duration {
+    Duration::from_secs(60 * 60)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tls {
+    /// The connection is encrypted from the start
+    Implicit,
+    /// The connection is upgraded with STARTTLS
+    #[default]
+    Starttls,
+    /// Unencrypted, e.g. for a relay on the same machine
+    None,
+}
+
+/// A file that wurmloch gave up on
+#[derive(Debug, Clone)]
+pub struct Failure {
+    pub file: String,
+    pub rule: String,
+    pub reason: String,
+}
+
+/// Sends digests in the background, so that a slow mail server does not hold up sorting
+pub struct Email {
+    settings: EmailSettings,
+    tx: Sender<String>,
+}
+
+impl Email {
+    pub fn start(settings: EmailSettings) -> Email {
+        let (tx, rx) = channel::<String>();
+        let worker = settings.clone();
+        thread::spawn(move || {
+            for message in rx {
+                deliver(&worker, &message);
+            }
+        });
+        Email { settings, tx }
+    }
+}
+
+/// Counts sorted and failed files and sends a digest of the failures once they cross a threshold
+#[derive(Default)]
+pub struct Alerts {
+    /// When files were processed and whether they failed, within the window
+    outcomes: VecDeque<(Instant, bool)>,
+    /// Failures that have not been sent yet
+    pending: Vec<Failure>,
+    last_sent: Option<Instant>,
+}
+
+impl Alerts {
+    pub fn sorted(&mut self) {
+        self.outcomes.push_back((Instant::now(), false));
+    }
+
+    pub fn failed(&mut self, failure: Failure) {
+        self.outcomes.push_back((Instant::now(), true));
+        self.pending.push(failure);
+    }
+
+    /// Sends a digest in the background if it is due
+    pub fn flush_due(&mut self, email: &Email) {
+        if let Some(message) = self.digest(&email.settings) {
+            email.tx.send(message).ok();
+        }
+    }
+
+    /// Sends a digest right away if it is due, before wurmloch exits
+    pub fn flush(&mut self, email: &Email) {
+        if let Some(message) = self.digest(&email.settings) {
+            deliver(&email.settings, &message);
+        }
+    }
+
+    fn digest(&mut self, settings: &EmailSettings) -> Option<String> {
+        while self
+            .outcomes
+            .front()
+            .is_some_and(|(when, _)| when.elapsed() > settings.window)
+        {
+            self.outcomes.pop_front();
+        }
+        if self.pending.is_empty()
+            || self
+                .last_sent
+                .is_some_and(|sent| sent.elapsed() < settings.min_interval)
+        {
+            return None;
+        }
+        let total = self.outcomes.len();
+        let failed = self.outcomes.iter().filter(|(_, failed)| *failed).count();
+        let rate_exceeded = settings.error_rate.is_some_and(|rate| {
+            total >= MIN_FILES_FOR_RATE && failed as f64 / total as f64 >= rate
+        });
+        if failed < settings.after_failures && !rate_exceeded {
+            return None;
+        }
+
+        let mut body = format!(
+            "{} of {} files could not be sorted within {}.\n",
+            failed,
+            total,
+            humantime::format_duration(settings.window)
+        );
+        for failure in self.pending.iter() {
+            body.push_str(&format!(
+                "\n{}\n  Rule: {}\n  Reason: {}\n",
+                failure.file, failure.rule, failure.reason
+            ));
+        }
+        let subject = match self.pending.len() {
+            1 => String::from("wurmloch could not sort 1 file"),
+            count => format!("wurmloch could not sort {} files", count),
+        };
+        self.pending.clear();
+        self.outcomes.clear();
+        self.last_sent = Some(Instant::now());
+        Some(message(settings, &subject, &body))
+    }
+}
+
+fn message(settings: &EmailSettings, subject: &str, body: &str) -> String {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(body);
+    let mut lines = vec![
+        format!("From: {}", &settings.from),
+        format!("To: {}", settings.to.join(", ")),
+        format!("Subject: {}", subject),
+        format!("Date: {}", Local::now().to_rfc2822()),
+        String::from("MIME-Version: 1.0"),
+        String::from("Content-Type: text/plain; charset=utf-8"),
+        String::from("Content-Transfer-Encoding: base64"),
+        String::new(),
+    ];
+    lines.extend(
+        encoded
+            .as_bytes()
+            .chunks(76)
+            .map(|chunk| String::from_utf8_lossy(chunk).into_owned()),
+    );
+    lines.join("\r\n")
+}
+
+fn deliver(settings: &EmailSettings, message: &str) {
+    match send(settings, message) {
+        Ok(()) => info!("Sent an email alert to {}.", settings.to.join(", ")),
+        Err(e) => warn!("Could not send email alert: {:#}", e),
+    }
+}
+
+fn send(settings: &EmailSettings, message: &str) -> Result<()> {
+    let port = settings.port.unwrap_or(match settings.tls {
+        Tls::Implicit => 465,
+        Tls::Starttls => 587,
+        Tls::None => 25,
+    });
+    let tcp = TcpStream::connect((settings.host.as_str(), port))
+        .context(format!("Could not connect to {}:{}.", &settings.host, port))?;
+    tcp.set_read_timeout(Some(Duration::from_secs(30)))?;
+    tcp.set_write_timeout(Some(Duration::from_secs(30)))?;
+    let stream = match settings.tls {
+        Tls::Implicit => Transport::Tls(Box::new(encrypt(&settings.host, tcp)?)),
+        Tls::Starttls | Tls::None => Transport::Plain(tcp),
+    };
+    let mut smtp = Smtp { stream };
+    smtp.expect(220)?;
+    let hostname = gethostname::gethostname().to_string_lossy().into_owned();
+    smtp.command(&format!("EHLO {}", &hostname), 250)?;
+    if settings.tls == Tls::Starttls {
+        smtp.command("STARTTLS", 220)?;
+        let tcp = match smtp.stream {
+            Transport::Plain(tcp) => tcp,
+            Transport::Tls(_) => unreachable!(),
+        };
+        smtp.stream = Transport::Tls(Box::new(encrypt(&settings.host, tcp)?));
+        smtp.command(&format!("EHLO {}", &hostname), 250)?;
+    }
+    if let (Some(user), Some(password)) = (&settings.user, &settings.password) {
+        let credentials =
+            base64::engine::general_purpose::STANDARD.encode(format!("\0{}\0{}", user, password));
+        smtp.command(&format!("AUTH PLAIN {}", credentials), 235)
+            .context("The SMTP server did not accept the credentials.")?;
+    }
+    smtp.command(&format!("MAIL FROM:<{}>", address(&settings.from)), 250)?;
+    for to in settings.to.iter() {
+        smtp.command(&format!("RCPT TO:<{}>", address(to)), 250)?;
+    }
+    smtp.command("DATA", 354)?;
+    smtp.command(&format!("{}\r\n.", message), 250)?;
+    smtp.command("QUIT", 221).ok();
+    Ok(())
+}
+
+/// `jane@example.com` of `Jane <jane@example.com>`
+fn address(mailbox: &str) -> &str {
+    match (mailbox.find('<'), mailbox.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &mailbox[start + 1..end],
+        _ => mailbox.trim(),
+    }
+}
+
+fn encrypt(host: &str, tcp: TcpStream) -> Result<StreamOwned<ClientConnection, TcpStream>> {
+    let mut roots = RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let config =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+    let name = ServerName::try_from(String::from(host))
+        .context(format!("Invalid SMTP server {:?}.", host))?;
+    let connection = ClientConnection::new(Arc::new(config), name)?;
+    Ok(StreamOwned::new(connection, tcp))
+}
+
+enum Transport {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Transport::Plain(stream) => stream.read(buf),
+            Transport::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Transport::Plain(stream) => stream.write(buf),
+            Transport::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Transport::Plain(stream) => stream.flush(),
+            Transport::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+struct Smtp {
+    stream: Transport,
+}
+
+impl Smtp {
+    fn command(&mut self, line: &str, expected: u16) -> Result<()> {
+        self.stream.write_all(format!("{}\r\n", line).as_bytes())?;
+        self.stream.flush()?;
+        self.expect(expected)
+    }
+
+    /// Reads a reply, which may span several lines, and checks its code
+    fn expect(&mut self, expected: u16) -> Result<()> {
+        loop {
+            let line = self.line()?;
+            let code: u16 = line
+                .get(..3)
+                .and_then(|code| code.parse().ok())
+                .ok_or_else(|| anyhow!("Unexpected reply of the SMTP server: {:?}", &line))?;
+            if line.as_bytes().get(3) == Some(&b'-') {
+                continue;
+            }
+            if code != expected {
+                return Err(anyhow!("The SMTP server replied {:?}.", line.trim_end()));
+            }
+            return Ok(());
+        }
+    }
+
+    /// Reads byte by byte, so that nothing after the reply is consumed before STARTTLS
+    fn line(&mut self) -> Result<String> {
+        let mut line = Vec::new();
+        let mut byte = [0u8];
+        while !line.ends_with(b"\n") {
+            if self.stream.read(&mut byte)? == 0 {
+                return Err(anyhow!("The SMTP server closed the connection."));
+            }
+            line.push(byte[0]);
+        }
+        Ok(String::from_utf8_lossy(&line).into_owned())
+    }
+}
@@ -0,0 +1,138 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// Kinds of errors that can be handled differently
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorClass {
+    /// Reading the file or writing to the target is not allowed
+    Permission,
+    /// The target directory does not exist
+    MissingTarget,
+    /// Any other error of the filesystem or the network, e.g. a full disk
+    Io,
+    /// Errors that are no I/O errors, e.g. a failed command
+    Other,
+}
+
+impl ErrorClass {
+    pub const ALL: [ErrorClass; 4] = [
+        ErrorClass::Permission,
+        ErrorClass::MissingTarget,
+        ErrorClass::Io,
+        ErrorClass::Other,
+    ];
+
+    /// The class of the first I/O error in the chain of causes
+    pub fn of(error: &anyhow::Error) -> ErrorClass {
+        let kind = error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<io::Error>())
+            .map(io::Error::kind);
+        match kind {
+            Some(io::ErrorKind::PermissionDenied) => ErrorClass::Permission,
+            // The file itself is checked before its rule is applied
+            Some(io::ErrorKind::NotFound) => ErrorClass::MissingTarget,
+            Some(_) => ErrorClass::Io,
+            None => ErrorClass::Other,
+        }
+    }
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ErrorClass::Permission => "permission",
+            ErrorClass::MissingTarget => "missing_target",
+            ErrorClass::Io => "io",
+            ErrorClass::Other => "other",
+        })
+    }
+}
+
+/// What happens to a file after all attempts to sort it failed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorPolicy {
+    /// Leave it where it is, or move it to the `error_target` of `retry`, and keep sorting
+    #[default]
+    Continue,
+    /// Stop wurmloch
+    Halt,
+    /// Move it to this directory
+    Divert(PathBuf),
+}
+
+/// Policies by error class, written as one policy for all classes or as a map with a policy per
+/// class
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "Policies", into = "Policies")]
+pub struct OnError {
+    pub permission: ErrorPolicy,
+    pub missing_target: ErrorPolicy,
+    pub io: ErrorPolicy,
+    pub other: ErrorPolicy,
+}
+
+impl OnError {
+    pub fn policy(&self, class: ErrorClass) -> &ErrorPolicy {
+        match class {
+            ErrorClass::Permission => &self.permission,
+            ErrorClass::MissingTarget => &self.missing_target,
+            ErrorClass::Io => &self.io,
+            ErrorClass::Other => &self.other,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Policies {
+    All(ErrorPolicy),
+    PerClass(PerClass),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PerClass {
+    #[serde(default)]
+    permission: ErrorPolicy,
+    #[serde(default)]
+    missing_target: ErrorPolicy,
+    #[serde(default)]
+    io: ErrorPolicy,
+    #[serde(default)]
+    other: ErrorPolicy,
+}
+
+impl From<Policies> for OnError {
+    fn from(policies: Policies) -> OnError {
+        match policies {
+            Policies::All(policy) => OnError {
+                permission: policy.clone(),
+                missing_target: policy.clone(),
+                io: policy.clone(),
+                other: policy,
+            },
+            Policies::PerClass(classes) => OnError {
+                permission: classes.permission,
+                missing_target: classes.missing_target,
+                io: classes.io,
+                other: classes.other,
+            },
+        }
+    }
+}
+
+impl From<OnError> for Policies {
+    fn from(on_error: OnError) -> Policies {
+        Policies::PerClass(PerClass {
+            permission: on_error.permission,
+            missing_target: on_error.missing_target,
+            io: on_error.io,
+            other: on_error.other,
+        })
+    }
+}
@@ -0,0 +1,119 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryFormat {
+    /// One JSON object per line
+    #[default]
+    Jsonl,
+    Csv,
+}
+
+impl HistoryFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            HistoryFormat::Jsonl => "jsonl",
+            HistoryFormat::Csv => "csv",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistorySettings {
+    pub format: HistoryFormat,
+
+    /// Defaults to `history.<format>` in the data directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+}
+
+/// What happened to a single file
+#[derive(Debug, Serialize)]
+pub struct Entry<'a> {
+    pub time: DateTime<Local>,
+    pub rule: &'a str,
+    pub action: &'a str,
+    pub source: &'a Path,
+    pub destination: Option<&'a Path>,
+    /// How an existing file at the destination was dealt with
+    pub conflict: &'static str,
+    pub duration_ms: u128,
+    pub bytes: u64,
+}
+
+impl Entry<'_> {
+    const CSV_HEADER: &'static str =
+        "time,rule,action,source,destination,conflict,duration_ms,bytes";
+
+    fn to_csv(&self) -> String {
+        [
+            self.time.to_rfc3339(),
+            csv_field(self.rule),
+            self.action.to_string(),
+            csv_field(&self.source.to_string_lossy()),
+            csv_field(
+                &self
+                    .destination
+                    .map(|d| d.to_string_lossy())
+                    .unwrap_or_default(),
+            ),
+            self.conflict.to_string(),
+            self.duration_ms.to_string(),
+            self.bytes.to_string(),
+        ]
+        .join(",")
+    }
+}
+
+/// Quotes a field if necessary
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Machine-readable log of everything that has been done to files, one line per action
+pub struct History {
+    path: PathBuf,
+    format: HistoryFormat,
+}
+
+impl History {
+    pub fn open(settings: &HistorySettings, data_dir: &Path) -> History {
+        let path = settings
+            .path
+            .clone()
+            .unwrap_or_else(|| data_dir.join(format!("history.{}", settings.format.extension())));
+        History {
+            path,
+            format: settings.format,
+        }
+    }
+
+    pub fn write(&self, entry: &Entry) -> Result<()> {
+        let new = !self.path.exists();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .context(format!("Could not open history {:?}.", &self.path))?;
+
+        let line = match self.format {
+            HistoryFormat::Jsonl => serde_json::to_string(entry)?,
+            HistoryFormat::Csv if new => format!("{}\n{}", Entry::CSV_HEADER, entry.to_csv()),
+            HistoryFormat::Csv => entry.to_csv(),
+        };
+        // One write per line, so that lines of files sorted at the same time do not mix
+        file.write_all(format!("{}\n", line).as_bytes())
+            .context(format!("Could not write history {:?}.", &self.path))
+    }
+}
@@ -0,0 +1,106 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+
+/// Requests that are answered at the same time. Further connections are closed right away.
+const MAX_CONNECTIONS: usize = 16;
+/// Longest request line and headers that are read
+const MAX_REQUEST_SIZE: u64 = 16 * 1024;
+
+/// Request line and headers of a request. The body is never read.
+#[derive(Debug, Default)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    /// Value of the header, whose name is compared case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub struct Response {
+    pub status: &'static str,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+/// Answers the connections to `listener` in the background. Every request gets its own thread,
+/// so that a slow client does not hold up others. `name` tells the server apart in logs.
+pub fn serve<F>(listener: TcpListener, name: &'static str, handle: F)
+where
+    F: Fn(&Request) -> Result<Response> + Send + Sync + 'static,
+{
+    let handle = Arc::new(handle);
+    let open = Arc::new(AtomicUsize::new(0));
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if open.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                open.fetch_sub(1, Ordering::SeqCst);
+                debug!("Too many {} requests at once. Connection closed.", name);
+                continue;
+            }
+            let handle = Arc::clone(&handle);
+            let open = Arc::clone(&open);
+            thread::spawn(move || {
+                if let Err(e) = respond(stream, handle.as_ref()) {
+                    debug!("{} request failed: {:#}", name, e);
+                }
+                open.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+}
+
+fn respond(mut stream: TcpStream, handle: &dyn Fn(&Request) -> Result<Response>) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let request = read(&stream)?;
+    let response = handle(&request)?;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        response.status,
+        response.content_type,
+        response.body.len(),
+        response.body
+    )?;
+    Ok(())
+}
+
+fn read(stream: &TcpStream) -> Result<Request> {
+    let mut reader = BufReader::new(stream.take(MAX_REQUEST_SIZE));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut words = request_line.split_whitespace();
+    let method = words.next().unwrap_or_default();
+    let target = words.next().unwrap_or_default();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let mut headers = Vec::new();
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        if let Some((name, value)) = header.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+        header.clear();
+    }
+    Ok(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query.to_string(),
+        headers,
+    })
+}
@@ -0,0 +1,628 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use regex::{Captures, Regex};
+use serde_yaml::Value;
+
+use crate::config::{ConfigRule, RuleType};
+
+/// Tools whose rules can be imported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum Format {
+    /// The YAML configuration of organize (organize-tool)
+    Organize,
+    /// Rules exported from Hazel (`.hazelrules`), best effort
+    Hazel,
+}
+
+/// Rules converted from another tool
+pub struct Imported {
+    pub rules: Vec<ConfigRule>,
+    /// What could not be converted or has to be checked, one sentence each
+    pub notes: Vec<String>,
+}
+
+/// Converts the rules of another tool. Everything that has no equivalent is left out and
+/// mentioned in the notes.
+pub fn import(format: Format, path: &Path) -> Result<Imported> {
+    let mut notes = Vec::new();
+    let rules = match format {
+        Format::Organize => organize(path, &mut notes)?,
+        Format::Hazel => hazel(path, &mut notes)?,
+    };
+    Ok(Imported { rules, notes })
+}
+
+fn organize(path: &Path, notes: &mut Vec<String>) -> Result<Vec<ConfigRule>> {
+    let text = fs::read_to_string(path).context(format!("Could not read {:?}.", path))?;
+    let config: Value =
+        serde_yaml::from_str(&text).context(format!("{:?} is not valid YAML.", path))?;
+    let rules = config
+        .get("rules")
+        .and_then(Value::as_sequence)
+        .context(format!("{:?} contains no list of rules.", path))?;
+    notes.push(String::from(
+        "organize renames files whose name is taken in the target. wurmloch replaces them \
+         unless `safe: true` is set.",
+    ));
+    Ok(rules
+        .iter()
+        .enumerate()
+        .filter_map(|(index, rule)| organize_rule(rule, index, notes))
+        .collect())
+}
+
+/// The conditions of a rule that end up in its pattern
+#[derive(Default)]
+struct NameFilter {
+    extensions: Vec<String>,
+    /// A complete pattern for the name without the extension
+    glob: Option<String>,
+    starts_with: String,
+    contains: Option<String>,
+    ends_with: String,
+}
+
+impl NameFilter {
+    fn pattern(&self, kind: Option<RuleType>) -> String {
+        let stem = match &self.glob {
+            Some(glob) => glob.clone(),
+            None => match &self.contains {
+                Some(contains) => format!(
+                    "{}*{}*{}",
+                    escape(&self.starts_with),
+                    escape(contains),
+                    escape(&self.ends_with)
+                ),
+                None => format!("{}*{}", escape(&self.starts_with), escape(&self.ends_with)),
+            },
+        };
+        match self.extensions.as_slice() {
+            [] if stem == "*" || kind == Some(RuleType::Dir) => stem,
+            [] => format!("{}{{,.*}}", stem),
+            [extension] => format!("{}.{}", stem, escape(extension)),
+            extensions => format!("{}.{{{}}}", stem, extensions.join(",")),
+        }
+    }
+}
+
+fn organize_rule(rule: &Value, index: usize, notes: &mut Vec<String>) -> Option<ConfigRule> {
+    let name = match rule.get("name").and_then(Value::as_str) {
+        Some(name) => name.to_string(),
+        None => format!("Rule {}", index + 1),
+    };
+    if rule.get("enabled").and_then(Value::as_bool) == Some(false) {
+        notes.push(format!("{}: disabled in organize. Left out.", name));
+        return None;
+    }
+    let locations = strings(rule.get("locations"), "path");
+    if !locations.is_empty() {
+        notes.push(format!(
+            "{}: organize looks in {}. Point wurmloch to this folder.",
+            name,
+            locations.join(", ")
+        ));
+    }
+    match rule.get("filter_mode").and_then(Value::as_str) {
+        None | Some("all") => {}
+        Some(mode) => notes.push(format!(
+            "{}: filter_mode {} is not supported. All filters have to match.",
+            name, mode
+        )),
+    }
+
+    let mut imported = ConfigRule::default();
+    if rule.get("targets").and_then(Value::as_str) == Some("dirs") {
+        imported.kind = Some(RuleType::Dir);
+    }
+    let mut filter = NameFilter::default();
+    for (key, args) in rule
+        .get("filters")
+        .and_then(Value::as_sequence)
+        .into_iter()
+        .flatten()
+        .filter_map(entry)
+    {
+        match key.as_str() {
+            "extension" => {
+                filter.extensions = strings(Some(&args), "extension")
+                    .iter()
+                    .map(|e| e.trim_start_matches('.').to_string())
+                    .collect()
+            }
+            "name" => match &args {
+                Value::String(glob) => filter.glob = Some(glob.clone()),
+                _ => {
+                    let first = |key: &str| strings(args.get(key), key).into_iter().next();
+                    filter.starts_with = first("startswith").unwrap_or_default();
+                    filter.contains = first("contains");
+                    filter.ends_with = first("endswith").unwrap_or_default();
+                    if ["startswith", "contains", "endswith"]
+                        .iter()
+                        .any(|key| strings(args.get(key), key).len() > 1)
+                    {
+                        notes.push(format!(
+                            "{}: only the first value of each name condition is used.",
+                            name
+                        ));
+                    }
+                }
+            },
+            "lastmodified" | "created" => {
+                if args.get("mode").and_then(Value::as_str).unwrap_or("older") != "older" {
+                    notes.push(format!(
+                        "{}: only `mode: older` of {} is supported. Left out.",
+                        name, key
+                    ));
+                    continue;
+                }
+                if key == "created" {
+                    notes.push(format!(
+                        "{}: the modification date is used instead of the creation date.",
+                        name
+                    ));
+                }
+                imported.older_than = Some(age(&args));
+            }
+            "filecontent" => match args.as_str() {
+                Some(expression) => imported.content_matches = Some(expression.to_string()),
+                None => notes.push(format!(
+                    "{}: filecontent needs a regular expression. Left out.",
+                    name
+                )),
+            },
+            _ => notes.push(format!(
+                "{}: the filter {} is not supported. Left out.",
+                name, key
+            )),
+        }
+    }
+    imported.pattern = filter.pattern(imported.kind);
+
+    for (key, args) in rule
+        .get("actions")
+        .and_then(Value::as_sequence)
+        .into_iter()
+        .flatten()
+        .filter_map(entry)
+    {
+        let is_main = matches!(key.as_str(), "move" | "copy" | "delete" | "trash" | "shell");
+        if is_main && (imported.action.is_some() || !imported.target.first().as_os_str().is_empty())
+        {
+            notes.push(format!(
+                "{}: only one action is supported. {} left out.",
+                name, key
+            ));
+            continue;
+        }
+        match key.as_str() {
+            "move" | "copy" => {
+                let dest = match &args {
+                    Value::String(dest) => Some(dest.as_str()),
+                    _ => args.get("dest").and_then(Value::as_str),
+                };
+                let dest = match dest {
+                    Some(dest) => translate(dest, &name, notes),
+                    None => {
+                        notes.push(format!("{}: {} has no destination. Left out.", name, key));
+                        continue;
+                    }
+                };
+                // organize treats a destination without a trailing slash as the new path
+                let (target, rename) = match dest.strip_suffix('/') {
+                    Some(dir) => (dir.to_string(), None),
+                    None => match dest.rsplit_once('/') {
+                        Some((dir, new_name)) => (dir.to_string(), Some(new_name.to_string())),
+                        None => (dest.clone(), None),
+                    },
+                };
+                imported.target = expand_home(&target).into();
+                if rename.is_some() {
+                    imported.rename = rename;
+                }
+                if key == "copy" {
+                    imported.action = Some(key.clone());
+                }
+                imported.create_target = Some(true);
+            }
+            "rename" => {
+                let new_name = match &args {
+                    Value::String(new_name) => Some(new_name.as_str()),
+                    _ => args.get("new_name").and_then(Value::as_str),
+                };
+                imported.rename = new_name.map(|new_name| translate(new_name, &name, notes));
+            }
+            "delete" | "trash" => imported.action = Some(key.clone()),
+            "shell" => {
+                let command = match &args {
+                    Value::String(command) => Some(command.as_str()),
+                    _ => args.get("cmd").and_then(Value::as_str),
+                };
+                imported.action = Some(String::from("run"));
+                imported.command = command.map(|command| translate(command, &name, notes));
+            }
+            _ => notes.push(format!(
+                "{}: the action {} is not supported. Left out.",
+                name, key
+            )),
+        }
+    }
+    let moves = imported.action.is_none() || imported.action.as_deref() == Some("copy");
+    if moves && imported.target.first().as_os_str().is_empty() {
+        notes.push(format!("{}: has no target. Rule left out.", name));
+        return None;
+    }
+    Some(imported)
+}
+
+/// Filters and actions are either a plain name or a map with one entry from the name to its
+/// arguments
+fn entry(item: &Value) -> Option<(String, Value)> {
+    match item {
+        Value::String(name) => Some((name.clone(), Value::Null)),
+        Value::Mapping(map) if map.len() == 1 => {
+            let (key, args) = map.iter().next()?;
+            Some((key.as_str()?.to_string(), args.clone()))
+        }
+        _ => None,
+    }
+}
+
+/// A string, a list of strings or maps with the string under `key`
+fn strings(value: Option<&Value>, key: &str) -> Vec<String> {
+    match value {
+        Some(Value::String(s)) => vec![s.clone()],
+        Some(Value::Sequence(items)) => items
+            .iter()
+            .flat_map(|item| strings(Some(item), key))
+            .collect(),
+        Some(Value::Mapping(_)) => strings(value.and_then(|v| v.get(key)), key),
+        _ => Vec::new(),
+    }
+}
+
+/// Age like `days: 30, hours: 12`
+fn age(args: &Value) -> Duration {
+    let units = [
+        ("years", 365 * 86400),
+        ("months", 30 * 86400),
+        ("weeks", 7 * 86400),
+        ("days", 86400),
+        ("hours", 3600),
+        ("minutes", 60),
+        ("seconds", 1),
+    ];
+    let seconds = units
+        .iter()
+        .map(|(unit, seconds)| {
+            args.get(*unit).and_then(Value::as_f64).unwrap_or(0.0) * *seconds as f64
+        })
+        .sum::<f64>();
+    Duration::from_secs(seconds as u64)
+}
+
+/// Replaces placeholders of organize by those of wurmloch
+fn translate(template: &str, rule: &str, notes: &mut Vec<String>) -> String {
+    let placeholders: BTreeMap<&str, &str> = [
+        ("path", "path"),
+        ("name", "stem"),
+        ("extension", "ext"),
+        ("lastmodified.year", "year"),
+        ("lastmodified.month", "month"),
+        ("lastmodified.day", "day"),
+        ("created.year", "year"),
+        ("created.month", "month"),
+        ("created.day", "day"),
+    ]
+    .iter()
+    .copied()
+    .collect();
+    let placeholder = Regex::new(r"\{\s*([^{}]+?)\s*\}").unwrap();
+    placeholder
+        .replace_all(template, |captures: &Captures| {
+            let name = &captures[1];
+            match placeholders.get(name) {
+                Some(replacement) => format!("{{{}}}", replacement),
+                None => {
+                    notes.push(format!(
+                        "{}: the placeholder {} is not supported.",
+                        rule, &captures[0]
+                    ));
+                    captures[0].to_string()
+                }
+            }
+        })
+        .into_owned()
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Makes glob characters match literally
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if "*?[]{}".contains(c) {
+            escaped.push('[');
+            escaped.push(c);
+            escaped.push(']');
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// Hazel keeps its rules in an archive of Objective-C objects. Their layout is not documented,
+/// so only conditions on the extension and actions that move, copy or delete files are
+/// recognized.
+fn hazel(path: &Path, notes: &mut Vec<String>) -> Result<Vec<ConfigRule>> {
+    let archive =
+        plist::Value::from_file(path).context(format!("{:?} is no Hazel rule file.", path))?;
+    let root = match unarchive(&archive) {
+        Some(root) => root,
+        None => archive,
+    };
+    let mut rules = Vec::new();
+    find_objects(&root, "rule", &mut rules);
+    if rules.is_empty() {
+        return Err(anyhow!(
+            "{:?} contains no rules. Export them in Hazel with Rules > Export Rules.",
+            path
+        ));
+    }
+    Ok(rules
+        .iter()
+        .enumerate()
+        .filter_map(|(index, rule)| hazel_rule(rule, index, notes))
+        .collect())
+}
+
+fn hazel_rule(
+    rule: &plist::Dictionary,
+    index: usize,
+    notes: &mut Vec<String>,
+) -> Option<ConfigRule> {
+    let name = rule
+        .iter()
+        .find(|(key, value)| key.to_lowercase().contains("name") && value.as_string().is_some())
+        .and_then(|(_, value)| value.as_string())
+        .map_or_else(|| format!("Rule {}", index + 1), str::to_string);
+    let root = plist::Value::Dictionary(rule.clone());
+
+    let mut filter = NameFilter::default();
+    let mut conditions = Vec::new();
+    find_objects(&root, "condition", &mut conditions);
+    for condition in conditions {
+        let words = leaves(&plist::Value::Dictionary(condition.clone()));
+        let is_extension = words.iter().any(|w| w.to_lowercase().contains("extension"));
+        let value = words.iter().find(|w| {
+            !w.to_lowercase().contains("extension")
+                && !w.is_empty()
+                && w.len() <= 10
+                && w.chars().all(char::is_alphanumeric)
+        });
+        match (is_extension, value) {
+            (true, Some(extension)) => filter.extensions.push(extension.clone()),
+            _ => notes.push(format!(
+                "{}: the condition {:?} is not supported. Left out.",
+                name, words
+            )),
+        }
+    }
+    let mut imported = ConfigRule {
+        pattern: filter.pattern(None),
+        ..ConfigRule::default()
+    };
+
+    let mut actions = Vec::new();
+    find_objects(&root, "action", &mut actions);
+    for action in actions {
+        let class = action
+            .get("$class")
+            .and_then(plist::Value::as_string)
+            .unwrap_or_default()
+            .to_lowercase();
+        let kind = ["move", "copy", "trash", "delete"]
+            .iter()
+            .copied()
+            .find(|kind| class.contains(kind));
+        let kind = match kind {
+            Some(kind)
+                if imported.action.is_none() && imported.target.first().as_os_str().is_empty() =>
+            {
+                kind
+            }
+            Some(_) => {
+                notes.push(format!(
+                    "{}: only one action is supported. {} left out.",
+                    name, class
+                ));
+                continue;
+            }
+            None => {
+                notes.push(format!(
+                    "{}: the action {} is not supported. Left out.",
+                    name, class
+                ));
+                continue;
+            }
+        };
+        match kind {
+            "move" | "copy" => {
+                let folder = leaves(&plist::Value::Dictionary(action.clone()))
+                    .into_iter()
+                    .find_map(|leaf| folder(&leaf));
+                match folder {
+                    Some(folder) => imported.target = folder.into(),
+                    None => notes.push(format!(
+                        "{}: the folder of the action could not be read. Add the target yourself.",
+                        name
+                    )),
+                }
+                if kind == "copy" {
+                    imported.action = Some(String::from("copy"));
+                }
+            }
+            kind => imported.action = Some(kind.to_string()),
+        }
+    }
+    if imported.action.is_none() && imported.target.first().as_os_str().is_empty() {
+        notes.push(format!("{}: no supported action. Rule left out.", name));
+        return None;
+    }
+    Some(imported)
+}
+
+/// Resolves the references of an `NSKeyedArchiver` archive. Objects are turned into
+/// dictionaries with their class name under `$class`.
+fn unarchive(archive: &plist::Value) -> Option<plist::Value> {
+    let archive = archive.as_dictionary()?;
+    let objects = archive.get("$objects")?.as_array()?;
+    let top = archive.get("$top")?;
+    Some(resolve(top, objects, 0))
+}
+
+fn resolve(value: &plist::Value, objects: &[plist::Value], depth: usize) -> plist::Value {
+    // Archives may contain cycles
+    if depth > 64 {
+        return plist::Value::String(String::new());
+    }
+    match value {
+        plist::Value::Uid(uid) => match objects.get(uid.get() as usize) {
+            Some(object) => resolve(object, objects, depth + 1),
+            None => plist::Value::String(String::new()),
+        },
+        plist::Value::Array(items) => plist::Value::Array(
+            items
+                .iter()
+                .map(|item| resolve(item, objects, depth + 1))
+                .collect(),
+        ),
+        plist::Value::Dictionary(dict) => {
+            if let Some(string) = dict.get("NS.string") {
+                return resolve(string, objects, depth + 1);
+            }
+            if let Some(url) = dict.get("NS.relative") {
+                return resolve(url, objects, depth + 1);
+            }
+            let items = dict.get("NS.objects").and_then(plist::Value::as_array);
+            let keys = dict.get("NS.keys").and_then(plist::Value::as_array);
+            match (keys, items) {
+                (Some(keys), Some(items)) => {
+                    let mut resolved = plist::Dictionary::new();
+                    for (key, item) in keys.iter().zip(items) {
+                        if let plist::Value::String(key) = resolve(key, objects, depth + 1) {
+                            resolved.insert(key, resolve(item, objects, depth + 1));
+                        }
+                    }
+                    plist::Value::Dictionary(resolved)
+                }
+                (None, Some(items)) => plist::Value::Array(
+                    items
+                        .iter()
+                        .map(|item| resolve(item, objects, depth + 1))
+                        .collect(),
+                ),
+                _ => {
+                    let mut resolved = plist::Dictionary::new();
+                    for (key, item) in dict.iter() {
+                        let item = if key == "$class" {
+                            let class = resolve(item, objects, depth + 1);
+                            let class = class
+                                .as_dictionary()
+                                .and_then(|c| c.get("$classname"))
+                                .and_then(plist::Value::as_string)
+                                .unwrap_or_default()
+                                .to_string();
+                            plist::Value::String(class)
+                        } else {
+                            resolve(item, objects, depth + 1)
+                        };
+                        resolved.insert(key.clone(), item);
+                    }
+                    plist::Value::Dictionary(resolved)
+                }
+            }
+        }
+        value => value.clone(),
+    }
+}
+
+/// Objects whose class name ends with `kind`, outermost first
+fn find_objects(value: &plist::Value, kind: &str, found: &mut Vec<plist::Dictionary>) {
+    match value {
+        plist::Value::Dictionary(dict) => {
+            let class = dict
+                .get("$class")
+                .and_then(plist::Value::as_string)
+                .unwrap_or_default();
+            if class.to_lowercase().ends_with(kind) {
+                found.push(dict.clone());
+                return;
+            }
+            for item in dict.values() {
+                find_objects(item, kind, found);
+            }
+        }
+        plist::Value::Array(items) => {
+            for item in items {
+                find_objects(item, kind, found);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// All strings inside the value except class names
+fn leaves(value: &plist::Value) -> Vec<String> {
+    match value {
+        plist::Value::String(s) if s != "$null" => vec![s.clone()],
+        plist::Value::Dictionary(dict) => dict
+            .iter()
+            .filter(|(key, _)| *key != "$class")
+            .flat_map(|(_, item)| leaves(item))
+            .collect(),
+        plist::Value::Array(items) => items.iter().flat_map(leaves).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// The path of a folder given as a path or a `file://` URL
+fn folder(text: &str) -> Option<PathBuf> {
+    let path = match text.strip_prefix("file://") {
+        Some(url) => percent_decode(url),
+        None if text.starts_with('/') || text.starts_with("~/") => text.to_string(),
+        None => return None,
+    };
+    Some(expand_home(path.trim_end_matches('/')))
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok());
+        match (bytes[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,398 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, TimeZone};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+use crate::journal::Event;
+use crate::transfer::{self, Checksum};
+
+pub const INDEX_FILE_NAME: &str = "index.sqlite";
+
+/// A processed file as found by a search
+#[derive(Debug)]
+pub struct Found {
+    pub time: DateTime<Local>,
+    pub rule: String,
+    pub source: PathBuf,
+    /// `None` for deleted files
+    pub destination: Option<PathBuf>,
+    /// `None` for files that were indexed before sizes were recorded
+    pub size: Option<u64>,
+}
+
+/// A file that was given up on, as found by [`Index::failures`]
+#[derive(Debug)]
+pub struct Failure {
+    pub time: DateTime<Local>,
+    pub rule: String,
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+/// Restricts a search
+#[derive(Debug, Default)]
+pub struct Query<'a> {
+    /// Glob that is matched against the original name and the destination
+    pub pattern: &'a str,
+    pub rule: Option<&'a str>,
+    pub since: Option<DateTime<Local>>,
+    pub until: Option<DateTime<Local>>,
+}
+
+/// What happens to a file with the same content as a file that wurmloch has placed before
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnDuplicate {
+    /// Leave it in the wormhole
+    Skip,
+    /// Move it to the trash
+    DeleteSource,
+    /// Apply the action anyway
+    KeepBoth,
+}
+
+/// Searchable record of all processed files. It can be shared by the workers that apply
+/// actions.
+pub struct Index {
+    connection: Mutex<Connection>,
+}
+
+impl Index {
+    pub fn open(path: &Path) -> Result<Index> {
+        let connection =
+            Connection::open(path).context(format!("Could not open index {:?}.", path))?;
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS files (
+                    time INTEGER NOT NULL,
+                    rule TEXT NOT NULL,
+                    name TEXT NOT NULL,
+                    source TEXT NOT NULL,
+                    destination TEXT
+                );
+                CREATE INDEX IF NOT EXISTS files_time ON files (time);
+                CREATE TABLE IF NOT EXISTS hashes (
+                    path TEXT PRIMARY KEY,
+                    hash TEXT NOT NULL,
+                    size INTEGER NOT NULL,
+                    modified INTEGER NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS hashes_hash ON hashes (hash);
+                CREATE TABLE IF NOT EXISTS counters (
+                    rule TEXT PRIMARY KEY,
+                    period TEXT NOT NULL,
+                    next INTEGER NOT NULL
+                );
+                CREATE TABLE IF NOT EXISTS unmatched (
+                    time INTEGER NOT NULL,
+                    path TEXT NOT NULL
+                );
+                CREATE TABLE IF NOT EXISTS evaluated (
+                    path TEXT PRIMARY KEY,
+                    size INTEGER NOT NULL,
+                    modified INTEGER NOT NULL,
+                    rules TEXT NOT NULL
+                );
+                CREATE TABLE IF NOT EXISTS failures (
+                    time INTEGER NOT NULL,
+                    rule TEXT NOT NULL,
+                    path TEXT NOT NULL,
+                    reason TEXT NOT NULL
+                );",
+            )
+            .context(format!("Could not initialize index {:?}.", path))?;
+        // Indexes created by older versions have no sizes
+        let has_size = connection.prepare("SELECT size FROM files LIMIT 0").is_ok();
+        if !has_size {
+            connection
+                .execute("ALTER TABLE files ADD COLUMN size INTEGER", [])
+                .context(format!("Could not upgrade index {:?}.", path))?;
+        }
+        Ok(Index {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Adds a file that an action of `size` bytes was applied to
+    pub fn add(&self, event: &Event, size: u64) -> Result<()> {
+        let (rule, source, destination) = match event {
+            Event::Move { rule, from, to, .. }
+            | Event::Copy { rule, from, to, .. }
+            | Event::Compress { rule, from, to, .. } => (rule, from, Some(to)),
+            Event::Delete { rule, from, .. } => (rule, from, None),
+            Event::Undo { .. } | Event::Decision { .. } => return Ok(()),
+        };
+        let name = source.file_name().unwrap_or_default().to_string_lossy();
+        self.connection()
+            .execute(
+                "INSERT INTO files (time, rule, name, source, destination, size)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    Local::now().timestamp(),
+                    rule,
+                    name,
+                    source.to_string_lossy(),
+                    destination.map(|d| d.to_string_lossy()),
+                    size as i64
+                ],
+            )
+            .context("Could not add file to the index.")?;
+        Ok(())
+    }
+
+    /// Matching files, newest first
+    pub fn find(&self, query: &Query) -> Result<Vec<Found>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT time, rule, source, destination, size FROM files
+             WHERE (name GLOB ?1 OR destination GLOB ?1)
+               AND (?2 IS NULL OR rule = ?2)
+               AND (?3 IS NULL OR time >= ?3)
+               AND (?4 IS NULL OR time <= ?4)
+             ORDER BY time DESC",
+        )?;
+        let rows = statement.query_map(
+            params![
+                query.pattern,
+                query.rule,
+                query.since.map(|t| t.timestamp()),
+                query.until.map(|t| t.timestamp())
+            ],
+            |row| {
+                Ok(Found {
+                    time: Local
+                        .timestamp_opt(row.get(0)?, 0)
+                        .single()
+                        .unwrap_or_else(Local::now),
+                    rule: row.get(1)?,
+                    source: PathBuf::from(row.get::<_, String>(2)?),
+                    destination: row.get::<_, Option<String>>(3)?.map(PathBuf::from),
+                    size: row.get::<_, Option<i64>>(4)?.map(|size| size as u64),
+                })
+            },
+        )?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Remembers a file that no rule matched
+    pub fn add_unmatched(&self, path: &Path) -> Result<()> {
+        self.connection()
+            .execute(
+                "INSERT INTO unmatched (time, path) VALUES (?1, ?2)",
+                params![Local::now().timestamp(), path.to_string_lossy()],
+            )
+            .context("Could not add unmatched file to the index.")?;
+        Ok(())
+    }
+
+    /// Files that no rule matched since the given time, each of them once
+    pub fn unmatched(&self, since: Option<DateTime<Local>>) -> Result<Vec<PathBuf>> {
+        let connection = self.connection();
+        let mut statement = connection
+            .prepare("SELECT DISTINCT path FROM unmatched WHERE ?1 IS NULL OR time >= ?1")?;
+        let rows = statement.query_map(params![since.map(|t| t.timestamp())], |row| {
+            row.get::<_, String>(0)
+        })?;
+        Ok(rows
+            .map(|row| row.map(PathBuf::from))
+            .collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Remembers a file that stayed where it is, because no rule matched it or its rule left it
+    /// there, so that it is not evaluated again as long as it and the rules with this
+    /// fingerprint stay the same
+    pub fn add_evaluated(&self, path: &Path, rules: &str) -> Result<()> {
+        let (size, modified) = stat(path).context(format!("Could not read {:?}.", path))?;
+        self.connection()
+            .execute(
+                "INSERT OR REPLACE INTO evaluated (path, size, modified, rules)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![path.to_string_lossy(), size, modified, rules],
+            )
+            .context("Could not add evaluated file to the index.")?;
+        Ok(())
+    }
+
+    /// Whether the file stayed where it is when it was evaluated with the same rules before
+    pub fn is_evaluated(&self, path: &Path, rules: &str) -> Result<bool> {
+        let connection = self.connection();
+        let mut statement = connection
+            .prepare("SELECT size, modified FROM evaluated WHERE path = ?1 AND rules = ?2")?;
+        let mut rows = statement.query_map(params![path.to_string_lossy(), rules], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?))
+        })?;
+        Ok(match rows.next().transpose()? {
+            Some(evaluated) => stat(path) == Some(evaluated),
+            None => false,
+        })
+    }
+
+    /// Forgets all evaluated files, so that they are evaluated again. Returns how many.
+    pub fn clear_evaluated(&self) -> Result<usize> {
+        let cleared = self
+            .connection()
+            .execute("DELETE FROM evaluated", [])
+            .context("Could not clear the evaluated files.")?;
+        Ok(cleared)
+    }
+
+    /// Remembers a file that was given up on
+    pub fn add_failure(&self, rule: &str, path: &Path, reason: &str) -> Result<()> {
+        self.connection()
+            .execute(
+                "INSERT INTO failures (time, rule, path, reason) VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    Local::now().timestamp(),
+                    rule,
+                    path.to_string_lossy(),
+                    reason
+                ],
+            )
+            .context("Could not add failure to the index.")?;
+        Ok(())
+    }
+
+    /// Files that were given up on since the given time, oldest first
+    pub fn failures(&self, since: Option<DateTime<Local>>) -> Result<Vec<Failure>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT time, rule, path, reason FROM failures
+             WHERE ?1 IS NULL OR time >= ?1
+             ORDER BY time",
+        )?;
+        let rows = statement.query_map(params![since.map(|t| t.timestamp())], |row| {
+            Ok(Failure {
+                time: Local
+                    .timestamp_opt(row.get(0)?, 0)
+                    .single()
+                    .unwrap_or_else(Local::now),
+                rule: row.get(1)?,
+                path: PathBuf::from(row.get::<_, String>(2)?),
+                reason: row.get(3)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Where the file that was placed at `destination` most recently came from
+    pub fn origin(&self, destination: &Path) -> Result<Option<PathBuf>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT source FROM files WHERE destination = ?1 ORDER BY time DESC LIMIT 1",
+        )?;
+        let mut rows = statement.query_map(params![destination.to_string_lossy()], |row| {
+            row.get::<_, String>(0)
+        })?;
+        Ok(rows.next().transpose()?.map(PathBuf::from))
+    }
+
+    /// Period and next number of the `{counter}` of a rule
+    pub fn counter(&self, rule: &str) -> Result<Option<(String, u64)>> {
+        let connection = self.connection();
+        let mut statement =
+            connection.prepare("SELECT period, next FROM counters WHERE rule = ?1")?;
+        let mut rows = statement.query_map(params![rule], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+        })?;
+        Ok(rows
+            .next()
+            .transpose()?
+            .map(|(period, next)| (period, next as u64)))
+    }
+
+    pub fn set_counter(&self, rule: &str, period: &str, next: u64) -> Result<()> {
+        self.connection()
+            .execute(
+                "INSERT OR REPLACE INTO counters (rule, period, next) VALUES (?1, ?2, ?3)",
+                params![rule, period, next as i64],
+            )
+            .context(format!("Could not store the counter of rule {}.", rule))?;
+        Ok(())
+    }
+
+    /// Remembers the content of a file that has been placed, to recognize duplicates of it
+    pub fn add_hash(&self, path: &Path, hash: &str) -> Result<()> {
+        let (size, modified) = stat(path).context(format!("Could not read {:?}.", path))?;
+        self.connection()
+            .execute(
+                "INSERT OR REPLACE INTO hashes (path, hash, size, modified)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![path.to_string_lossy(), hash, size, modified],
+            )
+            .context("Could not add hash to the index.")?;
+        Ok(())
+    }
+
+    /// A placed file with this content other than `path`. Files that have been changed since
+    /// are hashed again.
+    pub fn find_duplicate(&self, hash: &str, path: &Path) -> Result<Option<PathBuf>> {
+        let rows = {
+            let connection = self.connection();
+            let mut statement =
+                connection.prepare("SELECT path, size, modified FROM hashes WHERE hash = ?1")?;
+            let rows = statement.query_map(params![hash], |row| {
+                Ok((
+                    PathBuf::from(row.get::<_, String>(0)?),
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            })?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()?
+        };
+        for (placed, size, modified) in rows {
+            if placed == path {
+                continue;
+            }
+            let same = match stat(&placed) {
+                Some(stat) if stat == (size, modified) => return Ok(Some(placed)),
+                Some((current_size, _)) => {
+                    current_size == size && transfer::hash(&placed, Checksum::Sha256)? == hash
+                }
+                None => false,
+            };
+            if same {
+                self.add_hash(&placed, hash)?;
+                return Ok(Some(placed));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Forgets placed files that no longer exist or have been changed. Returns how many.
+    pub fn prune_hashes(&self) -> Result<usize> {
+        let connection = self.connection();
+        let mut statement = connection.prepare("SELECT path, size, modified FROM hashes")?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let mut pruned = 0;
+        for (path, size, modified) in rows {
+            if stat(Path::new(&path)) != Some((size, modified)) {
+                connection.execute("DELETE FROM hashes WHERE path = ?1", params![path])?;
+                pruned += 1;
+            }
+        }
+        Ok(pruned)
+    }
+}
+
+/// Size and modification time of a regular file
+fn stat(path: &Path) -> Option<(i64, i64)> {
+    let metadata = fs::metadata(path).ok().filter(|m| m.is_file())?;
+    let modified = DateTime::<Local>::from(metadata.modified().ok()?);
+    Some((metadata.len() as i64, modified.timestamp()))
+}
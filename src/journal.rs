@@ -0,0 +1,459 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use serde::{Deserialize, Serialize};
+
+use crate::trace::Evaluation;
+use crate::transfer;
+use crate::trash::Trash;
+
+pub const JOURNAL_FILE_NAME: &str = "journal.yaml";
+
+/// One entry of the journal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub id: String,
+    pub at: DateTime<Local>,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum Event {
+    /// A file that was replaced at `to` is in the trash if there is a `replaced` trash ID
+    Move {
+        rule: String,
+        from: PathBuf,
+        to: PathBuf,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        replaced: Option<String>,
+    },
+    /// `size` and `modified` tell whether the copy has changed since
+    Copy {
+        rule: String,
+        from: PathBuf,
+        to: PathBuf,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        replaced: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        size: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        modified: Option<DateTime<Local>>,
+    },
+    /// The file is in the trash if there is a `trash_id`, otherwise it is gone for good
+    Delete {
+        rule: String,
+        from: PathBuf,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        trash_id: Option<String>,
+    },
+    /// The original is in the trash if there is a `trash_id`
+    Compress {
+        rule: String,
+        from: PathBuf,
+        to: PathBuf,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        trash_id: Option<String>,
+    },
+    /// The record with this ID has been reversed
+    Undo { record: String },
+    /// How the rules were evaluated for a file, recorded with `--trace-decisions`
+    Decision {
+        path: PathBuf,
+        rules: Vec<Evaluation>,
+        decision: String,
+    },
+}
+
+impl Event {
+    /// Whether `undo` can reverse it
+    pub fn is_reversible(&self) -> bool {
+        matches!(
+            self,
+            Event::Move { .. } | Event::Copy { .. } | Event::Delete { .. } | Event::Compress { .. }
+        )
+    }
+}
+
+/// Append-only log of every action that has been applied to a file. Every record is a YAML
+/// document of its own.
+pub struct Journal {
+    path: PathBuf,
+}
+
+impl Journal {
+    pub fn open(path: PathBuf) -> Journal {
+        Journal { path }
+    }
+
+    pub fn append(&self, event: Event) -> Result<()> {
+        let at = Local::now();
+        let record = Record {
+            id: at.format("%Y%m%d-%H%M%S-%f").to_string(),
+            at,
+            event,
+        };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .context(format!("Could not open journal {:?}.", &self.path))?;
+        let mut document = serde_yaml::to_string(&record)?;
+        document.push('\n');
+        file.write_all(document.as_bytes())
+            .context(format!("Could not write journal {:?}.", &self.path))
+    }
+
+    /// All records, oldest first
+    pub fn records(&self) -> Result<Vec<Record>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let content = fs::read_to_string(&self.path)
+            .context(format!("Could not read journal {:?}.", &self.path))?;
+
+        let mut records = Vec::new();
+        for document in content.split("---\n").filter(|d| !d.trim().is_empty()) {
+            match serde_yaml::from_str::<Record>(document) {
+                Ok(record) => records.push(record),
+                Err(e) => warn!("Ignoring broken journal record: {}", e),
+            }
+        }
+        Ok(records)
+    }
+
+    /// Moves, copies, deletions and archives that have not been undone yet, newest first.
+    /// Traced decisions and earlier undos cannot be reversed and are left out.
+    pub fn undoable(&self) -> Result<Vec<Record>> {
+        let records = self.records()?;
+        let undone: Vec<&str> = records
+            .iter()
+            .filter_map(|r| match &r.event {
+                Event::Undo { record } => Some(record.as_str()),
+                _ => None,
+            })
+            .collect();
+        let mut undoable: Vec<Record> = records
+            .iter()
+            .filter(|r| r.event.is_reversible() && !undone.contains(&r.id.as_str()))
+            .cloned()
+            .collect();
+        undoable.reverse();
+        Ok(undoable)
+    }
+}
+
+/// Which records `undo` reverses
+#[derive(Debug, Clone, Copy)]
+pub enum Selection {
+    /// The newest ones
+    Last(usize),
+    /// All that were recorded since then
+    Since(DateTime<Local>),
+}
+
+/// A record that `undo` tried to reverse
+#[derive(Debug)]
+pub struct Reversal {
+    pub id: String,
+    pub rule: String,
+    /// Where the file was before the action
+    pub from: PathBuf,
+    pub result: Result<()>,
+}
+
+/// Reverses the selected actions, newest first. Every reversed action is journaled, so that it
+/// is not undone twice.
+pub fn undo(journal: &Journal, trash: &Trash, selection: Selection) -> Result<Vec<Reversal>> {
+    let records = journal.undoable()?;
+    let records: Vec<Record> = match selection {
+        Selection::Since(since) => records.into_iter().filter(|r| r.at >= since).collect(),
+        Selection::Last(last) => records.into_iter().take(last).collect(),
+    };
+
+    let mut reversals = Vec::new();
+    for record in records {
+        let (rule, from, result) = match &record.event {
+            Event::Move {
+                rule,
+                from,
+                to,
+                replaced,
+            } => (
+                rule,
+                from,
+                move_back(from, to).and_then(|_| put_back(trash, replaced.as_deref())),
+            ),
+            Event::Copy {
+                rule,
+                from,
+                to,
+                replaced,
+                size,
+                modified,
+            } => (
+                rule,
+                from,
+                remove_copy(trash, to, *size, *modified)
+                    .and_then(|_| put_back(trash, replaced.as_deref())),
+            ),
+            Event::Delete {
+                rule,
+                from,
+                trash_id: Some(trash_id),
+            } => (rule, from, trash.restore(trash_id, None).map(|_| ())),
+            Event::Delete { rule, from, .. } => (
+                rule,
+                from,
+                Err(anyhow!(
+                    "{:?} was too large for the trash and has been deleted permanently.",
+                    from
+                )),
+            ),
+            Event::Compress {
+                rule,
+                from,
+                to,
+                trash_id,
+            } => (rule, from, undo_compress(trash, to, trash_id.as_deref())),
+            Event::Undo { .. } | Event::Decision { .. } => continue,
+        };
+        if result.is_ok() {
+            journal.append(Event::Undo {
+                record: record.id.clone(),
+            })?;
+        }
+        reversals.push(Reversal {
+            id: record.id.clone(),
+            rule: rule.clone(),
+            from: from.clone(),
+            result,
+        });
+    }
+    Ok(reversals)
+}
+
+/// Moves a sorted file from `to` back to where it came from
+pub(crate) fn move_back(from: &Path, to: &Path) -> Result<()> {
+    if !to.exists() {
+        return Err(anyhow!("{:?} does not exist anymore.", to));
+    }
+    if from.exists() {
+        return Err(anyhow!("{:?} already exists.", from));
+    }
+    if let Some(parent) = from.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    transfer::move_path(to, from, None, None).context(format!("Could not move {:?} back.", to))
+}
+
+/// Size and modification time of a placed file, to recognize it later
+pub(crate) fn stamp(path: &Path) -> Result<(u64, DateTime<Local>)> {
+    let modified = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .context(format!("Could not read modification time of {:?}.", path))?;
+    let size = transfer::size(path).context(format!("Could not read size of {:?}.", path))?;
+    Ok((size, modified.into()))
+}
+
+/// Removes a copy, unless it has changed since it was made. Copies of records without a stamp
+/// cannot be recognized and are moved to the trash instead.
+fn remove_copy(
+    trash: &Trash,
+    copy: &Path,
+    size: Option<u64>,
+    modified: Option<DateTime<Local>>,
+) -> Result<()> {
+    if !copy.exists() {
+        return Err(anyhow!("{:?} does not exist anymore.", copy));
+    }
+    let (size, modified) = match (size, modified) {
+        (Some(size), Some(modified)) => (size, modified),
+        _ => {
+            trash
+                .stage(copy)
+                .context(format!("Could not remove the copy {:?}.", copy))?;
+            return Ok(());
+        }
+    };
+    if stamp(copy)? != (size, modified) {
+        return Err(anyhow!(
+            "{:?} has changed since it was copied and was left in place.",
+            copy
+        ));
+    }
+    transfer::remove_path(copy).context(format!("Could not remove the copy {:?}.", copy))
+}
+
+/// Restores the file that an action replaced
+pub(crate) fn put_back(trash: &Trash, replaced: Option<&str>) -> Result<()> {
+    if let Some(trash_id) = replaced {
+        trash
+            .restore(trash_id, None)
+            .context("Could not restore the replaced file.")?;
+    }
+    Ok(())
+}
+
+/// Restores the original, if it was deleted, and removes the archive
+fn undo_compress(trash: &Trash, archive: &Path, trash_id: Option<&str>) -> Result<()> {
+    if let Some(trash_id) = trash_id {
+        trash.restore(trash_id, None)?;
+    }
+    transfer::remove_path(archive).context(format!("Could not remove the archive {:?}.", archive))
+}
+
+/// Accepts a duration that lies in the past (`2h`, `1day`) or a local time
+/// (`2021-03-14 15:00`)
+pub fn parse_time(since: &str) -> Result<DateTime<Local>> {
+    if let Ok(duration) = humantime::parse_duration(since) {
+        return Ok(Local::now() - chrono::Duration::from_std(duration)?);
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(since) {
+        return Ok(time.with_timezone(&Local));
+    }
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(time) = NaiveDateTime::parse_from_str(since, format) {
+            return Local
+                .from_local_datetime(&time)
+                .single()
+                .context(format!("{:?} is ambiguous.", since));
+        }
+    }
+    Err(anyhow!(
+        "{:?} is neither a duration like 2h nor a time like \"2021-03-14 15:00\".",
+        since
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::trash::TrashSettings;
+
+    use super::*;
+
+    fn decision(path: &Path) -> Event {
+        Event::Decision {
+            path: path.to_path_buf(),
+            rules: Vec::new(),
+            decision: String::from("no rule matched"),
+        }
+    }
+
+    fn open(dir: &Path) -> (Journal, Trash) {
+        let journal = Journal::open(dir.join(JOURNAL_FILE_NAME));
+        let trash = Trash::open(dir.join("trash"), TrashSettings::default()).unwrap();
+        (journal, trash)
+    }
+
+    #[test]
+    fn traced_decisions_are_not_undone() {
+        let dir = tempfile::tempdir().unwrap();
+        let (journal, trash) = open(dir.path());
+        let from = dir.path().join("report.pdf");
+        let to = dir.path().join("sorted.pdf");
+        fs::write(&to, "report").unwrap();
+
+        journal.append(decision(&from)).unwrap();
+        journal
+            .append(Event::Move {
+                rule: String::from("*.pdf"),
+                from: from.clone(),
+                to: to.clone(),
+                replaced: None,
+            })
+            .unwrap();
+        journal.append(decision(&dir.path().join("a.txt"))).unwrap();
+        journal.append(decision(&dir.path().join("b.txt"))).unwrap();
+
+        assert_eq!(journal.undoable().unwrap().len(), 1);
+        let reversals = undo(&journal, &trash, Selection::Last(1)).unwrap();
+        assert_eq!(reversals.len(), 1);
+        assert!(reversals[0].result.is_ok());
+        assert_eq!(reversals[0].from, from);
+        assert!(from.exists());
+        assert!(!to.exists());
+        assert!(journal.undoable().unwrap().is_empty());
+    }
+
+    #[test]
+    fn replaced_files_come_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let (journal, trash) = open(dir.path());
+        let from = dir.path().join("report.pdf");
+        let to = dir.path().join("sorted.pdf");
+        fs::write(&to, "old").unwrap();
+        let replaced = trash.stage(&to).unwrap().id;
+        fs::write(&to, "new").unwrap();
+        journal
+            .append(Event::Move {
+                rule: String::from("*.pdf"),
+                from: from.clone(),
+                to: to.clone(),
+                replaced: Some(replaced),
+            })
+            .unwrap();
+
+        let reversals = undo(&journal, &trash, Selection::Last(1)).unwrap();
+        assert!(reversals[0].result.is_ok());
+        assert_eq!(fs::read_to_string(&from).unwrap(), "new");
+        assert_eq!(fs::read_to_string(&to).unwrap(), "old");
+        assert!(trash.entries().unwrap().is_empty());
+    }
+
+    #[test]
+    fn changed_copies_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let (journal, trash) = open(dir.path());
+        let from = dir.path().join("report.pdf");
+        let to = dir.path().join("copy.pdf");
+        fs::write(&from, "report").unwrap();
+        fs::write(&to, "report").unwrap();
+        let copy = |(size, modified)| Event::Copy {
+            rule: String::from("*.pdf"),
+            from: from.clone(),
+            to: to.clone(),
+            replaced: None,
+            size: Some(size),
+            modified: Some(modified),
+        };
+        journal.append(copy(stamp(&to).unwrap())).unwrap();
+        fs::write(&to, "edited report").unwrap();
+
+        let reversals = undo(&journal, &trash, Selection::Last(1)).unwrap();
+        assert!(reversals[0].result.is_err());
+        assert_eq!(fs::read_to_string(&to).unwrap(), "edited report");
+        assert_eq!(journal.undoable().unwrap().len(), 1);
+
+        fs::write(&to, "report").unwrap();
+        journal.append(copy(stamp(&to).unwrap())).unwrap();
+        let reversals = undo(&journal, &trash, Selection::Last(1)).unwrap();
+        assert!(reversals[0].result.is_ok());
+        assert!(!to.exists());
+        assert!(from.exists());
+    }
+
+    #[test]
+    fn deleted_files_come_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let (journal, trash) = open(dir.path());
+        let path = dir.path().join("report.pdf");
+        fs::write(&path, "report").unwrap();
+        let trash_id = trash.stage(&path).unwrap().id;
+        journal
+            .append(Event::Delete {
+                rule: String::from("*.pdf"),
+                from: path.clone(),
+                trash_id: Some(trash_id),
+            })
+            .unwrap();
+
+        let reversals = undo(&journal, &trash, Selection::Last(1)).unwrap();
+        assert!(reversals[0].result.is_ok());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "report");
+    }
+}
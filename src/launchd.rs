@@ -0,0 +1,112 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result};
+
+use crate::config;
+
+const LABEL: &str = "io.github.gonsor.wurmloch";
+
+/// Writes a LaunchAgent that runs wurmloch on the given directory whenever the user logs in
+/// and loads it right away
+pub fn install(watch_dir: &Path) -> Result<()> {
+    let watch_dir = watch_dir
+        .canonicalize()
+        .context(format!("Could not find {:?}.", watch_dir))?;
+    config::ensure_confirmed()?;
+    let exe = env::current_exe().context("Could not determine the path of wurmloch.")?;
+    let home = dirs::home_dir().context("Could not determine the home directory.")?;
+    let log_dir = home.join("Library").join("Logs");
+    fs::create_dir_all(&log_dir).context(format!("Could not create {:?}.", &log_dir))?;
+    let log_path = log_dir.join("wurmloch.log");
+
+    let plist_path = plist_path()?;
+    if plist_path.exists() {
+        // Replaces an agent with an older watch directory or binary
+        launchctl(&["unload", "-w"], &plist_path)?;
+    }
+    let plist = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{label}</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>{exe}</string>
+        <string>{dir}</string>
+    </array>
+    <key>EnvironmentVariables</key>
+    <dict>
+        <key>WURMLOCH_LOG</key>
+        <string>info</string>
+    </dict>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+    <key>StandardOutPath</key>
+    <string>{log}</string>
+    <key>StandardErrorPath</key>
+    <string>{log}</string>
+</dict>
+</plist>
+"#,
+        label = LABEL,
+        exe = escape(&exe),
+        dir = escape(&watch_dir),
+        log = escape(&log_path),
+    );
+    fs::write(&plist_path, plist).context(format!("Could not write {:?}.", &plist_path))?;
+    println!("Created {}", plist_path.display());
+
+    launchctl(&["load", "-w"], &plist_path)?;
+    println!("wurmloch is running now and starts whenever you log in.");
+    println!("Its log is written to {}", log_path.display());
+    Ok(())
+}
+
+/// Stops wurmloch and removes the LaunchAgent
+pub fn uninstall() -> Result<()> {
+    let plist_path = plist_path()?;
+    if !plist_path.exists() {
+        return Err(anyhow!("There is no LaunchAgent at {:?}.", &plist_path));
+    }
+    launchctl(&["unload", "-w"], &plist_path)?;
+    fs::remove_file(&plist_path).context(format!("Could not remove {:?}.", &plist_path))?;
+    println!("Removed {}", plist_path.display());
+    Ok(())
+}
+
+fn plist_path() -> Result<PathBuf> {
+    let home = dirs::home_dir().context("Could not determine the home directory.")?;
+    let agent_dir = home.join("Library").join("LaunchAgents");
+    fs::create_dir_all(&agent_dir).context(format!("Could not create {:?}.", &agent_dir))?;
+    Ok(agent_dir.join(format!("{}.plist", LABEL)))
+}
+
+fn escape(path: &Path) -> String {
+    path.display()
+        .to_string()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn launchctl(args: &[&str], plist_path: &Path) -> Result<()> {
+    let status = Command::new("launchctl")
+        .args(args)
+        .arg(plist_path)
+        .status()
+        .context("Could not run launchctl.")?;
+    if !status.success() {
+        return Err(anyhow!("launchctl {} failed.", args.join(" ")));
+    }
+    Ok(())
+}
@@ -0,0 +1,3025 @@
+//! The sorting engine of wurmloch. [`run`] turns a directory into a wormhole and sorts
+//! everything that is dropped on it according to the rule file. [`Sorter`] and [`handle_file`]
+//! sort single files, e.g. in other programs or in tests.
+
+#[macro_use]
+extern crate log;
+
+#[macro_use]
+extern crate anyhow;
+
+use std::collections::{BTreeMap, HashMap};
+use std::env::consts::OS;
+use std::ffi::OsStr;
+use std::fs;
+use std::iter;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use globset::{Glob, GlobMatcher};
+use indicatif::{ProgressBar, ProgressStyle};
+use notify::event::{AccessKind, AccessMode, ModifyKind, RenameMode};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode};
+use notify_debouncer_full::{
+    new_debouncer, DebounceEventResult, DebouncedEvent, Debouncer, RecommendedCache,
+};
+use regex::Regex;
+
+pub use action::Action;
+use action::{ActionSettings, Delete, Move, OnConflict};
+use attributes::Attributes;
+pub use config::{Config, ConfigRule, RuleType};
+use contents::ContentsCondition;
+use control::RuleInfo;
+use counter::Counter;
+use email::{Alerts, Email, Failure};
+use errors::{ErrorClass, ErrorPolicy};
+use history::History;
+use index::{Index, OnDuplicate};
+use journal::{Event, Journal};
+use matching::Patterns;
+use mqtt::Mqtt;
+use notifications::{Desktop, NotificationSettings};
+pub use output::Output;
+use output::Printer;
+use ownership::Ownership;
+pub use plugin::Registry;
+use poll::{Backend, Poller};
+use provenance::Provenance;
+use quarantine::{Blocked, Check};
+use quirks::Quirks;
+use retry::{RetryQueue, RetrySettings};
+use schedule::Schedule;
+use shard::Layout;
+use space::OnFull;
+use template::Template;
+use throttle::Throttle;
+use transfer::Checksum;
+use trash::{Trash, TrashSettings};
+use video::VideoConditions;
+use volumes::Volumes;
+use webhook::{Webhook, WebhookEvent};
+use window::TimeWindow;
+use workers::Pool;
+
+pub mod action;
+mod analysis;
+pub mod api;
+mod archive;
+mod attributes;
+mod audio;
+mod audit;
+pub mod config;
+mod contents;
+pub mod control;
+pub mod counter;
+#[cfg(unix)]
+pub mod daemon;
+#[cfg(target_os = "linux")]
+pub mod dbus;
+pub mod document;
+pub mod email;
+pub mod errors;
+pub mod history;
+mod http;
+pub mod import;
+pub mod index;
+pub mod journal;
+pub mod launchd;
+mod lock;
+pub mod logging;
+pub mod matching;
+pub mod metrics;
+pub mod mqtt;
+pub mod normalize;
+pub mod notifications;
+pub mod output;
+mod ownership;
+mod photo;
+mod planning;
+pub mod plugin;
+pub mod poll;
+pub mod presets;
+pub mod provenance;
+mod quarantine;
+mod quirks;
+pub mod rclone;
+pub mod retry;
+pub mod s3;
+mod scan;
+pub mod schedule;
+pub mod secrets;
+pub mod shard;
+mod space;
+pub mod stats;
+pub mod suggest;
+pub mod systemd;
+pub mod template;
+pub mod throttle;
+pub mod trace;
+pub mod transfer;
+pub mod trash;
+#[cfg(all(feature = "tray", target_os = "linux"))]
+pub mod tray;
+pub mod tui;
+pub mod units;
+pub mod update;
+pub mod video;
+pub mod volumes;
+mod watch_limit;
+pub mod webdav;
+pub mod webhook;
+pub mod window;
+mod workers;
+
+pub const APP_NAME: &str = "Wurmloch";
+const RULES_FILE_NAME: &str = "rules.yaml";
+
+/// How a directory is watched and sorted
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// This directory will be turned into a wormhole
+    pub watch_dir: PathBuf,
+    /// React to file events after this delay
+    pub watch_delay: Duration,
+    /// Only log what would happen to files without changing anything
+    pub dry_run: bool,
+    /// Start even if another wurmloch already watches the same directory
+    pub force: bool,
+    /// Serve Prometheus metrics on this address
+    pub metrics_addr: Option<SocketAddr>,
+    /// Only warn about rules that would move files in circles instead of refusing them
+    pub permissive: bool,
+    /// Also react to files in subdirectories of the watch directory
+    pub recursive: bool,
+    /// Confirm newly created rules without asking
+    pub yes: bool,
+    /// Number of files that are sorted at the same time
+    pub workers: usize,
+    /// Number of files that can wait for a worker. Events for further files are dropped and
+    /// the files are picked up by a rescan.
+    pub queue_size: usize,
+    /// How the watch directory learns about new files
+    pub backend: Backend,
+    /// How often the `poll` backend reads the watch directory
+    pub poll_interval: Duration,
+    /// Wait for the watch directory to appear instead of failing if it does not exist
+    pub wait_for_dir: bool,
+    /// Raise `fs.inotify.max_user_watches` when the watch directory needs more watches
+    pub raise_watch_limit: bool,
+    /// Never delete or replace files, regardless of the rule file
+    pub safe: bool,
+    /// Log and journal how the rules were evaluated for every file
+    pub trace_decisions: bool,
+    /// What is printed for every file apart from the log
+    pub output: Output,
+}
+
+impl Options {
+    /// Options with the same defaults as the command line
+    pub fn new(watch_dir: PathBuf) -> Options {
+        Options {
+            watch_dir,
+            watch_delay: Duration::from_secs(2),
+            dry_run: false,
+            force: false,
+            metrics_addr: None,
+            permissive: false,
+            recursive: true,
+            yes: false,
+            workers: 4,
+            queue_size: 10_000,
+            backend: Backend::Auto,
+            poll_interval: Duration::from_secs(10),
+            wait_for_dir: false,
+            raise_watch_limit: false,
+            safe: false,
+            trace_decisions: false,
+            output: Output::Human,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Rule {
+    /// Name of the rule or its pattern
+    pub name: String,
+    pub matcher: GlobMatcher,
+    pub kind: RuleType,
+    pub video: VideoConditions,
+    /// Matched against the text of documents
+    pub content_matches: Option<Regex>,
+    /// Matched against the beginning of plain-text files
+    pub contents_match: Option<ContentsCondition>,
+    pub action: Box<dyn Action>,
+    /// The same action with the other targets, tried in order if the target of `action` is
+    /// unavailable
+    pub failover: Vec<Box<dyn Action>>,
+    /// Numbers for `{counter}` in the new name, shared by all targets
+    pub counter: Option<Arc<Counter>>,
+    /// Files with the same content as a file that has been placed before are handled
+    /// differently
+    pub on_duplicate: Option<OnDuplicate>,
+    /// Record where sorted files came from
+    pub provenance: Option<Provenance>,
+    pub limit: Option<BatchLimit>,
+    pub reserve: Option<SpaceReserve>,
+    /// How long matching files have to stay unchanged, instead of the watch delay
+    pub settle: Option<Duration>,
+    /// When the rule sorts files. Empty means always.
+    pub active_during: Vec<TimeWindow>,
+    /// Only files that were last modified longer ago match
+    pub older_than: Option<Duration>,
+    /// Identifies rules that delete files by age, which only do so once they have been
+    /// confirmed
+    pub cleanup: Option<String>,
+    /// Show desktop notifications
+    pub notify: bool,
+    /// Only log what the rule would do
+    pub dry_run: bool,
+}
+
+impl Rule {
+    /// Summary for `wurmloch status` and the API
+    fn info(&self) -> RuleInfo {
+        RuleInfo {
+            name: self.name.clone(),
+            pattern: self.matcher.glob().glob().to_string(),
+            action: self.action.name().to_string(),
+            target: self.action.target().map(Template::to_string),
+            dry_run: self.dry_run,
+        }
+    }
+
+    /// Whether the rule applies to the file. Conditions on the content are only checked if the
+    /// name matches.
+    pub fn matches(&self, path: &Path, kind: RuleType) -> bool {
+        path.file_name().is_some_and(|f| self.matcher.is_match(f))
+            && self.conditions_met(path, kind)
+    }
+
+    /// Whether the rule applies to a file whose name matches the pattern
+    fn conditions_met(&self, path: &Path, kind: RuleType) -> bool {
+        self.kind == kind
+            && self.video.are_met(path)
+            && self
+                .content_matches
+                .as_ref()
+                .is_none_or(|regex| document::content_matches(path, regex))
+            && self
+                .contents_match
+                .as_ref()
+                .is_none_or(|condition| condition.is_met(path))
+            && self.older_than.is_none_or(|age| is_older(path, age))
+    }
+
+    /// Each condition of the rule and whether the file meets it, for `--trace-decisions`
+    fn checks(&self, path: &Path, kind: RuleType) -> Vec<(&'static str, bool)> {
+        let mut checks = vec![("type", self.kind == kind)];
+        if !self.video.is_empty() {
+            checks.push(("video", self.video.are_met(path)));
+        }
+        if let Some(regex) = &self.content_matches {
+            checks.push(("content_matches", document::content_matches(path, regex)));
+        }
+        if let Some(condition) = &self.contents_match {
+            checks.push(("contents_match", condition.is_met(path)));
+        }
+        if let Some(age) = self.older_than {
+            checks.push(("older_than", is_older(path, age)));
+        }
+        checks
+    }
+}
+
+/// What happens to a file after all attempts failed
+#[derive(Debug)]
+enum OnFailure {
+    Continue,
+    Halt,
+    Divert(Box<dyn Action>),
+}
+
+/// Limits how many files a rule accepts per batch
+#[derive(Debug)]
+pub struct BatchLimit {
+    pub max: usize,
+    /// Applied to files beyond the limit. Without it, they are deferred to the next batch.
+    pub overflow: Option<Box<dyn Action>>,
+}
+
+/// Keeps free space on the filesystems that a rule writes to
+#[derive(Debug)]
+pub struct SpaceReserve {
+    /// Bytes that stay free after a file has been written
+    pub min_free: u64,
+    pub on_full: OnFull,
+    /// Applied instead if the target of the rule is full and `on_full` names another target
+    pub alternate: Option<Box<dyn Action>>,
+}
+
+/// State that lives as long as wurmloch is watching
+#[derive(Default)]
+pub struct Session {
+    batch: Batch,
+    retries: RetryQueue,
+    control: Arc<control::State>,
+    /// Files that arrived while sorting was paused
+    held: Vec<PathBuf>,
+    /// Files that are still being written and when to look at them again
+    settling: HashMap<PathBuf, Instant>,
+    /// Files that arrived outside the active hours of their rule and when these begin
+    waiting: HashMap<PathBuf, DateTime<Local>>,
+    /// When files were sorted, to ignore repeated events for them
+    recent: HashMap<PathBuf, SystemTime>,
+    desktop: Desktop,
+    alerts: Alerts,
+    printer: Printer,
+    /// Emits signals for sorted and failed files
+    #[cfg(target_os = "linux")]
+    bus: Option<dbus::Bus>,
+}
+
+impl Session {
+    /// A session that can be paused, stopped and queried through `control`
+    pub fn new(control: Arc<control::State>) -> Session {
+        Session {
+            control,
+            ..Session::default()
+        }
+    }
+
+    /// Files that have been left alone long enough to look at them again
+    fn settled(&mut self) -> Vec<PathBuf> {
+        let now = Instant::now();
+        let settled: Vec<PathBuf> = self
+            .settling
+            .iter()
+            .filter(|(_, due)| **due <= now)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in &settled {
+            self.settling.remove(path);
+        }
+        settled
+    }
+
+    fn next_settled(&self) -> Option<Instant> {
+        self.settling.values().min().copied()
+    }
+
+    /// Files whose active hours have begun
+    fn opened(&mut self) -> Vec<PathBuf> {
+        let now = Local::now();
+        let opened: Vec<PathBuf> = self
+            .waiting
+            .iter()
+            .filter(|(_, opens)| **opens <= now)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in &opened {
+            self.waiting.remove(path);
+        }
+        opened
+    }
+
+    fn next_opened(&self) -> Option<Instant> {
+        let opens = *self.waiting.values().min()?;
+        let wait = (opens - Local::now()).to_std().unwrap_or_default();
+        // The clock may jump, e.g. after the computer woke up
+        Some(Instant::now() + wait.min(Duration::from_secs(60)))
+    }
+}
+
+/// Files that were handled since the watch directory was quiet for the last time
+#[derive(Default)]
+struct Batch {
+    /// Number of files accepted per rule index
+    accepted: HashMap<usize, usize>,
+    deferred: Vec<PathBuf>,
+}
+
+/// Applied to files that match no rule
+#[derive(Debug)]
+struct Fallback {
+    action: Box<dyn Action>,
+    /// Record where the file came from
+    quarantine: bool,
+    on_duplicate: Option<OnDuplicate>,
+    provenance: Option<Provenance>,
+}
+
+/// Everything that is derived from the configuration to sort files
+pub struct Sorter {
+    rules: Vec<Rule>,
+    /// Patterns of all rules, in the same order
+    patterns: Patterns,
+    fallback: Option<Fallback>,
+    retry: RetrySettings,
+    notifications: NotificationSettings,
+    webhook: Option<Webhook>,
+    mqtt: Option<Mqtt>,
+    email: Option<Email>,
+    /// Applied to files whose action failed in every attempt
+    error_action: Option<Box<dyn Action>>,
+    /// What happens to files that could not be sorted, by the class of the error
+    on_error: Vec<(ErrorClass, OnFailure)>,
+    quirks: Quirks,
+    trash: Trash,
+    /// Limits of files and bytes per target device
+    throttle: Throttle,
+    journal: Journal,
+    index: Index,
+    history: Option<History>,
+    dry_run: bool,
+    /// Files are never deleted or replaced
+    safe: bool,
+    trace_decisions: bool,
+    /// How long files have to stay unchanged unless their rule says otherwise
+    watch_delay: Duration,
+    /// How long repeated events for a sorted file are ignored
+    cooldown: Duration,
+    /// When files are sorted at all. Empty means always.
+    active_during: Vec<TimeWindow>,
+    /// Subdirectory levels below the watch directory whose files are sorted
+    max_depth: Option<usize>,
+    /// Targets inside the watch directory. Their content is never sorted again.
+    excluded: Vec<PathBuf>,
+    /// Identifies the rules and the fallback, so that files that were left in place are evaluated
+    /// again once they change
+    fingerprint: String,
+    /// Where files go that a safety check refused
+    quarantine_dir: PathBuf,
+}
+
+impl Sorter {
+    pub fn new(config: &Config, registry: &Registry, options: &Options) -> Result<Sorter> {
+        Sorter::with_data_dir(config, registry, options, &data_dir()?)
+    }
+
+    /// Like `new`, with the trash, the journal, the index and the quarantine in `data_dir`
+    fn with_data_dir(
+        config: &Config,
+        registry: &Registry,
+        options: &Options,
+        data_dir: &Path,
+    ) -> Result<Sorter> {
+        let safe = options.safe || config.safe;
+        let rules = parse_rules(config, registry, safe);
+        let sorter = Sorter {
+            patterns: Patterns::new(rules.iter().map(|rule| rule.matcher.glob()))?,
+            rules,
+            fallback: parse_fallback(config, registry).map(|mut fallback| {
+                if safe && fallback.on_duplicate == Some(OnDuplicate::DeleteSource) {
+                    warn!("Safe mode: the fallback skips duplicates instead of deleting them.");
+                    fallback.on_duplicate = Some(OnDuplicate::Skip);
+                }
+                fallback
+            }),
+            retry: config.retry.clone(),
+            notifications: config.notifications.clone(),
+            webhook: config.notifications.webhook.clone().map(Webhook::start),
+            mqtt: config
+                .notifications
+                .mqtt
+                .clone()
+                .map(Mqtt::start)
+                .transpose()?,
+            email: config.notifications.email.clone().map(Email::start),
+            error_action: match &config.retry.error_target {
+                Some(target) => parse_move(target, true, config.verify, config, registry),
+                None => None,
+            },
+            on_error: ErrorClass::ALL
+                .iter()
+                .map(|class| (*class, parse_on_error(config, registry, *class)))
+                .collect(),
+            quirks: Quirks::new(&config.disabled_quirks),
+            trash: Trash::open(data_dir.join(trash::TRASH_DIR_NAME), config.trash.clone())?,
+            throttle: Throttle::new(config.max_files_per_minute, config.max_bytes_per_second),
+            journal: Journal::open(data_dir.join(journal::JOURNAL_FILE_NAME)),
+            index: Index::open(&data_dir.join(index::INDEX_FILE_NAME))?,
+            history: config
+                .history
+                .as_ref()
+                .map(|settings| History::open(settings, data_dir)),
+            dry_run: options.dry_run,
+            safe,
+            trace_decisions: options.trace_decisions,
+            watch_delay: options.watch_delay,
+            cooldown: config.cooldown.unwrap_or(Duration::from_secs(10)),
+            active_during: config.active_during.clone(),
+            max_depth: if options.recursive {
+                config.max_depth
+            } else {
+                Some(0)
+            },
+            excluded: Vec::new(),
+            fingerprint: audit::fingerprint(&(&config.rules, &config.fallback_target)),
+            quarantine_dir: match &config.quarantine_dir {
+                Some(dir) => dir.clone(),
+                None => data_dir.join(quarantine::QUARANTINE_DIR_NAME),
+            },
+        }
+        .exclude_nested_targets(&options.watch_dir);
+        for counter in sorter.rules.iter().filter_map(|rule| rule.counter.as_ref()) {
+            counter.load(&sorter.index)?;
+        }
+
+        analysis::check(&sorter, &options.watch_dir, options.permissive)?;
+        Ok(sorter)
+    }
+
+    /// Valid rules in the order of their priority
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Targets of all actions that have one, named after the rule they belong to
+    fn move_targets(&self) -> Vec<(String, &Template)> {
+        let mut actions: Vec<(String, &dyn Action)> = Vec::new();
+        for rule in self.rules.iter() {
+            let pattern = rule.name.clone();
+            if let Some(overflow) = rule.limit.as_ref().and_then(|l| l.overflow.as_ref()) {
+                actions.push((pattern.clone(), overflow.as_ref()));
+            }
+            if let Some(alternate) = rule.reserve.as_ref().and_then(|r| r.alternate.as_ref()) {
+                actions.push((pattern.clone(), alternate.as_ref()));
+            }
+            for failover in rule.failover.iter() {
+                actions.push((pattern.clone(), failover.as_ref()));
+            }
+            actions.push((pattern, rule.action.as_ref()));
+        }
+        if let Some(fallback) = &self.fallback {
+            actions.push((String::from("fallback"), fallback.action.as_ref()));
+        }
+        if let Some(error_action) = &self.error_action {
+            actions.push((String::from("error"), error_action.as_ref()));
+        }
+        for (_, on_failure) in self.on_error.iter() {
+            if let OnFailure::Divert(action) = on_failure {
+                actions.push((String::from("error"), action.as_ref()));
+            }
+        }
+
+        actions
+            .into_iter()
+            .filter_map(|(name, action)| action.target().map(|target| (name, target)))
+            .collect()
+    }
+
+    /// Excludes all targets inside the watch directory from sorting, so that moved files are
+    /// not picked up again
+    fn exclude_nested_targets(mut self, watch_dir: &Path) -> Sorter {
+        let mut excluded = Vec::new();
+        for (_, target) in self.move_targets() {
+            let dir = target.static_dir();
+            if dir != watch_dir && dir.starts_with(watch_dir) && !excluded.contains(&dir) {
+                info!(
+                    "Target {:?} is inside the watch directory. Its content is not sorted.",
+                    &dir
+                );
+                excluded.push(dir);
+            }
+        }
+        self.excluded = excluded;
+        self
+    }
+
+    /// Whether a path is inside a target in the watch directory
+    fn is_excluded(&self, path: &Path) -> bool {
+        self.excluded.iter().any(|dir| path.starts_with(dir))
+    }
+
+    /// The rule with the highest priority that matches
+    pub fn find_rule(&self, path: &Path, kind: RuleType) -> Option<&Rule> {
+        self.matching_rules(path, kind).next().map(|(_, rule)| rule)
+    }
+
+    /// All rules that apply to the file with their index, highest priority first. Only rules
+    /// whose pattern matches are checked further.
+    fn matching_rules<'a: 'p, 'p>(
+        &'a self,
+        path: &'p Path,
+        kind: RuleType,
+    ) -> impl Iterator<Item = (usize, &'a Rule)> + 'p {
+        let candidates = match path.file_name() {
+            Some(name) => self.patterns.matching(name),
+            None => Vec::new(),
+        };
+        candidates
+            .into_iter()
+            .map(move |index| (index, &self.rules[index]))
+            .filter(move |(_, rule)| rule.conditions_met(path, kind))
+    }
+
+    /// Shortest time that files have to stay unchanged for any rule. The watch directory
+    /// reports changes after this delay.
+    fn shortest_settle(&self) -> Duration {
+        self.rules
+            .iter()
+            .filter_map(|rule| rule.settle)
+            .fold(self.watch_delay, Duration::min)
+    }
+
+    /// Whether a path below the watch directory is nested deeper than `max_depth`
+    fn is_too_deep(&self, watch_dir: &Path, path: &Path) -> bool {
+        match (self.max_depth, path.strip_prefix(watch_dir)) {
+            (Some(max_depth), Ok(relative)) => relative.components().count() > max_depth + 1,
+            _ => false,
+        }
+    }
+}
+
+/// Sorts new files until `control` is stopped
+pub fn run(options: &Options, registry: Registry, control: Arc<control::State>) -> Result<()> {
+    let watch_dir = &options.watch_dir;
+    let mut systemd = systemd::Notifier::from_env();
+    if options.wait_for_dir && watch_dir.is_absolute() {
+        wait_for_directory(watch_dir, &mut systemd);
+    }
+    check_watch_directory(watch_dir)?;
+    let _lock = lock::acquire(&data_dir()?, watch_dir, options.force)?;
+
+    let (config_path, _) = config::create_if_missing()?;
+    let mut config = config::load(&config_path)?;
+    let mut sorter = Arc::new(Sorter::new(&config, &registry, options)?);
+    // Until the first start has been confirmed, example rules may still be in place
+    if !options.dry_run
+        && !audit::rules_confirmed()
+        && !audit::confirm_first_run(&sorter, watch_dir, options.yes)?
+    {
+        return Ok(());
+    }
+    sorter.trash.purge()?;
+    let mut session = Session::new(control);
+    session.printer = Printer::new(options.output, None);
+    let mut pool = Pool::new(options.workers, options.queue_size);
+    let (tx, rx) = channel();
+
+    // Start watching
+    let watch_delay = options.watch_delay;
+    let mut _conf_watchers = watch_config(&tx, &config, watch_delay);
+    // Rules that settle faster than the watch delay need to hear about files earlier
+    let mut settle = sorter.shortest_settle();
+    let backend = match options.backend {
+        Backend::Auto => match poll::network_filesystem(watch_dir) {
+            Some(filesystem) => {
+                warn!(
+                    "{:?} is on a {} share. Changes made by other machines are not reported, \
+                     so wurmloch looks for new files every {:?} instead. Start it with \
+                     --backend native to rely on notifications anyway.",
+                    watch_dir, filesystem, options.poll_interval
+                );
+                Backend::Poll
+            }
+            None => Backend::Native,
+        },
+        backend => backend,
+    };
+    let (mut _dir_watcher, mut poller) = watch_wormhole(&tx, &sorter, options, backend, settle);
+    // Folders that got no inotify watch because the limit was reached
+    let mut unwatched: Vec<Poller> = Vec::new();
+    let mut watch_limit_reported = false;
+    let mut next_rescan = config.rescan.as_ref().and_then(Schedule::next_instant);
+    // Changes when the directory is removed or replaced, e.g. by unmounting its drive
+    let mut identity = dir_identity(watch_dir);
+    let mut volumes = Volumes::default();
+    let mut volume_watchers = HashMap::new();
+
+    {
+        let mut status = session.control.status();
+        status.watched = vec![watch_dir.clone()];
+        status.rules = sorter.rules.iter().map(Rule::info).collect();
+    }
+    let _control_server =
+        control::Server::start(&data_dir()?, watch_dir, Arc::clone(&session.control))?;
+    #[cfg(unix)]
+    control::handle_signals(Arc::clone(&session.control))?;
+    #[cfg(target_os = "linux")]
+    {
+        session.bus = match dbus::Bus::start(Arc::clone(&session.control)) {
+            Ok(bus) => Some(bus),
+            Err(e) => {
+                info!("The D-Bus interface is not available: {:#}", e);
+                None
+            }
+        };
+    }
+    if let Some(addr) = options.metrics_addr {
+        metrics::serve(addr, Arc::clone(&session.control))?;
+    }
+    if let Some(api) = &config.api {
+        api::serve(api, Arc::clone(&session.control))?;
+    }
+
+    // Stop between two events, so that no file is left half-copied
+    let control = Arc::clone(&session.control);
+    ctrlc::set_handler(move || control.stop()).context("Could not install the signal handler.")?;
+
+    systemd.ready();
+    let mut last_event = Instant::now();
+    while !session.control.is_stopping() {
+        let current = dir_identity(watch_dir);
+        if current != identity {
+            match (identity, current) {
+                (_, None) => {
+                    // The watcher does not notice when the directory comes back
+                    warn!(
+                        "{:?} has disappeared. Waiting for it to come back ...",
+                        watch_dir
+                    );
+                    _dir_watcher = None;
+                    poller = None;
+                    unwatched.clear();
+                }
+                (previous, Some(_)) => {
+                    if previous.is_some() {
+                        info!("{:?} has been replaced. Watching it again.", watch_dir);
+                    } else {
+                        info!("{:?} is back. Watching it again.", watch_dir);
+                    }
+                    settle = sorter.shortest_settle();
+                    (_dir_watcher, poller) = watch_wormhole(&tx, &sorter, options, backend, settle);
+                    unwatched.clear();
+                }
+            }
+            identity = current;
+        }
+        for change in volumes.check(&config.volumes) {
+            match change {
+                volumes::Change::Attached(name, dir) => {
+                    info!("{} is mounted at {:?}. Sorting its files ...", name, dir);
+                    let mode = recursive_mode(options);
+                    volume_watchers
+                        .insert(dir.clone(), watch(Sender::clone(&tx), &dir, mode, settle));
+                    dispatch_all(&mut pool, &sorter, &mut session, &dir);
+                }
+                volumes::Change::Detached(name, dir) => {
+                    info!("{} has been unmounted.", name);
+                    volume_watchers.remove(&dir);
+                }
+            }
+        }
+        for (task, result) in pool.finished() {
+            finish(&sorter, &mut session, task, result);
+        }
+        for name in volumes.imported(|dir| pool.is_busy_below(dir)) {
+            info!("All files of {} have been sorted. It can be removed.", name);
+        }
+        if let Some(since) = pool.take_overflow() {
+            rescan(&mut pool, &sorter, watch_dir, since, watch_delay);
+        }
+        start(&mut pool, &sorter, &mut session);
+        systemd.keepalive();
+        systemd.status(service_status(&session));
+        {
+            let mut status = session.control.status();
+            status.queued = session.batch.deferred.len()
+                + session.retries.len()
+                + session.held.len()
+                + session.settling.len()
+                + session.waiting.len();
+            status.work_queue = pool.queued();
+            status.in_progress = pool.running();
+            status.coalesced = pool.coalesced;
+            status.turned_away = pool.turned_away;
+        }
+        session.desktop.flush_due(sorter.notifications.batch_window);
+        if let Some(email) = &sorter.email {
+            session.alerts.flush_due(email);
+        }
+        if !session.control.is_paused() {
+            for path in std::mem::take(&mut session.held) {
+                dispatch(&mut pool, &sorter, &mut session, &path);
+            }
+        }
+        for path in session.retries.due() {
+            dispatch(&mut pool, &sorter, &mut session, &path);
+        }
+        for path in session.settled() {
+            dispatch(&mut pool, &sorter, &mut session, &path);
+        }
+        for path in session.opened() {
+            dispatch(&mut pool, &sorter, &mut session, &path);
+        }
+        for poller in poller.iter_mut().chain(unwatched.iter_mut()) {
+            if poller.next() > Instant::now() {
+                continue;
+            }
+            let found = poller.poll(&sorter);
+            if !found.is_empty() {
+                last_event = Instant::now();
+            }
+            for path in found {
+                dispatch(&mut pool, &sorter, &mut session, &path);
+            }
+        }
+
+        let requested = session.control.take_rescan();
+        if requested || next_rescan.is_some_and(|due| due <= Instant::now()) {
+            // Catches files whose events were missed and rules that only match later
+            if identity.is_some() {
+                info!(
+                    "Looking through {:?} for files that were missed ...",
+                    watch_dir
+                );
+                dispatch_all(&mut pool, &sorter, &mut session, watch_dir);
+            }
+            next_rescan = config.rescan.as_ref().and_then(Schedule::next_instant);
+        }
+
+        // Look for finished files more often while there are some
+        let timeout = if pool.is_idle() {
+            watch_delay
+        } else {
+            watch_delay.min(Duration::from_millis(100))
+        };
+        let next = session
+            .next_settled()
+            .into_iter()
+            .chain(session.next_opened())
+            .chain(poller.iter().chain(unwatched.iter()).map(Poller::next))
+            .chain(next_rescan)
+            .min();
+        let timeout = match next {
+            Some(due) => timeout.min(due.saturating_duration_since(Instant::now())),
+            None => timeout,
+        };
+        let received = rx.recv_timeout(timeout);
+        let quiet_since = SystemTime::now() - last_event.elapsed();
+        if received.is_ok() {
+            last_event = Instant::now();
+        }
+        // Asked for over D-Bus or the control port
+        let mut changed_config = session.control.take_reload().then(|| config_path.clone());
+        match received {
+            Ok(Ok(events)) => {
+                for event in events {
+                    match Change::of(&event) {
+                        Change::Appeared(path) | Change::Written(path) | Change::Modified(path)
+                            if config.is_source(&path) =>
+                        {
+                            // Editors that save atomically rename a temporary file over the
+                            // original. Reloaded once for all events.
+                            changed_config = Some(path)
+                        }
+                        // Includes programs that write to a temporary file first and rename it
+                        // when they are done, and programs that finished writing in place
+                        Change::Appeared(path) | Change::Written(path) => {
+                            let root = if path.starts_with(watch_dir) {
+                                Some(watch_dir.as_path())
+                            } else {
+                                volumes.containing(&path)
+                            };
+                            match root {
+                                None => {}
+                                Some(root) if sorter.is_too_deep(root, &path) => {
+                                    trace!("{:?} is deeper than max_depth. Ignored.", &path)
+                                }
+                                Some(_) if sorter.is_excluded(&path) => {
+                                    trace!("{:?} is inside a target. Ignored.", &path)
+                                }
+                                Some(_) => dispatch(&mut pool, &sorter, &mut session, &path),
+                            }
+                        }
+                        // It may already be back with the same inode
+                        Change::Removed(path) if path == *watch_dir && identity.is_some() => {
+                            warn!(
+                                "{:?} has disappeared. Waiting for it to come back ...",
+                                watch_dir
+                            );
+                            _dir_watcher = None;
+                            poller = None;
+                            unwatched.clear();
+                            identity = None;
+                        }
+                        Change::Rescan => {
+                            warn!(
+                                "Some file events were lost. Looking through {:?} ...",
+                                watch_dir
+                            );
+                            rescan(&mut pool, &sorter, watch_dir, quiet_since, watch_delay);
+                        }
+                        Change::Modified(_) | Change::Removed(_) | Change::Other => {
+                            trace!("Unhandled notify event: {:#?}.", event)
+                        }
+                    }
+                }
+            }
+            Ok(Err(errors)) => {
+                for e in errors {
+                    if !watch_limit::is_exhausted(&e) {
+                        error!("{}", e);
+                        continue;
+                    }
+                    // Folders created after the limit was reached are not watched
+                    if !watch_limit_reported {
+                        watch_limit_reported = true;
+                        let recursive = options.recursive;
+                        if watch_limit::report(watch_dir, recursive, options.raise_watch_limit) {
+                            (_dir_watcher, poller) =
+                                watch_wormhole(&tx, &sorter, options, backend, settle);
+                            unwatched.clear();
+                            rescan(&mut pool, &sorter, watch_dir, quiet_since, watch_delay);
+                            continue;
+                        }
+                    }
+                    if options.backend != Backend::Auto {
+                        continue;
+                    }
+                    for dir in e.paths {
+                        let polled = poller
+                            .iter()
+                            .chain(unwatched.iter())
+                            .any(|p| dir.starts_with(p.dir()));
+                        if polled || !dir.starts_with(watch_dir) {
+                            continue;
+                        }
+                        warn!(
+                            "{:?} is not watched. Looking for new files in it every {:?} \
+                             instead.",
+                            dir, options.poll_interval
+                        );
+                        let depth = dir.strip_prefix(watch_dir).map_or(0, |d| d.iter().count());
+                        unwatched.push(Poller::subtree(&dir, depth, options.poll_interval));
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Timeout) if last_event.elapsed() >= watch_delay => {
+                // The watch directory is quiet, a new batch begins
+                last_event = Instant::now();
+                session.batch.accepted.clear();
+                let cooldown = sorter.cooldown;
+                session
+                    .recent
+                    .retain(|_, sorted| sorted.elapsed().is_ok_and(|since| since < cooldown));
+                for path in std::mem::take(&mut session.batch.deferred) {
+                    if path.exists() {
+                        dispatch(&mut pool, &sorter, &mut session, &path);
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(e) => error!("{}", e),
+        }
+        if let Some(path) = changed_config {
+            match reload(&config_path, &config, &registry, options) {
+                Ok((new_config, new_sorter)) => {
+                    config = new_config;
+                    // Files that are being sorted finish with the previous rules
+                    sorter = Arc::new(new_sorter);
+                    session.batch.accepted.clear();
+                    session.control.status().rules = sorter.rules.iter().map(Rule::info).collect();
+                    _conf_watchers = watch_config(&tx, &config, watch_delay);
+                    next_rescan = config.rescan.as_ref().and_then(Schedule::next_instant);
+                    if identity.is_some() && sorter.shortest_settle() != settle {
+                        settle = sorter.shortest_settle();
+                        (_dir_watcher, poller) =
+                            watch_wormhole(&tx, &sorter, options, backend, settle);
+                        unwatched.clear();
+                    }
+                }
+                Err(e) => {
+                    // Keep the last working rules until the next change fixes the file
+                    error!("The changed configuration is invalid and was NOT loaded.");
+                    error!("Reason: {:#}", e);
+                    error!(
+                        "The previous rules stay active. Fix {:?} and save it again.",
+                        &path
+                    );
+                }
+            }
+        }
+    }
+
+    systemd.stopping();
+    let waiting = pool.queued();
+    for (task, result) in pool.shutdown() {
+        finish(&sorter, &mut session, task, result);
+    }
+    session.desktop.flush();
+    if let Some(email) = &sorter.email {
+        session.alerts.flush(email);
+    }
+    let unprocessed = rx
+        .try_iter()
+        .flatten()
+        .flatten()
+        .filter(|event| matches!(event.kind, EventKind::Create(_)))
+        .count();
+    let status = session.control.status();
+    info!(
+        "Shutting down. Moved {} and deleted {} files, gave up on {}.",
+        status.moved, status.deleted, status.given_up
+    );
+    let waiting = session.batch.deferred.len()
+        + session.retries.len()
+        + session.held.len()
+        + session.settling.len()
+        + waiting
+        + unprocessed;
+    if waiting > 0 {
+        warn!(
+            "{} files have not been sorted yet and stay in the wormhole.",
+            waiting
+        );
+    }
+    Ok(())
+}
+
+/// Sorts the files that are in a directory now and returns when all of them have been handled.
+/// Files whose action fails are not tried again.
+pub fn sort(options: &Options, registry: Registry, control: Arc<control::State>) -> Result<()> {
+    let dir = &options.watch_dir;
+    check_watch_directory(dir)?;
+    let _lock = lock::acquire(&data_dir()?, dir, options.force)?;
+
+    let (config_path, created) = config::create_if_missing()?;
+    if created {
+        return Err(anyhow!(
+            "Example rules have been created in {:?}. Adjust them and sort again.",
+            config_path
+        ));
+    }
+    let config = config::load(&config_path)?;
+    let sorter = Arc::new(Sorter::new(&config, &registry, options)?);
+    let started = Instant::now();
+
+    let spinner = ProgressBar::new_spinner().with_message(format!("Reading {:?} ...", dir));
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    let files = scan::files_in(&sorter, dir, 0).context(format!("Could not read {:?}.", dir))?;
+    spinner.finish_and_clear();
+    info!("Found {} files in {:?}.", files.len(), dir);
+
+    let progress = ProgressBar::new(files.len() as u64).with_style(ProgressStyle::with_template(
+        "{bar:40} {pos}/{len} files, {elapsed} ({eta} left)",
+    )?);
+    let mut session = Session::new(control);
+    session.printer = Printer::new(options.output, Some(progress.clone()));
+    let stop = Arc::clone(&session.control);
+    ctrlc::set_handler(move || stop.stop()).context("Could not install the signal handler.")?;
+    let mut pool = Pool::new(options.workers, files.len());
+    for path in files.iter() {
+        pool.push(path);
+    }
+    while !session.control.is_stopping() && !pool.is_idle() {
+        start(&mut pool, &sorter, &mut session);
+        for (task, result) in pool.wait_for_finished() {
+            finish(&sorter, &mut session, task, result);
+        }
+        progress.set_position((files.len() - pool.queued() - pool.running()) as u64);
+    }
+    let unsorted = pool.queued();
+    for (task, result) in pool.shutdown() {
+        finish(&sorter, &mut session, task, result);
+    }
+    progress.finish_and_clear();
+    session.desktop.flush();
+    if let Some(email) = &sorter.email {
+        session.alerts.flush(email);
+    }
+
+    let status = session.control.status();
+    let elapsed = humantime::format_duration(Duration::from_secs(started.elapsed().as_secs()));
+    if options.dry_run {
+        println!("Checked {} files in {}.", files.len(), elapsed);
+        return Ok(());
+    }
+    let handled = status.moved + status.deleted + status.errors;
+    println!(
+        "Sorted {} files in {}: {} moved, {} deleted, {} failed, {} left alone.",
+        files.len(),
+        elapsed,
+        status.moved,
+        status.deleted,
+        status.errors,
+        files.len().saturating_sub(handled)
+    );
+    if unsorted > 0 {
+        println!("Stopped before {} files could be sorted.", unsorted);
+    }
+    Ok(())
+}
+
+/// Plans what the rules would do with the files that are in a directory now and writes the plan
+/// to `output` for review. Nothing is changed.
+pub fn write_plan(options: &Options, registry: Registry, output: &Path) -> Result<()> {
+    let dir = &options.watch_dir;
+    check_watch_directory(dir)?;
+    let config = config::load(&config::create_if_missing()?.0)?;
+    let sorter = Sorter::new(&config, &registry, options)?;
+    let files = scan::files_in(&sorter, dir, 0).context(format!("Could not read {:?}.", dir))?;
+    let mut session = Session::new(Arc::default());
+    let plan = planning::create(&sorter, &mut session, &files, dir);
+    planning::write(&plan, output)?;
+    if !session.settling.is_empty() {
+        println!(
+            "{} files changed too recently and are not part of the plan.",
+            session.settling.len()
+        );
+    }
+    println!(
+        "Planned {} operations for the {} files in {:?}. Nothing has been changed so far.",
+        plan.operations.len(),
+        files.len(),
+        dir
+    );
+    println!(
+        "Review {:?} and carry them out with `wurmloch apply {}`.",
+        output,
+        output.display()
+    );
+    Ok(())
+}
+
+/// Carries out a plan written by [`write_plan`]. Files that have changed since are left alone.
+pub fn apply_plan(
+    plan_file: &Path,
+    force: bool,
+    output: Output,
+    registry: Registry,
+    control: Arc<control::State>,
+) -> Result<()> {
+    let plan = planning::read(plan_file)?;
+    let options = Options {
+        force,
+        output,
+        ..Options::new(plan.dir.clone())
+    };
+    check_watch_directory(&plan.dir)?;
+    let _lock = lock::acquire(&data_dir()?, &plan.dir, force)?;
+    let config = config::load(&config::create_if_missing()?.0)?;
+    let sorter = Sorter::new(&config, &registry, &options)?;
+    info!(
+        "Applying {} operations planned at {}.",
+        plan.operations.len(),
+        plan.created.format("%Y-%m-%d %H:%M:%S")
+    );
+
+    let mut session = Session::new(control);
+    session.printer = Printer::new(options.output, None);
+    let stop = Arc::clone(&session.control);
+    ctrlc::set_handler(move || stop.stop()).context("Could not install the signal handler.")?;
+    let applied = planning::apply(&sorter, &mut session, &plan);
+    session.desktop.flush();
+    if let Some(email) = &sorter.email {
+        session.alerts.flush(email);
+    }
+
+    println!(
+        "Carried out {} of {} operations: {} failed, {} left alone because they have changed \
+         since the plan was made.",
+        applied.done,
+        plan.operations.len(),
+        applied.failed,
+        applied.changed
+    );
+    let unapplied = plan.operations.len() - applied.done - applied.failed - applied.changed;
+    if unapplied > 0 {
+        println!(
+            "Stopped before {} operations could be carried out.",
+            unapplied
+        );
+    }
+    Ok(())
+}
+
+/// Shows what the cleanup rules that have not been confirmed yet would delete in the directory
+/// and confirms them
+pub fn confirm_cleanup(options: &Options, registry: Registry, yes: bool) -> Result<()> {
+    let dir = &options.watch_dir;
+    check_watch_directory(dir)?;
+    let config = config::load(&config::create_if_missing()?.0)?;
+    let sorter = Sorter::new(&config, &registry, options)?;
+    audit::confirm_cleanup(&sorter, dir, yes)
+}
+
+/// One line about the work so far
+fn service_status(session: &Session) -> String {
+    let status = session.control.status();
+    let paused = if session.control.is_paused() {
+        "Paused. "
+    } else {
+        ""
+    };
+    format!(
+        "{}Moved {} and deleted {} files, {} errors, {} waiting.",
+        paused,
+        status.moved,
+        status.deleted,
+        status.errors,
+        status.queued + status.work_queue + status.in_progress
+    )
+}
+
+/// Loads the changed configuration and validates all rules again
+fn reload(
+    config_path: &Path,
+    previous: &Config,
+    registry: &Registry,
+    options: &Options,
+) -> Result<(Config, Sorter)> {
+    info!("Configuration changed. Reloading ...");
+    let config = config::load(config_path)?;
+    config::log_changes(previous, &config);
+    let sorter = Sorter::new(&config, registry, options)?;
+    Ok((config, sorter))
+}
+
+fn check_watch_directory(path: &Path) -> Result<()> {
+    if path.is_relative() {
+        return Err(anyhow!(
+            "Watch directory {:?} must be an absolute path.",
+            path
+        ));
+    } else if !path.exists() {
+        return Err(anyhow!("Watch directory {:?} does not exist.", path));
+    } else if !path.is_dir() {
+        return Err(anyhow!("Watch directory {:?} is not a directory.", path));
+    }
+    Ok(())
+}
+
+/// Waits until the watch directory exists, e.g. until its drive has been mounted
+fn wait_for_directory(path: &Path, systemd: &mut systemd::Notifier) {
+    if path.exists() {
+        return;
+    }
+    info!("Waiting for {:?} to appear ...", path);
+    // The drive may never be connected, which must not hold up the boot
+    systemd.ready();
+    systemd.status(format!("Waiting for {:?} to appear.", path));
+    while !path.exists() {
+        systemd.keepalive();
+        thread::sleep(Duration::from_secs(1));
+    }
+    info!("{:?} has appeared.", path);
+}
+
+type DirWatcher = Debouncer<RecommendedWatcher, RecommendedCache>;
+
+/// Watches the wormhole with the notifications of the operating system or by reading it
+/// regularly
+fn watch_wormhole(
+    tx: &Sender<DebounceEventResult>,
+    sorter: &Sorter,
+    options: &Options,
+    backend: Backend,
+    settle: Duration,
+) -> (Option<DirWatcher>, Option<Poller>) {
+    let dir = &options.watch_dir;
+    let mode = recursive_mode(options);
+    if backend == Backend::Poll {
+        return (None, Some(Poller::new(sorter, dir, options.poll_interval)));
+    }
+    let e = match watch(Sender::clone(tx), dir, mode, settle) {
+        Ok(watcher) => return (Some(watcher), None),
+        Err(e) => e,
+    };
+    let exhausted = e
+        .downcast_ref::<notify::Error>()
+        .is_some_and(watch_limit::is_exhausted);
+    if !exhausted {
+        error!("{:#}", e);
+        return (None, None);
+    }
+    if watch_limit::report(dir, options.recursive, options.raise_watch_limit) {
+        match watch(Sender::clone(tx), dir, mode, settle) {
+            Ok(watcher) => return (Some(watcher), None),
+            Err(e) => error!("{:#}", e),
+        }
+    }
+    if options.backend != Backend::Auto {
+        return (None, None);
+    }
+    warn!(
+        "Looking for new files in {:?} every {:?} instead of watching it.",
+        dir, options.poll_interval
+    );
+    (None, Some(Poller::new(sorter, dir, options.poll_interval)))
+}
+
+fn recursive_mode(options: &Options) -> RecursiveMode {
+    if options.recursive {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    }
+}
+
+/// Device and inode of the directory, if it exists
+fn dir_identity(dir: &Path) -> Option<(u64, u64)> {
+    let metadata = fs::metadata(dir)
+        .ok()
+        .filter(|metadata| metadata.is_dir())?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        Some((metadata.dev(), metadata.ino()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        Some((0, 0))
+    }
+}
+
+fn watch(
+    tx: Sender<DebounceEventResult>,
+    path: &Path,
+    mode: RecursiveMode,
+    watch_delay: Duration,
+) -> Result<DirWatcher> {
+    // Events are collected for a quarter of the delay, which must not be zero
+    let watch_delay = watch_delay.max(Duration::from_millis(100));
+    let mut watcher = new_debouncer(watch_delay, None, tx)
+        .context("Could not initialize file watcher for this platform.")?;
+
+    watcher
+        .watch(path, mode)
+        .context(format!("Could not watch {:#?}.", path))?;
+
+    info!("Watching {:?} ...", path);
+    Ok(watcher)
+}
+
+fn watch_config(
+    tx: &Sender<DebounceEventResult>,
+    config: &Config,
+    watch_delay: Duration,
+) -> Vec<Result<DirWatcher>> {
+    config
+        .watch_dirs()
+        .iter()
+        .map(|dir| {
+            watch(
+                Sender::clone(tx),
+                dir,
+                RecursiveMode::Recursive,
+                watch_delay,
+            )
+        })
+        .collect()
+}
+
+/// Applies the first matching rule to a new file in the wormhole. Failed files are tried again
+/// later by [`run`].
+pub fn handle_file(sorter: &Sorter, session: &mut Session, path: &Path) -> Result<()> {
+    if let Some(task) = plan(sorter, session, path) {
+        let result = execute(sorter, &task);
+        finish(sorter, session, task, result);
+    }
+    Ok(())
+}
+
+/// What a file event means for wurmloch
+enum Change {
+    /// A file was created, moved in or renamed to the path
+    Appeared(PathBuf),
+    /// A program finished writing the file
+    Written(PathBuf),
+    /// The content changed, maybe while it is still being written
+    Modified(PathBuf),
+    Removed(PathBuf),
+    /// Events were lost, e.g. because too many happened at once
+    Rescan,
+    Other,
+}
+
+impl Change {
+    fn of(event: &DebouncedEvent) -> Change {
+        if event.need_rescan() {
+            return Change::Rescan;
+        }
+        let path = match event.paths.last() {
+            Some(path) => path.clone(),
+            None => return Change::Other,
+        };
+        match event.kind {
+            // The last path of a rename is the new name
+            EventKind::Create(_)
+            | EventKind::Modify(ModifyKind::Name(RenameMode::To | RenameMode::Both)) => {
+                Change::Appeared(path)
+            }
+            // Some platforms do not tell the old from the new name
+            EventKind::Modify(ModifyKind::Name(RenameMode::Any)) if path.exists() => {
+                Change::Appeared(path)
+            }
+            EventKind::Access(AccessKind::Close(AccessMode::Write)) => Change::Written(path),
+            EventKind::Remove(_) => Change::Removed(path),
+            // Only configuration files are reloaded on every change. Platforms without
+            // close events report files in the wormhole when they appear.
+            EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Any) if path.exists() => {
+                Change::Modified(path)
+            }
+            _ => Change::Other,
+        }
+    }
+}
+
+/// Queues the file and hands queued files to the workers that are free
+fn dispatch(
+    pool: &mut Pool<(Task, Result<Applied>)>,
+    sorter: &Arc<Sorter>,
+    session: &mut Session,
+    path: &Path,
+) {
+    pool.push(path);
+    start(pool, sorter, session);
+}
+
+fn start(pool: &mut Pool<(Task, Result<Applied>)>, sorter: &Arc<Sorter>, session: &mut Session) {
+    while let Some(path) = pool.next() {
+        if let Some(task) = plan(sorter, session, &path) {
+            let sorter = Arc::clone(sorter);
+            let failed = task.clone();
+            pool.submit(
+                path,
+                move || {
+                    let result = execute(&sorter, &task);
+                    (task, result)
+                },
+                move |panic| {
+                    (
+                        failed,
+                        Err(anyhow!("wurmloch crashed while sorting it: {}", panic)),
+                    )
+                },
+            );
+        }
+    }
+}
+
+/// Sorts all files in the directory, e.g. on a freshly mounted volume
+fn dispatch_all(
+    pool: &mut Pool<(Task, Result<Applied>)>,
+    sorter: &Arc<Sorter>,
+    session: &mut Session,
+    dir: &Path,
+) {
+    match scan::files_in(sorter, dir, 0) {
+        Ok(files) => {
+            for path in files {
+                dispatch(pool, sorter, session, &path);
+            }
+        }
+        Err(e) => error!("Could not read {:?}: {}", dir, e),
+    }
+}
+
+/// Queues the files that changed since the queue overflowed, since their events were dropped
+fn rescan(
+    pool: &mut Pool<(Task, Result<Applied>)>,
+    sorter: &Sorter,
+    watch_dir: &Path,
+    since: SystemTime,
+    watch_delay: Duration,
+) {
+    info!("Looking for files that did not fit into the queue ...");
+    let files = match scan::files_in(sorter, watch_dir, 0) {
+        Ok(files) => files,
+        Err(e) => {
+            error!("Could not read {:?}: {}", watch_dir, e);
+            return;
+        }
+    };
+    // Events arrive after the watch delay, and the timestamps of some filesystems are only
+    // precise to the second
+    let changed_since = since - watch_delay - Duration::from_secs(1);
+    for path in files {
+        if !pool.is_running(&path) && changed(&path).is_some_and(|changed| changed >= changed_since)
+        {
+            pool.push(&path);
+        }
+    }
+    pool.rescanned(since);
+}
+
+/// Whether the file was last modified longer than `age` ago
+fn is_older(path: &Path, age: Duration) -> bool {
+    fs::symlink_metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| modified.elapsed().is_ok_and(|elapsed| elapsed >= age))
+}
+
+/// When the file was put into its directory or changed for the last time
+fn changed(path: &Path) -> Option<SystemTime> {
+    let metadata = fs::symlink_metadata(path).ok()?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let ctime = Duration::new(metadata.ctime() as u64, metadata.ctime_nsec() as u32);
+        Some(SystemTime::UNIX_EPOCH + ctime)
+    }
+    #[cfg(not(unix))]
+    metadata.created().ok().max(metadata.modified().ok())
+}
+
+/// Whether the file arrived outside the time windows. It is looked at again when the next one
+/// begins.
+fn is_waiting(session: &mut Session, path: &Path, windows: &[TimeWindow], who: &str) -> bool {
+    let now = Local::now();
+    if window::is_active(windows, now) {
+        return false;
+    }
+    if session.waiting.contains_key(path) {
+        return true;
+    }
+    match window::next_active(windows, now) {
+        Some(opens) => {
+            info!(
+                "{} is only active during {}. {:?} waits until {}.",
+                who,
+                window::describe(windows),
+                path.file_name().unwrap_or_default(),
+                opens.format("%a %H:%M")
+            );
+            session.waiting.insert(path.to_path_buf(), opens);
+        }
+        None => warn!(
+            "{} is never active during {}. {:?} is ignored.",
+            who,
+            window::describe(windows),
+            path.file_name().unwrap_or_default()
+        ),
+    }
+    true
+}
+
+/// Whether the file was sorted less than the cooldown ago. Repeated events for it are ignored,
+/// unless it changed since. Then it is looked at again after the cooldown.
+fn is_cooling_down(sorter: &Sorter, session: &mut Session, path: &Path) -> bool {
+    let sorted = match session.recent.get(path) {
+        Some(sorted) => *sorted,
+        None => return false,
+    };
+    let since = sorted.elapsed().unwrap_or_default();
+    if since >= sorter.cooldown {
+        session.recent.remove(path);
+        return false;
+    }
+    if changed(path).is_some_and(|changed| changed > sorted) {
+        debug!(
+            "{:?} changed again {:?} after it was sorted. Waiting for the cooldown.",
+            path, since
+        );
+        session.settling.insert(
+            path.to_path_buf(),
+            Instant::now() + (sorter.cooldown - since),
+        );
+    } else {
+        debug!("{:?} was sorted {:?} ago. Event ignored.", path, since);
+    }
+    true
+}
+
+/// Whether the file changed too recently to be sorted. It is looked at again once it has been
+/// left alone for `settle` or, by default, the watch delay.
+fn is_settling(
+    sorter: &Sorter,
+    session: &mut Session,
+    path: &Path,
+    settle: Option<Duration>,
+) -> bool {
+    let settle = settle.unwrap_or(sorter.watch_delay);
+    // Files changed in the future have settled
+    let age = match changed(path).and_then(|changed| SystemTime::now().duration_since(changed).ok())
+    {
+        Some(age) if age < settle => age,
+        _ => return false,
+    };
+    let wait = settle - age;
+    debug!(
+        "{:?} changed {:?} ago. Waiting {:?} for it to settle.",
+        path, age, wait
+    );
+    session
+        .settling
+        .insert(path.to_path_buf(), Instant::now() + wait);
+    true
+}
+
+/// What is done with a file
+#[derive(Debug, Clone)]
+struct Task {
+    path: PathBuf,
+    /// Index of the rule, `None` for the fallback
+    rule: Option<usize>,
+    /// Apply the overflow action of the rule because its batch is full
+    overflow: bool,
+    /// Name of the rule in logs and reports
+    name: String,
+    notify: bool,
+}
+
+/// Finds the rule for the file and counts it against the batch limit of the rule. Returns
+/// `None` if nothing is to be done now.
+fn plan(sorter: &Sorter, session: &mut Session, path: &Path) -> Option<Task> {
+    if session.control.is_paused() {
+        debug!("Paused. {:?} is held back.", path);
+        session.held.push(path.to_path_buf());
+        return None;
+    }
+
+    let filename = path.file_name()?;
+    if let Some(quirk) = sorter.quirks.matching(filename) {
+        debug!("{:?} is a temporary file ({}). Ignored.", filename, quirk);
+        return None;
+    }
+
+    let kind = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => RuleType::Dir,
+        Ok(_) => RuleType::File,
+        Err(_) => {
+            // e.g. a file inside a directory that has already been moved as a whole
+            debug!("{:?} does not exist anymore. Ignored.", path);
+            session.retries.forget(path);
+            return None;
+        }
+    };
+    if is_cooling_down(sorter, session, path) {
+        return None;
+    }
+    if is_waiting(session, path, &sorter.active_during, "wurmloch") {
+        return None;
+    }
+
+    if !sorter.trace_decisions {
+        match sorter.index.is_evaluated(path, &sorter.fingerprint) {
+            Ok(true) => {
+                debug!("{:?} was left in place before. Ignored.", filename);
+                return None;
+            }
+            Ok(false) => {}
+            Err(e) => error!("{:#}", e),
+        }
+    }
+
+    let _scope = logging::file(path);
+    debug!(" --- Processing {:?} --- ", filename);
+    let mut matching = sorter.matching_rules(path, kind);
+    if let Some((index, rule)) = matching.next() {
+        // First rule match = highest priority match
+        let name = rule.name.clone();
+        debug!("Rule {} matched.", &name);
+        for (_, lower) in matching {
+            // Consecutive rule matches are ignored
+            debug!(
+                "Rule '{}' would have also matched but has lower priority.",
+                &lower.name
+            );
+        }
+        if is_waiting(
+            session,
+            path,
+            &rule.active_during,
+            &format!("Rule {}", name),
+        ) {
+            return None;
+        }
+        if is_settling(sorter, session, path, rule.settle) {
+            return None;
+        }
+        if let Some(fingerprint) = &rule.cleanup {
+            if !sorter.dry_run && !audit::is_cleanup_confirmed(fingerprint) {
+                info!(
+                    "Dry run: {:?} would {}. Rule {} has not been confirmed yet.",
+                    filename,
+                    rule.action.describe(path).unwrap_or_default(),
+                    &name
+                );
+                return None;
+            }
+        }
+        let accepted = session.batch.accepted.entry(index).or_default();
+        let overflow = match &rule.limit {
+            Some(limit) if *accepted >= limit.max => match &limit.overflow {
+                Some(_) => {
+                    debug!("Batch limit of {} reached. Using overflow.", limit.max);
+                    true
+                }
+                None => {
+                    info!(
+                        "Batch limit of {} reached. {:?} deferred.",
+                        limit.max, filename
+                    );
+                    session.batch.deferred.push(path.to_path_buf());
+                    return None;
+                }
+            },
+            _ => false,
+        };
+        // Given back if the action fails
+        *accepted += 1;
+        if sorter.trace_decisions {
+            let decision = if overflow {
+                format!("Rule {} applies with its overflow target.", name)
+            } else {
+                format!("Rule {} applies.", name)
+            };
+            trace::record(sorter, path, kind, &decision);
+        }
+        return Some(Task {
+            path: path.to_path_buf(),
+            rule: Some(index),
+            overflow,
+            name,
+            notify: rule.notify,
+        });
+    }
+
+    if kind == RuleType::Dir {
+        // The files inside are sorted on their own
+        debug!("No rule found for directory {:?}. Ignored.", filename);
+    } else if sorter.fallback.is_some() {
+        if is_settling(sorter, session, path, None) {
+            return None;
+        }
+        if sorter.trace_decisions {
+            trace::record(sorter, path, kind, "No rule matches. The fallback applies.");
+        }
+        return Some(Task {
+            path: path.to_path_buf(),
+            rule: None,
+            overflow: false,
+            name: String::from("fallback"),
+            notify: sorter.notifications.desktop,
+        });
+    } else {
+        warn!("No rule found for file {:?}. Ignored.", filename);
+        if !sorter.dry_run {
+            if let Err(e) = sorter.index.add_unmatched(path) {
+                error!("{:#}", e);
+            }
+            remember_evaluated(sorter, path, filename);
+        }
+        if sorter.trace_decisions {
+            trace::record(sorter, path, kind, "No rule matches. The file stays.");
+        }
+    }
+    session.retries.forget(path);
+    None
+}
+
+/// Applies the action of the task. Runs on a worker in [`run`].
+fn execute(sorter: &Sorter, task: &Task) -> Result<Applied> {
+    let path = &task.path;
+    let filename = path.file_name().unwrap_or_default();
+    let _scope = logging::file(path);
+    match task.rule.map(|index| &sorter.rules[index]) {
+        Some(rule) if rule.dry_run && !sorter.dry_run => {
+            logging::rule(&task.name);
+            info!(
+                "Dry run of rule {}: {:?} would {}.",
+                &task.name,
+                filename,
+                rule.action.describe(path)?
+            );
+            Ok(Applied {
+                action: String::from("dry_run"),
+                ..Applied::default()
+            })
+        }
+        Some(rule) => {
+            let action = match (&rule.limit, task.overflow) {
+                (
+                    Some(BatchLimit {
+                        overflow: Some(overflow),
+                        ..
+                    }),
+                    true,
+                ) => overflow,
+                _ => {
+                    if let Some(applied) = try_failover(sorter, rule, task, filename) {
+                        return applied;
+                    }
+                    rule.failover.last().unwrap_or(&rule.action)
+                }
+            };
+            let action = match &rule.reserve {
+                Some(reserve) => match make_room(action.as_ref(), reserve, path) {
+                    Some(action) => action,
+                    None => {
+                        return Ok(Applied {
+                            action: String::from("full"),
+                            ..Applied::default()
+                        })
+                    }
+                },
+                None => action.as_ref(),
+            };
+            apply_rule_action(
+                action,
+                rule.on_duplicate,
+                rule.provenance,
+                &task.name,
+                sorter,
+                path,
+                filename,
+            )
+        }
+        None => match &sorter.fallback {
+            Some(fallback) => apply_fallback(fallback, sorter, path, filename),
+            None => Err(anyhow!("The fallback has been removed.")),
+        },
+    }
+}
+
+/// Applies the first of the rule's targets that is available, except for the last one, which
+/// `execute` applies as usual. None if no target but the last is available.
+fn try_failover(
+    sorter: &Sorter,
+    rule: &Rule,
+    task: &Task,
+    filename: &OsStr,
+) -> Option<Result<Applied>> {
+    let path = &task.path;
+    let count = rule.failover.len();
+    if count == 0 {
+        return None;
+    }
+    let candidates =
+        iter::once(&rule.action).chain(rule.failover.iter().take(count.saturating_sub(1)));
+    for (i, action) in candidates.enumerate() {
+        let target = action.target().map_or("", |t| t.source());
+        if let Some(reserve) = &rule.reserve {
+            if let Some((dir, free)) = space::lacking(action.as_ref(), path, reserve.min_free) {
+                warn!(
+                    "Could not use target {}: {:?} has only {} free. Trying the next one.",
+                    target,
+                    dir,
+                    units::format_size(free)
+                );
+                continue;
+            }
+        }
+        let result = apply_rule_action(
+            action.as_ref(),
+            rule.on_duplicate,
+            rule.provenance,
+            &task.name,
+            sorter,
+            path,
+            filename,
+        );
+        match result {
+            Ok(applied) => {
+                if i > 0 {
+                    info!("Used target {} for {:?}.", target, path);
+                }
+                return Some(Ok(applied));
+            }
+            Err(e) => warn!(
+                "Could not use target {}: {:#}. Trying the next one.",
+                target, e
+            ),
+        }
+    }
+    let last = rule.failover[count - 1].target().map_or("", |t| t.source());
+    info!("Using the last target {} for {:?}.", last, path);
+    None
+}
+
+/// The action to apply if the target of `action` has too little free space left: the
+/// alternate target, if it has enough, or none
+fn make_room<'a>(
+    action: &'a dyn Action,
+    reserve: &'a SpaceReserve,
+    path: &Path,
+) -> Option<&'a dyn Action> {
+    let (dir, free) = match space::lacking(action, path, reserve.min_free) {
+        Some(lacking) => lacking,
+        None => return Some(action),
+    };
+    let free = units::format_size(free);
+    if let Some(alternate) = &reserve.alternate {
+        if space::lacking(alternate.as_ref(), path, reserve.min_free).is_none() {
+            info!(
+                "{:?} has only {} free. Using the on_full target.",
+                dir, free
+            );
+            return Some(alternate.as_ref());
+        }
+    }
+    let filename = path.file_name().unwrap_or_default();
+    if reserve.on_full == OnFull::Skip {
+        warn!(
+            "{:?} has only {} free. {:?} is left alone.",
+            dir, free, filename
+        );
+    } else {
+        error!(
+            "{:?} has only {} free. {:?} is left alone.",
+            dir, free, filename
+        );
+    }
+    None
+}
+
+/// Remembers a file that stays where it is, so that rescans and restarts do not handle it again
+/// as long as neither the file nor the rules change
+fn remember_evaluated(sorter: &Sorter, path: &Path, filename: &OsStr) {
+    // A file that is too young for a rule now may match it later without changing
+    let ages = sorter
+        .patterns
+        .matching(filename)
+        .into_iter()
+        .any(|index| sorter.rules[index].older_than.is_some());
+    if ages {
+        return;
+    }
+    if let Err(e) = sorter.index.add_evaluated(path, &sorter.fingerprint) {
+        error!("{:#}", e);
+    }
+}
+
+/// Reports the result of the task and schedules failed files for another attempt
+fn finish(sorter: &Sorter, session: &mut Session, task: Task, result: Result<Applied>) {
+    let path = &task.path;
+    let filename = path.file_name().unwrap_or_default();
+    let _scope = logging::file(path);
+    let counter = task
+        .rule
+        .and_then(|index| sorter.rules[index].counter.as_ref());
+    let applied = match result {
+        Ok(applied) => applied,
+        Err(e) => {
+            if let Some(counter) = counter {
+                counter.release(path);
+            }
+            if task.rule.is_some() {
+                error!("Could not apply rule {} to {:?}.", &task.name, filename);
+            } else {
+                error!("Could not apply fallback to {:?}.", filename);
+            }
+            error!("Reason: {:#}", e);
+            session
+                .control
+                .status()
+                .failed(&task.name, path, &format!("{:#}", e));
+            if let Some(accepted) = task
+                .rule
+                .and_then(|index| session.batch.accepted.get_mut(&index))
+            {
+                *accepted = accepted.saturating_sub(1);
+            }
+            if let Some(blocked) = Blocked::of(&e) {
+                quarantine(sorter, session, &task, blocked.check, &format!("{:#}", e));
+                return;
+            }
+            if !session.retries.failed(path, &sorter.retry) {
+                let reason = format!("{:#}", e);
+                if let Err(e) = sorter.index.add_failure(&task.name, path, &reason) {
+                    error!("{:#}", e);
+                }
+                if task.notify {
+                    session.desktop.failed(
+                        &filename.to_string_lossy(),
+                        &format!("Gave up after {} attempts.", sorter.retry.max_attempts),
+                    );
+                }
+                session.printer.failed(filename, &task.name, &reason);
+                if sorter.email.is_some() {
+                    session.alerts.failed(Failure {
+                        file: path.display().to_string(),
+                        rule: task.name.clone(),
+                        reason: reason.clone(),
+                    });
+                }
+                announce(
+                    sorter,
+                    session,
+                    &WebhookEvent {
+                        event: "failed",
+                        file: path.display().to_string(),
+                        rule: task.name,
+                        destination: None,
+                        outcome: reason,
+                    },
+                );
+                give_up(sorter, session, path, filename, &e);
+            }
+            return;
+        }
+    };
+    session.retries.forget(path);
+    session.recent.insert(path.clone(), SystemTime::now());
+    if let Some(counter) = counter {
+        let placed =
+            !sorter.dry_run && !matches!(applied.action.as_str(), "skip" | "full" | "dry_run");
+        if !placed {
+            counter.release(path);
+        } else if let Err(e) = counter.commit(path, &sorter.index) {
+            error!("{:#}", e);
+        }
+    }
+    let alert = task
+        .rule
+        .and_then(|index| sorter.rules[index].reserve.as_ref())
+        .is_some_and(|reserve| reserve.on_full == OnFull::Alert);
+    if applied.action == "full" && alert {
+        session
+            .desktop
+            .failed(&filename.to_string_lossy(), "The target is full.");
+        announce(
+            sorter,
+            session,
+            &WebhookEvent {
+                event: "full",
+                file: path.display().to_string(),
+                rule: task.name.clone(),
+                destination: None,
+                outcome: String::from("The target is full."),
+            },
+        );
+    }
+    if !sorter.dry_run && applied.action != "dry_run" {
+        session
+            .control
+            .status()
+            .processed(&task.name, path, &applied);
+        if task.notify {
+            session.desktop.sorted(describe_applied(filename, &applied));
+        }
+        session.printer.sorted(filename, &task.name, &applied);
+        announce_sorted(sorter, session, &task.name, path, &applied);
+        if sorter.email.is_some() {
+            session.alerts.sorted();
+        }
+        // E.g. copied, unpacked or compressed files, and files a command ran for
+        let left = !matches!(applied.action.as_str(), "skip" | "full");
+        if left && fs::symlink_metadata(path).is_ok() {
+            remember_evaluated(sorter, path, filename);
+        }
+    }
+}
+
+/// Moves a file that a safety check refused to the quarantine directory. It is not tried again,
+/// as another attempt would be refused as well.
+fn quarantine(sorter: &Sorter, session: &mut Session, task: &Task, check: Check, reason: &str) {
+    let path = &task.path;
+    let filename = path.file_name().unwrap_or_default();
+    session.retries.forget(path);
+    if let Err(e) = sorter.index.add_failure(&task.name, path, reason) {
+        error!("{:#}", e);
+    }
+    session.printer.failed(filename, &task.name, reason);
+    match quarantine::isolate(path, &sorter.quarantine_dir, &task.name, check, reason) {
+        Ok(moved_to) => {
+            warn!("Quarantined {:?} as {:?}.", path, &moved_to);
+            session.control.status().quarantined += 1;
+            if task.notify {
+                session
+                    .desktop
+                    .failed(&filename.to_string_lossy(), "Refused and quarantined.");
+            }
+        }
+        Err(e) => {
+            error!("Could not quarantine {:?}.", filename);
+            error!("Reason: {:#}", e);
+        }
+    }
+}
+
+/// Handles a file after all attempts failed as `on_error` says for the class of the error
+fn give_up(
+    sorter: &Sorter,
+    session: &mut Session,
+    path: &Path,
+    filename: &OsStr,
+    error: &anyhow::Error,
+) {
+    session.control.status().given_up += 1;
+    let class = ErrorClass::of(error);
+    let on_failure = sorter
+        .on_error
+        .iter()
+        .find(|(c, _)| *c == class)
+        .map(|(_, on_failure)| on_failure);
+    let (action, target) = match on_failure {
+        Some(OnFailure::Halt) => {
+            error!(
+                "Giving up on {:?} after {} attempts ({} error). Stopping as on_error says.",
+                path, sorter.retry.max_attempts, class
+            );
+            session.control.stop();
+            return;
+        }
+        Some(OnFailure::Divert(action)) => (action, "the on_error target"),
+        Some(OnFailure::Continue) | None => match &sorter.error_action {
+            Some(action) => (action, "the error target"),
+            None => {
+                error!(
+                    "Giving up on {:?} after {} attempts ({} error).",
+                    path, sorter.retry.max_attempts, class
+                );
+                return;
+            }
+        },
+    };
+    warn!(
+        "Giving up on {:?} after {} attempts ({} error). Moving it to {}.",
+        path, sorter.retry.max_attempts, class, target
+    );
+    if let Err(e) = apply_action(action.as_ref(), "error", sorter, path, filename) {
+        error!("Could not move {:?} to {}.", filename, target);
+        error!("Reason: {:#}", e);
+    }
+}
+
+fn apply_fallback(
+    fallback: &Fallback,
+    sorter: &Sorter,
+    path: &Path,
+    filename: &OsStr,
+) -> Result<Applied> {
+    debug!("No rule found for file {:?}. Using fallback.", filename);
+    let applied = apply_rule_action(
+        fallback.action.as_ref(),
+        fallback.on_duplicate,
+        fallback.provenance,
+        "fallback",
+        sorter,
+        path,
+        filename,
+    )?;
+    if let Some(moved_to) = &applied.moved_to {
+        if fallback.quarantine {
+            quarantine::record(moved_to, path)?;
+        }
+    }
+    Ok(applied)
+}
+
+/// Reports a sorted file to the webhook, the MQTT broker and D-Bus, if there are any
+fn announce_sorted(sorter: &Sorter, session: &Session, rule: &str, path: &Path, applied: &Applied) {
+    announce(
+        sorter,
+        session,
+        &WebhookEvent {
+            event: "sorted",
+            file: path.display().to_string(),
+            rule: rule.to_string(),
+            destination: applied.moved_to.as_ref().map(|d| d.display().to_string()),
+            outcome: String::from(match applied.action.as_str() {
+                "move" => "moved",
+                "copy" => "copied",
+                "extract" => "extracted",
+                "compress" => "compressed",
+                "delete" => "deleted",
+                "skip" => "skipped",
+                name => name,
+            }),
+        },
+    );
+}
+
+fn announce(sorter: &Sorter, session: &Session, event: &WebhookEvent) {
+    if let Some(webhook) = &sorter.webhook {
+        webhook.send(event);
+    }
+    if let Some(mqtt) = &sorter.mqtt {
+        mqtt.send(event);
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(bus) = &session.bus {
+        bus.send(event);
+    }
+}
+
+/// One line of a desktop notification
+fn describe_applied(filename: &OsStr, applied: &Applied) -> String {
+    match applied.moved_to.as_deref().and_then(Path::parent) {
+        Some(target) => format!("{} → {}", filename.to_string_lossy(), target.display()),
+        None if applied.action == "delete" => format!("{} → trash", filename.to_string_lossy()),
+        None if applied.action == "skip" => {
+            format!("{} → duplicate, skipped", filename.to_string_lossy())
+        }
+        None if applied.action == "full" => {
+            format!("{} → target full, left alone", filename.to_string_lossy())
+        }
+        None => format!("{} → {}", filename.to_string_lossy(), applied.action),
+    }
+}
+
+/// Result of an action that has been applied
+#[derive(Debug, Default)]
+pub struct Applied {
+    /// Name of the action
+    pub action: String,
+    /// Where the file has been moved or copied to, if it still exists
+    pub moved_to: Option<PathBuf>,
+    pub bytes: u64,
+    pub duration: Duration,
+}
+
+/// Checks whether the file is a duplicate of a file that has been placed before and applies the
+/// action unless the policy says otherwise. Placed files are remembered for later checks and
+/// their provenance is recorded.
+fn apply_rule_action(
+    action: &dyn Action,
+    on_duplicate: Option<OnDuplicate>,
+    provenance: Option<Provenance>,
+    rule: &str,
+    sorter: &Sorter,
+    path: &Path,
+    filename: &OsStr,
+) -> Result<Applied> {
+    let needs_hash = on_duplicate.is_some() || provenance.is_some();
+    let hash = if needs_hash && path.is_file() && !sorter.dry_run {
+        Some(
+            transfer::hash(path, Checksum::Sha256)
+                .context(format!("Could not hash {:?}.", path))?,
+        )
+    } else {
+        None
+    };
+    if let (Some(hash), Some(on_duplicate)) = (&hash, on_duplicate) {
+        let original = match on_duplicate {
+            OnDuplicate::KeepBoth => None,
+            _ => sorter.index.find_duplicate(hash, path)?,
+        };
+        if let Some(original) = original {
+            if on_duplicate == OnDuplicate::Skip {
+                info!(
+                    "{:?} is a duplicate of {:?}. Left alone.",
+                    filename, original
+                );
+                return Ok(Applied {
+                    action: String::from("skip"),
+                    ..Applied::default()
+                });
+            }
+            info!(
+                "{:?} is a duplicate of {:?}. Deleting it.",
+                filename, original
+            );
+            return apply_action(&Delete, rule, sorter, path, filename);
+        }
+    }
+
+    let applied = apply_action(action, rule, sorter, path, filename)?;
+    // Uploaded files are not on this machine, so there is nothing to hash or record next to them
+    let placed = match &applied.moved_to {
+        Some(placed) if placed.is_absolute() => placed,
+        _ => return Ok(applied),
+    };
+    // Other actions place something else than the file, e.g. an archive of it
+    let hash = match applied.action.as_str() {
+        "move" | "copy" => hash,
+        _ if provenance.is_some() && placed.is_file() => {
+            transfer::hash(placed, Checksum::Sha256).ok()
+        }
+        _ => None,
+    };
+    if let (Some(hash), Some(_)) = (&hash, on_duplicate) {
+        if placed.is_file() {
+            if let Err(e) = sorter.index.add_hash(placed, hash) {
+                error!("{:#}", e);
+            }
+        }
+    }
+    if let Some(provenance) = provenance {
+        let record = provenance::Record {
+            path: placed.clone(),
+            original_path: path.to_path_buf(),
+            rule: rule.to_string(),
+            action: applied.action.clone(),
+            sha256: hash,
+            sorted_at: Local::now(),
+        };
+        if let Err(e) = provenance::write(provenance, &record) {
+            error!("{:#}", e);
+        }
+    }
+    Ok(applied)
+}
+
+/// Applies the action of the named rule and records it in the journal
+pub fn apply_action(
+    action: &dyn Action,
+    rule: &str,
+    sorter: &Sorter,
+    path: &Path,
+    filename: &OsStr,
+) -> Result<Applied> {
+    logging::rule(rule);
+    if sorter.dry_run {
+        info!("Dry run: {:?} would {}.", filename, action.describe(path)?);
+        return Ok(Applied::default());
+    }
+
+    // Where the file goes, or where it is if it stays on the same disk
+    let device = match action.target().map(|target| target.expand(path)) {
+        Some(Ok(target)) => throttle::device(&target),
+        _ => throttle::device(&path.to_string_lossy()),
+    };
+    sorter.throttle.file(&device);
+
+    let started = Instant::now();
+    let bytes = transfer::size(path).unwrap_or_default();
+    let context = action::Context {
+        rule,
+        trash: &sorter.trash,
+        throttle: &sorter.throttle,
+        safe: sorter.safe,
+    };
+    let outcome = action.apply(path, &context)?;
+    if let Some(event) = outcome.event {
+        record(sorter, event, bytes);
+    }
+
+    if let Some(history) = &sorter.history {
+        let entry = history::Entry {
+            time: Local::now(),
+            rule,
+            action: action.name(),
+            source: path,
+            destination: outcome.destination.as_deref(),
+            conflict: if outcome.replaced { "replaced" } else { "none" },
+            duration_ms: started.elapsed().as_millis(),
+            bytes,
+        };
+        if let Err(e) = history.write(&entry) {
+            error!("{:#}", e);
+        }
+    }
+    Ok(Applied {
+        action: action.name().to_string(),
+        moved_to: outcome.destination,
+        bytes,
+        duration: started.elapsed(),
+    })
+}
+
+/// Adds the action to the journal and the index. A failure to do so does not undo the action
+/// that has already been applied.
+fn record(sorter: &Sorter, event: Event, bytes: u64) {
+    if let Err(e) = sorter.index.add(&event, bytes) {
+        error!("{:#}", e);
+    }
+    if let Err(e) = sorter.journal.append(event) {
+        error!("{:#}", e);
+    }
+}
+
+/// Where the trash, the journal and the other data of wurmloch are kept
+pub fn data_dir() -> Result<PathBuf> {
+    let data_dir = dirs::data_local_dir()
+        .context("Could not determine data directory.")?
+        .join(APP_NAME);
+    fs::create_dir_all(&data_dir)
+        .context(format!("Could not create data directory {:?}.", &data_dir))?;
+    Ok(data_dir)
+}
+
+pub fn open_trash(settings: TrashSettings) -> Result<Trash> {
+    Trash::open(data_dir()?.join(trash::TRASH_DIR_NAME), settings)
+}
+
+pub fn open_journal() -> Result<Journal> {
+    Ok(Journal::open(data_dir()?.join(journal::JOURNAL_FILE_NAME)))
+}
+
+pub fn open_index() -> Result<Index> {
+    Index::open(&data_dir()?.join(index::INDEX_FILE_NAME))
+}
+
+fn is_valid_target(path: &Path, create_target: bool) -> bool {
+    if path.is_relative() {
+        error!("Target {:?} is not an absolute path. Rule ignored.", &path);
+        return false;
+    } else if !path.exists() {
+        if create_target {
+            debug!("Target {:?} does not exist yet and will be created.", &path);
+            return true;
+        }
+        error!("Target {:?} does not exist. Rule ignored.", &path);
+        return false;
+    } else if !path.is_dir() {
+        error!("Target {:?} is not a directory. Rule ignored.", &path);
+        return false;
+    }
+    true
+}
+
+/// Whether the target is on a server instead of this machine
+fn is_remote(target: &str) -> bool {
+    s3::is_url(target) || webdav::is_url(target)
+}
+
+fn compile_pattern(pattern: &str, vars: &BTreeMap<String, String>) -> Result<GlobMatcher> {
+    let pattern = config::substitute_vars(pattern, vars)?;
+    Ok(Glob::new(&pattern)?.compile_matcher())
+}
+
+fn parse_target(
+    target: &Path,
+    vars: &BTreeMap<String, String>,
+    registry: &Registry,
+) -> Result<Template> {
+    let target = target.to_str().context("Target is not valid UTF-8.")?;
+    Template::parse(&config::substitute_vars(target, vars)?, registry)
+}
+
+fn parse_command(
+    command: &str,
+    vars: &BTreeMap<String, String>,
+    registry: &Registry,
+) -> Result<Vec<Template>> {
+    action::split_command(&config::substitute_vars(command, vars)?)?
+        .iter()
+        .map(|word| Template::parse(word, registry))
+        .collect()
+}
+
+fn parse_attributes(rule: &ConfigRule, config: &Config, registry: &Registry) -> Result<Attributes> {
+    let mut attributes = Attributes {
+        record_origin: rule.record_origin.unwrap_or(config.record_origin),
+        ..Attributes::default()
+    };
+    for tag in &rule.tags {
+        attributes
+            .tags
+            .push(parse_target(Path::new(tag), &config.vars, registry)?);
+    }
+    for (name, value) in &rule.xattrs {
+        let value = parse_target(Path::new(value), &config.vars, registry)?;
+        attributes.xattrs.push((name.clone(), value));
+    }
+    if cfg!(not(unix)) && !attributes.is_empty() {
+        return Err(anyhow!("Extended attributes are only supported on Unix."));
+    }
+    Ok(attributes)
+}
+
+/// The handling of failed files that `on_error` sets for the error class. A divert target that
+/// cannot be used falls back to `continue`.
+fn parse_on_error(config: &Config, registry: &Registry, class: ErrorClass) -> OnFailure {
+    match config.on_error.policy(class) {
+        ErrorPolicy::Continue => OnFailure::Continue,
+        ErrorPolicy::Halt => OnFailure::Halt,
+        ErrorPolicy::Divert(target) => {
+            match parse_move(target, true, config.verify, config, registry) {
+                Some(action) => OnFailure::Divert(action),
+                None => {
+                    error!(
+                        "Target {:?} of on_error for {} errors ignored.",
+                        target, class
+                    );
+                    OnFailure::Continue
+                }
+            }
+        }
+    }
+}
+
+fn parse_move(
+    target: &Path,
+    create_target: bool,
+    verify: Option<Checksum>,
+    config: &Config,
+    registry: &Registry,
+) -> Option<Box<dyn Action>> {
+    match parse_target(target, &config.vars, registry) {
+        Ok(target) if is_valid_target(&target.static_dir(), create_target) => {
+            Some(Box::new(Move {
+                target,
+                create_target,
+                rename: None,
+                // Collected files must neither replace each other nor be quarantined
+                on_conflict: Some(OnConflict::Rename),
+                verify,
+                ownership: Ownership::default(),
+                attributes: Attributes {
+                    record_origin: config.record_origin,
+                    ..Attributes::default()
+                },
+                layout: Layout::default(),
+            }))
+        }
+        Ok(_) => None,
+        Err(e) => {
+            error!(
+                "Target {:?} cannot be parsed. Rule ignored. Reason: {:#}",
+                target, e
+            );
+            None
+        }
+    }
+}
+
+/// Creates the action of the rule that puts files into `target`
+fn parse_action(
+    rule: &ConfigRule,
+    target: &Path,
+    counter: Option<&Arc<Counter>>,
+    config: &Config,
+    registry: &Registry,
+) -> Option<Box<dyn Action>> {
+    let name = rule.action.as_deref().unwrap_or("move");
+    let factory = match registry.get_action(name) {
+        Some(factory) => factory,
+        None => {
+            error!("Action {} is unknown. Rule ignored.", name);
+            return None;
+        }
+    };
+    let create_target = rule.create_target.unwrap_or(config.create_target);
+    let target = if target.as_os_str().is_empty() {
+        None
+    } else {
+        match parse_target(target, &config.vars, registry) {
+            // Remote targets are checked when files are transferred
+            Ok(target) if name == "rclone" => Some(target),
+            Ok(target) if is_remote(target.source()) && matches!(name, "move" | "copy") => {
+                Some(target)
+            }
+            // One of several targets may be unavailable for now, e.g. an unmounted share
+            Ok(target)
+                if !rule.target.failover().is_empty() && target.static_dir().is_absolute() =>
+            {
+                Some(target)
+            }
+            Ok(target) if is_valid_target(&target.static_dir(), create_target) => Some(target),
+            Ok(_) => return None,
+            Err(e) => {
+                error!(
+                    "Target {:?} cannot be parsed. Rule ignored. Reason: {:#}",
+                    target, e
+                );
+                return None;
+            }
+        }
+    };
+    let archive_name = match &rule.archive_name {
+        Some(name) => match parse_target(Path::new(name), &config.vars, registry) {
+            Ok(name) => Some(name),
+            Err(e) => {
+                error!(
+                    "Archive name {:?} cannot be parsed. Rule ignored. Reason: {:#}",
+                    name, e
+                );
+                return None;
+            }
+        },
+        None => None,
+    };
+    let rename = match (&rule.rename, rule.normalize.is_empty()) {
+        (Some(name), _) => {
+            match parse_target(Path::new(rename_shorthand(name)), &config.vars, registry) {
+                Ok(name) => Some(name.normalized(&rule.normalize)),
+                Err(e) => {
+                    error!(
+                        "New name {:?} cannot be parsed. Rule ignored. Reason: {:#}",
+                        name, e
+                    );
+                    return None;
+                }
+            }
+        }
+        // Normalizing the name is a rename to the original name
+        (None, false) => match Template::parse("{name}", registry) {
+            Ok(name) => Some(name.normalized(&rule.normalize)),
+            Err(e) => {
+                error!("Rule {} ignored. Reason: {:#}", rule.name(), e);
+                return None;
+            }
+        },
+        (None, true) => None,
+    };
+    let rename = match (rename, counter) {
+        (Some(rename), Some(counter)) if rename.uses_counter() => {
+            Some(rename.counted(counter.clone()))
+        }
+        (rename, _) => rename,
+    };
+    let command = match &rule.command {
+        Some(command) => match parse_command(command, &config.vars, registry) {
+            Ok(command) => Some(command),
+            Err(e) => {
+                error!(
+                    "Command {:?} cannot be parsed. Rule ignored. Reason: {:#}",
+                    command, e
+                );
+                return None;
+            }
+        },
+        None => None,
+    };
+    let ownership = match Ownership::parse(rule.chmod.as_deref(), rule.chown.as_deref()) {
+        Ok(ownership) => ownership,
+        Err(e) => {
+            error!("Rule {} ignored. Reason: {:#}", rule.name(), e);
+            return None;
+        }
+    };
+    let attributes = match parse_attributes(rule, config, registry) {
+        Ok(attributes) => attributes,
+        Err(e) => {
+            error!(
+                "Attributes cannot be parsed. Rule {} ignored. Reason: {:#}",
+                rule.name(),
+                e
+            );
+            return None;
+        }
+    };
+    let layout = match Layout::new(rule.shard.clone(), rule.max_files_per_dir) {
+        Ok(layout) => layout,
+        Err(e) => {
+            error!("Rule {} ignored. Reason: {:#}", rule.name(), e);
+            return None;
+        }
+    };
+    match factory.create(ActionSettings {
+        target,
+        create_target,
+        command,
+        timeout: rule.timeout,
+        subfolder: rule.subfolder.unwrap_or(false),
+        delete_original: rule.delete_original.unwrap_or(false),
+        format: rule.format.clone(),
+        archive_name,
+        rename,
+        on_conflict: rule.on_conflict,
+        verify: rule.verify.or(config.verify),
+        ownership,
+        attributes,
+        layout,
+        s3: config.s3.clone(),
+        webdav: config.webdav.clone(),
+        rclone: config.rclone.clone(),
+    }) {
+        Ok(action) => Some(action),
+        Err(e) => {
+            error!(
+                "Action {} cannot be created. Rule ignored. Reason: {:#}",
+                name, e
+            );
+            None
+        }
+    }
+}
+
+fn parse_rule(rule: &ConfigRule, config: &Config, registry: &Registry) -> Option<Rule> {
+    let matcher = match compile_pattern(&rule.pattern, &config.vars) {
+        Ok(matcher) => matcher,
+        Err(e) => {
+            error!(
+                "Pattern {} cannot be compiled. Rule ignored. Reason: {:#}",
+                &rule.pattern, e
+            );
+            return None;
+        }
+    };
+    let content_matches = match &rule.content_matches {
+        Some(content_matches) => match Regex::new(content_matches) {
+            Ok(regex) => Some(regex),
+            Err(e) => {
+                error!(
+                    "Expression {} cannot be compiled. Rule {} ignored. Reason: {}",
+                    content_matches,
+                    rule.name(),
+                    e
+                );
+                return None;
+            }
+        },
+        None => None,
+    };
+    let contents_match = match &rule.contents_match {
+        Some(contents_match) => match Regex::new(contents_match) {
+            Ok(regex) => Some(ContentsCondition {
+                regex,
+                max_bytes: rule
+                    .contents_max_bytes
+                    .unwrap_or(contents::DEFAULT_MAX_BYTES),
+            }),
+            Err(e) => {
+                error!(
+                    "Expression {} cannot be compiled. Rule {} ignored. Reason: {}",
+                    contents_match,
+                    rule.name(),
+                    e
+                );
+                return None;
+            }
+        },
+        None => None,
+    };
+    let counter = match &rule.rename {
+        Some(rename)
+            if parse_target(Path::new(rename_shorthand(rename)), &config.vars, registry)
+                .is_ok_and(|rename| rename.uses_counter()) =>
+        {
+            Some(Arc::new(Counter::new(
+                rule.name(),
+                rule.counter.clone().unwrap_or_default(),
+            )))
+        }
+        _ => None,
+    };
+    let action = parse_action(
+        rule,
+        rule.target.first(),
+        counter.as_ref(),
+        config,
+        registry,
+    )?;
+    let failover = rule
+        .target
+        .failover()
+        .iter()
+        .map(|target| parse_action(rule, target, counter.as_ref(), config, registry))
+        .collect::<Option<Vec<_>>>()?;
+    let cleanup = match rule.older_than {
+        Some(_) if action.name() == "delete" => Some(audit::fingerprint(rule)),
+        _ => None,
+    };
+    if let Some(fingerprint) = &cleanup {
+        if !audit::is_cleanup_confirmed(fingerprint) {
+            warn!(
+                "Rule {} deletes files by age but has not been confirmed. It only logs what it \
+                 would delete until `wurmloch confirm-cleanup` is run.",
+                rule.name()
+            );
+        }
+    }
+    let limit = match rule.max_per_batch {
+        Some(max) => Some(BatchLimit {
+            max,
+            overflow: match &rule.overflow_target {
+                Some(target) => Some(parse_move(
+                    target,
+                    rule.create_target.unwrap_or(config.create_target),
+                    rule.verify.or(config.verify),
+                    config,
+                    registry,
+                )?),
+                None => None,
+            },
+        }),
+        None => None,
+    };
+
+    let reserve = match rule.min_free_space.or(config.min_free_space) {
+        Some(min_free) => {
+            let on_full = rule
+                .on_full
+                .clone()
+                .or_else(|| config.on_full.clone())
+                .unwrap_or(OnFull::Skip);
+            let alternate = match &on_full {
+                OnFull::Target(target) => Some(parse_move(
+                    target,
+                    rule.create_target.unwrap_or(config.create_target),
+                    rule.verify.or(config.verify),
+                    config,
+                    registry,
+                )?),
+                _ => None,
+            };
+            Some(SpaceReserve {
+                min_free,
+                on_full,
+                alternate,
+            })
+        }
+        None => None,
+    };
+
+    Some(Rule {
+        name: rule.name().to_string(),
+        counter,
+        matcher,
+        kind: rule.kind.unwrap_or_default(),
+        video: rule.video.clone(),
+        content_matches,
+        contents_match,
+        action,
+        failover,
+        on_duplicate: rule.on_duplicate.or(config.on_duplicate),
+        provenance: rule.provenance.or(config.provenance),
+        limit,
+        reserve,
+        settle: rule.settle,
+        active_during: rule.active_during.clone(),
+        older_than: rule.older_than,
+        cleanup,
+        notify: rule.notify.unwrap_or(config.notifications.desktop),
+        dry_run: rule.dry_run.unwrap_or(false),
+    })
+}
+
+fn parse_fallback(config: &Config, registry: &Registry) -> Option<Fallback> {
+    let target = config.fallback_target.as_ref()?;
+    match parse_move(
+        target,
+        config.create_target,
+        config.verify,
+        config,
+        registry,
+    ) {
+        Some(action) => Some(Fallback {
+            action,
+            quarantine: config.quarantine,
+            on_duplicate: config.on_duplicate,
+            provenance: config.provenance,
+        }),
+        None => {
+            error!("Fallback target {:?} ignored.", target);
+            None
+        }
+    }
+}
+
+/// The template that a built-in `rename` stands for. `date_prefix` puts the date a photo was
+/// taken or the modification date in front of the name.
+fn rename_shorthand(rename: &str) -> &str {
+    match rename {
+        "date_prefix" => "{exif.date}_{name}",
+        rename => rename,
+    }
+}
+
+/// Whether the rule has not been switched off with `enabled: false`
+fn is_enabled(rule: &ConfigRule) -> bool {
+    if rule.enabled == Some(false) {
+        info!("Rule {} is disabled. Skipped.", rule.name());
+        return false;
+    }
+    true
+}
+
+/// Whether the rule is meant for this machine. Rules without `hosts` or `os` apply everywhere.
+fn applies_here(rule: &ConfigRule, hostname: &str) -> bool {
+    if !rule.hosts.is_empty() && !rule.hosts.iter().any(|h| h.eq_ignore_ascii_case(hostname)) {
+        debug!(
+            "Rule {} only applies to hosts {:?}. Skipped.",
+            rule.name(),
+            &rule.hosts
+        );
+        return false;
+    }
+    if !rule.os.is_empty() && !rule.os.iter().any(|os| os.eq_ignore_ascii_case(OS)) {
+        debug!(
+            "Rule {} only applies to {:?}. Skipped.",
+            rule.name(),
+            &rule.os
+        );
+        return false;
+    }
+    true
+}
+
+/// Refuses rules that delete files or cannot be kept from replacing them, with any of their
+/// actions, and lets the others skip duplicates and keep originals instead of deleting them
+fn make_safe(mut rule: Rule) -> Option<Rule> {
+    let pattern = rule.name.clone();
+    let actions: Vec<&dyn Action> = iter::once(rule.action.as_ref())
+        .chain(rule.failover.iter().map(|action| action.as_ref()))
+        .chain(
+            rule.limit
+                .iter()
+                .filter_map(|limit| limit.overflow.as_deref()),
+        )
+        .chain(
+            rule.reserve
+                .iter()
+                .filter_map(|reserve| reserve.alternate.as_deref()),
+        )
+        .collect();
+    for action in actions.iter() {
+        match action.name() {
+            "delete" => {
+                error!(
+                    "Rule {} ignored. Safe mode does not allow deleting files.",
+                    pattern
+                );
+                return None;
+            }
+            "rclone" => {
+                error!(
+                    "Rule {} ignored. Safe mode cannot keep rclone from replacing files.",
+                    pattern
+                );
+                return None;
+            }
+            _ => {}
+        }
+    }
+    if actions.iter().any(|action| action.deletes_original()) {
+        warn!(
+            "Safe mode: rule {} keeps the originals instead of deleting them.",
+            pattern
+        );
+    }
+    if rule.on_duplicate == Some(OnDuplicate::DeleteSource) {
+        warn!(
+            "Safe mode: rule {} skips duplicates instead of deleting them.",
+            pattern
+        );
+        rule.on_duplicate = Some(OnDuplicate::Skip);
+    }
+    Some(rule)
+}
+
+fn parse_rules(config: &Config, registry: &Registry, safe: bool) -> Vec<Rule> {
+    info!("Parsing rules ...");
+    if safe {
+        info!("Safe mode: files are never deleted or replaced.");
+    }
+
+    let hostname = gethostname::gethostname();
+    let hostname = hostname.to_string_lossy();
+    let rules: Vec<Rule> = config
+        .rules
+        .iter()
+        .filter(|r| is_enabled(r))
+        .filter(|r| applies_here(r, &hostname))
+        .filter_map(|r| parse_rule(r, config, registry))
+        .filter_map(|rule| if safe { make_safe(rule) } else { Some(rule) })
+        .collect();
+
+    info!("Successfully parsed {} rules.", rules.len());
+    rules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub(crate) fn sorter(dir: &Path, rules: &str) -> Sorter {
+        let config: Config = serde_yaml::from_str(rules).unwrap();
+        let mut options = Options::new(dir.join("wormhole"));
+        options.watch_delay = Duration::from_secs(0);
+        fs::create_dir_all(&options.watch_dir).unwrap();
+        Sorter::with_data_dir(&config, &Registry::new(), &options, &dir.join("data")).unwrap()
+    }
+
+    fn sort(sorter: &Sorter, session: &mut Session, path: &Path) {
+        let task = plan(sorter, session, path).unwrap();
+        let result = execute(sorter, &task);
+        finish(sorter, session, task, result);
+    }
+
+    #[test]
+    fn overwrites_are_quarantined() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        fs::create_dir(&target).unwrap();
+        fs::write(target.join("report.pdf"), "old").unwrap();
+        let sorter = sorter(
+            dir.path(),
+            &format!("rules:\n  - pattern: '*.pdf'\n    target: {:?}\n", target),
+        );
+        let mut session = Session::new(Arc::new(control::State::default()));
+        let path = dir.path().join("wormhole/report.pdf");
+        fs::write(&path, "new").unwrap();
+
+        sort(&sorter, &mut session, &path);
+
+        assert_eq!(
+            fs::read_to_string(target.join("report.pdf")).unwrap(),
+            "old"
+        );
+        let quarantined = sorter.quarantine_dir.join("report.pdf");
+        assert_eq!(fs::read_to_string(quarantined).unwrap(), "new");
+        let record = sorter.quarantine_dir.join("report.pdf.origin.yaml");
+        let origin: quarantine::Origin =
+            serde_yaml::from_str(&fs::read_to_string(record).unwrap()).unwrap();
+        assert_eq!(origin.original_path, path);
+        assert_eq!(origin.check, Some(quarantine::Check::Overwrite));
+        assert_eq!(session.control.status().quarantined, 1);
+    }
+
+    #[test]
+    fn safe_mode_ignores_deleting_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        fs::create_dir(&target).unwrap();
+        let sorter = sorter(
+            dir.path(),
+            &format!(
+                "safe: true\nrules:\n  - pattern: '*.tmp'\n    action: delete\n    target: {:?}\n  \
+                 - pattern: '*.pdf'\n    target: {:?}\n    on_duplicate: delete_source\n",
+                target, target
+            ),
+        );
+        let rules = sorter.rules();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].name, "*.pdf");
+        assert_eq!(rules[0].on_duplicate, Some(OnDuplicate::Skip));
+    }
+}
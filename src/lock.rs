@@ -0,0 +1,80 @@
+use std::fs::{self, File, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::process;
+
+use anyhow::{Context, Result};
+
+const LOCK_DIR_NAME: &str = "locks";
+
+/// Held as long as wurmloch watches a directory. The operating system releases the lock when
+/// wurmloch exits, even if it crashes.
+pub struct InstanceLock {
+    _file: File,
+}
+
+/// Makes sure that no other wurmloch watches the same directory. With `force`, a second
+/// instance only gets a warning.
+pub fn acquire(data_dir: &Path, watch_dir: &Path, force: bool) -> Result<Option<InstanceLock>> {
+    let watch_dir = watch_dir
+        .canonicalize()
+        .context(format!("Could not find {:?}.", watch_dir))?;
+    let lock_dir = data_dir.join(LOCK_DIR_NAME);
+    fs::create_dir_all(&lock_dir).context(format!("Could not create {:?}.", &lock_dir))?;
+    let path = lock_dir.join(format!("{:016x}.lock", fnv1a(&watch_dir)));
+    let mut file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .context(format!("Could not open lock file {:?}.", &path))?;
+
+    match file.try_lock() {
+        Ok(()) => {
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0))?;
+            writeln!(file, "{}\n{}", process::id(), watch_dir.display())?;
+            Ok(Some(InstanceLock { _file: file }))
+        }
+        Err(TryLockError::WouldBlock) => {
+            let mut content = String::new();
+            file.read_to_string(&mut content).ok();
+            let owner = content.lines().next().unwrap_or("unknown");
+            if force {
+                warn!(
+                    "{:?} is already watched by another wurmloch (PID {}). Both may handle \
+                     the same files.",
+                    &watch_dir, owner
+                );
+                Ok(None)
+            } else {
+                Err(anyhow!(
+                    "{:?} is already watched by another wurmloch (PID {}). Stop it first or \
+                     start with --force.",
+                    &watch_dir,
+                    owner
+                ))
+            }
+        }
+        Err(TryLockError::Error(e)) => Err(e).context(format!("Could not lock {:?}.", &path)),
+    }
+}
+
+/// Identifies the instance that watches a directory in the names of its lock, control and PID
+/// files
+pub fn key(watch_dir: &Path) -> Result<String> {
+    let watch_dir = watch_dir
+        .canonicalize()
+        .context(format!("Could not find {:?}.", watch_dir))?;
+    Ok(format!("{:016x}", fnv1a(&watch_dir)))
+}
+
+/// Stable across platforms and Rust versions, unlike the hasher of the standard library
+fn fnv1a(path: &Path) -> u64 {
+    path.to_string_lossy()
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+}
@@ -0,0 +1,428 @@
+use std::cell::RefCell;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{Context as _, Result};
+use chrono::{Local, SecondsFormat};
+use log::{Log, Metadata, Record};
+use pretty_env_logger::env_logger;
+use pretty_env_logger::env_logger::filter::Filter;
+use serde::Serialize;
+
+#[cfg(unix)]
+use {log::Level, std::os::unix::net::UnixDatagram, std::process};
+
+/// Size at which a log file is rotated, unless `--log-max-size` says otherwise
+pub const DEFAULT_MAX_SIZE: u64 = 10 << 20;
+/// Rotated log files that are kept, unless `--log-keep` says otherwise
+pub const DEFAULT_KEEP: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum LogFormat {
+    /// Human-readable, colored lines
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum LogTarget {
+    /// Standard error
+    Stderr,
+    /// The systemd journal, with the file, rule and target as fields
+    Journald,
+    /// The local syslog daemon, with the file, rule and target as structured data
+    Syslog,
+}
+
+/// What the current thread is working on. Added to every JSON log line.
+#[derive(Debug, Default, Serialize)]
+struct Context {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rule: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<String>,
+}
+
+thread_local! {
+    static CONTEXT: RefCell<Context> = RefCell::new(Context::default());
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    time: String,
+    level: String,
+    module: &'a str,
+    event: String,
+    #[serde(flatten)]
+    context: &'a Context,
+}
+
+/// Filter for the log level chosen with `-v` and `-q`. `None` leaves it to the environment
+/// variable.
+pub fn verbosity(verbose: u64, quiet: bool) -> Option<&'static str> {
+    match (verbose, quiet) {
+        (_, true) => Some("error"),
+        (0, false) => None,
+        (1, false) => Some("info"),
+        // Debug output of the libraries is mostly noise
+        (2, false) => Some("info,wurmloch=debug"),
+        _ => Some("trace"),
+    }
+}
+
+/// Filter directives from the command line, or else from the environment variable
+fn directives(verbosity: Option<&str>, env_name: &str) -> Option<String> {
+    verbosity
+        .map(String::from)
+        .or_else(|| env::var(env_name).ok())
+}
+
+fn filter(verbosity: Option<&str>, env_name: &str) -> Filter {
+    let mut builder = env_logger::filter::Builder::new();
+    if let Some(directives) = directives(verbosity, env_name) {
+        builder.parse(&directives);
+    }
+    builder.build()
+}
+
+/// JSON object of a log line for `--log-format json`
+fn json_line(record: &Record) -> serde_json::Result<String> {
+    CONTEXT.with(|context| {
+        serde_json::to_string(&Line {
+            time: Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+            level: record.level().to_string().to_lowercase(),
+            module: record.module_path().unwrap_or_default(),
+            event: record.args().to_string(),
+            context: &context.borrow(),
+        })
+    })
+}
+
+/// Sets up the logger. The log level is taken from `verbosity`, or else read from the given
+/// environment variable.
+pub fn init(
+    format: LogFormat,
+    target: LogTarget,
+    verbosity: Option<&str>,
+    env_name: &str,
+) -> Result<()> {
+    if target != LogTarget::Stderr {
+        return init_system(target, verbosity, env_name);
+    }
+
+    let mut builder = match format {
+        LogFormat::Text => pretty_env_logger::formatted_builder(),
+        LogFormat::Json => {
+            let mut builder = env_logger::Builder::new();
+            builder.format(|buf, record| writeln!(buf, "{}", json_line(record)?));
+            builder
+        }
+    };
+    if let Some(directives) = directives(verbosity, env_name) {
+        builder.parse_filters(&directives);
+    }
+    builder.init();
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn init_system(target: LogTarget, _verbosity: Option<&str>, _env_name: &str) -> Result<()> {
+    Err(anyhow!(
+        "Logging to {:?} is only available on Linux and other Unix systems.",
+        target
+    ))
+}
+
+#[cfg(unix)]
+fn init_system(target: LogTarget, verbosity: Option<&str>, env_name: &str) -> Result<()> {
+    let path = match target {
+        LogTarget::Journald => "/run/systemd/journal/socket",
+        _ if Path::new("/dev/log").exists() => "/dev/log",
+        _ => "/var/run/syslog",
+    };
+    let socket = UnixDatagram::unbound()?;
+    socket
+        .connect(path)
+        .context(format!("Could not connect to {:?}.", path))?;
+    let filter = filter(verbosity, env_name);
+    log::set_max_level(filter.filter());
+    log::set_boxed_logger(Box::new(SystemLogger {
+        target,
+        filter,
+        socket,
+        hostname: gethostname::gethostname().to_string_lossy().into_owned(),
+    }))?;
+    Ok(())
+}
+
+/// Sends log records to journald or syslog
+#[cfg(unix)]
+struct SystemLogger {
+    target: LogTarget,
+    filter: Filter,
+    socket: UnixDatagram,
+    hostname: String,
+}
+
+#[cfg(unix)]
+impl Log for SystemLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        let message = CONTEXT.with(|context| match self.target {
+            LogTarget::Journald => journald_entry(record, &context.borrow()),
+            _ => syslog_line(record, &context.borrow(), &self.hostname),
+        });
+        // There is nowhere left to report a failure to
+        self.socket.send(&message).ok();
+    }
+
+    fn flush(&self) {}
+}
+
+/// Syslog severity of a log level
+#[cfg(unix)]
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Entry in the native journald protocol
+#[cfg(unix)]
+fn journald_entry(record: &Record, context: &Context) -> Vec<u8> {
+    let mut entry = Vec::new();
+    let mut field = |name: &str, value: &str| {
+        entry.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            // Multi-line values are prefixed with their length instead
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            entry.push(b'=');
+        }
+        entry.extend_from_slice(value.as_bytes());
+        entry.push(b'\n');
+    };
+    field("MESSAGE", &record.args().to_string());
+    field("PRIORITY", &severity(record.level()).to_string());
+    field("SYSLOG_IDENTIFIER", "wurmloch");
+    field("CODE_MODULE", record.module_path().unwrap_or_default());
+    if let Some(path) = &context.path {
+        field("WURMLOCH_PATH", path);
+    }
+    if let Some(rule) = &context.rule {
+        field("WURMLOCH_RULE", rule);
+    }
+    if let Some(target) = &context.target {
+        field("WURMLOCH_TARGET", target);
+    }
+    entry
+}
+
+/// RFC 5424 message with the context as structured data
+#[cfg(unix)]
+fn syslog_line(record: &Record, context: &Context, hostname: &str) -> Vec<u8> {
+    // Facility "user"
+    let priority = 8 + severity(record.level());
+    let escape = |value: &str| {
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace(']', "\\]")
+    };
+    let params: Vec<String> = [
+        ("path", &context.path),
+        ("rule", &context.rule),
+        ("target", &context.target),
+    ]
+    .iter()
+    .filter_map(|(name, value)| {
+        value
+            .as_ref()
+            .map(|value| format!("{}=\"{}\"", name, escape(value)))
+    })
+    .collect();
+    let structured_data = if params.is_empty() {
+        String::from("-")
+    } else {
+        format!("[wurmloch@32473 {}]", params.join(" "))
+    };
+    format!(
+        "<{}>1 {} {} wurmloch {} - {} {}",
+        priority,
+        Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+        hostname,
+        process::id(),
+        structured_data,
+        record.args()
+    )
+    .into_bytes()
+}
+
+/// Clears the context of the current thread when dropped
+pub struct Scope;
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        CONTEXT.with(|context| *context.borrow_mut() = Context::default());
+    }
+}
+
+/// Adds a file that is processed to the log context until the returned scope is dropped
+pub fn file(path: &Path) -> Scope {
+    CONTEXT.with(|context| {
+        *context.borrow_mut() = Context {
+            path: Some(path.display().to_string()),
+            ..Context::default()
+        }
+    });
+    Scope
+}
+
+/// Adds the rule that is applied to the current file to the log context
+pub fn rule(rule: &str) {
+    CONTEXT.with(|context| context.borrow_mut().rule = Some(rule.to_string()));
+}
+
+/// Adds the destination of the current file to the log context
+pub fn target(target: &Path) {
+    CONTEXT.with(|context| context.borrow_mut().target = Some(target.display().to_string()));
+}
+
+/// Log file that is rotated when it grows too large
+#[derive(Debug, Clone)]
+pub struct LogFile {
+    pub path: PathBuf,
+    /// Size at which the file is renamed to `<path>.1`, and `<path>.1` to `<path>.2` and so on
+    pub max_size: u64,
+    /// Number of rotated files that are kept
+    pub keep: usize,
+}
+
+impl LogFile {
+    pub fn new(path: PathBuf) -> LogFile {
+        LogFile {
+            path,
+            max_size: DEFAULT_MAX_SIZE,
+            keep: DEFAULT_KEEP,
+        }
+    }
+}
+
+/// Sets up a logger that appends lines to a file, for services without a console
+pub fn init_file(
+    log_file: LogFile,
+    format: LogFormat,
+    verbosity: Option<&str>,
+    env_name: &str,
+) -> Result<()> {
+    let file = open(&log_file.path)?;
+    let written = file.metadata().map_or(0, |metadata| metadata.len());
+    let filter = filter(verbosity, env_name);
+    log::set_max_level(filter.filter());
+    log::set_boxed_logger(Box::new(FileLogger {
+        log_file,
+        format,
+        filter,
+        file: Mutex::new((file, written)),
+    }))?;
+    Ok(())
+}
+
+fn open(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context(format!("Could not open log file {:?}.", path))
+}
+
+struct FileLogger {
+    log_file: LogFile,
+    format: LogFormat,
+    filter: Filter,
+    /// The open file and how many bytes it contains
+    file: Mutex<(File, u64)>,
+}
+
+impl FileLogger {
+    /// Shifts the rotated files by one, dropping the oldest, and starts a new file
+    fn rotate(&self, file: &mut File) -> Result<()> {
+        let path = &self.log_file.path;
+        let rotated = |n: usize| {
+            let mut name = path.as_os_str().to_owned();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        if self.log_file.keep == 0 {
+            fs::remove_file(path).ok();
+        } else {
+            for n in (1..self.log_file.keep).rev() {
+                fs::rename(rotated(n), rotated(n + 1)).ok();
+            }
+            fs::rename(path, rotated(1)).context(format!("Could not rotate {:?}.", path))?;
+        }
+        *file = open(path)?;
+        Ok(())
+    }
+}
+
+impl Log for FileLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        let line = match self.format {
+            LogFormat::Text => format!(
+                "{} {:<5} {} > {}\n",
+                Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+                record.level(),
+                record.module_path().unwrap_or_default(),
+                record.args()
+            ),
+            LogFormat::Json => match json_line(record) {
+                Ok(line) => line + "\n",
+                Err(_) => return,
+            },
+        };
+        let mut guard = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        let (file, written) = &mut *guard;
+        if *written > 0 && *written + line.len() as u64 > self.log_file.max_size {
+            // The log is the only place left to report a failure to. It is tried again once
+            // the file has grown by another `max_size`.
+            if let Err(e) = self.rotate(file) {
+                let message = format!("Could not rotate the log: {:#}\n", e);
+                file.write_all(message.as_bytes()).ok();
+            }
+            *written = 0;
+        }
+        if file.write_all(line.as_bytes()).is_ok() {
+            *written += line.len() as u64;
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut guard) = self.file.lock() {
+            guard.0.flush().ok();
+        }
+    }
+}
@@ -1,242 +1,820 @@
-#[macro_use]
-extern crate log;
-
-#[macro_use]
-extern crate anyhow;
-
 use std::fs;
-use std::io::prelude::Write;
+use std::io::{self, BufRead, Write};
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::{Context, Result};
-use clap::Parser;
-use globset::{Glob, GlobMatcher};
-use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
-use serde::{Deserialize, Serialize};
+use clap::{CommandFactory, Parser};
+
+#[cfg(unix)]
+use wurmloch::daemon;
+use wurmloch::journal::{self, Selection};
+use wurmloch::poll::Backend;
+use wurmloch::trash::TrashSettings;
+use wurmloch::{
+    config, control, data_dir, import, index, launchd, logging, open_index, open_journal,
+    open_trash, presets, provenance, s3, secrets, stats, suggest, systemd, tui, units, update,
+    webdav, Options, Output, Registry, APP_NAME,
+};
 
-const APP_NAME: &str = "Wurmloch";
-const RULES_FILE_NAME: &str = "rules.yaml";
+mod man;
+#[cfg(windows)]
+mod service;
 
 /// Sort your filesystem by turning a folder into a wormhole
 #[derive(Parser, Debug)]
-#[clap(name = APP_NAME)]
+#[clap(
+    name = APP_NAME,
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// This directory will be turned into a wormhole
     #[clap(name = "WATCH_DIR", required = true, parse(from_os_str))]
-    watch_dir: PathBuf,
+    watch_dir: Option<PathBuf>,
 
     /// React to file events after this delay (in seconds)
     #[clap(short, long, default_value = "2")]
     watch_delay: u64,
+
+    /// Continue in the background and write the log to a file
+    #[cfg(unix)]
+    #[clap(long)]
+    daemon: bool,
+
+    /// Only log what would happen to files without changing anything
+    #[clap(long)]
+    dry_run: bool,
+
+    /// Start even if another wurmloch already watches the same directory
+    #[clap(long)]
+    force: bool,
+
+    /// Never delete or replace files, like `safe: true` in the rule file
+    #[clap(long)]
+    safe: bool,
+
+    /// Log how every rule was evaluated for each file and record it in the journal
+    #[clap(long)]
+    trace_decisions: bool,
+
+    /// Format of the log output
+    #[clap(long, arg_enum, default_value = "text")]
+    log_format: logging::LogFormat,
+
+    /// Where to write the log to. journald and syslog are only available on Unix systems.
+    #[clap(long, arg_enum, default_value = "stderr")]
+    log_target: logging::LogTarget,
+
+    /// Write the log to this file instead, and rotate it when it grows too large
+    #[clap(
+        long,
+        parse(from_os_str),
+        value_name = "PATH",
+        conflicts_with = "log-target"
+    )]
+    log_file: Option<PathBuf>,
+
+    /// Size at which the log file is rotated
+    #[clap(long, default_value = "10MiB", parse(try_from_str = units::parse_size))]
+    log_max_size: u64,
+
+    /// Number of rotated log files that are kept
+    #[clap(long, default_value = "5", value_name = "N")]
+    log_keep: usize,
+
+    /// Log more: -v for what happens to files, -vv for details, -vvv for everything. Overrides
+    /// WURMLOCH_LOG.
+    #[clap(short, long, parse(from_occurrences), global = true)]
+    verbose: u64,
+
+    /// Do not print anything and log only errors
+    #[clap(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Watch directory of the running instance that pause, status, stop and friends talk to,
+    /// if several are running
+    #[clap(long, global = true, parse(from_os_str), value_name = "DIR")]
+    instance: Option<PathBuf>,
+
+    /// What to print for every file apart from the log
+    #[clap(long, arg_enum, default_value = "human", global = true)]
+    print: Output,
+
+    /// Serve Prometheus metrics on this address, e.g. 127.0.0.1:9184
+    #[clap(long)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Only warn about rules that would move files in circles instead of refusing them
+    #[clap(long)]
+    permissive: bool,
+
+    /// Also react to files in subdirectories of the watch directory
+    #[clap(long, default_value = "true", parse(try_from_str), value_name = "BOOL")]
+    recursive: bool,
+
+    /// Confirm newly created rules without asking
+    #[clap(short, long)]
+    yes: bool,
+
+    /// Number of files that are sorted at the same time
+    #[clap(short = 'j', long, default_value = "4", value_name = "N")]
+    workers: usize,
+
+    /// Number of files that can wait for a worker. Further files are picked up once there is
+    /// room again.
+    #[clap(long, default_value = "10000", value_name = "N")]
+    queue_size: usize,
+
+    /// How to learn about new files. `auto` polls on network shares.
+    #[clap(long, arg_enum, default_value = "auto")]
+    backend: Backend,
+
+    /// How often the poll backend looks for new files, e.g. 30s
+    #[clap(long, default_value = "10s", parse(try_from_str = humantime::parse_duration))]
+    poll_interval: Duration,
+
+    /// Wait for the watch directory to appear, e.g. a removable drive or a network share,
+    /// instead of failing if it does not exist
+    #[clap(long)]
+    wait_for_dir: bool,
+
+    /// Raise fs.inotify.max_user_watches if the watch directory has more folders than inotify
+    /// may watch. Requires root.
+    #[clap(long)]
+    raise_watch_limit: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct ConfigRule {
-    pattern: String,
-    target: PathBuf,
-}
-
-impl ConfigRule {
-    fn examples() -> [ConfigRule; 3] {
-        [
-            ConfigRule {
-                pattern: String::from("*.jpg"),
-                target: dirs::picture_dir().unwrap_or_default(),
-            },
-            ConfigRule {
-                pattern: String::from("*.pdf"),
-                target: dirs::document_dir().unwrap_or_default(),
-            },
-            ConfigRule {
-                pattern: String::from("*.mp3"),
-                target: dirs::audio_dir().unwrap_or_default(),
-            },
-        ]
+impl Args {
+    fn output(&self) -> Output {
+        if self.quiet {
+            Output::Off
+        } else {
+            self.print
+        }
     }
+
+    fn options(&self) -> Options {
+        Options {
+            watch_delay: Duration::from_secs(self.watch_delay),
+            dry_run: self.dry_run,
+            force: self.force,
+            metrics_addr: self.metrics_addr,
+            permissive: self.permissive,
+            recursive: self.recursive,
+            yes: self.yes,
+            workers: self.workers,
+            queue_size: self.queue_size,
+            backend: self.backend,
+            poll_interval: self.poll_interval,
+            wait_for_dir: self.wait_for_dir,
+            raise_watch_limit: self.raise_watch_limit,
+            safe: self.safe,
+            trace_decisions: self.trace_decisions,
+            output: self.output(),
+            ..Options::new(self.watch_dir.clone().unwrap())
+        }
+    }
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Update wurmloch to the newest release
+    SelfUpdate {
+        /// Only check whether an update is available
+        #[clap(long)]
+        check_only: bool,
+
+        /// Release channel to update from
+        #[clap(long, arg_enum, default_value = "stable")]
+        channel: update::Channel,
+    },
+
+    /// Manage files that were deleted by rules
+    Trash {
+        #[clap(subcommand)]
+        command: TrashCommand,
+    },
+
+    /// Pause sorting of the running instance. New files stay in the wormhole.
+    Pause,
+
+    /// Resume sorting of the running instance
+    Resume,
+
+    /// Make the running instance read its configuration again
+    Reload,
+
+    /// Show statistics of the running instance
+    Status {
+        /// Number of recent actions to show
+        #[clap(long, default_value = "10")]
+        last: usize,
+    },
+
+    /// Live view of the running instance
+    Tui,
+
+    /// Show the state of the running instance as a tray icon
+    Tray,
+
+    /// Search for processed files
+    Find {
+        /// Matched against the original file name and the destination, e.g. `*.pdf`
+        pattern: String,
+
+        /// Only files that were handled by this rule, e.g. "*.pdf"
+        #[clap(long)]
+        rule: Option<String>,
+
+        /// Only files processed since this time, e.g. `2h` or "2021-03-14 15:00"
+        #[clap(long)]
+        since: Option<String>,
+
+        /// Only files processed until this time
+        #[clap(long)]
+        until: Option<String>,
+    },
+
+    /// Report which rules sorted how many files, where they went, which files no rule matched
+    /// and what failed
+    Stats {
+        /// Only files processed since this time, e.g. `7d` or "2021-03-14 15:00"
+        #[clap(long, default_value = "30d")]
+        since: String,
+    },
+
+    /// Suggest rules for the kinds of files that no rule matched
+    Suggest {
+        /// Only files that were ignored since this time, e.g. `7d` or "2021-03-14 15:00"
+        #[clap(long, default_value = "30d")]
+        since: String,
+    },
+
+    /// Manage the files that no rule matched and that are not evaluated again until they or the
+    /// rules change
+    State {
+        #[clap(subcommand)]
+        command: StateCommand,
+    },
+
+    /// Forget placed files that have been removed or changed, so that new files with their old
+    /// content are no duplicates anymore
+    PruneIndex,
+
+    /// Reverse the most recent moves and deletions
+    Undo {
+        /// Number of actions to reverse
+        #[clap(long, default_value = "1", conflicts_with = "since")]
+        last: usize,
+
+        /// Reverse all actions since this time, e.g. `2h` or "2021-03-14 15:00"
+        #[clap(long)]
+        since: Option<String>,
+    },
+
+    /// Create the rule file from a preset or with example rules
+    Init {
+        /// Built-in rule set to start with
+        #[clap(long, possible_values = presets::NAMES)]
+        preset: Option<String>,
+
+        /// Replace an existing rule file. It is kept as rules.yaml.bak.
+        #[clap(long)]
+        force: bool,
+    },
+
+    /// Sort the files that are in a directory now and exit
+    Sort {
+        #[clap(parse(from_os_str))]
+        dir: PathBuf,
+
+        /// Only log what would happen to files without changing anything
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Number of files that are sorted at the same time
+        #[clap(short = 'j', long, default_value = "4", value_name = "N")]
+        workers: usize,
+
+        /// Sort even if wurmloch already watches the directory
+        #[clap(long)]
+        force: bool,
+
+        /// Log how every rule was evaluated for each file and record it in the journal
+        #[clap(long)]
+        trace_decisions: bool,
+    },
+
+    /// Write what the rules would do with the files in a directory to a file for review. Nothing
+    /// is changed.
+    Plan {
+        #[clap(parse(from_os_str))]
+        dir: PathBuf,
+
+        /// Where to write the plan
+        #[clap(short, long, parse(from_os_str), default_value = "plan.json")]
+        output: PathBuf,
+    },
+
+    /// Carry out a plan exactly as it was written. Files that have changed since are left
+    /// alone.
+    Apply {
+        #[clap(parse(from_os_str))]
+        plan: PathBuf,
+
+        /// Apply even if wurmloch already watches the directory
+        #[clap(long)]
+        force: bool,
+    },
+
+    /// Show what rules that delete files by age would delete in a directory and confirm them.
+    /// Until then, they only log what they would delete.
+    ConfirmCleanup {
+        #[clap(parse(from_os_str))]
+        dir: PathBuf,
+
+        /// Confirm without asking
+        #[clap(short, long)]
+        yes: bool,
+    },
+
+    /// Move sorted files back to where they came from
+    Restore {
+        /// Files or glob patterns like `~/Documents/Invoices/*.pdf`
+        #[clap(required = true)]
+        paths: Vec<String>,
+    },
+
+    /// Convert the rules of organize or Hazel into wurmloch rules. Features without an
+    /// equivalent are reported and left out.
+    Import {
+        /// Tool that the rules come from
+        #[clap(long, arg_enum)]
+        format: import::Format,
+
+        /// File with the rules, e.g. organize's `config.yaml`
+        #[clap(parse(from_os_str))]
+        file: PathBuf,
+
+        /// Write the rules to this file instead of the standard output
+        #[clap(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    /// Store the access key of a bucket for `s3://` targets in the keyring
+    S3Login {
+        /// Name of the bucket
+        bucket: String,
+    },
+
+    /// Store the user and password of a WebDAV server, e.g. a Nextcloud app password, in the
+    /// keyring
+    WebdavLogin {
+        /// Host of the server as written in targets, e.g. `cloud.example.com`
+        host: String,
+    },
+
+    /// Store the value of a secret that the rule file reads with `keyring: <entry>`
+    SetSecret {
+        /// Name of the keyring entry
+        entry: String,
+    },
+
+    /// Stop wurmloch that was started with --daemon
+    #[cfg(unix)]
+    Stop,
+
+    /// Run wurmloch as a systemd service that starts on login
+    InstallService {
+        /// Install a service of the current user
+        #[clap(long)]
+        user: bool,
+
+        /// This directory will be turned into a wormhole
+        #[clap(name = "WATCH_DIR", parse(from_os_str))]
+        watch_dir: PathBuf,
+    },
+
+    /// Run wurmloch on login with launchd (macOS)
+    InstallLaunchd {
+        /// This directory will be turned into a wormhole
+        #[clap(name = "WATCH_DIR", parse(from_os_str))]
+        watch_dir: PathBuf,
+    },
+
+    /// Stop wurmloch and remove it from launchd (macOS)
+    UninstallLaunchd,
+
+    /// Print a completion script for a shell, e.g. `wurmloch completions bash >
+    /// /etc/bash_completion.d/wurmloch`
+    Completions {
+        #[clap(arg_enum)]
+        shell: clap_complete::Shell,
+    },
+
+    /// Print the man page, e.g. `wurmloch man | man -l -`
+    Man,
+
+    /// Run wurmloch as a Windows service
+    #[cfg(windows)]
+    Service {
+        #[clap(subcommand)]
+        command: service::ServiceCommand,
+    },
 }
 
-#[derive(Debug)]
-struct Rule {
-    matcher: GlobMatcher,
-    target: PathBuf,
+#[derive(clap::Subcommand, Debug)]
+enum StateCommand {
+    /// Forget them, so that they are evaluated again on the next rescan or restart
+    Clear,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum TrashCommand {
+    /// List all files in the trash
+    List,
+
+    /// Move a file from the trash back to where it was deleted
+    Restore {
+        /// Entry ID as shown by `trash list`
+        id: String,
+
+        /// Restore into this directory instead of the original location
+        #[clap(long, parse(from_os_str))]
+        to: Option<PathBuf>,
+    },
+
+    /// Permanently delete all files in the trash
+    Empty,
 }
 
 fn main() -> Result<()> {
-    pretty_env_logger::init_custom_env(&format!("{}_LOG", APP_NAME.to_uppercase()));
     let args = Args::parse();
+    // A service has no console and sets up its own logger
+    #[cfg(windows)]
+    let is_service = matches!(
+        &args.command,
+        Some(Command::Service {
+            command: service::ServiceCommand::Run { .. }
+        })
+    );
+    #[cfg(not(windows))]
+    let is_service = false;
+    // Forks before any thread is started and before the logger decides on colors
+    #[cfg(unix)]
+    let _pid_file = match (&args.watch_dir, args.daemon) {
+        (Some(watch_dir), true) => Some(daemon::start(watch_dir, args.force)?),
+        _ => None,
+    };
+    if !is_service {
+        let verbosity = logging::verbosity(args.verbose, args.quiet);
+        let env_name = format!("{}_LOG", APP_NAME.to_uppercase());
+        match &args.log_file {
+            Some(path) => logging::init_file(
+                logging::LogFile {
+                    path: path.clone(),
+                    max_size: args.log_max_size,
+                    keep: args.log_keep,
+                },
+                args.log_format,
+                verbosity,
+                &env_name,
+            )?,
+            None => logging::init(args.log_format, args.log_target, verbosity, &env_name)?,
+        }
+    }
 
-    check_watch_directory(&args.watch_dir)?;
-
-    let (config_path, config) = load_or_create_config()?;
-    let mut rules = parse_rules(&config)?;
-    let (tx, rx) = channel();
-
-    // Start watching
-    let watch_delay = Duration::from_secs(args.watch_delay);
-    let _conf_watcher = watch(Sender::clone(&tx), &config_path, watch_delay);
-    let _dir_watcher = watch(tx, &args.watch_dir, watch_delay);
-
-    loop {
-        match rx.recv() {
-            Ok(event) => match event {
-                DebouncedEvent::Create(path) => handle_file(&rules, &path)?,
-                DebouncedEvent::Write(path) => {
-                    if path == config_path {
-                        // Configuration file changed
-                        rules = parse_rules(&fs::read_to_string(&path).unwrap())?;
-                    }
-                }
-                _ => trace!("Unhandled notify event: {:#?}.", event),
-            },
-            Err(e) => error!("{}", e),
+    match &args.command {
+        Some(Command::SelfUpdate {
+            check_only,
+            channel,
+        }) => update::self_update(*channel, *check_only),
+        Some(Command::Trash { command }) => manage_trash(command),
+        Some(Command::Pause) => send_control(&args, "pause"),
+        Some(Command::Resume) => send_control(&args, "resume"),
+        Some(Command::Reload) => send_control(&args, "reload"),
+        Some(Command::Status { last }) => send_control(&args, &format!("status {}", last)),
+        Some(Command::Tui) => tui::run(
+            &data_dir()?,
+            args.instance.as_deref(),
+            &config::rules_path()?,
+        ),
+        Some(Command::Tray) => show_tray(args.instance.as_deref()),
+        Some(Command::Find {
+            pattern,
+            rule,
+            since,
+            until,
+        }) => find(pattern, rule.as_deref(), since.as_deref(), until.as_deref()),
+        Some(Command::Stats { since }) => stats(since),
+        Some(Command::Suggest { since }) => suggest(since),
+        Some(Command::State {
+            command: StateCommand::Clear,
+        }) => {
+            let cleared = open_index()?.clear_evaluated()?;
+            println!("Forgot {} files. They are evaluated again.", cleared);
+            Ok(())
+        }
+        Some(Command::PruneIndex) => {
+            let pruned = open_index()?.prune_hashes()?;
+            println!("Removed {} files from the index.", pruned);
+            Ok(())
+        }
+        Some(Command::Undo { last, since }) => undo(*last, since.as_deref()),
+        Some(Command::Init { preset, force }) => {
+            let path = config::init(preset.as_deref(), *force)?;
+            println!("Created {:?}.", path);
+            Ok(())
+        }
+        Some(Command::Sort {
+            dir,
+            dry_run,
+            workers,
+            force,
+            trace_decisions,
+        }) => {
+            let options = Options {
+                dry_run: *dry_run,
+                workers: *workers,
+                force: *force,
+                trace_decisions: *trace_decisions,
+                output: args.output(),
+                ..Options::new(
+                    dir.canonicalize()
+                        .context(format!("Could not find {:?}.", dir))?,
+                )
+            };
+            wurmloch::sort(&options, Registry::new(), Arc::default())
+        }
+        Some(Command::Plan { dir, output }) => {
+            let options = Options::new(
+                dir.canonicalize()
+                    .context(format!("Could not find {:?}.", dir))?,
+            );
+            wurmloch::write_plan(&options, Registry::new(), output)
+        }
+        Some(Command::Apply { plan, force }) => {
+            wurmloch::apply_plan(plan, *force, args.output(), Registry::new(), Arc::default())
+        }
+        Some(Command::ConfirmCleanup { dir, yes }) => {
+            let options = Options::new(
+                dir.canonicalize()
+                    .context(format!("Could not find {:?}.", dir))?,
+            );
+            wurmloch::confirm_cleanup(&options, Registry::new(), *yes)
         }
+        Some(Command::Restore { paths }) => restore(paths),
+        Some(Command::Import {
+            format,
+            file,
+            output,
+        }) => import_rules(*format, file, output.as_deref()),
+        Some(Command::S3Login { bucket }) => s3_login(bucket),
+        Some(Command::WebdavLogin { host }) => webdav_login(host),
+        Some(Command::SetSecret { entry }) => set_secret(entry),
+        Some(Command::InstallService { user, watch_dir }) => {
+            systemd::install_service(watch_dir, *user)
+        }
+        #[cfg(unix)]
+        Some(Command::Stop) => daemon::stop(args.instance.as_deref()),
+        Some(Command::InstallLaunchd { watch_dir }) => launchd::install(watch_dir),
+        Some(Command::UninstallLaunchd) => launchd::uninstall(),
+        Some(Command::Completions { shell }) => {
+            let bin_name = env!("CARGO_BIN_NAME");
+            clap_complete::generate(*shell, &mut Args::command(), bin_name, &mut io::stdout());
+            Ok(())
+        }
+        Some(Command::Man) => {
+            print!(
+                "{}",
+                man::render(&Args::command().name(env!("CARGO_BIN_NAME")))
+            );
+            Ok(())
+        }
+        #[cfg(windows)]
+        Some(Command::Service { command }) => service::manage(command),
+        None => wurmloch::run(&args.options(), Registry::new(), Arc::default()),
     }
 }
 
-fn check_watch_directory(path: &Path) -> Result<()> {
-    if path.is_relative() {
-        return Err(anyhow!(
-            "Watch directory {:?} must be an absolute path.",
-            path
-        ));
-    } else if !path.exists() {
-        return Err(anyhow!("Watch directory {:?} does not exist.", path));
-    } else if !path.is_dir() {
-        return Err(anyhow!("Watch directory {:?} is not a directory.", path));
+fn manage_trash(command: &TrashCommand) -> Result<()> {
+    let trash = open_trash(TrashSettings::default())?;
+    match command {
+        TrashCommand::List => {
+            for entry in trash.entries()? {
+                println!(
+                    "{}  {}  {:>12}  {}",
+                    &entry.id,
+                    entry.deleted_at.format("%Y-%m-%d %H:%M:%S"),
+                    entry.size,
+                    entry.original_path.display()
+                );
+            }
+        }
+        TrashCommand::Restore { id, to } => {
+            let path = trash.restore(id, to.as_deref())?;
+            println!("Restored {}.", path.display());
+        }
+        TrashCommand::Empty => {
+            let count = trash.empty()?;
+            println!("Deleted {} files permanently.", count);
+        }
     }
     Ok(())
 }
 
-fn watch(
-    tx: Sender<DebouncedEvent>,
-    path: &Path,
-    watch_delay: Duration,
-) -> Result<RecommendedWatcher> {
-    let mut watcher: RecommendedWatcher = Watcher::new(tx, watch_delay)
-        .context("Could not initialize file watcher for this platform.")?;
-
-    watcher
-        .watch(path, RecursiveMode::Recursive)
-        .context(format!("Could not watch {:#?}.", path))?;
-
-    info!("Watching {:?} ...", path);
-    Ok(watcher)
-}
-
-fn handle_file(rules: &[Rule], path: &Path) -> Result<()> {
-    if let Some(filename) = path.file_name() {
-        debug!(" --- Processing {:?} --- ", filename);
-        let mut rule_found = false;
-        for rule in rules.iter() {
-            if rule.matcher.is_match(filename) {
-                if !rule_found {
-                    // First rule match = highest priority match. Apply rule.
-                    debug!("Rule {} matched.", &rule.matcher.glob().to_string());
-                    match fs::rename(&path, &rule.target.join(filename)) {
-                        Ok(_) => {
-                            debug!("Moved {:?} to {:?}.", filename, &rule.target);
-                            rule_found = true;
-                        }
-                        Err(e) => {
-                            error!("Could not move {:?} to {:?}.", filename, &rule.target);
-                            error!("Reason: {}.", e);
-                        }
-                    }
-                } else {
-                    // Consecutive rule matches are ignored
-                    debug!(
-                        "Rule '{}' would have also matched but has lower priority.",
-                        &rule.matcher.glob().to_string()
-                    );
-                }
+#[cfg(all(feature = "tray", target_os = "linux"))]
+fn show_tray(instance: Option<&Path>) -> Result<()> {
+    wurmloch::tray::run(&data_dir()?, instance)
+}
+
+#[cfg(not(all(feature = "tray", target_os = "linux")))]
+fn show_tray(_instance: Option<&Path>) -> Result<()> {
+    Err(anyhow::anyhow!(
+        "wurmloch was built without the tray icon. It is available on Linux with --features tray."
+    ))
+}
+
+fn send_control(args: &Args, command: &str) -> Result<()> {
+    print!(
+        "{}",
+        control::send(&data_dir()?, args.instance.as_deref(), command)?
+    );
+    Ok(())
+}
+
+fn find(pattern: &str, rule: Option<&str>, since: Option<&str>, until: Option<&str>) -> Result<()> {
+    let query = index::Query {
+        pattern,
+        rule,
+        since: since.map(journal::parse_time).transpose()?,
+        until: until.map(journal::parse_time).transpose()?,
+    };
+    for found in open_index()?.find(&query)? {
+        println!(
+            "{}  {}  {}  ->  {}",
+            found.time.format("%Y-%m-%d %H:%M:%S"),
+            found.rule,
+            found.source.display(),
+            match &found.destination {
+                Some(destination) => destination.display().to_string(),
+                None => String::from("deleted"),
             }
-        }
-        if !rule_found {
-            warn!("No rule found for file {:?}. Ignored.", filename);
-        }
+        );
     }
     Ok(())
 }
 
-fn load_or_create_config() -> Result<(PathBuf, String)> {
-    let config: String;
-
-    // ensure that the config directory exists
-    let config_dir = dirs::config_dir().context("Could not determine configuration directory.")?;
-    let app_dir = config_dir.join(APP_NAME);
-    fs::create_dir_all(&app_dir).context(format!(
-        "Could not create configuration directory {:?}.",
-        &app_dir
-    ))?;
-
-    // ensure that a rule file exists
-    let rule_path = app_dir.join(RULES_FILE_NAME);
-    if !rule_path.exists() {
-        // no config file, create an example
-        let mut file = fs::File::create(&rule_path).context(format!(
-            "Could not create configuration file {:?}.",
-            &rule_path
-        ))?;
-        config = String::from(serde_yaml::to_string(&ConfigRule::examples()).unwrap());
-        file.write_all(config.as_bytes()).unwrap();
-        info!("Created example configuration {:?}.", &rule_path);
+/// The rule file and its rules, if it has been created yet
+fn current_config() -> Result<(PathBuf, Option<wurmloch::Config>)> {
+    let rules_path = config::rules_path()?;
+    if rules_path.exists() {
+        let config = config::load(&rules_path)?;
+        Ok((rules_path, Some(config)))
     } else {
-        // use existing config
-        config = fs::read_to_string(&rule_path).context(format!(
-            "Could not read configuration file {:#?}.",
-            &rule_path
-        ))?;
-        info!("Found existing configuration {:?}.", &rule_path);
+        Ok((rules_path, None))
     }
+}
 
-    Ok((rule_path, config))
+fn stats(since: &str) -> Result<()> {
+    let since = journal::parse_time(since)?;
+    let (_, config) = current_config()?;
+    let rules: Vec<&str> = config
+        .iter()
+        .flat_map(|config| &config.rules)
+        .filter(|rule| rule.enabled != Some(false))
+        .map(|rule| rule.name())
+        .collect();
+    print!("{}", stats::report(&open_index()?, &rules, Some(since))?);
+    Ok(())
 }
 
-fn is_valid_target(path: &Path) -> bool {
-    if path.is_relative() {
-        error!("Target {:?} is not an absolute path. Rule ignored.", &path);
-        return false;
-    } else if !path.exists() {
-        error!("Target {:?} does not exist. Rule ignored.", &path);
-        return false;
-    } else if !path.is_dir() {
-        error!("Target {:?} is not a directory. Rule ignored.", &path);
-        return false;
+fn suggest(since: &str) -> Result<()> {
+    let since = journal::parse_time(since)?;
+    let (rules_path, config) = current_config()?;
+    let suggestions = suggest::suggest(&open_index()?, config.as_ref(), Some(since))?;
+    if suggestions.is_empty() {
+        println!("No rules to suggest. Every kind of file that no rule matched was ignored less than 3 times.");
+        return Ok(());
     }
-    true
-}
-
-fn parse_rules(config: &str) -> Result<Vec<Rule>> {
-    info!("Parsing rules ...");
-
-    let yaml: Vec<ConfigRule> =
-        serde_yaml::from_str(config).context("Failed to parse rule configuration.")?;
-
-    let rules: Vec<Rule> = yaml
-        .into_iter()
-        .filter_map(|r| match Glob::new(&r.pattern) {
-            Ok(glob) => {
-                if is_valid_target(&r.target) {
-                    Some(Rule {
-                        matcher: glob.compile_matcher(),
-                        target: r.target,
-                    })
-                } else {
-                    None
-                }
-            }
-            Err(e) => {
-                error!(
-                    "Pattern {} cannot be compiled. Rule ignored. Reason: {}.",
-                    &r.pattern, e
-                );
-                None
-            }
-        })
+    for suggestion in &suggestions {
+        println!("{}", suggestion);
+    }
+    println!("Add the rules you want to {:?}.", rules_path);
+    Ok(())
+}
+
+fn undo(last: usize, since: Option<&str>) -> Result<()> {
+    let selection = match since {
+        Some(since) => Selection::Since(journal::parse_time(since)?),
+        None => Selection::Last(last),
+    };
+    let trash = open_trash(TrashSettings::default())?;
+    let reversals = journal::undo(&open_journal()?, &trash, selection)?;
+    if reversals.is_empty() {
+        println!("Nothing to undo.");
+    }
+    for reversal in reversals {
+        match reversal.result {
+            Ok(()) => println!(
+                "Restored {} (rule {}).",
+                reversal.from.display(),
+                reversal.rule
+            ),
+            Err(e) => println!("Could not undo {}: {:#}", reversal.id, e),
+        }
+    }
+    Ok(())
+}
+
+fn restore(patterns: &[String]) -> Result<()> {
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        let matches: Vec<_> = glob::glob(pattern)
+            .context(format!("Invalid pattern {:?}.", pattern))?
+            .filter_map(|path| path.ok())
+            .filter(|path| !provenance::is_record_file(path))
+            .collect();
+        if matches.is_empty() {
+            println!("No files match {}.", pattern);
+        }
+        paths.extend(matches);
+    }
+
+    let trash = open_trash(TrashSettings::default())?;
+    for restoration in provenance::restore(&open_journal()?, &open_index()?, &trash, &paths)? {
+        match restoration.result {
+            Ok(origin) => println!(
+                "Restored {} to {}.",
+                restoration.path.display(),
+                origin.display()
+            ),
+            Err(e) => println!("Could not restore {}: {:#}", restoration.path.display(), e),
+        }
+    }
+    Ok(())
+}
+
+fn s3_login(bucket: &str) -> Result<()> {
+    let access_key_id = prompt("Access key ID: ")?;
+    let secret_access_key = prompt("Secret access key: ")?;
+    s3::store_credentials(bucket, &access_key_id, &secret_access_key)?;
+    println!(
+        "Stored the credentials of bucket {} in the keyring.",
+        bucket
+    );
+    Ok(())
+}
+
+fn webdav_login(host: &str) -> Result<()> {
+    let user = prompt("User: ")?;
+    let password = prompt("Password: ")?;
+    webdav::store_credentials(host, &user, &password)?;
+    println!("Stored the credentials of {} in the keyring.", host);
+    Ok(())
+}
+
+fn import_rules(format: import::Format, file: &Path, output: Option<&Path>) -> Result<()> {
+    let imported = import::import(format, file)?;
+    // The notes stay with the rules as comments
+    let mut yaml: String = imported
+        .notes
+        .iter()
+        .map(|note| format!("# {}\n", note))
         .collect();
+    yaml.push_str(&serde_yaml::to_string(&imported.rules)?);
+    yaml.push('\n');
+    match output {
+        Some(output) => {
+            fs::write(output, yaml).context(format!("Could not write {:?}.", output))?;
+            for note in imported.notes.iter() {
+                println!("{}", note);
+            }
+            println!("Wrote {} rules to {:?}.", imported.rules.len(), output);
+        }
+        None => print!("{}", yaml),
+    }
+    Ok(())
+}
+
+fn set_secret(entry: &str) -> Result<()> {
+    let value = prompt("Value: ")?;
+    secrets::store(entry, &value)?;
+    println!("Stored secret {} in the keyring.", entry);
+    Ok(())
+}
 
-    info!("Successfully parsed {} rules.", rules.len());
-    Ok(rules)
+fn prompt(text: &str) -> Result<String> {
+    print!("{}", text);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
 }
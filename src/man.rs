@@ -0,0 +1,122 @@
+use clap::{Arg, Command};
+use roff::{bold, italic, roman, Inline, Roff};
+
+/// Renders a man page in roff from the definitions of the command line
+pub fn render(command: &Command) -> String {
+    let name = command.get_name();
+    let mut page = Roff::new();
+    page.control(
+        "TH",
+        [
+            name.to_uppercase().as_str(),
+            "1",
+            "",
+            &format!("{} {}", name, command.get_version().unwrap_or_default()),
+        ],
+    );
+
+    page.control("SH", ["NAME"]);
+    page.text([roman(format!(
+        "{} - {}",
+        name,
+        command.get_about().unwrap_or(env!("CARGO_PKG_DESCRIPTION"))
+    ))]);
+
+    page.control("SH", ["SYNOPSIS"]);
+    let mut synopsis = vec![bold(name), roman(" [OPTIONS]")];
+    for arg in command.get_positionals() {
+        synopsis.push(roman(" "));
+        synopsis.push(italic(value_name(arg)));
+    }
+    page.text(synopsis);
+    page.control("br", []);
+    page.text([bold(name), roman(" "), italic("COMMAND")]);
+
+    page.control("SH", ["OPTIONS"]);
+    options(&mut page, &command.get_arguments().collect::<Vec<_>>());
+
+    page.control("SH", ["COMMANDS"]);
+    commands(&mut page, command, name);
+
+    page.control("SH", ["ENVIRONMENT"]);
+    page.control("TP", []);
+    page.text([bold("WURMLOCH_LOG")]);
+    page.text([roman(
+        "Log level, e.g. info or debug. Only errors are logged if it is not set. -v and -q \
+         override it.",
+    )]);
+
+    page.control("SH", ["FILES"]);
+    page.text([roman(
+        "The rules are read from rules.yaml in the Wurmloch directory of the configuration \
+         directory, e.g. ~/.config/Wurmloch/rules.yaml.",
+    )]);
+    page.render()
+}
+
+fn options(page: &mut Roff, args: &[&Arg]) {
+    for arg in args.iter().filter(|arg| !arg.is_hide_set()) {
+        page.control("TP", []);
+        let mut flags: Vec<Inline> = Vec::new();
+        if let Some(short) = arg.get_short() {
+            flags.push(bold(format!("-{}", short)));
+        }
+        if let Some(long) = arg.get_long() {
+            if !flags.is_empty() {
+                flags.push(roman(", "));
+            }
+            flags.push(bold(format!("--{}", long)));
+        }
+        if arg.is_positional() {
+            flags.push(italic(value_name(arg)));
+        } else if arg.is_takes_value_set() {
+            flags.push(roman(" "));
+            flags.push(italic(value_name(arg)));
+        }
+        page.text(flags);
+        if let Some(help) = arg.get_long_help().or_else(|| arg.get_help()) {
+            page.text([roman(help)]);
+        }
+    }
+}
+
+/// Lists the subcommands with their options, and their subcommands after them
+fn commands(page: &mut Roff, command: &Command, prefix: &str) {
+    let subcommands = command
+        .get_subcommands()
+        .filter(|subcommand| !subcommand.is_hide_set() && subcommand.get_name() != "help");
+    for subcommand in subcommands {
+        let name = format!("{} {}", prefix, subcommand.get_name());
+        page.control("TP", []);
+        let mut usage = vec![bold(name.as_str())];
+        for arg in subcommand.get_positionals() {
+            usage.push(roman(" "));
+            usage.push(italic(value_name(arg)));
+        }
+        page.text(usage);
+        if let Some(about) = subcommand
+            .get_long_about()
+            .or_else(|| subcommand.get_about())
+        {
+            page.text([roman(about)]);
+        }
+        // --help and --version are already described for wurmloch itself
+        let args: Vec<&Arg> = subcommand
+            .get_arguments()
+            .filter(|arg| !matches!(arg.get_id(), "help" | "version"))
+            .collect();
+        if args.iter().any(|arg| !arg.is_positional()) {
+            page.control("RS", []);
+            options(page, &args);
+            page.control("RE", []);
+        }
+        commands(page, subcommand, &name);
+    }
+}
+
+fn value_name(arg: &Arg) -> String {
+    match arg.get_value_names() {
+        Some(names) => names.join(" "),
+        None => arg.get_id().to_uppercase(),
+    }
+}
@@ -0,0 +1,29 @@
+use std::ffi::OsStr;
+
+use anyhow::Result;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+/// The patterns of all rules compiled into one set, so that a file name is matched against all
+/// of them in a single pass instead of one pattern after the other
+#[derive(Debug)]
+pub struct Patterns {
+    set: GlobSet,
+}
+
+impl Patterns {
+    pub fn new<'a>(globs: impl IntoIterator<Item = &'a Glob>) -> Result<Patterns> {
+        let mut builder = GlobSetBuilder::new();
+        for glob in globs {
+            builder.add(glob.clone());
+        }
+        Ok(Patterns {
+            set: builder.build()?,
+        })
+    }
+
+    /// Indices of the patterns that match the name, in ascending order and thus in the order of
+    /// the priority of the rules
+    pub fn matching(&self, name: &OsStr) -> Vec<usize> {
+        self.set.matches(name)
+    }
+}
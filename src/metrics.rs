@@ -0,0 +1,205 @@
+use std::fmt::Write as _;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use crate::control::{State, Status};
+use crate::http::{self, Request, Response};
+
+/// Upper bounds of the latency buckets in seconds
+const BUCKETS: [f64; 8] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 10.0];
+
+/// Bytes that rclone reported as transferred, updated while transfers are running
+static TRANSFERRED: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn add_transferred(bytes: u64) {
+    TRANSFERRED.fetch_add(bytes, Ordering::Relaxed);
+}
+
+/// Cumulative Prometheus histogram
+#[derive(Debug, Default)]
+pub struct Histogram {
+    counts: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bound, count) in BUCKETS.iter().zip(self.counts.iter_mut()) {
+            if seconds <= *bound {
+                *count += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+/// Serves the statistics of the running instance in the Prometheus text format
+pub fn serve(addr: SocketAddr, state: Arc<State>) -> Result<()> {
+    let listener =
+        TcpListener::bind(addr).context(format!("Could not serve metrics on {}.", addr))?;
+    info!("Serving metrics on http://{}/metrics.", addr);
+    http::serve(listener, "Metrics", move |request| {
+        Ok(respond(request, &state))
+    });
+    Ok(())
+}
+
+fn respond(request: &Request, state: &State) -> Response {
+    let (status, body) = match request.path.as_str() {
+        "/metrics" | "/" => ("200 OK", render(&state.status(), state.is_paused())),
+        _ => ("404 Not Found", String::from("Not found\n")),
+    };
+    Response {
+        status,
+        content_type: "text/plain; version=0.0.4",
+        body,
+    }
+}
+
+fn render(status: &Status, paused: bool) -> String {
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        let _ = writeln!(out, "{}", value.trim_end());
+    };
+
+    metric(
+        "wurmloch_files_processed_total",
+        "counter",
+        "Files that have been moved or deleted.",
+        format!(
+            "wurmloch_files_processed_total{{action=\"move\"}} {}\n\
+             wurmloch_files_processed_total{{action=\"delete\"}} {}",
+            status.moved, status.deleted
+        ),
+    );
+    metric(
+        "wurmloch_bytes_moved_total",
+        "counter",
+        "Bytes that have been moved.",
+        format!("wurmloch_bytes_moved_total {}", status.bytes_moved),
+    );
+    metric(
+        "wurmloch_bytes_transferred_total",
+        "counter",
+        "Bytes that rclone has transferred, including running transfers.",
+        format!(
+            "wurmloch_bytes_transferred_total {}",
+            TRANSFERRED.load(Ordering::Relaxed)
+        ),
+    );
+    metric(
+        "wurmloch_rule_matches_total",
+        "counter",
+        "Files that have been handled per rule.",
+        status
+            .per_rule
+            .iter()
+            .map(|(rule, count)| {
+                format!(
+                    "wurmloch_rule_matches_total{{rule=\"{}\"}} {}\n",
+                    escape(rule),
+                    count
+                )
+            })
+            .collect(),
+    );
+    metric(
+        "wurmloch_failures_total",
+        "counter",
+        "Failed attempts to apply a rule and files that were given up on.",
+        format!(
+            "wurmloch_failures_total{{kind=\"attempt\"}} {}\n\
+             wurmloch_failures_total{{kind=\"given_up\"}} {}",
+            status.errors, status.given_up
+        ),
+    );
+    metric(
+        "wurmloch_quarantined_total",
+        "counter",
+        "Files that a safety check refused and that were moved to the quarantine directory.",
+        format!("wurmloch_quarantined_total {}", status.quarantined),
+    );
+    metric(
+        "wurmloch_queue_depth",
+        "gauge",
+        "Files waiting for the next batch, another attempt or the end of a pause.",
+        format!("wurmloch_queue_depth {}", status.queued),
+    );
+    metric(
+        "wurmloch_work_queue_depth",
+        "gauge",
+        "Files that wait for a worker.",
+        format!("wurmloch_work_queue_depth {}", status.work_queue),
+    );
+    metric(
+        "wurmloch_files_in_progress",
+        "gauge",
+        "Files that are being sorted.",
+        format!("wurmloch_files_in_progress {}", status.in_progress),
+    );
+    metric(
+        "wurmloch_events_coalesced_total",
+        "counter",
+        "Events for files that were already waiting for a worker.",
+        format!("wurmloch_events_coalesced_total {}", status.coalesced),
+    );
+    metric(
+        "wurmloch_work_queue_overflows_total",
+        "counter",
+        "Files that did not fit into the work queue and were left for a rescan.",
+        format!("wurmloch_work_queue_overflows_total {}", status.turned_away),
+    );
+    metric(
+        "wurmloch_paused",
+        "gauge",
+        "Whether sorting is paused.",
+        format!("wurmloch_paused {}", paused as u8),
+    );
+    metric(
+        "wurmloch_active_rules",
+        "gauge",
+        "Rules that are currently in use.",
+        format!("wurmloch_active_rules {}", status.rules.len()),
+    );
+
+    let latency = &status.latency;
+    let mut buckets = String::new();
+    for (bound, count) in BUCKETS.iter().zip(latency.counts.iter()) {
+        let _ = writeln!(
+            buckets,
+            "wurmloch_processing_seconds_bucket{{le=\"{}\"}} {}",
+            bound, count
+        );
+    }
+    let _ = write!(
+        buckets,
+        "wurmloch_processing_seconds_bucket{{le=\"+Inf\"}} {}\n\
+         wurmloch_processing_seconds_sum {}\n\
+         wurmloch_processing_seconds_count {}",
+        latency.count, latency.sum, latency.count
+    );
+    metric(
+        "wurmloch_processing_seconds",
+        "histogram",
+        "Time it took to apply an action.",
+        buckets,
+    );
+    out
+}
+
+/// Escapes a label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,149 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use rumqttc::tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use rumqttc::{Client, Event, MqttOptions, Packet, QoS, TlsConfiguration, Transport};
+use serde::{Deserialize, Serialize};
+
+use crate::webhook::WebhookEvent;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MqttSettings {
+    pub host: String,
+    /// Defaults to 8883 with TLS and 1883 without
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: bool,
+    /// PEM file with the certificate of a private certificate authority
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// Defaults to `wurmloch-<hostname>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+
+    /// Topic of the messages. `{event}` and `{rule}` are replaced, `+` and `#` in rule names
+    /// with `_`.
+    #[serde(default = "default_topic")]
+    pub topic: String,
+    /// Same as the payload of the webhook
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<String>,
+    /// 0, 1 or 2
+    #[serde(default = "default_qos")]
+    pub qos: u8,
+    #[serde(default)]
+    pub retain: bool,
+}
+
+fn default_topic() -> String {
+    String::from("wurmloch/{event}")
+}
+
+fn default_qos() -> u8 {
+    1
+}
+
+/// Publishes events to an MQTT broker. The connection is kept and restored in the background.
+pub struct Mqtt {
+    client: Client,
+    topic: String,
+    payload: Option<String>,
+    qos: QoS,
+    retain: bool,
+}
+
+impl Mqtt {
+    pub fn start(settings: MqttSettings) -> Result<Mqtt> {
+        let qos = match settings.qos {
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            2 => QoS::ExactlyOnce,
+            qos => return Err(anyhow!("Invalid MQTT QoS {}, use 0, 1 or 2.", qos)),
+        };
+        let port = settings
+            .port
+            .unwrap_or(if settings.tls { 8883 } else { 1883 });
+        let client_id = settings.client_id.clone().unwrap_or_else(|| {
+            format!("wurmloch-{}", gethostname::gethostname().to_string_lossy())
+        });
+        let mut options = MqttOptions::new(client_id, settings.host.as_str(), port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if let (Some(user), Some(password)) = (&settings.user, &settings.password) {
+            options.set_credentials(user, password);
+        }
+        if settings.tls {
+            let transport = match &settings.ca {
+                Some(ca) => Transport::tls(
+                    fs::read(ca).context(format!("Could not read MQTT CA {:?}.", ca))?,
+                    None,
+                    None,
+                ),
+                None => {
+                    let mut roots = RootCertStore::empty();
+                    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+                    let config = ClientConfig::builder()
+                        .with_root_certificates(roots)
+                        .with_no_client_auth();
+                    Transport::tls_with_config(TlsConfiguration::Rustls(Arc::new(config)))
+                }
+            };
+            options.set_transport(transport);
+        }
+
+        let (client, mut connection) = Client::new(options, 64);
+        let broker = format!("{}:{}", &settings.host, port);
+        thread::spawn(move || {
+            let mut connected = true;
+            for notification in connection.iter() {
+                match notification {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        info!("Connected to MQTT broker {}.", &broker);
+                        connected = true;
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        if connected {
+                            warn!("Could not reach MQTT broker {}: {}", &broker, e);
+                            connected = false;
+                        }
+                        thread::sleep(Duration::from_secs(5));
+                    }
+                }
+            }
+        });
+        Ok(Mqtt {
+            client,
+            topic: settings.topic,
+            payload: settings.payload,
+            qos,
+            retain: settings.retain,
+        })
+    }
+
+    pub fn send(&self, event: &WebhookEvent) {
+        let topic = self
+            .topic
+            .replace("{event}", event.event)
+            // Wildcards are not allowed in the topic of a message
+            .replace("{rule}", &event.rule.replace(['+', '#'], "_"));
+        let payload = event.render(self.payload.as_deref());
+        if let Err(e) = self
+            .client
+            .try_publish(topic, self.qos, self.retain, payload)
+        {
+            warn!(
+                "Could not publish {} event of {}: {}",
+                event.event, &event.file, e
+            );
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// A clean-up of the names of moved and copied files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Normalization {
+    /// `Scan.PDF` becomes `scan.pdf`
+    Lowercase,
+    /// Runs of spaces become a single `_`
+    Underscores,
+    /// `Café` becomes `Cafe`
+    StripDiacritics,
+    /// Composed characters, so that names from macOS and Linux compare equal
+    Nfc,
+}
+
+/// Applies the normalizations in the order they are listed
+pub fn normalize(name: &str, normalizations: &[Normalization]) -> String {
+    normalizations.iter().fold(
+        String::from(name),
+        |name, normalization| match normalization {
+            Normalization::Lowercase => name.to_lowercase(),
+            Normalization::Underscores => {
+                let mut normalized = String::with_capacity(name.len());
+                for c in name.chars() {
+                    if c != ' ' {
+                        normalized.push(c);
+                    } else if !normalized.ends_with('_') {
+                        normalized.push('_');
+                    }
+                }
+                normalized
+            }
+            Normalization::StripDiacritics => name
+                .nfd()
+                .filter(|c| !is_combining_mark(*c))
+                .nfc()
+                .collect(),
+            Normalization::Nfc => name.nfc().collect(),
+        },
+    )
+}
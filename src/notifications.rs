@@ -0,0 +1,104 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::email::EmailSettings;
+use crate::mqtt::MqttSettings;
+use crate::webhook::WebhookSettings;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationSettings {
+    /// Show desktop notifications. Rules can override this with `notify`.
+    pub desktop: bool,
+
+    /// Files sorted within this time are summarized in one notification
+    #[serde(with = "humantime_serde")]
+    pub batch_window: Duration,
+
+    /// Called for every sorted file and every file that could not be sorted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webhook: Option<WebhookSettings>,
+
+    /// Publishes the same events as the webhook
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mqtt: Option<MqttSettings>,
+
+    /// Sends a digest of failures once they pile up
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<EmailSettings>,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        NotificationSettings {
+            desktop: false,
+            batch_window: Duration::from_secs(5),
+            webhook: None,
+            mqtt: None,
+            email: None,
+        }
+    }
+}
+
+/// Collects sorted files and shows them as one desktop notification per batch window
+#[derive(Default)]
+pub struct Desktop {
+    sorted: Vec<String>,
+    first: Option<Instant>,
+}
+
+impl Desktop {
+    pub fn sorted(&mut self, description: String) {
+        self.first.get_or_insert_with(Instant::now);
+        self.sorted.push(description);
+    }
+
+    /// Failures are shown right away
+    pub fn failed(&self, filename: &str, reason: &str) {
+        show(&format!("Could not sort {}", filename), reason);
+    }
+
+    /// Shows the summary once the batch window is over
+    pub fn flush_due(&mut self, window: Duration) {
+        if self.first.is_some_and(|first| first.elapsed() >= window) {
+            self.flush();
+        }
+    }
+
+    pub fn flush(&mut self) {
+        self.first = None;
+        match self.sorted.len() {
+            0 => {}
+            1 => show("Sorted 1 file", &self.sorted.remove(0)),
+            count => {
+                let mut body = self.sorted.iter().take(5).cloned().collect::<Vec<_>>();
+                if count > body.len() {
+                    body.push(format!("... and {} more", count - body.len()));
+                }
+                show(&format!("Sorted {} files", count), &body.join("\n"));
+                self.sorted.clear();
+            }
+        }
+    }
+}
+
+#[cfg(feature = "desktop-notifications")]
+fn show(summary: &str, body: &str) {
+    if let Err(e) = notify_rust::Notification::new()
+        .appname("wurmloch")
+        .summary(summary)
+        .body(body)
+        .show()
+    {
+        warn!("Could not show desktop notification: {}", e);
+    }
+}
+
+#[cfg(not(feature = "desktop-notifications"))]
+fn show(summary: &str, _body: &str) {
+    debug!(
+        "Desktop notification {:?} not shown, wurmloch was built without them.",
+        summary
+    );
+}
@@ -0,0 +1,101 @@
+use std::env;
+use std::ffi::OsStr;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+
+use indicatif::ProgressBar;
+
+use crate::Applied;
+
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const RED: &str = "\x1b[31m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// What is printed for the files that were handled, apart from the log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum Output {
+    /// One line per file, e.g. `✔ invoice.pdf → ~/Documents/Invoices [rule: *.pdf]`
+    Human,
+    /// Nothing but the log
+    Off,
+}
+
+/// Prints one line for every file that was sorted or given up on
+#[derive(Default)]
+pub struct Printer {
+    enabled: bool,
+    color: bool,
+    home: Option<PathBuf>,
+    /// Lines are printed above it instead of through it
+    progress: Option<ProgressBar>,
+}
+
+impl Printer {
+    pub fn new(output: Output, progress: Option<ProgressBar>) -> Printer {
+        Printer {
+            enabled: output == Output::Human,
+            color: io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none(),
+            home: dirs::home_dir(),
+            progress,
+        }
+    }
+
+    pub fn sorted(&self, filename: &OsStr, rule: &str, applied: &Applied) {
+        if !self.enabled {
+            return;
+        }
+        let (icon, color) = match applied.action.as_str() {
+            "delete" => ("✘", RED),
+            "copy" => ("⧉", GREEN),
+            "skip" | "full" => ("↷", YELLOW),
+            _ => ("✔", GREEN),
+        };
+        let outcome = match applied.moved_to.as_deref().and_then(Path::parent) {
+            Some(target) => self.shorten(target),
+            None => String::from(match applied.action.as_str() {
+                "delete" => "trash",
+                "skip" => "duplicate, skipped",
+                "full" => "target full, left alone",
+                name => name,
+            }),
+        };
+        let text = format!("{} → {}", filename.to_string_lossy(), outcome);
+        self.print(icon, color, &text, rule);
+    }
+
+    pub fn failed(&self, filename: &OsStr, rule: &str, reason: &str) {
+        if self.enabled {
+            let text = format!("{}: {}", filename.to_string_lossy(), reason);
+            self.print("⚠", RED, &text, rule);
+        }
+    }
+
+    fn print(&self, icon: &str, color: &str, text: &str, rule: &str) {
+        let line = if self.color {
+            format!(
+                "{}{}{} {} {}[rule: {}]{}",
+                color, icon, RESET, text, DIM, rule, RESET
+            )
+        } else {
+            format!("{} {} [rule: {}]", icon, text, rule)
+        };
+        match &self.progress {
+            Some(progress) => progress.suspend(|| println!("{}", line)),
+            None => println!("{}", line),
+        }
+    }
+
+    /// Writes paths in the home directory as `~/...`
+    fn shorten(&self, path: &Path) -> String {
+        match self
+            .home
+            .as_deref()
+            .and_then(|home| path.strip_prefix(home).ok())
+        {
+            Some(relative) => Path::new("~").join(relative).display().to_string(),
+            None => path.display().to_string(),
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::io;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+/// Mode and owner that moved and copied files get, as set with `chmod` and `chown` in a rule
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Ownership {
+    pub mode: Option<u32>,
+    pub user: Option<u32>,
+    pub group: Option<u32>,
+}
+
+impl Ownership {
+    /// Parses an octal mode like `0644` and an owner like `user:group`, `user` or `:group`.
+    /// Users and groups may be given by name or ID.
+    pub fn parse(chmod: Option<&str>, chown: Option<&str>) -> Result<Ownership> {
+        if cfg!(not(unix)) && (chmod.is_some() || chown.is_some()) {
+            return Err(anyhow!("chmod and chown are only supported on Unix."));
+        }
+        let mode = match chmod {
+            Some(mode) => {
+                let digits = mode.trim().trim_start_matches("0o");
+                Some(
+                    u32::from_str_radix(digits, 8)
+                        .ok()
+                        .filter(|mode| *mode <= 0o7777)
+                        .context(format!("Invalid mode {:?}.", mode))?,
+                )
+            }
+            None => None,
+        };
+        let (user, group) = match chown {
+            Some(owner) => {
+                let (user, group) = owner.split_once(':').unwrap_or((owner, ""));
+                let user = match user.trim() {
+                    "" => None,
+                    user => Some(user_id(user).context(format!("Unknown user {:?}.", user))?),
+                };
+                let group = match group.trim() {
+                    "" => None,
+                    group => Some(group_id(group).context(format!("Unknown group {:?}.", group))?),
+                };
+                (user, group)
+            }
+            None => (None, None),
+        };
+        Ok(Ownership { mode, user, group })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &Ownership::default()
+    }
+
+    /// Applies mode and owner to a file, or to a directory and everything in it. Directories
+    /// keep their mode, as a mode for files would make them inaccessible.
+    pub fn apply(&self, path: &Path) -> io::Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let metadata = std::fs::symlink_metadata(path)?;
+        if metadata.is_dir() {
+            for entry in std::fs::read_dir(path)? {
+                self.apply(&entry?.path())?;
+            }
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            if self.user.is_some() || self.group.is_some() {
+                std::os::unix::fs::lchown(path, self.user, self.group)?;
+            }
+            if let Some(mode) = self.mode.filter(|_| metadata.is_file()) {
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn user_id(user: &str) -> Option<u32> {
+    if let Ok(id) = user.parse() {
+        return Some(id);
+    }
+    let name = std::ffi::CString::new(user).ok()?;
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0; 16 * 1024];
+    let mut result = std::ptr::null_mut();
+    let status = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if status == 0 && !result.is_null() {
+        Some(entry.pw_uid)
+    } else {
+        None
+    }
+}
+
+#[cfg(unix)]
+fn group_id(group: &str) -> Option<u32> {
+    if let Ok(id) = group.parse() {
+        return Some(id);
+    }
+    let name = std::ffi::CString::new(group).ok()?;
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0; 16 * 1024];
+    let mut result = std::ptr::null_mut();
+    let status = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if status == 0 && !result.is_null() {
+        Some(entry.gr_gid)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+fn user_id(_user: &str) -> Option<u32> {
+    None
+}
+
+#[cfg(not(unix))]
+fn group_id(_group: &str) -> Option<u32> {
+    None
+}
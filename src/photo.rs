@@ -0,0 +1,81 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use exif::{In, Tag, Value};
+
+use crate::plugin::{self, Plugin, Registry};
+
+/// Used for cameras and lenses that are not recorded in a photo
+const UNKNOWN: &str = "Unknown";
+
+/// Placeholders from the EXIF data of photos
+pub struct Exif;
+
+impl Plugin for Exif {
+    fn name(&self) -> &str {
+        "exif"
+    }
+
+    fn register(&self, registry: &mut Registry) {
+        registry.variable("exif.date", |path: &Path| taken(path, "%Y-%m-%d"));
+        registry.variable("exif.date.year", |path: &Path| taken(path, "%Y"));
+        registry.variable("exif.date.month", |path: &Path| taken(path, "%m"));
+        registry.variable("exif.date.day", |path: &Path| taken(path, "%d"));
+        registry.variable("exif.camera", |path: &Path| {
+            Ok(plugin::sanitize(&camera(path)))
+        });
+        registry.variable("exif.lens", |path: &Path| {
+            Ok(read(path)
+                .and_then(|exif| text(&exif, Tag::LensModel))
+                .map_or_else(|| String::from(UNKNOWN), |lens| plugin::sanitize(&lens)))
+        });
+    }
+}
+
+fn read(path: &Path) -> Option<exif::Exif> {
+    let file = File::open(path).ok()?;
+    exif::Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()
+}
+
+/// A text field without padding
+fn text(exif: &exif::Exif, tag: Tag) -> Option<String> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => {
+            let text = String::from_utf8_lossy(values.first()?)
+                .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+                .to_string();
+            Some(text).filter(|t| !t.is_empty())
+        }
+        _ => None,
+    }
+}
+
+/// When the photo was taken. Falls back to the modification date for files without EXIF data.
+fn taken(path: &Path, format: &str) -> Result<String> {
+    let taken = read(path)
+        .and_then(|exif| text(&exif, Tag::DateTimeOriginal).or_else(|| text(&exif, Tag::DateTime)))
+        .and_then(|taken| NaiveDateTime::parse_from_str(&taken, "%Y:%m:%d %H:%M:%S").ok());
+    match taken {
+        Some(taken) => Ok(taken.format(format).to_string()),
+        None => plugin::modified(path, format),
+    }
+}
+
+/// Make and model, e.g. `Canon EOS 80D`. Many models already start with the make.
+fn camera(path: &Path) -> String {
+    let exif = match read(path) {
+        Some(exif) => exif,
+        None => return String::from(UNKNOWN),
+    };
+    match (text(&exif, Tag::Make), text(&exif, Tag::Model)) {
+        (Some(make), Some(model)) if model.starts_with(&make) => model,
+        (Some(make), Some(model)) => format!("{} {}", make, model),
+        (Some(only), None) | (None, Some(only)) => only,
+        (None, None) => String::from(UNKNOWN),
+    }
+}
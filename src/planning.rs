@@ -0,0 +1,273 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::action::Action;
+use crate::{execute, finish, plan, BatchLimit, Session, Sorter, Task};
+
+/// Operations that were reviewed before they are applied with `wurmloch apply`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Plan {
+    pub created: DateTime<Local>,
+    /// Directory whose files are sorted
+    pub dir: PathBuf,
+    pub operations: Vec<Operation>,
+}
+
+/// What is done with one file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Operation {
+    pub path: PathBuf,
+    /// Name or pattern of the rule, none for the fallback
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule: Option<String>,
+    /// What the action does, e.g. `move to "/home/foo/pictures"`
+    pub action: String,
+    /// The overflow target of the rule is used because its batch limit is reached
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub overflow: bool,
+    /// Size and modification time when the plan was made. The file is left alone if they
+    /// have changed.
+    pub size: u64,
+    pub modified: Option<DateTime<Local>>,
+}
+
+/// Plans what the rules would do with the files in the directory without changing anything
+pub fn create(sorter: &Sorter, session: &mut Session, files: &[PathBuf], dir: &Path) -> Plan {
+    let mut operations = Vec::new();
+    for path in files {
+        let task = match plan(sorter, session, path) {
+            Some(task) => task,
+            None => continue,
+        };
+        let action = match action_of(sorter, &task) {
+            Some(action) => action,
+            None => continue,
+        };
+        let description = match action.describe(path) {
+            Ok(description) => description,
+            Err(e) => {
+                error!("Could not plan {:?}: {:#}", path, e);
+                continue;
+            }
+        };
+        let (size, modified) = state(path);
+        operations.push(Operation {
+            path: path.clone(),
+            rule: task.rule.map(|_| task.name.clone()),
+            action: description,
+            overflow: task.overflow,
+            size,
+            modified,
+        });
+    }
+    Plan {
+        created: Local::now(),
+        dir: dir.to_path_buf(),
+        operations,
+    }
+}
+
+pub fn write(plan: &Plan, path: &Path) -> Result<()> {
+    let json = serde_json::to_string_pretty(plan)?;
+    fs::write(path, json).context(format!("Could not write the plan to {:?}.", path))
+}
+
+pub fn read(path: &Path) -> Result<Plan> {
+    let json = fs::read_to_string(path).context(format!("Could not read the plan {:?}.", path))?;
+    serde_json::from_str(&json).context(format!("{:?} is not a valid plan.", path))
+}
+
+/// Number of operations that were carried out, that failed and that were left out because the
+/// file or the rules have changed since the plan was made
+pub struct Applied {
+    pub done: usize,
+    pub failed: usize,
+    pub changed: usize,
+}
+
+/// Carries out the operations of the plan exactly as they were planned
+pub fn apply(sorter: &Sorter, session: &mut Session, plan: &Plan) -> Applied {
+    let mut applied = Applied {
+        done: 0,
+        failed: 0,
+        changed: 0,
+    };
+    for operation in plan.operations.iter() {
+        if session.control.is_stopping() {
+            break;
+        }
+        let task = match task_for(sorter, operation) {
+            Ok(task) => task,
+            Err(e) => {
+                error!("{:?} is left alone. {:#}", operation.path, e);
+                applied.changed += 1;
+                continue;
+            }
+        };
+        let result = execute(sorter, &task);
+        if result.is_ok() {
+            applied.done += 1;
+        } else {
+            applied.failed += 1;
+        }
+        finish(sorter, session, task, result);
+    }
+    applied
+}
+
+/// The task that does what the operation says, if neither the file nor its rule has changed
+fn task_for(sorter: &Sorter, operation: &Operation) -> Result<Task> {
+    let path = &operation.path;
+    if state(path) != (operation.size, operation.modified) {
+        return Err(anyhow!("It has changed since the plan was made."));
+    }
+    let rule = match &operation.rule {
+        Some(name) => Some(
+            sorter
+                .rules
+                .iter()
+                .position(|rule| &rule.name == name)
+                .context(format!("Rule {} does not exist anymore.", name))?,
+        ),
+        None => None,
+    };
+    let task = Task {
+        path: path.clone(),
+        rule,
+        overflow: operation.overflow,
+        name: operation.rule.clone().unwrap_or_default(),
+        notify: rule.map_or(sorter.notifications.desktop, |index| {
+            sorter.rules[index].notify
+        }),
+    };
+    let action = action_of(sorter, &task).context("The action does not exist anymore.")?;
+    let description = action.describe(path)?;
+    if description != operation.action {
+        return Err(anyhow!(
+            "The rules would now {} instead of {}.",
+            description,
+            operation.action
+        ));
+    }
+    Ok(task)
+}
+
+/// The action that `execute` applies for the task, unless the target is full or unavailable
+fn action_of<'a>(sorter: &'a Sorter, task: &Task) -> Option<&'a dyn Action> {
+    match task.rule {
+        Some(index) => {
+            let rule = &sorter.rules[index];
+            match (&rule.limit, task.overflow) {
+                (
+                    Some(BatchLimit {
+                        overflow: Some(overflow),
+                        ..
+                    }),
+                    true,
+                ) => Some(overflow.as_ref()),
+                _ => Some(rule.action.as_ref()),
+            }
+        }
+        None => sorter.fallback.as_ref().map(|f| f.action.as_ref()),
+    }
+}
+
+/// Size and modification time of the file
+fn state(path: &Path) -> (u64, Option<DateTime<Local>>) {
+    match fs::metadata(path) {
+        Ok(metadata) => (metadata.len(), metadata.modified().ok().map(DateTime::from)),
+        Err(_) => (0, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::control::State;
+    use crate::tests::sorter;
+
+    use super::*;
+
+    fn rules(target: &Path) -> String {
+        format!("rules:\n  - pattern: '*.pdf'\n    target: {:?}\n", target)
+    }
+
+    #[test]
+    fn plans_are_applied_as_made() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        fs::create_dir(&target).unwrap();
+        let sorter = sorter(dir.path(), &rules(&target));
+        let mut session = Session::new(Arc::new(State::default()));
+        let path = dir.path().join("wormhole/report.pdf");
+        fs::write(&path, "report").unwrap();
+
+        let plan = create(
+            &sorter,
+            &mut session,
+            std::slice::from_ref(&path),
+            dir.path(),
+        );
+        assert_eq!(plan.operations.len(), 1);
+        assert!(task_for(&sorter, &plan.operations[0]).is_ok());
+        let applied = apply(&sorter, &mut session, &plan);
+        assert_eq!((applied.done, applied.failed, applied.changed), (1, 0, 0));
+        assert!(target.join("report.pdf").exists());
+    }
+
+    #[test]
+    fn changed_files_are_left_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        fs::create_dir(&target).unwrap();
+        let sorter = sorter(dir.path(), &rules(&target));
+        let mut session = Session::new(Arc::new(State::default()));
+        let path = dir.path().join("wormhole/report.pdf");
+        fs::write(&path, "report").unwrap();
+
+        let plan = create(
+            &sorter,
+            &mut session,
+            std::slice::from_ref(&path),
+            dir.path(),
+        );
+        fs::write(&path, "another report").unwrap();
+        let error = task_for(&sorter, &plan.operations[0]).unwrap_err();
+        assert!(error.to_string().contains("has changed"));
+        let applied = apply(&sorter, &mut session, &plan);
+        assert_eq!((applied.done, applied.failed, applied.changed), (0, 0, 1));
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn changed_rules_are_left_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        let elsewhere = dir.path().join("elsewhere");
+        fs::create_dir(&target).unwrap();
+        fs::create_dir(&elsewhere).unwrap();
+        let mut session = Session::new(Arc::new(State::default()));
+        let path = dir.path().join("wormhole/report.pdf");
+        let planned = sorter(dir.path(), &rules(&target));
+        fs::write(&path, "report").unwrap();
+        let plan = create(
+            &planned,
+            &mut session,
+            std::slice::from_ref(&path),
+            dir.path(),
+        );
+        drop(planned);
+
+        let sorter = sorter(dir.path(), &rules(&elsewhere));
+        let error = task_for(&sorter, &plan.operations[0]).unwrap_err();
+        assert!(error.to_string().contains("would now"));
+        let applied = apply(&sorter, &mut session, &plan);
+        assert_eq!((applied.done, applied.failed, applied.changed), (0, 0, 1));
+        assert!(path.exists());
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+
+use crate::action::{self, ActionFactory};
+use crate::{audio, document, photo, rclone, video};
+
+/// A template variable that is computed for a file when a target gets expanded
+pub trait Variable: Send + Sync {
+    fn resolve(&self, path: &Path) -> Result<String>;
+}
+
+impl<F> Variable for F
+where
+    F: Fn(&Path) -> Result<String> + Send + Sync,
+{
+    fn resolve(&self, path: &Path) -> Result<String> {
+        self(path)
+    }
+}
+
+/// Extension point for functionality that is not part of the rule engine itself
+pub trait Plugin {
+    fn name(&self) -> &str;
+
+    /// Registers everything the plugin provides
+    fn register(&self, registry: &mut Registry);
+}
+
+/// Everything that plugins have registered
+#[derive(Default)]
+pub struct Registry {
+    variables: HashMap<String, Arc<dyn Variable>>,
+    actions: HashMap<String, Arc<dyn ActionFactory>>,
+}
+
+impl Registry {
+    /// Creates a registry with all built-in plugins
+    pub fn new() -> Registry {
+        let mut registry = Registry::default();
+        let plugins = [
+            &Builtin as &dyn Plugin,
+            &photo::Exif,
+            &audio::AudioTags,
+            &video::VideoPlugin,
+            &document::DocumentPlugin,
+        ];
+        for plugin in plugins {
+            debug!("Registering plugin {}.", plugin.name());
+            plugin.register(&mut registry);
+        }
+        registry
+    }
+
+    /// Makes `{name}` available in templates. Registering a name twice replaces the variable.
+    pub fn variable(&mut self, name: &str, variable: impl Variable + 'static) {
+        if self
+            .variables
+            .insert(String::from(name), Arc::new(variable))
+            .is_some()
+        {
+            warn!("Template variable {{{}}} was registered twice.", name);
+        }
+    }
+
+    pub fn get_variable(&self, name: &str) -> Option<Arc<dyn Variable>> {
+        self.variables.get(name).cloned()
+    }
+
+    /// Makes `action: <name>` available in rules. Registering a name twice replaces the action.
+    pub fn action(&mut self, name: &str, factory: impl ActionFactory + 'static) {
+        if self
+            .actions
+            .insert(String::from(name), Arc::new(factory))
+            .is_some()
+        {
+            warn!("Action {} was registered twice.", name);
+        }
+    }
+
+    pub fn get_action(&self, name: &str) -> Option<Arc<dyn ActionFactory>> {
+        self.actions.get(name).cloned()
+    }
+}
+
+/// File name and modification date variables and the basic actions
+struct Builtin;
+
+impl Plugin for Builtin {
+    fn name(&self) -> &str {
+        "builtin"
+    }
+
+    fn register(&self, registry: &mut Registry) {
+        registry.variable("path", |path: &Path| {
+            Ok(path.to_string_lossy().into_owned())
+        });
+        registry.variable("name", |path: &Path| file_name_part(path.file_name()));
+        registry.variable("stem", |path: &Path| file_name_part(path.file_stem()));
+        registry.variable("ext", |path: &Path| file_name_part(path.extension()));
+        registry.variable("year", |path: &Path| modified(path, "%Y"));
+        registry.variable("month", |path: &Path| modified(path, "%m"));
+        registry.variable("day", |path: &Path| modified(path, "%d"));
+        registry.action("move", action::Move::create);
+        registry.action("copy", action::Copy::create);
+        registry.action("delete", action::Delete::create);
+        registry.action("trash", action::Delete::create);
+        registry.action("run", action::Run::create);
+        registry.action("extract", action::Extract::create);
+        registry.action("compress", action::Compress::create);
+        registry.action("rclone", rclone::Rclone::create);
+    }
+}
+
+fn file_name_part(part: Option<&std::ffi::OsStr>) -> Result<String> {
+    Ok(part.unwrap_or_default().to_string_lossy().into_owned())
+}
+
+/// What was read from the file that was read last. Placeholders and conditions are resolved
+/// one after another for the same file.
+pub(crate) struct LastRead<T> {
+    last: Mutex<Option<(PathBuf, Option<SystemTime>, T)>>,
+}
+
+impl<T: Clone> LastRead<T> {
+    pub const fn new() -> LastRead<T> {
+        LastRead {
+            last: Mutex::new(None),
+        }
+    }
+
+    /// The remembered value if the file has not changed since, otherwise a freshly read one
+    pub fn get(&self, path: &Path, read: impl FnOnce(&Path) -> Result<T>) -> Result<T> {
+        let modified = path.metadata().and_then(|m| m.modified()).ok();
+        let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((p, m, value)) = last.as_ref() {
+            if p == path && *m == modified {
+                return Ok(value.clone());
+            }
+        }
+        let value = read(path)?;
+        *last = Some((path.to_path_buf(), modified, value.clone()));
+        Ok(value)
+    }
+}
+
+/// Makes a value usable as a file or directory name on every system
+pub(crate) fn sanitize(value: &str) -> String {
+    let sanitized: String = value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    sanitized
+        .trim()
+        .trim_end_matches('.')
+        .trim_end()
+        .to_string()
+}
+
+pub(crate) fn modified(path: &Path, format: &str) -> Result<String> {
+    let modified = fs::metadata(path)
+        .and_then(|m| m.modified())
+        .context(format!("Could not read modification time of {:?}.", path))?;
+    Ok(DateTime::<Local>::from(modified).format(format).to_string())
+}
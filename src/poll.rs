@@ -0,0 +1,137 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::{scan, Sorter};
+
+/// How the watch directory learns about new files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum Backend {
+    /// Polling on network shares, the notifications of the operating system everywhere else
+    Auto,
+    /// inotify, FSEvents or ReadDirectoryChangesW
+    Native,
+    /// Reads the watch directory regularly
+    Poll,
+}
+
+/// Finds new files by reading the watch directory regularly. Used where the operating system
+/// is not told about changes, e.g. on network shares.
+pub(crate) struct Poller {
+    dir: PathBuf,
+    /// How deep `dir` is below the watch directory
+    depth: usize,
+    interval: Duration,
+    last: Instant,
+    known: HashSet<PathBuf>,
+}
+
+impl Poller {
+    /// Files that are already there are left alone, just like with the native backend
+    pub fn new(sorter: &Sorter, dir: &Path, interval: Duration) -> Poller {
+        info!(
+            "Looking for new files in {:?} every {:?} ...",
+            dir, interval
+        );
+        let mut poller = Poller {
+            dir: dir.to_path_buf(),
+            depth: 0,
+            interval,
+            last: Instant::now(),
+            known: HashSet::new(),
+        };
+        poller.poll(sorter);
+        poller
+    }
+
+    /// Polls a folder below the watch directory that could not be watched. Its files are new,
+    /// so they are all found by the first poll.
+    pub fn subtree(dir: &Path, depth: usize, interval: Duration) -> Poller {
+        Poller {
+            dir: dir.to_path_buf(),
+            depth,
+            interval,
+            last: Instant::now() - interval,
+            known: HashSet::new(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn next(&self) -> Instant {
+        self.last + self.interval
+    }
+
+    /// Files that appeared since the last poll
+    pub fn poll(&mut self, sorter: &Sorter) -> Vec<PathBuf> {
+        self.last = Instant::now();
+        let files = match scan::files_in(sorter, &self.dir, self.depth) {
+            Ok(files) => files,
+            Err(e) => {
+                // The share may be back at the next poll
+                error!("Could not read {:?}: {}", self.dir, e);
+                return Vec::new();
+            }
+        };
+        let new = files
+            .iter()
+            .filter(|path| !self.known.contains(*path))
+            .cloned()
+            .collect();
+        self.known = files.into_iter().collect();
+        new
+    }
+}
+
+/// Name of the network or FUSE filesystem the directory is on. The operating system does not
+/// report changes that other machines make to those.
+#[cfg(target_os = "linux")]
+pub fn network_filesystem(dir: &Path) -> Option<&'static str> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    // Magic numbers from statfs(2)
+    match stat.f_type as u32 {
+        0x6969 => Some("NFS"),
+        0x517b => Some("SMB"),
+        0xff53_4d42 => Some("CIFS"),
+        0xfe53_4d42 => Some("SMB2"),
+        0x6573_5546 => Some("FUSE"),
+        0x0102_1997 => Some("9P"),
+        0x5346_414f => Some("AFS"),
+        _ => None,
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub fn network_filesystem(dir: &Path) -> Option<&'static str> {
+    use std::ffi::{CStr, CString};
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    let name = unsafe { CStr::from_ptr(stat.f_fstypename.as_ptr()) };
+    match name.to_bytes() {
+        b"nfs" => Some("NFS"),
+        b"smbfs" => Some("SMB"),
+        b"afpfs" => Some("AFP"),
+        b"webdav" => Some("WebDAV"),
+        b"macfuse" | b"osxfuse" => Some("FUSE"),
+        _ => None,
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn network_filesystem(_dir: &Path) -> Option<&'static str> {
+    None
+}
@@ -0,0 +1,194 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use crate::config::ConfigRule;
+
+/// Names of the built-in rule sets
+pub const NAMES: &[&str] = &["downloads", "photographer", "papers", "developer"];
+
+/// The rules of a built-in rule set, with the usual folders of this system as targets
+pub fn rules(name: &str) -> Result<Vec<ConfigRule>> {
+    let rules = match name {
+        "downloads" => downloads(),
+        "photographer" => photographer(),
+        "papers" => papers(),
+        "developer" => developer(),
+        _ => {
+            return Err(anyhow!(
+                "Unknown preset {:?}. Available are {}.",
+                name,
+                NAMES.join(", ")
+            ))
+        }
+    };
+    Ok(rules)
+}
+
+/// Sorts what a browser downloads by its kind
+fn downloads() -> Vec<ConfigRule> {
+    let downloads = dir(dirs::download_dir(), "Downloads");
+    vec![
+        rule(
+            &["jpg", "jpeg", "png", "gif", "webp", "heic", "svg"],
+            dir(dirs::picture_dir(), "Pictures"),
+        ),
+        rule(
+            &["mp4", "mkv", "mov", "avi", "webm"],
+            dir(dirs::video_dir(), "Videos"),
+        ),
+        rule(
+            &["mp3", "flac", "ogg", "m4a", "wav", "opus"],
+            dir(dirs::audio_dir(), "Music"),
+        ),
+        rule(
+            &[
+                "pdf", "doc", "docx", "odt", "rtf", "xls", "xlsx", "ods", "ppt", "pptx", "odp",
+                "epub",
+            ],
+            dir(dirs::document_dir(), "Documents"),
+        ),
+        rule(
+            &["zip", "rar", "7z", "tar", "gz", "tgz", "xz", "zst", "bz2"],
+            downloads.join("Archives"),
+        ),
+        ConfigRule {
+            os: vec![String::from("linux")],
+            ..rule(
+                &["deb", "rpm", "AppImage", "flatpakref", "snap"],
+                downloads.join("Installers"),
+            )
+        },
+        ConfigRule {
+            os: vec![String::from("macos")],
+            ..rule(&["dmg", "pkg"], downloads.join("Installers"))
+        },
+        ConfigRule {
+            os: vec![String::from("windows")],
+            ..rule(&["exe", "msi", "msix"], downloads.join("Installers"))
+        },
+    ]
+}
+
+/// Sorts camera files by the date they were taken, keeping RAW files and their sidecars
+/// together
+fn photographer() -> Vec<ConfigRule> {
+    let pictures = dir(dirs::picture_dir(), "Pictures");
+    let by_date = "{exif.date.year}/{exif.date.year}-{exif.date.month}-{exif.date.day}";
+    vec![
+        rule(
+            &[
+                "cr2", "cr3", "nef", "arw", "raf", "orf", "rw2", "dng", "pef", "srw", "xmp",
+            ],
+            pictures.join("Raw").join(by_date),
+        ),
+        rule(&["jpg", "jpeg", "heic", "heif"], pictures.join(by_date)),
+        rule(
+            &["mp4", "mov", "mts", "m2ts"],
+            dir(dirs::video_dir(), "Videos").join("{year}/{year}-{month}-{day}"),
+        ),
+        rule(
+            &["psd", "tif", "tiff", "afphoto", "xcf"],
+            pictures.join("Edits/{year}"),
+        ),
+    ]
+}
+
+/// Files invoices, bank statements and other paperwork by year
+fn papers() -> Vec<ConfigRule> {
+    let documents = dir(dirs::document_dir(), "Documents");
+    vec![
+        ConfigRule {
+            content_matches: Some(String::from(r"(?i)\b(invoice|rechnung|facture)\b")),
+            ..rule(&["pdf"], documents.join("Invoices/{doc.year}"))
+        },
+        ConfigRule {
+            content_matches: Some(String::from(
+                r"(?i)(account statement|statement of account|kontoauszug)",
+            )),
+            ..rule(&["pdf"], documents.join("Bank/{doc.year}"))
+        },
+        ConfigRule {
+            content_matches: Some(String::from(r"(?i)\b(receipt|quittung|kassenbon)\b")),
+            ..rule(&["pdf"], documents.join("Receipts/{doc.year}"))
+        },
+        rule(&["pdf"], documents.join("Papers/{doc.year}")),
+        rule(
+            &["doc", "docx", "odt", "rtf", "xls", "xlsx", "ods"],
+            documents.join("Papers/{doc.year}"),
+        ),
+    ]
+}
+
+/// Puts the files that pile up during development where they can be found again
+fn developer() -> Vec<ConfigRule> {
+    let documents = dir(dirs::document_dir(), "Documents");
+    let downloads = dir(dirs::download_dir(), "Downloads");
+    vec![
+        rule(&["patch", "diff"], documents.join("Patches")),
+        rule(
+            &[
+                "json", "yaml", "yml", "toml", "csv", "xml", "sql", "parquet",
+            ],
+            documents.join("Data"),
+        ),
+        rule(&["log"], documents.join("Logs/{year}-{month}")),
+        rule(
+            &["iso", "img", "qcow2", "vmdk", "vdi", "ova"],
+            downloads.join("Disk images"),
+        ),
+        rule(
+            &["whl", "gem", "jar", "crate", "nupkg", "vsix", "apk"],
+            downloads.join("Packages"),
+        ),
+        rule(&["ttf", "otf", "woff", "woff2"], downloads.join("Fonts")),
+    ]
+}
+
+/// Extensions of the same kind as `extension`, e.g. `png` and `gif` for `jpg`, and the folder
+/// the presets put them in
+pub fn kind(extension: &str) -> Option<(Vec<String>, PathBuf)> {
+    let extension = extension.to_lowercase();
+    downloads().into_iter().chain(developer()).find_map(|rule| {
+        // Patterns of presets are all written by `rule`
+        let extensions: Vec<String> = rule
+            .pattern
+            .strip_prefix("*.{")?
+            .strip_suffix('}')?
+            .split(',')
+            .filter(|e| *e == e.to_lowercase())
+            .map(String::from)
+            .collect();
+        if extensions.contains(&extension) {
+            Some((extensions, rule.target.first().to_path_buf()))
+        } else {
+            None
+        }
+    })
+}
+
+/// A rule that moves files with the extensions, in lower and upper case, to the target
+fn rule(extensions: &[&str], target: PathBuf) -> ConfigRule {
+    let mut cases: Vec<String> = Vec::new();
+    for extension in extensions {
+        for case in [extension.to_lowercase(), extension.to_uppercase()].iter() {
+            if !cases.contains(case) {
+                cases.push(case.clone());
+            }
+        }
+        if !cases.iter().any(|c| c == extension) {
+            cases.push(extension.to_string());
+        }
+    }
+    ConfigRule {
+        pattern: format!("*.{{{}}}", cases.join(",")),
+        target: target.into(),
+        create_target: Some(true),
+        ..ConfigRule::default()
+    }
+}
+
+/// A well-known folder or the folder with this name in the home directory
+fn dir(known: Option<PathBuf>, name: &str) -> PathBuf {
+    known.unwrap_or_else(|| dirs::home_dir().unwrap_or_default().join(name))
+}
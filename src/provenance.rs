@@ -0,0 +1,205 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::index::Index;
+use crate::journal::{self, Event, Journal};
+use crate::trash::Trash;
+
+/// Appended to the file name of a sorted file to name its sidecar
+pub const SIDECAR_SUFFIX: &str = ".wurmloch.json";
+
+/// Collects the records of all files sorted into a directory
+pub const MANIFEST_FILE_NAME: &str = ".wurmloch-manifest.jsonl";
+
+/// Where the provenance of sorted files is recorded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provenance {
+    /// A `<name>.wurmloch.json` next to every file
+    Sidecar,
+    /// One line per file in a manifest in the target directory
+    Manifest,
+}
+
+/// Where a sorted file came from
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Record {
+    pub path: PathBuf,
+    pub original_path: PathBuf,
+    pub rule: String,
+    pub action: String,
+    /// Only for files, not for directories
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    pub sorted_at: DateTime<Local>,
+}
+
+pub fn write(provenance: Provenance, record: &Record) -> Result<()> {
+    match provenance {
+        Provenance::Sidecar => {
+            let sidecar = sidecar_path(&record.path);
+            fs::write(&sidecar, serde_json::to_string_pretty(record)?)
+                .context(format!("Could not write sidecar {:?}.", &sidecar))
+        }
+        Provenance::Manifest => {
+            let manifest = record
+                .path
+                .parent()
+                .unwrap_or_else(|| Path::new(""))
+                .join(MANIFEST_FILE_NAME);
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&manifest)
+                .context(format!("Could not open manifest {:?}.", &manifest))?;
+            writeln!(file, "{}", serde_json::to_string(record)?)
+                .context(format!("Could not write to manifest {:?}.", &manifest))
+        }
+    }
+}
+
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.file_name().unwrap_or_default());
+    name.push(SIDECAR_SUFFIX);
+    path.with_file_name(name)
+}
+
+/// Whether the file is a sidecar or a manifest
+pub fn is_record_file(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    name.ends_with(SIDECAR_SUFFIX) || name == MANIFEST_FILE_NAME
+}
+
+/// Where a sorted file came from according to its sidecar, the manifest of its directory or
+/// its extended attribute. `path` has to be canonical.
+pub fn origin(path: &Path) -> Option<PathBuf> {
+    let same_file = |record: &Record| fs::canonicalize(&record.path).ok().as_deref() == Some(path);
+    if let Ok(sidecar) = fs::read_to_string(sidecar_path(path)) {
+        if let Ok(record) = serde_json::from_str::<Record>(&sidecar) {
+            if same_file(&record) {
+                return Some(record.original_path);
+            }
+        }
+    }
+    if let Ok(manifest) = File::open(path.with_file_name(MANIFEST_FILE_NAME)) {
+        let found = BufReader::new(manifest)
+            .lines()
+            .map_while(|line| line.ok())
+            .filter_map(|line| serde_json::from_str::<Record>(&line).ok())
+            .filter(same_file)
+            .last();
+        if let Some(record) = found {
+            return Some(record.original_path);
+        }
+    }
+    #[cfg(unix)]
+    if let Ok(Some(origin)) = xattr::get(path, crate::attributes::ORIGIN_ATTRIBUTE) {
+        use std::os::unix::ffi::OsStringExt;
+
+        return Some(PathBuf::from(OsString::from_vec(origin)));
+    }
+    None
+}
+
+/// A file that `restore` tried to move back
+#[derive(Debug)]
+pub struct Restoration {
+    pub path: PathBuf,
+    /// Where the file is now
+    pub result: Result<PathBuf>,
+}
+
+/// Moves files back to their original path as recorded in the journal, their sidecar, the
+/// manifest of their directory, their extended attributes or the index. Files they replaced
+/// come back from the trash.
+pub fn restore(
+    journal: &Journal,
+    index: &Index,
+    trash: &Trash,
+    paths: &[PathBuf],
+) -> Result<Vec<Restoration>> {
+    let records = journal.undoable()?;
+    let mut restorations = Vec::new();
+    for path in paths {
+        let path = fs::canonicalize(path).context(format!("Could not resolve {:?}.", path))?;
+        let record = records.iter().find(|record| match &record.event {
+            Event::Move { to, .. } => fs::canonicalize(to).ok().as_ref() == Some(&path),
+            _ => false,
+        });
+        let origin = match &record.map(|record| &record.event) {
+            Some(Event::Move { from, .. }) => Some(from.clone()),
+            _ => match origin(&path) {
+                Some(origin) => Some(origin),
+                None => index.origin(&path)?,
+            },
+        };
+        let replaced = match &record.map(|record| &record.event) {
+            Some(Event::Move { replaced, .. }) => replaced.as_deref(),
+            _ => None,
+        };
+        let result = match origin {
+            Some(origin) => journal::move_back(&origin, &path)
+                .and_then(|_| journal::put_back(trash, replaced))
+                .map(|_| origin),
+            None => Err(anyhow!("Its origin is unknown.")),
+        };
+        if result.is_ok() {
+            if let Some(record) = record {
+                journal.append(Event::Undo {
+                    record: record.id.clone(),
+                })?;
+            }
+            let sidecar = sidecar_path(&path);
+            if sidecar.exists() {
+                fs::remove_file(&sidecar)
+                    .context(format!("Could not remove sidecar {:?}.", sidecar))?;
+            }
+        }
+        restorations.push(Restoration { path, result });
+    }
+    Ok(restorations)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::INDEX_FILE_NAME;
+    use crate::trash::TrashSettings;
+
+    use super::*;
+
+    #[test]
+    fn replaced_files_are_restored_too() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::open(dir.path().join(journal::JOURNAL_FILE_NAME));
+        let index = Index::open(&dir.path().join(INDEX_FILE_NAME)).unwrap();
+        let trash = Trash::open(dir.path().join("trash"), TrashSettings::default()).unwrap();
+        let from = dir.path().join("wormhole/report.pdf");
+        let to = fs::canonicalize(dir.path()).unwrap().join("report.pdf");
+        fs::write(&to, "old").unwrap();
+        let replaced = trash.stage(&to).unwrap().id;
+        fs::write(&to, "new").unwrap();
+        journal
+            .append(Event::Move {
+                rule: String::from("*.pdf"),
+                from: from.clone(),
+                to: to.clone(),
+                replaced: Some(replaced),
+            })
+            .unwrap();
+        let unknown = dir.path().join("unknown.pdf");
+        fs::write(&unknown, "unknown").unwrap();
+
+        let restorations = restore(&journal, &index, &trash, &[to.clone(), unknown]).unwrap();
+        assert_eq!(restorations[0].result.as_ref().unwrap(), &from);
+        assert_eq!(fs::read_to_string(&from).unwrap(), "new");
+        assert_eq!(fs::read_to_string(&to).unwrap(), "old");
+        assert!(restorations[1].result.is_err());
+        assert!(journal.undoable().unwrap().is_empty());
+    }
+}
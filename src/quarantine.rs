@@ -0,0 +1,124 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::action::numbered_name;
+use crate::transfer;
+
+/// Appended to the file name of a quarantined file to name its origin record
+const ORIGIN_SUFFIX: &str = ".origin.yaml";
+/// Directory in the data directory for files that a safety check refused
+pub const QUARANTINE_DIR_NAME: &str = "quarantine";
+
+/// Where a file that matched no rule or was refused by a safety check came from
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Origin {
+    pub original_path: PathBuf,
+    pub moved_at: DateTime<Local>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check: Option<Check>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Safety checks that can refuse an action
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Check {
+    /// The destination is taken and the conflict policy does not allow replacing it
+    Overwrite,
+    /// The copy differs from the original
+    Mismatch,
+    /// An archive entry points outside of the directory it is unpacked to
+    ZipSlip,
+}
+
+/// Error of an action that a safety check refused. The file is quarantined instead of being
+/// tried again.
+#[derive(Debug)]
+pub struct Blocked {
+    pub check: Check,
+    message: String,
+}
+
+impl Blocked {
+    pub fn new(check: Check, message: String) -> Blocked {
+        Blocked { check, message }
+    }
+
+    /// The refusal in the chain of causes, also inside I/O errors
+    pub fn of(error: &anyhow::Error) -> Option<&Blocked> {
+        error.chain().find_map(|cause| {
+            cause.downcast_ref::<Blocked>().or_else(|| {
+                cause
+                    .downcast_ref::<io::Error>()
+                    .and_then(io::Error::get_ref)
+                    .and_then(|inner| inner.downcast_ref::<Blocked>())
+            })
+        })
+    }
+}
+
+impl fmt::Display for Blocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Blocked {}
+
+/// Writes the origin record next to a file that has been moved to the fallback target
+pub fn record(moved_to: &Path, original_path: &Path) -> Result<()> {
+    write(
+        moved_to,
+        &Origin {
+            original_path: original_path.to_path_buf(),
+            moved_at: Local::now(),
+            rule: None,
+            check: None,
+            reason: None,
+        },
+    )
+}
+
+/// Moves a refused file into `dir` and writes an origin record with the rule and the reason next
+/// to it. Returns where the file is now.
+pub fn isolate(path: &Path, dir: &Path, rule: &str, check: Check, reason: &str) -> Result<PathBuf> {
+    fs::create_dir_all(dir).context(format!("Could not create {:?}.", dir))?;
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut moved_to = dir.join(name.as_ref());
+    let mut number = 0;
+    while moved_to.exists() {
+        number += 1;
+        moved_to = dir.join(numbered_name(&name, number));
+    }
+    transfer::move_path(path, &moved_to, None, None)
+        .context(format!("Could not move {:?} to {:?}.", path, dir))?;
+    write(
+        &moved_to,
+        &Origin {
+            original_path: path.to_path_buf(),
+            moved_at: Local::now(),
+            rule: Some(rule.to_string()),
+            check: Some(check),
+            reason: Some(reason.to_string()),
+        },
+    )?;
+    Ok(moved_to)
+}
+
+fn write(moved_to: &Path, origin: &Origin) -> Result<()> {
+    let mut name = OsString::from(moved_to.file_name().unwrap_or_default());
+    name.push(ORIGIN_SUFFIX);
+    let record_path = moved_to.with_file_name(name);
+    fs::write(&record_path, serde_yaml::to_string(origin)?)
+        .context(format!("Could not write origin record {:?}.", &record_path))
+}
@@ -0,0 +1,70 @@
+use std::ffi::OsStr;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+/// Files that programs create while they are working on something. They are never sorted.
+/// When such a file is renamed to its final name, the result is sorted like a new file.
+const QUIRKS: [(&str, &[&str]); 5] = [
+    ("libreoffice", &[".~lock.*#"]),
+    ("office", &["~$*", "~*.tmp"]),
+    ("rsync", &[".?*.??????"]),
+    ("macos", &[".DS_Store", "._*"]),
+    (
+        "downloads",
+        &["*.crdownload", "*.part", "*.partial", "*.download"],
+    ),
+];
+
+pub struct Quirks {
+    quirks: Vec<(&'static str, GlobSet)>,
+}
+
+impl Quirks {
+    /// All built-in quirks except the disabled ones. `all` disables every quirk.
+    pub fn new(disabled: &[String]) -> Quirks {
+        for name in disabled.iter() {
+            if name != "all" && !QUIRKS.iter().any(|(n, _)| n == name) {
+                warn!("Unknown quirk {:?} cannot be disabled.", name);
+            }
+        }
+
+        let quirks = QUIRKS
+            .iter()
+            .filter(|(name, _)| !disabled.iter().any(|d| d == name || d == "all"))
+            .map(|(name, patterns)| {
+                let mut set = GlobSetBuilder::new();
+                for pattern in patterns.iter() {
+                    set.add(Glob::new(pattern).unwrap());
+                }
+                (*name, set.build().unwrap())
+            })
+            .collect();
+        Quirks { quirks }
+    }
+
+    /// Name of the quirk that explains this file, if any
+    pub fn matching(&self, filename: &OsStr) -> Option<&'static str> {
+        self.quirks
+            .iter()
+            .find(|(name, set)| {
+                set.is_match(filename) && (*name != "rsync" || has_random_suffix(filename))
+            })
+            .map(|(name, _)| *name)
+    }
+}
+
+/// rsync appends six random letters and digits, like `mkstemp`. Unlike an extension such as
+/// `.backup` of a hidden file, they mix at least two of lower case, upper case and digits.
+fn has_random_suffix(filename: &OsStr) -> bool {
+    let filename = filename.to_string_lossy();
+    let suffix = match filename.get(filename.len().saturating_sub(6)..) {
+        Some(suffix) if suffix.bytes().all(|b| b.is_ascii_alphanumeric()) => suffix,
+        _ => return false,
+    };
+    let kinds = [
+        suffix.bytes().any(|b| b.is_ascii_lowercase()),
+        suffix.bytes().any(|b| b.is_ascii_uppercase()),
+        suffix.bytes().any(|b| b.is_ascii_digit()),
+    ];
+    kinds.iter().filter(|&&kind| kind).count() >= 2
+}
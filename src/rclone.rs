@@ -0,0 +1,204 @@
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{Context as _, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::action::{self, Action, ActionSettings, Context, Outcome};
+use crate::journal::Event;
+use crate::template::Template;
+use crate::{logging, metrics, transfer, units};
+
+/// How rclone is started
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RcloneSettings {
+    /// Name or path of the program
+    #[serde(default = "default_program")]
+    pub program: PathBuf,
+
+    /// Configuration file with the remotes. Defaults to the one of rclone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<PathBuf>,
+
+    /// Further arguments for every transfer, e.g. `["--drive-chunk-size", "64M"]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+}
+
+impl Default for RcloneSettings {
+    fn default() -> Self {
+        RcloneSettings {
+            program: default_program(),
+            config: None,
+            args: Vec::new(),
+        }
+    }
+}
+
+fn default_program() -> PathBuf {
+    PathBuf::from("rclone")
+}
+
+/// Copies files to a remote of rclone, e.g. `gdrive:Documents/{year}`, or moves them with
+/// `delete_original`
+#[derive(Debug)]
+pub struct Rclone {
+    pub target: Template,
+    /// New name of the file on the remote
+    pub rename: Option<Template>,
+    pub delete_original: bool,
+    pub settings: RcloneSettings,
+}
+
+impl Rclone {
+    pub fn create(settings: ActionSettings) -> Result<Box<dyn Action>> {
+        Ok(Box::new(Rclone {
+            target: settings.target.context("A target is required.")?,
+            rename: settings.rename,
+            delete_original: settings.delete_original,
+            settings: settings.rclone,
+        }))
+    }
+
+    /// `remote:path/name` of the file
+    fn destination(&self, path: &Path) -> Result<String> {
+        let target = self.target.expand(path)?;
+        let name = action::new_name(self.rename.as_ref(), path)?;
+        if !target.contains(':') {
+            return Err(anyhow!("{:?} does not name an rclone remote.", target));
+        }
+        Ok(match target.trim_end_matches('/') {
+            dir if dir.ends_with(':') => format!("{}{}", dir, name.to_string_lossy()),
+            dir => format!("{}/{}", dir, name.to_string_lossy()),
+        })
+    }
+}
+
+impl Action for Rclone {
+    fn name(&self) -> &str {
+        "rclone"
+    }
+
+    fn describe(&self, path: &Path) -> Result<String> {
+        let verb = if self.delete_original { "move" } else { "copy" };
+        Ok(format!(
+            "{} to {} with rclone",
+            verb,
+            self.destination(path)?
+        ))
+    }
+
+    fn apply(&self, path: &Path, context: &Context) -> Result<Outcome> {
+        let destination = self.destination(path)?;
+        logging::target(Path::new(&destination));
+        let size = transfer::size(path).unwrap_or_default();
+        let program = &self.settings.program;
+        let mut command = Command::new(program);
+        command
+            .arg(if self.delete_original {
+                "moveto"
+            } else {
+                "copyto"
+            })
+            .arg(path)
+            .arg(&destination)
+            .args([
+                "--use-json-log",
+                "--stats",
+                "5s",
+                "--stats-log-level",
+                "NOTICE",
+            ]);
+        if let Some(config) = &self.settings.config {
+            command.arg("--config").arg(config);
+        }
+        // rclone paces itself. Parallel transfers to the same remote each get the full limit.
+        if let Some(limit) = context.throttle.max_bytes_per_second() {
+            command.arg("--bwlimit").arg(format!("{}B", limit));
+        }
+        let mut child = command
+            .args(&self.settings.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context(format!("Could not run {:?}.", program))?;
+
+        // rclone logs one JSON object per line. Progress is reported in the `stats` of some.
+        let mut transferred = 0;
+        let mut last_error = None;
+        let stderr = child
+            .stderr
+            .take()
+            .context("Could not read the output of rclone.")?;
+        for line in BufReader::new(stderr).lines().map_while(|line| line.ok()) {
+            let entry: Value = match serde_json::from_str(&line) {
+                Ok(entry) => entry,
+                Err(_) => {
+                    debug!("rclone: {}", line);
+                    continue;
+                }
+            };
+            let message = entry["msg"].as_str().unwrap_or_default().trim();
+            if let Some(bytes) = entry["stats"]["bytes"].as_u64() {
+                metrics::add_transferred(bytes.saturating_sub(transferred));
+                transferred = transferred.max(bytes);
+                let total = entry["stats"]["totalBytes"].as_u64().unwrap_or_default();
+                let speed = entry["stats"]["speed"].as_f64().unwrap_or_default();
+                if total > 0 {
+                    info!(
+                        "Transferred {} of {} of {:?} ({} per second).",
+                        units::format_size(bytes),
+                        units::format_size(total),
+                        path,
+                        units::format_size(speed as u64)
+                    );
+                }
+            } else {
+                match entry["level"].as_str() {
+                    Some("error") | Some("critical") => {
+                        warn!("rclone: {}", message);
+                        last_error = Some(message.to_string());
+                    }
+                    _ => debug!("rclone: {}", message),
+                }
+            }
+        }
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(match last_error {
+                Some(error) => anyhow!("rclone failed ({}). {}", status, error),
+                None => anyhow!("rclone failed ({}).", status),
+            });
+        }
+        metrics::add_transferred(size.saturating_sub(transferred));
+        debug!("Transferred {:?} to {} with rclone.", path, &destination);
+
+        let from = path.to_path_buf();
+        let to = PathBuf::from(&destination);
+        let rule = context.rule.to_string();
+        Ok(Outcome {
+            destination: Some(to.clone()),
+            replaced: false,
+            event: Some(if self.delete_original {
+                Event::Move {
+                    rule,
+                    from,
+                    to,
+                    replaced: None,
+                }
+            } else {
+                Event::Copy {
+                    rule,
+                    from,
+                    to,
+                    replaced: None,
+                    size: None,
+                    modified: None,
+                }
+            }),
+        })
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetrySettings {
+    /// Attempts per file, including the first one
+    pub max_attempts: u32,
+
+    /// Delay before the second attempt. It doubles with every further attempt.
+    #[serde(with = "humantime_serde")]
+    pub delay: Duration,
+
+    /// Files are moved here when all attempts failed. Without it, they stay where they are.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_target: Option<PathBuf>,
+}
+
+impl Default for RetrySettings {
+    fn default() -> Self {
+        RetrySettings {
+            max_attempts: 5,
+            delay: Duration::from_secs(5),
+            error_target: None,
+        }
+    }
+}
+
+/// Files whose rule failed, waiting for another attempt
+#[derive(Default)]
+pub(crate) struct RetryQueue {
+    attempts: HashMap<PathBuf, u32>,
+    pending: Vec<(PathBuf, Instant)>,
+}
+
+impl RetryQueue {
+    /// Schedules another attempt with exponential backoff. Returns `false` if all attempts
+    /// are used up.
+    pub fn failed(&mut self, path: &Path, settings: &RetrySettings) -> bool {
+        let attempts = self.attempts.entry(path.to_path_buf()).or_default();
+        *attempts += 1;
+        if *attempts >= settings.max_attempts {
+            self.attempts.remove(path);
+            return false;
+        }
+
+        let delay = settings.delay * 2u32.saturating_pow(*attempts - 1);
+        info!(
+            "Attempt {} of {} for {:?} failed. Retrying in {:?}.",
+            attempts, settings.max_attempts, path, delay
+        );
+        self.pending
+            .push((path.to_path_buf(), Instant::now() + delay));
+        true
+    }
+
+    /// Forgets the failed attempts of a file
+    pub fn forget(&mut self, path: &Path) {
+        self.attempts.remove(path);
+    }
+
+    /// Number of files waiting for another attempt
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Takes all files whose next attempt is due
+    pub fn due(&mut self) -> Vec<PathBuf> {
+        let now = Instant::now();
+        let (due, pending) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|(_, at)| *at <= now);
+        self.pending = pending;
+        due.into_iter().map(|(path, _)| path).collect()
+    }
+}
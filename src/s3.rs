@@ -0,0 +1,618 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{Context as _, Result};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::action::{self, Action, ActionSettings, Context, Outcome};
+use crate::journal::Event;
+use crate::quarantine::{Blocked, Check};
+use crate::template::Template;
+use crate::throttle::{Limit, Paced};
+use crate::transfer::{self, Checksum};
+use crate::{logging, APP_NAME};
+
+/// Targets starting with this are uploaded to object storage
+pub const URL_PREFIX: &str = "s3://";
+
+/// Hash of an empty payload
+const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// S3 rejects smaller parts, except for the last one
+const MIN_PART_SIZE: u64 = 5 << 20;
+
+/// Where and how files are uploaded to `s3://` targets
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct S3Settings {
+    /// Defaults to `AWS_ENDPOINT_URL` or AWS, e.g. `http://localhost:9000` for MinIO
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+
+    /// Defaults to `AWS_REGION`, `AWS_DEFAULT_REGION` or `us-east-1`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+
+    /// Address buckets as `<endpoint>/<bucket>` instead of `<bucket>.<endpoint>`, as MinIO
+    /// expects by default
+    #[serde(default)]
+    pub path_style: bool,
+
+    /// Larger files are uploaded in parts
+    #[serde(default = "default_multipart_threshold", with = "crate::units::size")]
+    pub multipart_threshold: u64,
+
+    /// Size of the parts of large files, at least 5 MiB
+    #[serde(default = "default_part_size", with = "crate::units::size")]
+    pub part_size: u64,
+
+    /// Used for all buckets instead of the environment or the keyring, e.g.
+    /// `{secrets.s3_key_id}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_key_id: Option<String>,
+
+    /// Secret of `access_key_id`, e.g. `{secrets.s3_secret}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_access_key: Option<String>,
+
+    /// Token of temporary credentials
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_token: Option<String>,
+}
+
+impl Default for S3Settings {
+    fn default() -> Self {
+        S3Settings {
+            endpoint: None,
+            region: None,
+            path_style: false,
+            multipart_threshold: default_multipart_threshold(),
+            part_size: default_part_size(),
+            access_key_id: None,
+            secret_access_key: None,
+            session_token: None,
+        }
+    }
+}
+
+fn default_multipart_threshold() -> u64 {
+    64 << 20
+}
+
+fn default_part_size() -> u64 {
+    16 << 20
+}
+
+pub fn is_url(target: &str) -> bool {
+    target.starts_with(URL_PREFIX)
+}
+
+/// Uploads files to a bucket and deletes them once the upload has been verified, unless it is
+/// a copy. Created by the `move` and `copy` actions for `s3://` targets.
+#[derive(Debug)]
+pub struct Upload {
+    /// `s3://<bucket>/<prefix>`
+    pub target: Template,
+    /// New name of the object
+    pub rename: Option<Template>,
+    pub delete_original: bool,
+    pub settings: S3Settings,
+    /// Read once per bucket
+    credentials: Mutex<Vec<(String, Credentials)>>,
+}
+
+impl Upload {
+    pub fn create(settings: ActionSettings, delete_original: bool) -> Result<Box<dyn Action>> {
+        let target = settings.target.context("A target is required.")?;
+        if settings.s3.part_size < MIN_PART_SIZE {
+            return Err(anyhow!("The part size has to be at least 5 MiB."));
+        }
+        Ok(Box::new(Upload {
+            target,
+            rename: settings.rename,
+            delete_original,
+            settings: settings.s3,
+            credentials: Mutex::new(Vec::new()),
+        }))
+    }
+
+    /// Bucket and key of the object for the file
+    fn object(&self, path: &Path) -> Result<(String, String)> {
+        let target = self.target.expand(path)?;
+        let location = target.strip_prefix(URL_PREFIX).unwrap_or(&target);
+        let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+        if bucket.is_empty() {
+            return Err(anyhow!("{:?} does not name a bucket.", target));
+        }
+        let name = action::new_name(self.rename.as_ref(), path)?;
+        let key = Path::new(prefix.trim_matches('/')).join(name);
+        Ok((bucket.to_string(), key.to_string_lossy().replace('\\', "/")))
+    }
+
+    fn client(&self, bucket: &str) -> Result<Client> {
+        let mut cache = self.credentials.lock().unwrap_or_else(|e| e.into_inner());
+        let credentials = match cache.iter().find(|(b, _)| b == bucket) {
+            Some((_, credentials)) => credentials.clone(),
+            None => {
+                let credentials = Credentials::load(&self.settings, bucket)?;
+                cache.push((bucket.to_string(), credentials.clone()));
+                credentials
+            }
+        };
+        Ok(Client::new(&self.settings, bucket, credentials))
+    }
+}
+
+impl Action for Upload {
+    fn name(&self) -> &str {
+        if self.delete_original {
+            "move"
+        } else {
+            "copy"
+        }
+    }
+
+    fn describe(&self, path: &Path) -> Result<String> {
+        let (bucket, key) = self.object(path)?;
+        Ok(format!("upload to {}{}/{}", URL_PREFIX, bucket, key))
+    }
+
+    fn apply(&self, path: &Path, context: &Context) -> Result<Outcome> {
+        if !path.is_file() {
+            return Err(anyhow!("Only files can be uploaded to {:?}.", self.target));
+        }
+        let (bucket, mut key) = self.object(path)?;
+        let client = self.client(&bucket)?;
+        if context.safe && client.exists(&key)? {
+            key = free_key(&client, &key)?;
+        }
+        let destination = PathBuf::from(format!("{}{}/{}", URL_PREFIX, bucket, key));
+        logging::target(&destination);
+
+        let size = fs::metadata(path)?.len();
+        let limit = context.limit(&destination);
+        if size > self.settings.multipart_threshold {
+            client.put_multipart(&key, path, self.settings.part_size, limit.as_ref())?;
+        } else {
+            client.put(&key, path, size, limit.as_ref())?;
+        }
+        let uploaded = client
+            .size(&key)
+            .context(format!("Could not verify the upload of {:?}.", path))?;
+        if uploaded != size {
+            return Err(Blocked::new(
+                Check::Mismatch,
+                format!(
+                    "{} has {} bytes instead of {}.",
+                    destination.display(),
+                    uploaded,
+                    size
+                ),
+            )
+            .into());
+        }
+        debug!("Uploaded {:?} to {:?}.", path, &destination);
+
+        let from = path.to_path_buf();
+        let rule = context.rule.to_string();
+        let event = if self.delete_original {
+            fs::remove_file(path).context(format!("Could not delete {:?}.", path))?;
+            Event::Move {
+                rule,
+                from,
+                to: destination.clone(),
+                replaced: None,
+            }
+        } else {
+            Event::Copy {
+                rule,
+                from,
+                to: destination.clone(),
+                replaced: None,
+                size: None,
+                modified: None,
+            }
+        };
+        Ok(Outcome {
+            destination: Some(destination),
+            replaced: false,
+            event: Some(event),
+        })
+    }
+}
+
+#[derive(Clone)]
+struct Credentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("access_key_id", &self.access_key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Credentials {
+    /// From the settings, the environment or, if not set there, from the keyring
+    fn load(settings: &S3Settings, bucket: &str) -> Result<Credentials> {
+        if let (Some(access_key_id), Some(secret_access_key)) =
+            (&settings.access_key_id, &settings.secret_access_key)
+        {
+            return Ok(Credentials {
+                access_key_id: access_key_id.clone(),
+                secret_access_key: secret_access_key.clone(),
+                session_token: settings.session_token.clone(),
+            });
+        }
+        if let (Ok(access_key_id), Ok(secret_access_key)) = (
+            env::var("AWS_ACCESS_KEY_ID"),
+            env::var("AWS_SECRET_ACCESS_KEY"),
+        ) {
+            return Ok(Credentials {
+                access_key_id,
+                secret_access_key,
+                session_token: env::var("AWS_SESSION_TOKEN").ok(),
+            });
+        }
+        let stored = keyring_entry(bucket)?.get_password().map_err(|e| {
+            anyhow!(
+                "No credentials for bucket {}. Set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY \
+                 or run `{} s3-login {}`. Reason: {}",
+                bucket,
+                APP_NAME,
+                bucket,
+                e
+            )
+        })?;
+        let (access_key_id, secret_access_key) = stored.split_once(':').context(format!(
+            "Invalid credentials for bucket {} in the keyring.",
+            bucket
+        ))?;
+        Ok(Credentials {
+            access_key_id: access_key_id.to_string(),
+            secret_access_key: secret_access_key.to_string(),
+            session_token: None,
+        })
+    }
+}
+
+fn keyring_entry(bucket: &str) -> Result<keyring::Entry> {
+    keyring::Entry::new(APP_NAME, &format!("s3:{}", bucket))
+        .context("Could not access the keyring.")
+}
+
+/// Stores the credentials of a bucket in the keyring of the system
+pub fn store_credentials(bucket: &str, access_key_id: &str, secret_access_key: &str) -> Result<()> {
+    if access_key_id.is_empty() || secret_access_key.is_empty() {
+        return Err(anyhow!("Access key ID and secret access key are required."));
+    }
+    keyring_entry(bucket)?
+        .set_password(&format!("{}:{}", access_key_id, secret_access_key))
+        .context(format!(
+            "Could not store the credentials of bucket {}.",
+            bucket
+        ))
+}
+
+/// Signed requests to one bucket
+struct Client {
+    /// Scheme and host, e.g. `https://photos.s3.us-east-1.amazonaws.com`
+    origin: String,
+    host: String,
+    /// Path of the bucket on the host, empty unless path-style addressing is used
+    base_path: String,
+    region: String,
+    credentials: Credentials,
+}
+
+impl Client {
+    fn new(settings: &S3Settings, bucket: &str, credentials: Credentials) -> Client {
+        let region = settings
+            .region
+            .clone()
+            .or_else(|| env::var("AWS_REGION").ok())
+            .or_else(|| env::var("AWS_DEFAULT_REGION").ok())
+            .unwrap_or_else(|| String::from("us-east-1"));
+        let endpoint = settings
+            .endpoint
+            .clone()
+            .or_else(|| env::var("AWS_ENDPOINT_URL_S3").ok())
+            .or_else(|| env::var("AWS_ENDPOINT_URL").ok())
+            .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region));
+        let endpoint = endpoint.trim_end_matches('/');
+        let (scheme, host) = endpoint.split_once("://").unwrap_or(("https", endpoint));
+        let (host, base_path) = if settings.path_style {
+            (host.to_string(), format!("/{}", uri_encode(bucket, true)))
+        } else {
+            (format!("{}.{}", bucket, host), String::new())
+        };
+        Client {
+            origin: format!("{}://{}", scheme, host),
+            host,
+            base_path,
+            region,
+            credentials,
+        }
+    }
+
+    fn put(&self, key: &str, path: &Path, size: u64, limit: Option<&Limit>) -> Result<()> {
+        let hash = transfer::hash(path, Checksum::Sha256)
+            .context(format!("Could not hash {:?}.", path))?;
+        let file = File::open(path).context(format!("Could not open {:?}.", path))?;
+        let request = self
+            .request("PUT", key, &[], &hash)
+            .set("Content-Length", &size.to_string());
+        match limit {
+            Some(limit) => request.send(Paced::new(file, limit)),
+            None => request.send(file),
+        }
+        .map_err(error)
+        .context(format!("Could not upload {:?}.", path))?;
+        Ok(())
+    }
+
+    /// Uploads the file in parts. An incomplete upload is aborted, so that its parts do not
+    /// take up space.
+    fn put_multipart(
+        &self,
+        key: &str,
+        path: &Path,
+        part_size: u64,
+        limit: Option<&Limit>,
+    ) -> Result<()> {
+        let response = self
+            .request("POST", key, &[("uploads", "")], EMPTY_SHA256)
+            .call()
+            .map_err(error)
+            .context(format!("Could not start the upload of {:?}.", path))?
+            .into_string()?;
+        let upload_id = xml_value(&response, "UploadId")
+            .context("The response does not contain an upload ID.")?;
+
+        let result = self.put_parts(key, path, part_size, &upload_id, limit);
+        if result.is_err() {
+            if let Err(e) = self
+                .request("DELETE", key, &[("uploadId", &upload_id)], EMPTY_SHA256)
+                .call()
+            {
+                warn!("Could not abort the upload of {:?}: {}", path, error(e));
+            }
+        }
+        result
+    }
+
+    fn put_parts(
+        &self,
+        key: &str,
+        path: &Path,
+        part_size: u64,
+        upload_id: &str,
+        limit: Option<&Limit>,
+    ) -> Result<()> {
+        let mut file = File::open(path).context(format!("Could not open {:?}.", path))?;
+        let mut parts = String::new();
+        let mut buffer = Vec::new();
+        for number in 1.. {
+            buffer.clear();
+            (&mut file).take(part_size).read_to_end(&mut buffer)?;
+            if buffer.is_empty() && number > 1 {
+                break;
+            }
+            let hash = format!("{:x}", Sha256::digest(&buffer));
+            let number = number.to_string();
+            if let Some(limit) = limit {
+                limit.consume(buffer.len() as u64);
+            }
+            let response = self
+                .request(
+                    "PUT",
+                    key,
+                    &[("partNumber", &number), ("uploadId", upload_id)],
+                    &hash,
+                )
+                .send_bytes(&buffer)
+                .map_err(error)
+                .context(format!("Could not upload part {} of {:?}.", number, path))?;
+            let etag = response
+                .header("ETag")
+                .context("The response does not contain an ETag.")?;
+            parts.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                number, etag
+            ));
+            debug!("Uploaded part {} of {:?}.", number, path);
+        }
+
+        let body = format!(
+            "<CompleteMultipartUpload>{}</CompleteMultipartUpload>",
+            parts
+        );
+        let response = self
+            .request(
+                "POST",
+                key,
+                &[("uploadId", upload_id)],
+                &format!("{:x}", Sha256::digest(body.as_bytes())),
+            )
+            .send_string(&body)
+            .map_err(error)
+            .context(format!("Could not complete the upload of {:?}.", path))?
+            .into_string()?;
+        // Errors may be reported after the response has started with a success status
+        if response.contains("<Error>") {
+            return Err(anyhow!(
+                "Could not complete the upload of {:?}: {}",
+                path,
+                xml_value(&response, "Message").unwrap_or(response)
+            ));
+        }
+        Ok(())
+    }
+
+    fn exists(&self, key: &str) -> Result<bool> {
+        match self.request("HEAD", key, &[], EMPTY_SHA256).call() {
+            Ok(_) => Ok(true),
+            Err(ureq::Error::Status(404, _)) => Ok(false),
+            Err(e) => Err(error(e)),
+        }
+    }
+
+    /// Size of an object
+    fn size(&self, key: &str) -> Result<u64> {
+        let response = self
+            .request("HEAD", key, &[], EMPTY_SHA256)
+            .call()
+            .map_err(error)?;
+        response
+            .header("Content-Length")
+            .and_then(|length| length.parse().ok())
+            .context("The response does not contain the size.")
+    }
+
+    /// A request signed with AWS Signature Version 4
+    fn request(
+        &self,
+        method: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        payload_hash: &str,
+    ) -> ureq::Request {
+        let now = Utc::now();
+        let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let path = format!("{}/{}", self.base_path, uri_encode(key, false));
+        let mut query: Vec<_> = query
+            .iter()
+            .map(|(name, value)| (uri_encode(name, true), uri_encode(value, true)))
+            .collect();
+        query.sort();
+        let query = query
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let mut headers = vec![
+            ("host", self.host.as_str()),
+            ("x-amz-content-sha256", payload_hash),
+            ("x-amz-date", timestamp.as_str()),
+        ];
+        if let Some(token) = &self.credentials.session_token {
+            headers.push(("x-amz-security-token", token));
+        }
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+            .collect();
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method, path, query, canonical_headers, signed_headers, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{:x}",
+            timestamp,
+            scope,
+            Sha256::digest(canonical_request.as_bytes())
+        );
+        let secret = format!("AWS4{}", self.credentials.secret_access_key);
+        let key = [date.as_str(), &self.region, "s3", "aws4_request"]
+            .iter()
+            .fold(secret.into_bytes(), |key, part| hmac(&key, part.as_bytes()));
+        let signature = hex(&hmac(&key, string_to_sign.as_bytes()));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.credentials.access_key_id, scope, signed_headers, signature
+        );
+
+        let mut url = format!("{}{}", self.origin, path);
+        if !query.is_empty() {
+            url.push('?');
+            url.push_str(&query);
+        }
+        let mut request = ureq::request(method, &url)
+            .set("Authorization", &authorization)
+            .set("x-amz-content-sha256", payload_hash)
+            .set("x-amz-date", &timestamp);
+        if let Some(token) = &self.credentials.session_token {
+            request = request.set("x-amz-security-token", token);
+        }
+        request
+    }
+}
+
+/// `name (1).ext`, `name (2).ext` and so on in the same prefix, whichever is not taken yet
+fn free_key(client: &Client, key: &str) -> Result<String> {
+    let (prefix, name) = match key.rsplit_once('/') {
+        Some((prefix, name)) => (format!("{}/", prefix), name),
+        None => (String::new(), key),
+    };
+    for number in 1.. {
+        let free = format!("{}{}", prefix, action::numbered_name(name, number));
+        if !client.exists(&free)? {
+            info!("{} exists already. Using {} instead.", key, free);
+            return Ok(free);
+        }
+    }
+    unreachable!()
+}
+
+/// The message that S3 sent with an error status
+fn error(error: ureq::Error) -> anyhow::Error {
+    match error {
+        ureq::Error::Status(status, response) => {
+            let body = response.into_string().unwrap_or_default();
+            match xml_value(&body, "Message") {
+                Some(message) => anyhow!("Status {}: {}", status, message),
+                None => anyhow!("Status {}.", status),
+            }
+        }
+        error => anyhow!(error),
+    }
+}
+
+/// Text of the first element with the name
+fn xml_value(xml: &str, name: &str) -> Option<String> {
+    let start = xml.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", name))?;
+    Some(xml[start..end].to_string())
+}
+
+/// Percent-encodes everything but unreserved characters and, in paths, slashes
+pub(crate) fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
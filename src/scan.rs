@@ -0,0 +1,39 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use rayon::prelude::*;
+
+use crate::config::RuleType;
+use crate::Sorter;
+
+/// All files below a directory up to the maximum depth, sorted by path. Directories that match a
+/// `dir` rule are listed instead of their content. Subdirectories are read in parallel.
+pub(crate) fn files_in(sorter: &Sorter, dir: &Path, depth: usize) -> io::Result<Vec<PathBuf>> {
+    let mut files = walk(sorter, dir, depth)?;
+    files.par_sort();
+    Ok(files)
+}
+
+fn walk(sorter: &Sorter, dir: &Path, depth: usize) -> io::Result<Vec<PathBuf>> {
+    let entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    let nested = entries
+        .into_par_iter()
+        .map(|path| {
+            if sorter.is_excluded(&path) {
+                Ok(Vec::new())
+            } else if path.is_dir() && sorter.find_rule(&path, RuleType::Dir).is_none() {
+                if sorter.max_depth.is_none_or(|max_depth| depth < max_depth) {
+                    walk(sorter, &path, depth + 1)
+                } else {
+                    Ok(Vec::new())
+                }
+            } else {
+                Ok(vec![path])
+            }
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    Ok(nested.into_iter().flatten().collect())
+}
@@ -0,0 +1,73 @@
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use croner::Cron;
+use serde::{Deserialize, Serialize};
+
+/// When something happens regularly: after an interval like `30m` or at the times of a cron
+/// expression like `*/30 * * * *`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Schedule {
+    text: String,
+    kind: Kind,
+}
+
+#[derive(Debug, Clone)]
+enum Kind {
+    Every(Duration),
+    Cron(Box<Cron>),
+}
+
+impl Schedule {
+    pub fn parse(text: &str) -> Result<Schedule> {
+        let kind = match humantime::parse_duration(text) {
+            Ok(interval) if interval.is_zero() => {
+                return Err(anyhow!("The interval must not be zero."))
+            }
+            Ok(interval) => Kind::Every(interval),
+            Err(_) => {
+                let cron = Cron::new(text).parse().context(format!(
+                    "{:?} is neither an interval nor a cron expression.",
+                    text
+                ))?;
+                Kind::Cron(Box::new(cron))
+            }
+        };
+        Ok(Schedule {
+            text: text.to_string(),
+            kind,
+        })
+    }
+
+    /// The first time after `after`
+    pub fn next(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        match &self.kind {
+            Kind::Every(interval) => Some(after + chrono::Duration::from_std(*interval).ok()?),
+            Kind::Cron(cron) => cron.find_next_occurrence(&after, false).ok(),
+        }
+    }
+
+    /// The next time from now on as an instant, e.g. for a timeout
+    pub fn next_instant(&self) -> Option<Instant> {
+        let now = Local::now();
+        let wait = (self.next(now)? - now).to_std().unwrap_or_default();
+        Some(Instant::now() + wait)
+    }
+}
+
+impl TryFrom<String> for Schedule {
+    type Error = anyhow::Error;
+
+    fn try_from(text: String) -> Result<Schedule> {
+        Schedule::parse(&text)
+    }
+}
+
+impl From<Schedule> for String {
+    fn from(schedule: Schedule) -> String {
+        schedule.text
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::APP_NAME;
+
+/// Where the value of a secret comes from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Secret {
+    /// Name of an environment variable
+    Env(String),
+    /// Name of a variable in the dotenv file
+    Dotenv(String),
+    /// Name of an entry in the keyring of the system, as stored with `wurmloch set-secret`
+    Keyring(String),
+}
+
+/// Looks up the secrets that settings refer to. The dotenv file is only read if it is needed.
+pub struct Secrets<'a> {
+    secrets: &'a BTreeMap<String, Secret>,
+    dotenv_path: PathBuf,
+    dotenv: Option<BTreeMap<String, String>>,
+}
+
+impl<'a> Secrets<'a> {
+    pub fn new(secrets: &'a BTreeMap<String, Secret>, dotenv_path: PathBuf) -> Secrets<'a> {
+        Secrets {
+            secrets,
+            dotenv_path,
+            dotenv: None,
+        }
+    }
+
+    /// Replaces all `{secrets.<name>}` references with the values of the secrets
+    pub fn substitute(&mut self, text: &str) -> Result<String> {
+        crate::config::substitute(text, "secrets", |name| self.get(name))
+    }
+
+    fn get(&mut self, name: &str) -> Result<String> {
+        let secret = self
+            .secrets
+            .get(name)
+            .context(format!("Unknown secret {{secrets.{}}}.", name))?;
+        match secret {
+            Secret::Env(variable) => env::var(variable).context(format!(
+                "Secret {} cannot be resolved. Environment variable {} is not set.",
+                name, variable
+            )),
+            Secret::Dotenv(variable) => {
+                if self.dotenv.is_none() {
+                    self.dotenv = Some(read_dotenv(&self.dotenv_path)?);
+                }
+                let dotenv = self.dotenv.as_ref().unwrap();
+                dotenv.get(variable).cloned().context(format!(
+                    "Secret {} cannot be resolved. {:?} does not contain {}.",
+                    name, &self.dotenv_path, variable
+                ))
+            }
+            Secret::Keyring(entry) => keyring_entry(entry)?.get_password().map_err(|e| {
+                anyhow!(
+                    "Secret {} cannot be resolved. Run `{} set-secret {}` to store it in the \
+                     keyring. Reason: {}",
+                    name,
+                    APP_NAME,
+                    entry,
+                    e
+                )
+            }),
+        }
+    }
+}
+
+fn keyring_entry(entry: &str) -> Result<keyring::Entry> {
+    keyring::Entry::new(APP_NAME, entry).context("Could not access the keyring.")
+}
+
+/// Stores the value of a `keyring` secret
+pub fn store(entry: &str, value: &str) -> Result<()> {
+    if value.is_empty() {
+        return Err(anyhow!("A value is required."));
+    }
+    keyring_entry(entry)?
+        .set_password(value)
+        .context(format!("Could not store secret {} in the keyring.", entry))
+}
+
+/// Reads `NAME=value` lines. Empty lines, comments and an `export` in front of the name are
+/// ignored, quotes around the value are removed.
+fn read_dotenv(path: &Path) -> Result<BTreeMap<String, String>> {
+    let content =
+        fs::read_to_string(path).context(format!("Could not read dotenv file {:?}.", path))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        if fs::metadata(path)?.permissions().mode() & 0o077 != 0 {
+            warn!(
+                "{:?} can be read by other users. Restrict it with `chmod 600`.",
+                path
+            );
+        }
+    }
+    let mut variables = BTreeMap::new();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (name, value) = match line.split_once('=') {
+            Some(variable) => variable,
+            None => {
+                warn!("Invalid line in {:?} ignored: {}", path, line);
+                continue;
+            }
+        };
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|quote| {
+                value
+                    .strip_prefix(*quote)
+                    .and_then(|value| value.strip_suffix(*quote))
+            })
+            .unwrap_or(value);
+        variables.insert(name.trim().to_string(), value.to_string());
+    }
+    Ok(variables)
+}
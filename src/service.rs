@@ -0,0 +1,219 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use log::error;
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+    ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{
+    self, ServiceControlHandlerResult, ServiceStatusHandle,
+};
+use windows_service::service_dispatcher;
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+
+use wurmloch::{control, data_dir, logging, Options, Registry, APP_NAME};
+
+const SERVICE_NAME: &str = "wurmloch";
+const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+const LOG_FILE_NAME: &str = "wurmloch.log";
+
+/// Watch directory of the running service. The service entry point cannot take arguments.
+static WATCH_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+#[derive(clap::Subcommand, Debug)]
+pub enum ServiceCommand {
+    /// Register wurmloch as a Windows service that starts with the system
+    Install {
+        /// This directory will be turned into a wormhole
+        #[clap(name = "WATCH_DIR", parse(from_os_str))]
+        watch_dir: PathBuf,
+
+        /// Run the service as this user, e.g. `.\foo`, so that it uses the rules of this user.
+        /// Defaults to the local system account.
+        #[clap(long, requires = "password")]
+        account: Option<String>,
+
+        /// Password of the account
+        #[clap(long)]
+        password: Option<String>,
+    },
+    /// Stop and remove the service
+    Uninstall,
+    /// Entry point for the service control manager
+    #[clap(hide = true)]
+    Run {
+        #[clap(name = "WATCH_DIR", parse(from_os_str))]
+        watch_dir: PathBuf,
+    },
+}
+
+pub fn manage(command: &ServiceCommand) -> Result<()> {
+    match command {
+        ServiceCommand::Install {
+            watch_dir,
+            account,
+            password,
+        } => install(watch_dir, account.as_deref(), password.as_deref()),
+        ServiceCommand::Uninstall => uninstall(),
+        ServiceCommand::Run { watch_dir } => {
+            logging::init_file(
+                logging::LogFile::new(data_dir()?.join(LOG_FILE_NAME)),
+                logging::LogFormat::Text,
+                None,
+                &format!("{}_LOG", APP_NAME.to_uppercase()),
+            )?;
+            WATCH_DIR.get_or_init(|| watch_dir.clone());
+            // Blocks until the service has stopped
+            service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+                .context("Could not connect to the service control manager.")
+        }
+    }
+}
+
+fn install(watch_dir: &Path, account: Option<&str>, password: Option<&str>) -> Result<()> {
+    let watch_dir = watch_dir
+        .canonicalize()
+        .context(format!("Could not find {:?}.", watch_dir))?;
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )
+    .context("Could not open the service control manager. Run this as administrator.")?;
+    let info = ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from("wurmloch"),
+        service_type: SERVICE_TYPE,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe()
+            .context("Could not determine the path of wurmloch.")?,
+        launch_arguments: vec![
+            OsString::from("service"),
+            OsString::from("run"),
+            watch_dir.clone().into_os_string(),
+        ],
+        dependencies: vec![],
+        account_name: account.map(OsString::from),
+        account_password: password.map(OsString::from),
+    };
+    let service = manager
+        .create_service(&info, ServiceAccess::CHANGE_CONFIG | ServiceAccess::START)
+        .context("Could not create the service.")?;
+    service.set_description(format!("Sorts the files in {}", watch_dir.display()))?;
+    service
+        .start::<&str>(&[])
+        .context("The service was created but could not be started.")?;
+    println!("wurmloch runs as a service now and starts with Windows.");
+    println!(
+        "Its log is written to {}",
+        data_dir()?.join(LOG_FILE_NAME).display()
+    );
+    Ok(())
+}
+
+fn uninstall() -> Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        .context("Could not open the service control manager. Run this as administrator.")?;
+    let service = manager
+        .open_service(
+            SERVICE_NAME,
+            ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+        )
+        .context("Could not find the service.")?;
+    // Marks the service for deletion, which happens as soon as it has stopped
+    service.delete().context("Could not remove the service.")?;
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop().context("Could not stop the service.")?;
+    }
+    println!("The service has been removed.");
+    Ok(())
+}
+
+windows_service::define_windows_service!(ffi_service_main, service_main);
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        error!("The service failed.");
+        error!("Reason: {:#}", e);
+    }
+}
+
+fn run_service() -> Result<()> {
+    let control = Arc::new(control::State::default());
+    let handle: Arc<OnceLock<ServiceStatusHandle>> = Arc::new(OnceLock::new());
+
+    let handler_control = Arc::clone(&control);
+    let handler_handle = Arc::clone(&handle);
+    let event_handler = move |event| -> ServiceControlHandlerResult {
+        let state = match event {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                handler_control.stop();
+                ServiceState::StopPending
+            }
+            ServiceControl::Pause => {
+                handler_control.pause();
+                ServiceState::Paused
+            }
+            ServiceControl::Continue => {
+                handler_control.resume();
+                ServiceState::Running
+            }
+            ServiceControl::Interrogate => return ServiceControlHandlerResult::NoError,
+            _ => return ServiceControlHandlerResult::NotImplemented,
+        };
+        if let Some(handle) = handler_handle.get() {
+            set_state(handle, state, ServiceExitCode::Win32(0)).ok();
+        }
+        ServiceControlHandlerResult::NoError
+    };
+    let status_handle = service_control_handler::register(SERVICE_NAME, event_handler)?;
+    handle.get_or_init(|| status_handle);
+    set_state(
+        &status_handle,
+        ServiceState::Running,
+        ServiceExitCode::Win32(0),
+    )?;
+
+    let watch_dir = WATCH_DIR.get().context("No watch directory given.")?;
+    let options = Options::new(watch_dir.clone());
+    let result = wurmloch::run(&options, Registry::new(), control);
+
+    let exit_code = match result {
+        Ok(()) => ServiceExitCode::Win32(0),
+        Err(_) => ServiceExitCode::ServiceSpecific(1),
+    };
+    set_state(&status_handle, ServiceState::Stopped, exit_code)?;
+    result
+}
+
+fn set_state(
+    handle: &ServiceStatusHandle,
+    state: ServiceState,
+    exit_code: ServiceExitCode,
+) -> windows_service::Result<()> {
+    let controls_accepted = match state {
+        ServiceState::StopPending | ServiceState::Stopped => ServiceControlAccept::empty(),
+        _ => {
+            ServiceControlAccept::STOP
+                | ServiceControlAccept::PAUSE_CONTINUE
+                | ServiceControlAccept::SHUTDOWN
+        }
+    };
+    handle.set_service_status(ServiceStatus {
+        service_type: SERVICE_TYPE,
+        current_state: state,
+        controls_accepted,
+        exit_code,
+        checkpoint: 0,
+        wait_hint: match state {
+            // The current file is finished first
+            ServiceState::StopPending => Duration::from_secs(30),
+            _ => Duration::default(),
+        },
+        process_id: None,
+    })
+}
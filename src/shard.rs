@@ -0,0 +1,160 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Subdirectories that spread the files of a target, e.g. `first_letter` or `hash/2`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Shard {
+    /// `a/`, `b/`, ... after the first letter or digit of the name, `_/` for anything else
+    FirstLetter,
+    /// Levels of two hex digits of the SHA-256 of the name, e.g. `3f/a2/` for `hash/2`
+    Hash(usize),
+}
+
+impl Shard {
+    pub fn parse(text: &str) -> Result<Shard> {
+        match text.split_once('/') {
+            None if text == "first_letter" => Ok(Shard::FirstLetter),
+            None if text == "hash" => Ok(Shard::Hash(1)),
+            Some(("hash", levels)) => match levels.parse() {
+                Ok(levels @ 1..=8) => Ok(Shard::Hash(levels)),
+                _ => Err(anyhow!(
+                    "{:?} is not a number of levels between 1 and 8.",
+                    levels
+                )),
+            },
+            _ => Err(anyhow!(
+                "Unknown shard {:?}. Use first_letter, hash or hash/<levels>.",
+                text
+            )),
+        }
+    }
+
+    /// The subdirectories for a file with this name
+    fn dirs(&self, name: &Path) -> PathBuf {
+        let name = name.to_string_lossy();
+        match self {
+            Shard::FirstLetter => {
+                let letter = match name.chars().next() {
+                    Some(c) if c.is_alphanumeric() => c.to_lowercase().to_string(),
+                    _ => String::from("_"),
+                };
+                PathBuf::from(letter)
+            }
+            Shard::Hash(levels) => {
+                let hash = format!("{:x}", Sha256::digest(name.as_bytes()));
+                (0..*levels).map(|i| &hash[i * 2..i * 2 + 2]).collect()
+            }
+        }
+    }
+}
+
+/// How moved and copied files are arranged inside the target
+#[derive(Debug, Clone, Default)]
+pub struct Layout {
+    pub shard: Option<Shard>,
+    /// Files go to numbered subdirectories like `0001/`, with a new one as soon as the last
+    /// one is full
+    pub max_files_per_dir: Option<usize>,
+}
+
+impl Layout {
+    pub fn new(shard: Option<Shard>, max_files_per_dir: Option<usize>) -> Result<Layout> {
+        if max_files_per_dir == Some(0) {
+            return Err(anyhow!("max_files_per_dir must be at least 1."));
+        }
+        Ok(Layout {
+            shard,
+            max_files_per_dir,
+        })
+    }
+
+    /// The directory inside the target for a file with this name
+    pub fn dir(&self, target: &Path, name: &Path) -> PathBuf {
+        let dir = match &self.shard {
+            Some(shard) => target.join(shard.dirs(name)),
+            None => target.to_path_buf(),
+        };
+        match self.max_files_per_dir {
+            Some(max) => rollover(&dir, max),
+            None => dir,
+        }
+    }
+
+    /// Like `dir`, but creates the subdirectories. The target itself is left to
+    /// `create_target`, so nothing is created below a missing target, e.g. the mount point of an
+    /// unmounted share.
+    pub fn create_dir(&self, target: &Path, name: &Path) -> Result<PathBuf> {
+        let dir = self.dir(target, name);
+        if dir != target && !dir.exists() {
+            if !target.is_dir() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("The target directory {:?} does not exist.", target),
+                )
+                .into());
+            }
+            fs::create_dir_all(&dir).context(format!("Could not create directory {:?}.", &dir))?;
+            debug!("Created directory {:?}.", &dir);
+        }
+        Ok(dir)
+    }
+}
+
+/// The last numbered subdirectory or the next one if it is full
+fn rollover(dir: &Path, max: usize) -> PathBuf {
+    let last = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let is_number = !name.is_empty() && name.bytes().all(|b| b.is_ascii_digit());
+            if is_number {
+                name.parse::<u32>().ok()
+            } else {
+                None
+            }
+        })
+        .max();
+    let number = match last {
+        Some(last) if count(&dir.join(format!("{:04}", last))) >= max => last + 1,
+        Some(last) => last,
+        None => 1,
+    };
+    dir.join(format!("{:04}", number))
+}
+
+fn count(dir: &Path) -> usize {
+    fs::read_dir(dir).map_or(0, |entries| entries.count())
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Shard::FirstLetter => f.write_str("first_letter"),
+            Shard::Hash(levels) => write!(f, "hash/{}", levels),
+        }
+    }
+}
+
+impl TryFrom<String> for Shard {
+    type Error = anyhow::Error;
+
+    fn try_from(text: String) -> Result<Shard> {
+        Shard::parse(&text)
+    }
+}
+
+impl From<Shard> for String {
+    fn from(shard: Shard) -> String {
+        shard.to_string()
+    }
+}
@@ -0,0 +1,40 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::action::Action;
+use crate::{throttle, transfer};
+
+/// What happens to a file whose target has too little free space
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnFull {
+    /// Leave it in the wormhole
+    Skip,
+    /// Leave it in the wormhole and report an error, including a desktop notification and the
+    /// webhook
+    Alert,
+    /// Move it to this directory instead
+    #[serde(untagged)]
+    Target(PathBuf),
+}
+
+/// The directory the action writes to and its free bytes, if these are less than the size of
+/// the file plus `reserve`. Moves within the same filesystem need no space and remote targets
+/// are not checked.
+pub fn lacking(action: &dyn Action, path: &Path, reserve: u64) -> Option<(PathBuf, u64)> {
+    let target = PathBuf::from(action.target()?.expand(path).ok()?);
+    if !target.is_absolute() {
+        return None;
+    }
+    // The target directory may not exist yet
+    let dir = target.ancestors().find(|dir| dir.exists())?;
+    if action.name() == "move"
+        && throttle::device(&path.to_string_lossy()) == throttle::device(&dir.to_string_lossy())
+    {
+        return None;
+    }
+    let needed = transfer::size(path).ok()? + reserve;
+    let free = fs4::available_space(dir).ok()?;
+    (free < needed).then(|| (dir.to_path_buf(), free))
+}
@@ -0,0 +1,187 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use chrono::{DateTime, Local, NaiveDate};
+
+use crate::index::{Index, Query};
+use crate::units;
+
+/// Entries shown for targets and unmatched files, the most frequent first
+const TOP: usize = 10;
+
+/// Files and bytes handled by a rule, in a target or on a day
+#[derive(Debug, Default, Clone, Copy)]
+struct Tally {
+    files: usize,
+    bytes: u64,
+}
+
+impl Tally {
+    fn add(&mut self, bytes: Option<u64>) {
+        self.files += 1;
+        self.bytes += bytes.unwrap_or_default();
+    }
+}
+
+/// What wurmloch did in a period, aggregated from the index
+#[derive(Debug)]
+pub struct Report {
+    since: Option<DateTime<Local>>,
+    rules: Vec<(String, Tally)>,
+    /// Rules in the rule file that did not handle a single file
+    idle: Vec<String>,
+    targets: Vec<(String, Tally)>,
+    days: Vec<(NaiveDate, Tally)>,
+    unmatched: Vec<(String, usize)>,
+    /// Rule, files given up on and the most recent reason
+    errors: Vec<(String, usize, String)>,
+}
+
+/// Aggregates the files processed since the given time. `rules` are the names of the rules in
+/// the rule file, to find those that handled nothing.
+pub fn report(index: &Index, rules: &[&str], since: Option<DateTime<Local>>) -> Result<Report> {
+    let files = index.find(&Query {
+        pattern: "*",
+        since,
+        ..Default::default()
+    })?;
+    let mut per_rule: HashMap<String, Tally> = HashMap::new();
+    let mut per_target: HashMap<String, Tally> = HashMap::new();
+    let mut per_day: HashMap<NaiveDate, Tally> = HashMap::new();
+    for file in &files {
+        per_rule
+            .entry(file.rule.clone())
+            .or_default()
+            .add(file.size);
+        let target = match file.destination.as_deref().and_then(Path::parent) {
+            Some(target) => target.display().to_string(),
+            None => String::from("(deleted)"),
+        };
+        per_target.entry(target).or_default().add(file.size);
+        per_day
+            .entry(file.time.date_naive())
+            .or_default()
+            .add(file.size);
+    }
+    let idle = rules
+        .iter()
+        .filter(|rule| !per_rule.contains_key(**rule))
+        .map(|rule| rule.to_string())
+        .collect();
+
+    let mut unmatched: HashMap<String, usize> = HashMap::new();
+    for path in index.unmatched(since)? {
+        *unmatched.entry(pattern_of(&path)).or_default() += 1;
+    }
+
+    // A file that failed again in a later run is counted once
+    let mut errors: HashMap<String, (HashSet<PathBuf>, String)> = HashMap::new();
+    for failure in index.failures(since)? {
+        let entry = errors.entry(failure.rule).or_default();
+        entry.0.insert(failure.path);
+        entry.1 = failure.reason;
+    }
+
+    let mut days: Vec<_> = per_day.into_iter().collect();
+    days.sort_by_key(|(day, _)| *day);
+    let mut errors: Vec<_> = errors
+        .into_iter()
+        .map(|(rule, (files, reason))| (rule, files.len(), reason))
+        .collect();
+    errors.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    Ok(Report {
+        since,
+        rules: most_first(per_rule, |tally| tally.files),
+        idle,
+        targets: most_first(per_target, |tally| tally.files),
+        days,
+        unmatched: most_first(unmatched, |count| *count),
+        errors,
+    })
+}
+
+fn most_first<T>(map: HashMap<String, T>, count: impl Fn(&T) -> usize) -> Vec<(String, T)> {
+    let mut entries: Vec<_> = map.into_iter().collect();
+    entries.sort_by(|a, b| count(&b.1).cmp(&count(&a.1)).then_with(|| a.0.cmp(&b.0)));
+    entries
+}
+
+/// A pattern for a rule that would match the file, e.g. `*.pdf`
+fn pattern_of(path: &Path) -> String {
+    match path.extension() {
+        Some(extension) => format!("*.{}", extension.to_string_lossy().to_lowercase()),
+        None => path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.since {
+            Some(since) => writeln!(f, "Since {}", since.format("%Y-%m-%d %H:%M"))?,
+            None => writeln!(f, "Since the first file")?,
+        }
+        let tally = |tally: &Tally| {
+            format!(
+                "{:>7} files  {:>10}",
+                tally.files,
+                units::format_size(tally.bytes)
+            )
+        };
+
+        writeln!(f, "\nRules")?;
+        if self.rules.is_empty() {
+            writeln!(f, "  No files were sorted.")?;
+        }
+        for (rule, files) in &self.rules {
+            writeln!(f, "  {}  {}", tally(files), rule)?;
+        }
+        if !self.idle.is_empty() {
+            writeln!(f, "  Sorted nothing: {}", self.idle.join(", "))?;
+        }
+
+        if !self.targets.is_empty() {
+            writeln!(f, "\nTargets")?;
+            for (target, files) in self.targets.iter().take(TOP) {
+                writeln!(f, "  {}  {}", tally(files), target)?;
+            }
+            if self.targets.len() > TOP {
+                writeln!(f, "  and {} more", self.targets.len() - TOP)?;
+            }
+        }
+
+        if !self.days.is_empty() {
+            writeln!(f, "\nDays")?;
+            for (day, files) in &self.days {
+                writeln!(f, "  {}  {}", tally(files), day.format("%Y-%m-%d %a"))?;
+            }
+        }
+
+        if !self.unmatched.is_empty() {
+            writeln!(f, "\nNo rule matched")?;
+            for (pattern, files) in self.unmatched.iter().take(TOP) {
+                writeln!(f, "  {:>7} files  {}", files, pattern)?;
+            }
+            if self.unmatched.len() > TOP {
+                writeln!(f, "  and {} more patterns", self.unmatched.len() - TOP)?;
+            }
+        }
+
+        if !self.errors.is_empty() {
+            writeln!(f, "\nGave up on")?;
+            for (rule, files, reason) in &self.errors {
+                writeln!(
+                    f,
+                    "  {:>7} files  {}, last because: {}",
+                    files, rule, reason
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use globset::{Glob, GlobMatcher};
+
+use crate::config::Config;
+use crate::index::Index;
+use crate::presets;
+
+/// Files that have to be ignored before a rule is suggested for them
+const MIN_FILES: usize = 3;
+/// File names shown for a suggestion
+const EXAMPLES: usize = 3;
+
+/// A rule for files that no rule matched
+#[derive(Debug)]
+pub struct Suggestion {
+    pub pattern: String,
+    pub target: PathBuf,
+    /// Ignored files the rule would have matched
+    pub files: usize,
+    pub examples: Vec<String>,
+}
+
+/// Rules for the kinds of files that no rule matched since the given time, the most ignored
+/// first. Files that a rule of `config` matches by now are left out.
+pub fn suggest(
+    index: &Index,
+    config: Option<&Config>,
+    since: Option<DateTime<Local>>,
+) -> Result<Vec<Suggestion>> {
+    let rules: Vec<(GlobMatcher, &Path)> = config
+        .iter()
+        .flat_map(|config| &config.rules)
+        .filter(|rule| rule.enabled != Some(false))
+        .filter_map(|rule| {
+            let glob = Glob::new(&rule.pattern).ok()?;
+            Some((glob.compile_matcher(), rule.target.first()))
+        })
+        .collect();
+
+    let mut by_extension: HashMap<String, Vec<String>> = HashMap::new();
+    let mut without_extension: HashMap<String, Vec<String>> = HashMap::new();
+    for path in index.unmatched(since)? {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if rules.iter().any(|(glob, _)| glob.is_match(name.as_ref())) {
+            continue;
+        }
+        match path.extension() {
+            Some(extension) => by_extension
+                .entry(extension.to_string_lossy().to_lowercase())
+                .or_default()
+                .push(name.into_owned()),
+            None => {
+                if let Some(prefix) = prefix(&name) {
+                    without_extension
+                        .entry(prefix.to_string())
+                        .or_default()
+                        .push(name.into_owned());
+                }
+            }
+        }
+    }
+
+    // Suggestions for the same extension stay together, the more specific one first
+    let mut groups: Vec<Vec<Suggestion>> = Vec::new();
+    for (extension, names) in by_extension {
+        if names.len() < MIN_FILES {
+            continue;
+        }
+        let mut group = Vec::new();
+        let target = target(&extension, &rules);
+        // Names like `Screenshot 2021-03-14.png` get a rule of their own, before the one for
+        // all files with the extension
+        let mut prefixes: HashMap<&str, usize> = HashMap::new();
+        for name in &names {
+            if let Some(prefix) = prefix(name) {
+                *prefixes.entry(prefix).or_default() += 1;
+            }
+        }
+        let common = prefixes
+            .into_iter()
+            .filter(|(_, count)| {
+                *count >= MIN_FILES && *count * 2 >= names.len() && *count < names.len()
+            })
+            .max_by_key(|(_, count)| *count);
+        if let Some((prefix, _)) = common {
+            let matching: Vec<String> = names
+                .iter()
+                .filter(|n| n.starts_with(prefix))
+                .cloned()
+                .collect();
+            group.push(Suggestion {
+                pattern: format!("{}*.{}", prefix, extensions(&matching)),
+                target: target.join(prefix.trim_end_matches(|c: char| !c.is_alphanumeric())),
+                files: matching.len(),
+                examples: matching.into_iter().take(EXAMPLES).collect(),
+            });
+        }
+        group.push(Suggestion {
+            pattern: format!("*.{}", extensions(&names)),
+            target,
+            files: names.len(),
+            examples: names.into_iter().take(EXAMPLES).collect(),
+        });
+        groups.push(group);
+    }
+    for (prefix, names) in without_extension {
+        if names.len() >= MIN_FILES {
+            groups.push(vec![Suggestion {
+                pattern: format!("{}*", prefix),
+                target: other_files().join(prefix.trim_end_matches(|c: char| !c.is_alphanumeric())),
+                files: names.len(),
+                examples: names.into_iter().take(EXAMPLES).collect(),
+            }]);
+        }
+    }
+    let files = |group: &Vec<Suggestion>| group.iter().map(|s| s.files).max().unwrap_or_default();
+    groups.sort_by(|a, b| {
+        files(b)
+            .cmp(&files(a))
+            .then_with(|| a[0].pattern.cmp(&b[0].pattern))
+    });
+    Ok(groups.into_iter().flatten().collect())
+}
+
+/// The extension of the files in every case it is written in, e.g. `{JPG,jpg}`
+fn extensions(names: &[String]) -> String {
+    let mut cases: Vec<String> = names
+        .iter()
+        .filter_map(|name| Path::new(name).extension())
+        .map(|e| e.to_string_lossy().into_owned())
+        .collect();
+    cases.sort();
+    cases.dedup();
+    match cases.as_slice() {
+        [case] => case.clone(),
+        _ => format!("{{{}}}", cases.join(",")),
+    }
+}
+
+/// The start of generated names like `IMG_` in `IMG_2041.jpg` or `Screenshot ` in
+/// `Screenshot 2021-03-14.png`
+fn prefix(name: &str) -> Option<&str> {
+    let end = name.find(|c: char| c.is_ascii_digit())?;
+    let prefix = &name[..end];
+    if prefix.chars().filter(|c| c.is_alphabetic()).count() >= 3 {
+        Some(prefix)
+    } else {
+        None
+    }
+}
+
+/// The target of an existing rule for files of the same kind, or else the folder the presets use
+/// for them
+fn target(extension: &str, rules: &[(GlobMatcher, &Path)]) -> PathBuf {
+    match presets::kind(extension) {
+        Some((kind, folder)) => rules
+            .iter()
+            .find(|(glob, target)| {
+                !target.as_os_str().is_empty()
+                    && kind.iter().any(|e| glob.is_match(format!("file.{}", e)))
+            })
+            .map_or(folder, |(_, target)| target.to_path_buf()),
+        None => other_files().join(extension),
+    }
+}
+
+fn other_files() -> PathBuf {
+    dirs::document_dir()
+        .or_else(|| dirs::home_dir().map(|home| home.join("Documents")))
+        .unwrap_or_default()
+}
+
+impl fmt::Display for Suggestion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} files matching `{}` were ignored, e.g. {}. Suggested rule:",
+            self.files,
+            self.pattern,
+            self.examples.join(", ")
+        )?;
+        // JSON strings are valid in YAML and escape whatever needs it
+        let quote = |value: &str| serde_json::to_string(value).unwrap_or_default();
+        writeln!(f, "  - pattern: {}", quote(&self.pattern))?;
+        writeln!(f, "    target: {}", quote(&self.target.to_string_lossy()))
+    }
+}
@@ -0,0 +1,166 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::{self, Command};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+#[cfg(unix)]
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+use crate::config;
+
+const UNIT_NAME: &str = "wurmloch.service";
+
+/// Talks to systemd if wurmloch runs as a service of `Type=notify`. Does nothing otherwise.
+pub struct Notifier {
+    #[cfg(unix)]
+    socket: Option<(UnixDatagram, SocketAddr)>,
+    /// Keepalives are sent twice per watchdog interval
+    keepalive_interval: Option<Duration>,
+    last_keepalive: Instant,
+    last_status: String,
+}
+
+impl Notifier {
+    pub fn from_env() -> Notifier {
+        let watchdog_pid = env::var("WATCHDOG_PID").ok();
+        let keepalive_interval = env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.parse::<u64>().ok())
+            .filter(|_| watchdog_pid.is_none_or(|pid| pid == process::id().to_string()))
+            .map(|usec| Duration::from_micros(usec) / 2);
+        Notifier {
+            #[cfg(unix)]
+            socket: env::var_os("NOTIFY_SOCKET").and_then(|path| connect(Path::new(&path))),
+            keepalive_interval,
+            last_keepalive: Instant::now(),
+            last_status: String::new(),
+        }
+    }
+
+    /// Startup is complete
+    pub fn ready(&self) {
+        self.send("READY=1");
+    }
+
+    pub fn stopping(&self) {
+        self.send("STOPPING=1");
+    }
+
+    /// Tells the watchdog that the event loop is still alive, if it is time to
+    pub fn keepalive(&mut self) {
+        if let Some(interval) = self.keepalive_interval {
+            if self.last_keepalive.elapsed() >= interval {
+                self.send("WATCHDOG=1");
+                self.last_keepalive = Instant::now();
+            }
+        }
+    }
+
+    /// Shown by `systemctl status`
+    pub fn status(&mut self, status: String) {
+        if status != self.last_status {
+            self.send(&format!("STATUS={}", status));
+            self.last_status = status;
+        }
+    }
+
+    #[cfg(unix)]
+    fn send(&self, state: &str) {
+        if let Some((socket, addr)) = &self.socket {
+            if let Err(e) = socket.send_to_addr(state.as_bytes(), addr) {
+                debug!("Could not notify systemd: {}", e);
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn send(&self, _state: &str) {}
+}
+
+#[cfg(unix)]
+fn connect(path: &Path) -> Option<(UnixDatagram, SocketAddr)> {
+    let addr = match path.to_str().and_then(|path| path.strip_prefix('@')) {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            SocketAddr::from_abstract_name(name)
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => return None,
+        None => SocketAddr::from_pathname(path),
+    };
+    match (UnixDatagram::unbound(), addr) {
+        (Ok(socket), Ok(addr)) => Some((socket, addr)),
+        (Err(e), _) | (_, Err(e)) => {
+            warn!("Could not connect to systemd at {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Writes a systemd unit that runs wurmloch on the given directory, then enables and starts it
+pub fn install_service(watch_dir: &Path, user: bool) -> Result<()> {
+    if !user {
+        return Err(anyhow!(
+            "Only services of the current user are supported so far. Use --user."
+        ));
+    }
+    let watch_dir = watch_dir
+        .canonicalize()
+        .context(format!("Could not find {:?}.", watch_dir))?;
+    config::ensure_confirmed()?;
+    let exe = env::current_exe().context("Could not determine the path of wurmloch.")?;
+
+    let config_dir = dirs::config_dir().context("Could not determine configuration directory.")?;
+    let unit_dir = config_dir.join("systemd").join("user");
+    fs::create_dir_all(&unit_dir).context(format!("Could not create {:?}.", &unit_dir))?;
+    let unit_path = unit_dir.join(UNIT_NAME);
+    let unit = format!(
+        "[Unit]\n\
+         Description=wurmloch on {dir}\n\
+         Documentation=https://github.com/gonsor/wurmloch\n\
+         \n\
+         [Service]\n\
+         Type=notify\n\
+         ExecStart=\"{exe}\" \"{dir}\" --log-target journald\n\
+         WatchdogSec=30\n\
+         Restart=on-failure\n\
+         \n\
+         [Install]\n\
+         WantedBy=default.target\n",
+        exe = escape(&exe),
+        dir = escape(&watch_dir),
+    );
+    fs::write(&unit_path, unit).context(format!("Could not write {:?}.", &unit_path))?;
+    println!("Created {}", unit_path.display());
+
+    systemctl(&["daemon-reload"])?;
+    systemctl(&["enable", "--now", UNIT_NAME])?;
+    println!("wurmloch is running now and starts whenever you log in.");
+    println!("See its log with: journalctl --user -u {}", UNIT_NAME);
+    Ok(())
+}
+
+/// Quotes and specifiers in unit files
+fn escape(path: &Path) -> String {
+    path.display()
+        .to_string()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+}
+
+fn systemctl(args: &[&str]) -> Result<()> {
+    let status = Command::new("systemctl")
+        .arg("--user")
+        .args(args)
+        .status()
+        .context("Could not run systemctl.")?;
+    if !status.success() {
+        return Err(anyhow!("systemctl --user {} failed.", args.join(" ")));
+    }
+    Ok(())
+}
@@ -0,0 +1,407 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+
+use crate::counter::Counter;
+use crate::normalize::{normalize, Normalization};
+use crate::plugin::{self, Registry, Variable};
+
+/// A string with `{variable}` placeholders. Literal braces are written as `{{` and `}}`.
+/// `{variable:03}` pads the value with zeros to three characters. Filters transform the value,
+/// e.g. `{audio.artist|replace('/', '-')|lower}`.
+pub struct Template {
+    source: String,
+    segments: Vec<Segment>,
+    /// Applied to the expanded template
+    normalizations: Vec<Normalization>,
+    /// Numbers for `{counter}`
+    counter: Option<Arc<Counter>>,
+}
+
+enum Segment {
+    Literal(String),
+    Variable {
+        name: String,
+        variable: Arc<dyn Variable>,
+        /// Width to pad the value to
+        width: Option<usize>,
+        filters: Vec<Filter>,
+    },
+    /// Sequence number of the rule
+    Counter {
+        width: Option<usize>,
+        filters: Vec<Filter>,
+    },
+}
+
+/// Transformation of the value of a placeholder
+#[derive(Debug)]
+enum Filter {
+    Lower,
+    Upper,
+    /// Lower case letters and digits with dashes in between
+    Slugify,
+    /// Replaces characters that are not allowed in file names with `_`
+    Sanitize,
+    Replace(String, String),
+    /// Keeps at most this many characters
+    Truncate(usize),
+}
+
+impl Template {
+    pub fn parse(source: &str, registry: &Registry) -> Result<Template> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = source.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut placeholder = String::new();
+                    let mut quote = None;
+                    loop {
+                        match chars.next() {
+                            Some('}') if quote.is_none() => break,
+                            Some(c) => {
+                                match quote {
+                                    Some(q) if c == q => quote = None,
+                                    None if c == '\'' || c == '"' => quote = Some(c),
+                                    _ => {}
+                                }
+                                placeholder.push(c);
+                            }
+                            None => {
+                                return Err(anyhow!(
+                                    "Unclosed placeholder in template {:?}.",
+                                    source
+                                ))
+                            }
+                        }
+                    }
+                    let mut parts = split_unquoted(&placeholder, '|').into_iter();
+                    let name = parts.next().unwrap_or_default();
+                    let (name, width) = match name.split_once(':') {
+                        Some((name, spec)) => (name, Some(parse_width(spec, name)?)),
+                        None => (name, None),
+                    };
+                    let name = name.trim();
+                    let filters = parts
+                        .map(|filter| parse_filter(filter, name))
+                        .collect::<Result<Vec<_>>>()?;
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    if name == "counter" {
+                        segments.push(Segment::Counter { width, filters });
+                        continue;
+                    }
+                    let variable = registry
+                        .get_variable(name)
+                        .context(format!("Unknown template variable {{{}}}.", name))?;
+                    segments.push(Segment::Variable {
+                        name: String::from(name),
+                        variable,
+                        width,
+                        filters,
+                    });
+                }
+                '}' => return Err(anyhow!("Unmatched '}}' in template {:?}.", source)),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Template {
+            source: String::from(source),
+            segments,
+            normalizations: Vec::new(),
+            counter: None,
+        })
+    }
+
+    /// The template with the counter that `{counter}` is taken from
+    pub fn counted(self, counter: Arc<Counter>) -> Template {
+        Template {
+            counter: Some(counter),
+            ..self
+        }
+    }
+
+    pub fn uses_counter(&self) -> bool {
+        self.segments
+            .iter()
+            .any(|s| matches!(s, Segment::Counter { .. }))
+    }
+
+    /// The template with the normalizations applied to its expansion
+    pub fn normalized(self, normalizations: &[Normalization]) -> Template {
+        Template {
+            normalizations: normalizations.to_vec(),
+            ..self
+        }
+    }
+
+    /// The template as written
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn is_static(&self) -> bool {
+        self.segments
+            .iter()
+            .all(|s| matches!(s, Segment::Literal(_)))
+    }
+
+    /// The directory that does not depend on any variable
+    pub fn static_dir(&self) -> PathBuf {
+        let prefix = match self.segments.first() {
+            Some(Segment::Literal(literal)) => literal.as_str(),
+            _ => "",
+        };
+        if self.is_static() || prefix.ends_with(std::path::is_separator) {
+            PathBuf::from(prefix)
+        } else {
+            Path::new(prefix)
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default()
+        }
+    }
+
+    /// Replaces all placeholders with their values for the given file. Variables are only
+    /// resolved if they appear in the template.
+    pub fn expand(&self, path: &Path) -> Result<String> {
+        let mut expanded = String::new();
+        let mut resolved: Vec<(&str, String)> = Vec::new();
+        for segment in self.segments.iter() {
+            match segment {
+                Segment::Literal(literal) => expanded.push_str(literal),
+                Segment::Variable {
+                    name,
+                    variable,
+                    width,
+                    filters,
+                } => {
+                    let value = match resolved.iter().find(|(n, _)| n == name) {
+                        Some((_, value)) => value,
+                        None => {
+                            let value = variable.resolve(path).context(format!(
+                                "Could not resolve {{{}}} for {:?}.",
+                                name, path
+                            ))?;
+                            resolved.push((name, value));
+                            &resolved[resolved.len() - 1].1
+                        }
+                    };
+                    push_value(&mut expanded, name, value, *width, filters)?;
+                }
+                Segment::Counter { width, filters } => {
+                    let counter = self
+                        .counter
+                        .as_ref()
+                        .context("{counter} can only be used in the new name of a rule.")?;
+                    let number = counter.reserve(path).to_string();
+                    let width = width.or(counter.padding());
+                    push_value(&mut expanded, "counter", &number, width, filters)?;
+                }
+            }
+        }
+        if !self.normalizations.is_empty() {
+            expanded = normalize(&expanded, &self.normalizations);
+        }
+        Ok(expanded)
+    }
+}
+
+/// Appends the filtered value, padded with zeros to the width. Apart from `{path}`, values must
+/// not leave the directory they are placed in, e.g. `{stem|truncate(2)}` of `..x.pdf`.
+fn push_value(
+    expanded: &mut String,
+    name: &str,
+    value: &str,
+    width: Option<usize>,
+    filters: &[Filter],
+) -> Result<()> {
+    let value = filters
+        .iter()
+        .fold(String::from(value), |value, filter| filter.apply(&value));
+    if name != "path" && (value == "." || value == ".." || value.contains(std::path::is_separator))
+    {
+        return Err(anyhow!(
+            "{{{}}} becomes {:?}, which is not a valid file name.",
+            name,
+            value
+        ));
+    }
+    match width {
+        Some(width) => expanded.push_str(&format!("{:0>1$}", value, width)),
+        None => expanded.push_str(&value),
+    }
+    Ok(())
+}
+
+/// Only zero padding like `02` is supported
+fn parse_width(spec: &str, name: &str) -> Result<usize> {
+    match spec.trim().strip_prefix('0').map(str::parse) {
+        Some(Ok(width)) => Ok(width),
+        _ => Err(anyhow!(
+            "Unknown format :{} of {{{}}}. Only zero padding like :02 is supported.",
+            spec,
+            name.trim()
+        )),
+    }
+}
+
+/// Filters are written as `name` or `name(argument, ...)` with quoted strings or numbers as
+/// arguments
+fn parse_filter(text: &str, variable: &str) -> Result<Filter> {
+    let text = text.trim();
+    let (name, args) = match text.split_once('(') {
+        Some((name, rest)) => match rest.strip_suffix(')') {
+            Some(args) => (name.trim(), arguments(args)),
+            None => {
+                return Err(anyhow!(
+                    "Missing ')' after filter {} of {{{}}}.",
+                    text,
+                    variable
+                ))
+            }
+        },
+        None => (text, Vec::new()),
+    };
+    let filter = match (name, args.as_slice()) {
+        ("lower", []) => Filter::Lower,
+        ("upper", []) => Filter::Upper,
+        ("slugify", []) => Filter::Slugify,
+        ("sanitize", []) => Filter::Sanitize,
+        ("replace", [from, to]) if !from.is_empty() => Filter::Replace(from.clone(), to.clone()),
+        ("truncate", [length]) => match length.parse() {
+            Ok(length) => Filter::Truncate(length),
+            Err(_) => {
+                return Err(anyhow!(
+                    "truncate of {{{}}} needs a number of characters, not {:?}.",
+                    variable,
+                    length
+                ))
+            }
+        },
+        ("lower", _) | ("upper", _) | ("slugify", _) | ("sanitize", _) => {
+            return Err(anyhow!(
+                "Filter {} of {{{}}} takes no arguments.",
+                name,
+                variable
+            ))
+        }
+        ("replace", _) => {
+            return Err(anyhow!(
+                "Filter replace of {{{}}} needs a text and its replacement, e.g. \
+                 replace('/', '-').",
+                variable
+            ))
+        }
+        ("truncate", _) => {
+            return Err(anyhow!(
+                "Filter truncate of {{{}}} needs one argument, e.g. truncate(40).",
+                variable
+            ))
+        }
+        _ => {
+            return Err(anyhow!(
+                "Unknown filter {} of {{{}}}. Available are lower, upper, slugify, sanitize, \
+                 replace and truncate.",
+                name,
+                variable
+            ))
+        }
+    };
+    Ok(filter)
+}
+
+/// Comma-separated arguments without their quotes
+fn arguments(text: &str) -> Vec<String> {
+    if text.trim().is_empty() {
+        return Vec::new();
+    }
+    split_unquoted(text, ',')
+        .into_iter()
+        .map(|arg| {
+            let arg = arg.trim();
+            let quoted = arg.len() >= 2
+                && ((arg.starts_with('\'') && arg.ends_with('\''))
+                    || (arg.starts_with('"') && arg.ends_with('"')));
+            if quoted {
+                String::from(&arg[1..arg.len() - 1])
+            } else {
+                String::from(arg)
+            }
+        })
+        .collect()
+}
+
+/// Splits at the separator where it is not inside single or double quotes
+fn split_unquoted(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == separator => {
+                parts.push(&text[start..i]);
+                start = i + c.len_utf8();
+            }
+            None => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+impl Filter {
+    fn apply(&self, value: &str) -> String {
+        match self {
+            Filter::Lower => value.to_lowercase(),
+            Filter::Upper => value.to_uppercase(),
+            Filter::Slugify => {
+                let mut slug = String::new();
+                for c in value.chars() {
+                    if c.is_alphanumeric() {
+                        slug.extend(c.to_lowercase());
+                    } else if !slug.is_empty() && !slug.ends_with('-') {
+                        slug.push('-');
+                    }
+                }
+                slug.trim_end_matches('-').to_string()
+            }
+            Filter::Sanitize => plugin::sanitize(value),
+            Filter::Replace(from, to) => value.replace(from.as_str(), to),
+            Filter::Truncate(length) => value.chars().take(*length).collect(),
+        }
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl fmt::Debug for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", &self.source)
+    }
+}
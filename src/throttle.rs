@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Spreads files and bytes over time, separately for every disk and server that files are
+/// sorted to
+#[derive(Debug, Default)]
+pub struct Throttle {
+    max_files_per_minute: Option<u32>,
+    max_bytes_per_second: Option<u64>,
+    devices: Mutex<HashMap<String, Budget>>,
+}
+
+/// When the next file and the next bytes may go to a device
+#[derive(Debug)]
+struct Budget {
+    files: Instant,
+    bytes: Instant,
+}
+
+impl Throttle {
+    pub fn new(max_files_per_minute: Option<u32>, max_bytes_per_second: Option<u64>) -> Throttle {
+        Throttle {
+            max_files_per_minute: max_files_per_minute.filter(|max| *max > 0),
+            max_bytes_per_second: max_bytes_per_second.filter(|max| *max > 0),
+            devices: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Waits until another file may be sorted to the device
+    pub fn file(&self, device: &str) {
+        if let Some(max) = self.max_files_per_minute {
+            let wait = self.reserve(device, |budget| &mut budget.files, 60.0 / max as f64);
+            if !wait.is_zero() {
+                debug!("Waiting {:?} for the file limit of {}.", wait, device);
+                thread::sleep(wait);
+            }
+        }
+    }
+
+    /// Limits the bytes that are written to the device, if there is a limit
+    pub fn limit(&self, device: &str) -> Option<Limit<'_>> {
+        self.max_bytes_per_second.map(|max| Limit {
+            throttle: self,
+            device: device.to_string(),
+            bytes_per_second: max,
+        })
+    }
+
+    pub fn max_bytes_per_second(&self) -> Option<u64> {
+        self.max_bytes_per_second
+    }
+
+    /// Takes `seconds` of the budget of the device and returns how long to wait for them
+    fn reserve(
+        &self,
+        device: &str,
+        budget: impl Fn(&mut Budget) -> &mut Instant,
+        seconds: f64,
+    ) -> Duration {
+        let now = Instant::now();
+        let mut devices = self.devices.lock().unwrap_or_else(|e| e.into_inner());
+        let next = budget(devices.entry(device.to_string()).or_insert(Budget {
+            files: now,
+            bytes: now,
+        }));
+        let start = (*next).max(now);
+        *next = start + Duration::from_secs_f64(seconds);
+        start - now
+    }
+}
+
+/// Bytes per second that may be written to one device
+#[derive(Debug)]
+pub struct Limit<'a> {
+    throttle: &'a Throttle,
+    device: String,
+    bytes_per_second: u64,
+}
+
+impl Limit<'_> {
+    /// Waits until `bytes` more may be written
+    pub fn consume(&self, bytes: u64) {
+        let seconds = bytes as f64 / self.bytes_per_second as f64;
+        let wait = self
+            .throttle
+            .reserve(&self.device, |budget| &mut budget.bytes, seconds);
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+
+    pub fn bytes_per_second(&self) -> u64 {
+        self.bytes_per_second
+    }
+}
+
+/// Reads no faster than the limit allows
+pub struct Paced<'a, R> {
+    inner: R,
+    limit: &'a Limit<'a>,
+}
+
+impl<'a, R: Read> Paced<'a, R> {
+    pub fn new(inner: R, limit: &'a Limit<'a>) -> Paced<'a, R> {
+        Paced { inner, limit }
+    }
+}
+
+impl<R: Read> Read for Paced<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.limit.consume(read as u64);
+        Ok(read)
+    }
+}
+
+/// Names the disk or server a target is on: the host of URLs, the remote of rclone targets and
+/// the device of local directories
+pub fn device(target: &str) -> String {
+    if let Some((scheme, rest)) = target.split_once("://") {
+        return format!(
+            "{}://{}",
+            scheme,
+            rest.split('/').next().unwrap_or_default()
+        );
+    }
+    let path = Path::new(target);
+    if !path.is_absolute() {
+        // An rclone remote like `gdrive:Documents`
+        return target.split(':').next().unwrap_or(target).to_string();
+    }
+    local_device(path)
+}
+
+#[cfg(unix)]
+fn local_device(path: &Path) -> String {
+    use std::os::unix::fs::MetadataExt;
+
+    // The target directory may not exist yet
+    path.ancestors()
+        .find_map(|dir| fs::metadata(dir).ok())
+        .map(|metadata| format!("device {}", metadata.dev()))
+        .unwrap_or_default()
+}
+
+#[cfg(not(unix))]
+fn local_device(path: &Path) -> String {
+    use std::path::Component;
+
+    // Drive letters and network shares
+    match path.components().next() {
+        Some(Component::Prefix(prefix)) => prefix.as_os_str().to_string_lossy().to_uppercase(),
+        _ => String::new(),
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::journal::Event;
+use crate::{RuleType, Sorter};
+
+/// How a rule was evaluated for a file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Evaluation {
+    pub rule: String,
+    /// Whether the pattern matches the name of the file
+    pub pattern: bool,
+    /// Whether the file meets the conditions of the rule. Only checked if the pattern matches.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub conditions: BTreeMap<String, bool>,
+}
+
+impl Evaluation {
+    fn summary(&self) -> String {
+        let failed: Vec<&str> = self
+            .conditions
+            .iter()
+            .filter(|(_, met)| !**met)
+            .map(|(condition, _)| condition.as_str())
+            .collect();
+        match (self.pattern, failed.is_empty()) {
+            (false, _) => String::from("pattern does not match"),
+            (true, true) => String::from("matches"),
+            (true, false) => format!("pattern matches, {} not met", failed.join(", ")),
+        }
+    }
+}
+
+/// Logs how every rule was evaluated for the file and what was decided, and records it in the
+/// journal
+pub(crate) fn record(sorter: &Sorter, path: &Path, kind: RuleType, decision: &str) {
+    let candidates = match path.file_name() {
+        Some(name) => sorter.patterns.matching(name),
+        None => Vec::new(),
+    };
+    let rules: Vec<Evaluation> = sorter
+        .rules
+        .iter()
+        .enumerate()
+        .map(|(index, rule)| {
+            let pattern = candidates.contains(&index);
+            Evaluation {
+                rule: rule.name.clone(),
+                pattern,
+                conditions: if pattern {
+                    rule.checks(path, kind)
+                        .into_iter()
+                        .map(|(condition, met)| (String::from(condition), met))
+                        .collect()
+                } else {
+                    BTreeMap::new()
+                },
+            }
+        })
+        .collect();
+
+    info!("Decision for {:?}:", path);
+    for evaluation in rules.iter() {
+        info!("  Rule {}: {}", evaluation.rule, evaluation.summary());
+    }
+    info!("  {}", decision);
+
+    if sorter.dry_run {
+        return;
+    }
+    let event = Event::Decision {
+        path: path.to_path_buf(),
+        rules,
+        decision: String::from(decision),
+    };
+    if let Err(e) = sorter.journal.append(event) {
+        error!("Could not record the decision for {:?}: {:#}", path, e);
+    }
+}
@@ -0,0 +1,184 @@
+use std::fs::{self, File, FileTimes};
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::quarantine::{Blocked, Check};
+use crate::throttle::{Limit, Paced};
+
+/// Hash that copies are compared with before the original is deleted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Checksum {
+    Sha256,
+}
+
+/// Moves a file or directory. Falls back to copy and delete if source and destination are
+/// on different filesystems. With a checksum, the source is only deleted if the copy matches.
+/// With a limit, the copy is written no faster than it allows.
+pub fn move_path(
+    from: &Path,
+    to: &Path,
+    verify: Option<Checksum>,
+    limit: Option<&Limit>,
+) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            debug!("{:?} and {:?} are on different devices. Copying.", from, to);
+            copy_path(from, to, verify, limit)?;
+            remove_path(from)
+        }
+        result => result,
+    }
+}
+
+/// Copies a file or a whole directory tree. Files are cloned where the filesystem allows it.
+/// With a checksum, every copied file is compared with its original and removed again if they
+/// differ.
+pub fn copy_path(
+    from: &Path,
+    to: &Path,
+    verify: Option<Checksum>,
+    limit: Option<&Limit>,
+) -> io::Result<()> {
+    if from.is_dir() {
+        fs::create_dir_all(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_path(&entry.path(), &to.join(entry.file_name()), verify, limit)?;
+        }
+        preserve_metadata(from, to);
+        return Ok(());
+    }
+
+    if clone_file(from, to) {
+        // The clone shares its data with the original, there is nothing to compare
+        debug!("Cloned {:?} to {:?}.", from, to);
+        preserve_metadata(from, to);
+        return Ok(());
+    }
+    match limit {
+        Some(limit) => {
+            let mut source = Paced::new(File::open(from)?, limit);
+            io::copy(&mut source, &mut File::create(to)?)?;
+        }
+        None => {
+            fs::copy(from, to)?;
+        }
+    }
+    preserve_metadata(from, to);
+    if let Some(checksum) = verify {
+        let expected = hash(from, checksum)?;
+        if hash(to, checksum)? != expected {
+            let _ = fs::remove_file(to);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                Blocked::new(
+                    Check::Mismatch,
+                    format!("The copy {:?} does not match {:?}.", to, from),
+                ),
+            ));
+        }
+        debug!("Verified {:?} with {:?}.", to, checksum);
+    }
+    Ok(())
+}
+
+/// Gives a copy the permissions, timestamps and, if wurmloch is allowed to, the owner of the
+/// original
+fn preserve_metadata(from: &Path, to: &Path) {
+    let metadata = match fs::metadata(from) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            warn!("Could not read the metadata of {:?}: {}", from, e);
+            return;
+        }
+    };
+    if let Err(e) = set_times(to, &metadata) {
+        warn!("Could not copy the timestamps of {:?}: {}", from, e);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        // Only privileged users can give files away
+        if let Err(e) = std::os::unix::fs::chown(to, Some(metadata.uid()), Some(metadata.gid())) {
+            debug!("Could not copy the owner of {:?}: {}", from, e);
+        }
+    }
+    // Last, as changing the owner clears the setuid and setgid bits
+    if let Err(e) = fs::set_permissions(to, metadata.permissions()) {
+        warn!("Could not copy the permissions of {:?}: {}", from, e);
+    }
+}
+
+fn set_times(path: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+    let times = FileTimes::new()
+        .set_accessed(metadata.accessed()?)
+        .set_modified(metadata.modified()?);
+    // Read-only files and directories can have their times changed as well
+    #[cfg(not(windows))]
+    let file = File::open(path)?;
+    #[cfg(windows)]
+    let file = {
+        use std::os::windows::fs::OpenOptionsExt;
+
+        // FILE_WRITE_ATTRIBUTES, FILE_FLAG_BACKUP_SEMANTICS
+        File::options()
+            .access_mode(0x100)
+            .custom_flags(0x0200_0000)
+            .open(path)?
+    };
+    file.set_times(times)
+}
+
+/// Creates a copy-on-write clone (reflink) on filesystems that support it, e.g. Btrfs, XFS and
+/// APFS. The clone is created instantly and takes no additional space until one of the files is
+/// changed. Returns whether it worked.
+fn clone_file(from: &Path, to: &Path) -> bool {
+    if to.exists() {
+        return false;
+    }
+    match reflink_copy::reflink(from, to) {
+        Ok(()) => true,
+        Err(e) => {
+            debug!("Could not clone {:?}: {}", from, e);
+            false
+        }
+    }
+}
+
+/// Hex digest of a file
+pub fn hash(path: &Path, checksum: Checksum) -> io::Result<String> {
+    match checksum {
+        Checksum::Sha256 => {
+            let mut hasher = Sha256::new();
+            io::copy(&mut File::open(path)?, &mut hasher)?;
+            Ok(format!("{:x}", hasher.finalize()))
+        }
+    }
+}
+
+pub fn remove_path(path: &Path) -> io::Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Size of a file or the total size of all files in a directory tree
+pub fn size(path: &Path) -> io::Result<u64> {
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.is_dir() {
+        let mut total = 0;
+        for entry in fs::read_dir(path)? {
+            total += size(&entry?.path())?;
+        }
+        Ok(total)
+    } else {
+        Ok(metadata.len())
+    }
+}
@@ -0,0 +1,298 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::transfer;
+
+pub const TRASH_DIR_NAME: &str = ".wurmloch-trash";
+const ENTRY_EXTENSION: &str = "yaml";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrashSettings {
+    /// Staged files are deleted permanently after this time
+    #[serde(with = "humantime_serde")]
+    pub grace_period: Duration,
+
+    /// Upper bound for the total size of all staged files. The oldest entries are deleted
+    /// permanently to make room.
+    #[serde(with = "crate::units::size")]
+    pub max_size: u64,
+}
+
+impl Default for TrashSettings {
+    fn default() -> Self {
+        TrashSettings {
+            grace_period: Duration::from_secs(7 * 24 * 60 * 60),
+            max_size: 1 << 30,
+        }
+    }
+}
+
+/// A staged file or directory
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    #[serde(skip)]
+    pub id: String,
+    pub original_path: PathBuf,
+    pub deleted_at: DateTime<Local>,
+    pub size: u64,
+}
+
+/// Staging area for deleted files. Every entry consists of a directory `<id>` that holds the
+/// file itself and a metadata file `<id>.yaml`.
+pub struct Trash {
+    dir: PathBuf,
+    settings: TrashSettings,
+}
+
+impl Trash {
+    pub fn open(dir: PathBuf, settings: TrashSettings) -> Result<Trash> {
+        fs::create_dir_all(&dir).context(format!("Could not create trash {:?}.", &dir))?;
+        Ok(Trash { dir, settings })
+    }
+
+    /// Moves a file or directory into the trash instead of deleting it. Fails and leaves it in
+    /// place if it is larger than the whole trash.
+    pub fn stage(&self, path: &Path) -> Result<Entry> {
+        let size = self.fits(path)?;
+        self.make_room(size)?;
+
+        let now = Local::now();
+        let mut id = now.format("%Y%m%d-%H%M%S-%f").to_string();
+        while self.dir.join(&id).exists() {
+            id.push('x');
+        }
+        let entry = Entry {
+            id,
+            original_path: path.to_path_buf(),
+            deleted_at: now,
+            size,
+        };
+        let payload = self.payload_path(&entry);
+        fs::create_dir_all(payload.parent().unwrap())?;
+        transfer::move_path(path, &payload, None, None)
+            .context(format!("Could not move {:?} to the trash.", path))?;
+        fs::write(
+            self.entry_path(&entry.id),
+            serde_yaml::to_string(&entry).unwrap(),
+        )
+        .context(format!("Could not write trash entry {}.", &entry.id))?;
+        self.purge()?;
+        Ok(entry)
+    }
+
+    /// Checks that the file or directory fits into the trash and returns its size. Nothing is
+    /// ever deleted permanently because it is too large.
+    pub fn fits(&self, path: &Path) -> Result<u64> {
+        let size = transfer::size(path).context(format!("Could not read size of {:?}.", path))?;
+        if size > self.settings.max_size {
+            return Err(anyhow!(
+                "{:?} is larger than the trash ({} bytes) and was left in place. Raise \
+                 trash.max_size to delete it.",
+                path,
+                self.settings.max_size
+            ));
+        }
+        Ok(size)
+    }
+
+    /// All staged entries, oldest first
+    pub fn entries(&self) -> Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        for file in fs::read_dir(&self.dir).context(format!("Could not read {:?}.", &self.dir))? {
+            let path = file?.path();
+            if path.extension() != Some(ENTRY_EXTENSION.as_ref()) {
+                continue;
+            }
+            match fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|yaml| serde_yaml::from_str::<Entry>(&yaml).map_err(Into::into))
+            {
+                Ok(mut entry) => {
+                    entry.id = path.file_stem().unwrap().to_string_lossy().into_owned();
+                    entries.push(entry);
+                }
+                Err(e) => warn!("Ignoring broken trash entry {:?}: {}", &path, e),
+            }
+        }
+        entries.sort_by_key(|e| e.deleted_at);
+        Ok(entries)
+    }
+
+    /// Moves an entry back to its original location or into `dir`
+    pub fn restore(&self, id: &str, dir: Option<&Path>) -> Result<PathBuf> {
+        let entry = self
+            .entries()?
+            .into_iter()
+            .find(|e| e.id == id)
+            .context(format!("There is no trash entry {}.", id))?;
+        let destination = match dir {
+            Some(dir) => dir.join(entry.original_path.file_name().unwrap()),
+            None => entry.original_path.clone(),
+        };
+        if destination.exists() {
+            return Err(anyhow!("{:?} already exists.", &destination));
+        }
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        transfer::move_path(&self.payload_path(&entry), &destination, None, None)
+            .context(format!("Could not restore {:?}.", &destination))?;
+        self.remove(&entry)?;
+        Ok(destination)
+    }
+
+    /// Permanently deletes all entries whose grace period is over
+    pub fn purge(&self) -> Result<()> {
+        let now = Local::now();
+        for entry in self.entries()? {
+            let age = now
+                .signed_duration_since(entry.deleted_at)
+                .to_std()
+                .unwrap_or_default();
+            if age > self.settings.grace_period {
+                info!(
+                    "Grace period of {:?} is over. Deleting it permanently.",
+                    &entry.original_path
+                );
+                self.remove(&entry)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Permanently deletes all entries
+    pub fn empty(&self) -> Result<usize> {
+        let entries = self.entries()?;
+        for entry in entries.iter() {
+            self.remove(entry)?;
+        }
+        Ok(entries.len())
+    }
+
+    fn make_room(&self, size: u64) -> Result<()> {
+        let entries = self.entries()?;
+        let mut total: u64 = entries.iter().map(|e| e.size).sum();
+        for entry in entries.iter() {
+            if total + size <= self.settings.max_size {
+                break;
+            }
+            info!(
+                "Trash is full. Deleting {:?} permanently.",
+                &entry.original_path
+            );
+            self.remove(entry)?;
+            total -= entry.size;
+        }
+        Ok(())
+    }
+
+    fn remove(&self, entry: &Entry) -> Result<()> {
+        let dir = self.dir.join(&entry.id);
+        if dir.exists() {
+            fs::remove_dir_all(&dir).context(format!("Could not delete {:?}.", &dir))?;
+        }
+        fs::remove_file(self.entry_path(&entry.id))
+            .context(format!("Could not delete trash entry {}.", &entry.id))?;
+        Ok(())
+    }
+
+    fn payload_path(&self, entry: &Entry) -> PathBuf {
+        self.dir
+            .join(&entry.id)
+            .join(entry.original_path.file_name().unwrap_or_default())
+    }
+
+    fn entry_path(&self, id: &str) -> PathBuf {
+        self.dir.join(id).with_extension(ENTRY_EXTENSION)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trash(dir: &Path, grace_period: Duration, max_size: u64) -> Trash {
+        let settings = TrashSettings {
+            grace_period,
+            max_size,
+        };
+        Trash::open(dir.join(TRASH_DIR_NAME), settings).unwrap()
+    }
+
+    fn stage(trash: &Trash, dir: &Path, name: &str, size: usize) -> Entry {
+        let path = dir.join(name);
+        fs::write(&path, vec![0; size]).unwrap();
+        trash.stage(&path).unwrap()
+    }
+
+    fn names(trash: &Trash) -> Vec<String> {
+        trash
+            .entries()
+            .unwrap()
+            .iter()
+            .map(|e| {
+                e.original_path
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn oldest_entries_make_room() {
+        let dir = tempfile::tempdir().unwrap();
+        let trash = trash(dir.path(), Duration::from_secs(3600), 10);
+        stage(&trash, dir.path(), "a", 4);
+        stage(&trash, dir.path(), "b", 4);
+        stage(&trash, dir.path(), "c", 4);
+        assert_eq!(names(&trash), ["b", "c"]);
+
+        let path = dir.path().join("large");
+        fs::write(&path, vec![0; 11]).unwrap();
+        assert!(trash.stage(&path).is_err());
+        assert!(path.exists());
+        assert_eq!(names(&trash), ["b", "c"]);
+    }
+
+    #[test]
+    fn expired_entries_are_purged() {
+        let dir = tempfile::tempdir().unwrap();
+        let trash = trash(dir.path(), Duration::from_secs(3600), 100);
+        let old = stage(&trash, dir.path(), "old", 1);
+        stage(&trash, dir.path(), "new", 1);
+        let expired = Entry {
+            deleted_at: Local::now() - chrono::Duration::hours(2),
+            ..old
+        };
+        fs::write(
+            trash.entry_path(&expired.id),
+            serde_yaml::to_string(&expired).unwrap(),
+        )
+        .unwrap();
+
+        trash.purge().unwrap();
+        assert_eq!(names(&trash), ["new"]);
+        assert!(!dir.path().join(TRASH_DIR_NAME).join(&expired.id).exists());
+    }
+
+    #[test]
+    fn entries_are_restored() {
+        let dir = tempfile::tempdir().unwrap();
+        let trash = trash(dir.path(), Duration::from_secs(3600), 100);
+        let entry = stage(&trash, dir.path(), "report.pdf", 3);
+        assert!(!dir.path().join("report.pdf").exists());
+
+        let restored = trash.restore(&entry.id, None).unwrap();
+        assert_eq!(restored, dir.path().join("report.pdf"));
+        assert!(restored.exists());
+        assert!(trash.entries().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,282 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use ksni::blocking::TrayMethods;
+use ksni::menu::{StandardItem, SubMenu};
+use ksni::{Category, MenuItem, Status, ToolTip, Tray};
+
+use crate::control::{self, Snapshot};
+
+/// Entries in the submenus of recent moves and errors
+const MENU_ENTRIES: usize = 10;
+
+/// Tray icon that shows whether the running instance is sorting and lets it be paused. Talks to
+/// it over the control port like `wurmloch status`.
+pub fn run(data_dir: &Path, instance: Option<&Path>) -> Result<()> {
+    // Pinned to the first instance found, so that the icon does not jump between instances
+    let mut instance = control::instance(data_dir, instance)
+        .ok()
+        .or_else(|| instance.map(Path::to_path_buf));
+    let tray = WormholeTray {
+        data_dir: data_dir.to_path_buf(),
+        instance: instance.clone(),
+        snapshot: fetch(data_dir, instance.as_deref()),
+        seen_errors: 0,
+        quit: false,
+    };
+    let handle = tray
+        .spawn()
+        .context("Could not show the tray icon. Does the desktop support StatusNotifierItem?")?;
+    while !handle.is_closed() {
+        thread::sleep(Duration::from_secs(2));
+        if instance.is_none() {
+            instance = control::instance(data_dir, None).ok();
+        }
+        let snapshot = fetch(data_dir, instance.as_deref());
+        let quit = handle.update(|tray| {
+            tray.instance = instance.clone();
+            tray.snapshot = snapshot;
+            tray.quit
+        });
+        if quit.unwrap_or(true) {
+            break;
+        }
+    }
+    handle.shutdown().wait();
+    Ok(())
+}
+
+struct WormholeTray {
+    data_dir: PathBuf,
+    /// Watch directory of the instance the icon shows
+    instance: Option<PathBuf>,
+    /// `None` if wurmloch is not running
+    snapshot: Option<Snapshot>,
+    /// Errors that are not shown as new anymore
+    seen_errors: usize,
+    quit: bool,
+}
+
+fn fetch(data_dir: &Path, instance: Option<&Path>) -> Option<Snapshot> {
+    let reply = control::send(data_dir, instance, "snapshot").ok()?;
+    serde_json::from_str(&reply).ok()
+}
+
+impl WormholeTray {
+    fn new_errors(&self) -> usize {
+        self.snapshot.as_ref().map_or(0, |snapshot| {
+            snapshot.errors.saturating_sub(self.seen_errors)
+        })
+    }
+
+    fn send(&mut self, command: &str) {
+        if let Err(e) = control::send(&self.data_dir, self.instance.as_deref(), command) {
+            warn!("Could not {} wurmloch: {:#}", command, e);
+        }
+        self.snapshot = fetch(&self.data_dir, self.instance.as_deref());
+    }
+}
+
+impl Tray for WormholeTray {
+    fn id(&self) -> String {
+        String::from("wurmloch")
+    }
+
+    fn title(&self) -> String {
+        String::from("wurmloch")
+    }
+
+    fn category(&self) -> Category {
+        Category::SystemServices
+    }
+
+    fn status(&self) -> Status {
+        if self.new_errors() > 0 {
+            Status::NeedsAttention
+        } else {
+            Status::Active
+        }
+    }
+
+    fn icon_name(&self) -> String {
+        String::from(match &self.snapshot {
+            None => "action-unavailable",
+            Some(snapshot) if snapshot.paused => "media-playback-pause",
+            Some(_) => "folder-download",
+        })
+    }
+
+    fn attention_icon_name(&self) -> String {
+        String::from("dialog-warning")
+    }
+
+    fn tool_tip(&self) -> ToolTip {
+        let description = match &self.snapshot {
+            None => String::from("Not running"),
+            Some(snapshot) => {
+                let mut description = format!(
+                    "{}, {} files moved",
+                    if snapshot.paused { "Paused" } else { "Sorting" },
+                    snapshot.moved
+                );
+                match self.new_errors() {
+                    0 => {}
+                    1 => description.push_str(", 1 new error"),
+                    errors => description.push_str(&format!(", {} new errors", errors)),
+                }
+                description
+            }
+        };
+        ToolTip {
+            title: String::from("wurmloch"),
+            description,
+            ..ToolTip::default()
+        }
+    }
+
+    fn menu(&self) -> Vec<MenuItem<Self>> {
+        let snapshot = match &self.snapshot {
+            Some(snapshot) => snapshot,
+            None => {
+                return vec![
+                    StandardItem {
+                        label: String::from("wurmloch is not running"),
+                        enabled: false,
+                        ..StandardItem::default()
+                    }
+                    .into(),
+                    MenuItem::Separator,
+                    quit(),
+                ]
+            }
+        };
+
+        let recent: Vec<MenuItem<Self>> = snapshot
+            .recent
+            .iter()
+            .rev()
+            .take(MENU_ENTRIES)
+            .map(|action| {
+                StandardItem {
+                    label: escape(&format!(
+                        "{}  {} → {}",
+                        action.time.format("%H:%M"),
+                        action
+                            .file
+                            .file_name()
+                            .unwrap_or_default()
+                            .to_string_lossy(),
+                        &action.outcome
+                    )),
+                    enabled: false,
+                    ..StandardItem::default()
+                }
+                .into()
+            })
+            .collect();
+        let mut errors: Vec<MenuItem<Self>> = snapshot
+            .failures
+            .iter()
+            .rev()
+            .take(MENU_ENTRIES)
+            .map(|failure| {
+                StandardItem {
+                    label: escape(&format!(
+                        "{}  {}: {}",
+                        failure.time.format("%H:%M"),
+                        failure
+                            .file
+                            .file_name()
+                            .unwrap_or_default()
+                            .to_string_lossy(),
+                        &failure.reason
+                    )),
+                    enabled: false,
+                    ..StandardItem::default()
+                }
+                .into()
+            })
+            .collect();
+        if !errors.is_empty() {
+            errors.push(MenuItem::Separator);
+            errors.push(
+                StandardItem {
+                    label: String::from("Mark as read"),
+                    activate: Box::new(|tray: &mut Self| {
+                        tray.seen_errors =
+                            tray.snapshot.as_ref().map_or(0, |snapshot| snapshot.errors);
+                    }),
+                    ..StandardItem::default()
+                }
+                .into(),
+            );
+        }
+
+        vec![
+            StandardItem {
+                label: String::from(if snapshot.paused { "Resume" } else { "Pause" }),
+                activate: Box::new(|tray: &mut Self| {
+                    let paused = tray.snapshot.as_ref().is_some_and(|s| s.paused);
+                    tray.send(if paused { "resume" } else { "pause" });
+                }),
+                ..StandardItem::default()
+            }
+            .into(),
+            StandardItem {
+                label: String::from("Open watch folder"),
+                enabled: !snapshot.watched.is_empty(),
+                activate: Box::new(|tray: &mut Self| {
+                    let dir = tray
+                        .snapshot
+                        .as_ref()
+                        .and_then(|snapshot| snapshot.watched.first());
+                    if let Some(dir) = dir {
+                        if let Err(e) = Command::new("xdg-open").arg(dir).spawn() {
+                            warn!("Could not open {:?}: {}", dir, e);
+                        }
+                    }
+                }),
+                ..StandardItem::default()
+            }
+            .into(),
+            MenuItem::Separator,
+            SubMenu {
+                label: String::from("Recent moves"),
+                enabled: !recent.is_empty(),
+                submenu: recent,
+                ..SubMenu::default()
+            }
+            .into(),
+            SubMenu {
+                label: match self.new_errors() {
+                    0 => String::from("Errors"),
+                    errors => format!("Errors ({} new)", errors),
+                },
+                enabled: !errors.is_empty(),
+                submenu: errors,
+                ..SubMenu::default()
+            }
+            .into(),
+            MenuItem::Separator,
+            quit(),
+        ]
+    }
+}
+
+/// Closes the tray icon, wurmloch keeps running
+fn quit() -> MenuItem<WormholeTray> {
+    StandardItem {
+        label: String::from("Close tray icon"),
+        activate: Box::new(|tray: &mut WormholeTray| tray.quit = true),
+        ..StandardItem::default()
+    }
+    .into()
+}
+
+/// Underscores in labels mark access keys unless they are doubled
+fn escape(label: &str) -> String {
+    label.replace('_', "__")
+}
@@ -0,0 +1,248 @@
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, Paragraph, Row, Table};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::control::{self, Snapshot};
+
+/// How often the running instance is asked for its state
+const REFRESH: Duration = Duration::from_secs(1);
+
+/// Live view of the running instance. Talks to it over the control port like `wurmloch status`.
+pub fn run(data_dir: &Path, instance: Option<&Path>, config_path: &Path) -> Result<()> {
+    let instance = control::instance(data_dir, instance)?;
+    let control = Control {
+        data_dir,
+        instance: &instance,
+    };
+    let mut view = View {
+        snapshot: control.fetch()?,
+        connected: true,
+        message: String::new(),
+    };
+    let mut terminal = ratatui::init();
+    let result = watch(&mut terminal, &mut view, &control, config_path);
+    ratatui::restore();
+    result
+}
+
+/// Talks to the instance that was chosen when the view opened
+struct Control<'a> {
+    data_dir: &'a Path,
+    instance: &'a Path,
+}
+
+impl Control<'_> {
+    fn send(&self, command: &str) -> Result<String> {
+        control::send(self.data_dir, Some(self.instance), command)
+    }
+
+    fn fetch(&self) -> Result<Snapshot> {
+        let reply = self.send("snapshot")?;
+        serde_json::from_str(&reply).context("Unexpected reply of the running wurmloch.")
+    }
+}
+
+struct View {
+    snapshot: Snapshot,
+    /// Whether the last refresh reached the running instance
+    connected: bool,
+    /// Reply to the last key that was pressed
+    message: String,
+}
+
+fn watch(
+    terminal: &mut DefaultTerminal,
+    view: &mut View,
+    control: &Control,
+    config_path: &Path,
+) -> Result<()> {
+    let mut refreshed = Instant::now();
+    loop {
+        terminal.draw(|frame| draw(frame, view))?;
+        if event::poll(REFRESH.saturating_sub(refreshed.elapsed()))? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                let command = match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                    KeyCode::Char('p') if view.snapshot.paused => Some("resume"),
+                    KeyCode::Char('p') => Some("pause"),
+                    KeyCode::Char('r') => Some("rescan"),
+                    KeyCode::Char('c') => {
+                        ratatui::restore();
+                        view.message = edit(config_path);
+                        *terminal = ratatui::init();
+                        None
+                    }
+                    _ => None,
+                };
+                if let Some(command) = command {
+                    view.message = match control.send(command) {
+                        Ok(reply) => reply.trim().to_string(),
+                        Err(e) => format!("{:#}", e),
+                    };
+                }
+            }
+        }
+        if refreshed.elapsed() >= REFRESH {
+            match control.fetch() {
+                Ok(snapshot) => {
+                    view.snapshot = snapshot;
+                    view.connected = true;
+                }
+                Err(_) => view.connected = false,
+            }
+            refreshed = Instant::now();
+        }
+    }
+}
+
+/// Opens the rule file in `$VISUAL` or `$EDITOR`. The running instance reloads it when it is
+/// saved.
+fn edit(config_path: &Path) -> String {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| String::from(if cfg!(windows) { "notepad" } else { "vi" }));
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or_default();
+    match Command::new(program).args(words).arg(config_path).status() {
+        Ok(status) if status.success() => String::from("Saved changes are reloaded."),
+        Ok(status) => format!("{} exited with {}.", program, status),
+        Err(e) => format!("Could not start {}: {}", program, e),
+    }
+}
+
+fn draw(frame: &mut Frame, view: &View) {
+    let snapshot = &view.snapshot;
+    let [header, queue, middle, failures, footer] = Layout::vertical([
+        Constraint::Length(4),
+        Constraint::Length(3),
+        Constraint::Min(6),
+        Constraint::Length(8),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let uptime = (Local::now() - snapshot.started)
+        .to_std()
+        .unwrap_or_default();
+    let state = match (view.connected, snapshot.paused) {
+        (false, _) => Span::styled("not running", Style::default().fg(Color::Red)),
+        (true, true) => Span::styled("paused", Style::default().fg(Color::Yellow)),
+        (true, false) => Span::styled("sorting", Style::default().fg(Color::Green)),
+    };
+    let watched: Vec<String> = snapshot
+        .watched
+        .iter()
+        .map(|dir| dir.display().to_string())
+        .collect();
+    frame.render_widget(
+        Paragraph::new(vec![
+            Line::from(vec![
+                state,
+                Span::raw(format!(
+                    "  ·  {}  ·  up {}",
+                    watched.join(", "),
+                    humantime::format_duration(Duration::from_secs(uptime.as_secs()))
+                )),
+            ]),
+            Line::from(format!(
+                "{} moved  ·  {} deleted  ·  {} errors  ·  gave up on {}  ·  {} quarantined",
+                snapshot.moved,
+                snapshot.deleted,
+                snapshot.errors,
+                snapshot.given_up,
+                snapshot.quarantined
+            )),
+        ])
+        .block(Block::bordered().title(" wurmloch ")),
+        header,
+    );
+
+    frame.render_widget(
+        Paragraph::new(format!(
+            "{} waiting  ·  {} in the work queue  ·  {} being sorted",
+            snapshot.queued, snapshot.work_queue, snapshot.in_progress
+        ))
+        .block(Block::bordered().title(" Queue ")),
+        queue,
+    );
+
+    let [rules, recent] =
+        Layout::horizontal([Constraint::Percentage(30), Constraint::Percentage(70)]).areas(middle);
+    let rows = snapshot
+        .per_rule
+        .iter()
+        .map(|(rule, count)| Row::new(vec![count.to_string(), rule.clone()]));
+    frame.render_widget(
+        Table::new(rows, [Constraint::Length(8), Constraint::Fill(1)])
+            .block(Block::bordered().title(" Files per rule ")),
+        rules,
+    );
+    let items: Vec<ListItem> = snapshot
+        .recent
+        .iter()
+        .rev()
+        .map(|action| {
+            ListItem::new(Line::from(vec![
+                Span::styled(
+                    action.time.format("%H:%M:%S  ").to_string(),
+                    Style::default().add_modifier(Modifier::DIM),
+                ),
+                Span::styled(
+                    format!("{}  ", &action.rule),
+                    Style::default().fg(Color::Cyan),
+                ),
+                Span::raw(format!("{} → {}", action.file.display(), &action.outcome)),
+            ]))
+        })
+        .collect();
+    frame.render_widget(
+        List::new(items).block(Block::bordered().title(" Recent moves ")),
+        recent,
+    );
+
+    let items: Vec<ListItem> = snapshot
+        .failures
+        .iter()
+        .rev()
+        .map(|failure| {
+            ListItem::new(Line::from(vec![
+                Span::styled(
+                    failure.time.format("%H:%M:%S  ").to_string(),
+                    Style::default().add_modifier(Modifier::DIM),
+                ),
+                Span::styled(
+                    format!("{}: ", failure.file.display()),
+                    Style::default().fg(Color::Red),
+                ),
+                Span::raw(failure.reason.as_str()),
+            ]))
+        })
+        .collect();
+    frame.render_widget(
+        List::new(items).block(Block::bordered().title(" Errors ")),
+        failures,
+    );
+
+    frame.render_widget(
+        Paragraph::new(Line::from(vec![
+            Span::styled(
+                " p pause/resume  r rescan  c edit rules  q quit  ",
+                Style::default().add_modifier(Modifier::REVERSED),
+            ),
+            Span::raw(format!("  {}", &view.message)),
+        ])),
+        footer,
+    );
+}
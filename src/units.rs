@@ -0,0 +1,94 @@
+use anyhow::{Context, Result};
+
+const SIZE_UNITS: [(&str, u64); 9] = [
+    ("B", 1),
+    ("KB", 1_000),
+    ("MB", 1_000_000),
+    ("GB", 1_000_000_000),
+    ("TB", 1_000_000_000_000),
+    ("KIB", 1 << 10),
+    ("MIB", 1 << 20),
+    ("GIB", 1 << 30),
+    ("TIB", 1 << 40),
+];
+
+/// Parses a byte size like `4096`, `500MB` or `2 GiB`
+pub fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number: f64 = number
+        .parse()
+        .context(format!("Invalid size {:?}.", size))?;
+    let unit = unit.trim().to_uppercase();
+    let (_, factor) = SIZE_UNITS
+        .iter()
+        .find(|(u, _)| unit.is_empty() || *u == unit)
+        .context(format!("Unknown size unit {:?}.", unit))?;
+    Ok((number * *factor as f64) as u64)
+}
+
+/// A byte size for humans, e.g. `1.5 MiB`
+pub fn format_size(bytes: u64) -> String {
+    let units = ["KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = "B";
+    for next in units.iter() {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    if unit == "B" {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, unit)
+    }
+}
+
+/// Serde support for sizes that may be written as plain numbers or with a unit
+pub mod size {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        match Size::deserialize(deserializer)? {
+            Size::Bytes(bytes) => Ok(bytes),
+            Size::Text(text) => super::parse_size(&text).map_err(de::Error::custom),
+        }
+    }
+
+    pub fn serialize<S: Serializer>(size: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(*size)
+    }
+}
+
+/// Like [`size`], for settings that may be left out
+pub mod optional_size {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<u64>, D::Error> {
+        #[derive(Deserialize)]
+        struct Size(#[serde(with = "super::size")] u64);
+
+        Ok(Option::<Size>::deserialize(deserializer)?.map(|Size(bytes)| bytes))
+    }
+
+    pub fn serialize<S: Serializer>(size: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+        match size {
+            Some(size) => serializer.serialize_some(size),
+            None => serializer.serialize_none(),
+        }
+    }
+}
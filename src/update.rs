@@ -0,0 +1,154 @@
+use std::env;
+use std::env::consts::{ARCH, EXE_SUFFIX, OS};
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::process;
+
+use anyhow::{Context, Result};
+use minisign_verify::{PublicKey, Signature};
+use semver::Version;
+use serde::Deserialize;
+
+const RELEASES_URL: &str = "https://api.github.com/repos/gonsor/wurmloch/releases";
+
+/// minisign public key that release binaries are signed with. The maintainer sets it when
+/// building releases, builds without it cannot update themselves.
+const RELEASE_PUBLIC_KEY: Option<&str> = option_env!("WURMLOCH_RELEASE_KEY");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum Channel {
+    /// Only regular releases
+    Stable,
+    /// Regular releases and pre-releases
+    Prerelease,
+}
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    prerelease: bool,
+    draft: bool,
+    assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl Release {
+    fn version(&self) -> Option<Version> {
+        Version::parse(self.tag_name.trim_start_matches('v')).ok()
+    }
+
+    fn asset(&self, name: &str) -> Result<&Asset> {
+        self.assets.iter().find(|a| a.name == name).context(format!(
+            "Release {} provides no asset {:?} for this platform.",
+            &self.tag_name, name
+        ))
+    }
+}
+
+/// Checks the release channel and replaces the running binary with the newest release.
+/// With `check_only`, only reports whether an update is available.
+pub fn self_update(channel: Channel, check_only: bool) -> Result<()> {
+    let current = Version::parse(env!("CARGO_PKG_VERSION")).unwrap();
+    let release = match latest_release(channel)? {
+        Some(release) => release,
+        None => {
+            println!("No releases found.");
+            return Ok(());
+        }
+    };
+    let latest = release.version().unwrap();
+
+    if latest <= current {
+        println!("wurmloch {} is up to date.", current);
+        return Ok(());
+    }
+    println!("Update available: {} -> {}.", current, latest);
+    if check_only {
+        return Ok(());
+    }
+
+    let public_key = RELEASE_PUBLIC_KEY.context(
+        "This build of wurmloch has no release key to verify updates with. Update it the way \
+         it was installed.",
+    )?;
+    let asset_name = format!("wurmloch-{}-{}{}", OS, ARCH, EXE_SUFFIX);
+    let binary = download(&release.asset(&asset_name)?.browser_download_url)?;
+    let signature = download(
+        &release
+            .asset(&format!("{}.minisig", asset_name))?
+            .browser_download_url,
+    )?;
+    verify(public_key, &binary, &signature)?;
+    info!("Signature of {} verified.", &asset_name);
+
+    // Next to the binary instead of in a shared temporary directory, where others could place
+    // a link under the same name
+    let exe = env::current_exe().context("Could not determine the path of wurmloch.")?;
+    let tmp_path = exe.with_file_name(format!(".{}.{}.update", asset_name, process::id()));
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o755);
+    }
+    let written = options
+        .open(&tmp_path)
+        .and_then(|mut file| file.write_all(&binary));
+    let replaced = written
+        .context(format!("Could not write update to {:?}.", &tmp_path))
+        .and_then(|_| {
+            self_replace::self_replace(&tmp_path).context("Could not replace the running binary.")
+        });
+    fs::remove_file(&tmp_path).ok();
+    replaced?;
+
+    println!("Updated wurmloch to {}.", latest);
+    Ok(())
+}
+
+fn latest_release(channel: Channel) -> Result<Option<Release>> {
+    debug!("Querying releases from {} ...", RELEASES_URL);
+    let releases: Vec<Release> = ureq::get(RELEASES_URL)
+        .set("Accept", "application/vnd.github+json")
+        .call()
+        .context("Could not query the release channel.")?
+        .into_json()
+        .context("Could not read the release list.")?;
+
+    Ok(releases
+        .into_iter()
+        .filter(|r| !r.draft && (channel == Channel::Prerelease || !r.prerelease))
+        .filter(|r| r.version().is_some())
+        .max_by_key(|r| r.version()))
+}
+
+fn download(url: &str) -> Result<Vec<u8>> {
+    debug!("Downloading {} ...", url);
+    let mut bytes = Vec::new();
+    ureq::get(url)
+        .call()
+        .context(format!("Could not download {}.", url))?
+        .into_reader()
+        .read_to_end(&mut bytes)
+        .context(format!("Could not download {}.", url))?;
+    Ok(bytes)
+}
+
+fn verify(public_key: &str, binary: &[u8], signature: &[u8]) -> Result<()> {
+    let public_key =
+        PublicKey::decode(public_key.trim()).context("Embedded release public key is invalid.")?;
+    let signature = Signature::decode(
+        std::str::from_utf8(signature).context("Release signature is not valid UTF-8.")?,
+    )
+    .context("Release signature is malformed.")?;
+    public_key
+        .verify(binary, &signature, false)
+        .context("Release signature does not match. Update aborted.")?;
+    Ok(())
+}
@@ -0,0 +1,425 @@
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::plugin::{LastRead, Plugin, Registry};
+
+/// Upper limit for metadata that is read into memory
+const MAX_HEADER_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Properties of the first video track
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VideoInfo {
+    pub width: u32,
+    pub height: u32,
+    pub duration: Duration,
+    /// e.g. `h264`, `hevc` or `av1`
+    pub codec: String,
+}
+
+impl VideoInfo {
+    /// The shorter side, i.e. 1080 for 1080p videos in landscape and portrait
+    pub fn resolution(&self) -> u32 {
+        self.width.min(self.height)
+    }
+}
+
+/// Placeholders from the video track of MP4, MOV, MKV and WebM files
+pub struct VideoPlugin;
+
+impl Plugin for VideoPlugin {
+    fn name(&self) -> &str {
+        "video"
+    }
+
+    fn register(&self, registry: &mut Registry) {
+        registry.variable("video.width", |path: &Path| {
+            Ok(probe(path)?.width.to_string())
+        });
+        registry.variable("video.height", |path: &Path| {
+            Ok(probe(path)?.height.to_string())
+        });
+        registry.variable("video.duration", |path: &Path| {
+            Ok(probe(path)?.duration.as_secs().to_string())
+        });
+        registry.variable("video.codec", |path: &Path| Ok(probe(path)?.codec));
+    }
+}
+
+/// Conditions of a rule on video files. Files that are no videos never meet them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct VideoConditions {
+    /// Minimum number of lines, e.g. 1080
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_resolution: Option<u32>,
+
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub min_duration: Option<Duration>,
+
+    /// e.g. `hevc`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub video_codec: Option<String>,
+}
+
+impl VideoConditions {
+    pub fn is_empty(&self) -> bool {
+        self == &VideoConditions::default()
+    }
+
+    pub fn are_met(&self, path: &Path) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        let info = match probe(path) {
+            Ok(info) => info,
+            Err(e) => {
+                debug!("{:#}", e);
+                return false;
+            }
+        };
+        self.min_resolution
+            .is_none_or(|min| info.resolution() >= min)
+            && self.min_duration.is_none_or(|min| info.duration >= min)
+            && self
+                .video_codec
+                .as_ref()
+                .is_none_or(|codec| codec.eq_ignore_ascii_case(&info.codec))
+    }
+}
+
+static LAST_PROBED: LastRead<VideoInfo> = LastRead::new();
+
+/// Reads the properties of a video from the headers of the file
+pub fn probe(path: &Path) -> Result<VideoInfo> {
+    LAST_PROBED.get(path, read)
+}
+
+fn read(path: &Path) -> Result<VideoInfo> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut magic = [0; 8];
+    file.read_exact(&mut magic)
+        .context(format!("{:?} is not a video.", path))?;
+    file.seek(SeekFrom::Start(0))?;
+    if magic[..4] == [0x1a, 0x45, 0xdf, 0xa3] {
+        matroska::probe(&mut file)
+    } else if matches!(&magic[4..], b"ftyp" | b"moov" | b"mdat" | b"free" | b"wide") {
+        mp4::probe(&mut file)
+    } else {
+        return Err(anyhow!("{:?} is not a video.", path));
+    }
+    .context(format!(
+        "Could not read the video properties of {:?}.",
+        path
+    ))
+}
+
+fn read_body(file: &mut impl Read, size: u64) -> Result<Vec<u8>> {
+    if size > MAX_HEADER_SIZE {
+        return Err(anyhow!("Header of {} bytes is too large.", size));
+    }
+    let mut body = vec![0; size as usize];
+    file.read_exact(&mut body)?;
+    Ok(body)
+}
+
+/// MP4 and QuickTime (MOV) files, made of nested boxes
+mod mp4 {
+    use super::*;
+
+    pub fn probe(file: &mut (impl Read + Seek)) -> Result<VideoInfo> {
+        loop {
+            let (kind, size) = match header(file) {
+                Ok(header) => header,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Err(anyhow!("There is no movie header."))
+                }
+                Err(e) => return Err(e.into()),
+            };
+            match (&kind, size) {
+                (b"moov", Some(size)) => return parse_moov(&read_body(file, size)?),
+                (b"moov", None) => {
+                    let mut moov = Vec::new();
+                    file.take(MAX_HEADER_SIZE).read_to_end(&mut moov)?;
+                    return parse_moov(&moov);
+                }
+                (_, Some(size)) => {
+                    file.seek(SeekFrom::Current(size as i64))?;
+                }
+                (_, None) => return Err(anyhow!("There is no movie header.")),
+            }
+        }
+    }
+
+    /// Type and size of the body of the next box. Boxes without a size extend to the end of
+    /// the file.
+    fn header(file: &mut impl Read) -> io::Result<([u8; 4], Option<u64>)> {
+        let mut header = [0; 8];
+        file.read_exact(&mut header)?;
+        let kind = header[4..8].try_into().unwrap();
+        let size = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            0 => None,
+            1 => {
+                let mut size = [0; 8];
+                file.read_exact(&mut size)?;
+                Some(u64::from_be_bytes(size).saturating_sub(16))
+            }
+            size => Some(u64::from(size).saturating_sub(8)),
+        };
+        Ok((kind, size))
+    }
+
+    /// Boxes inside of a box that has been read into memory
+    fn children(mut data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        let mut children = Vec::new();
+        while data.len() >= 8 {
+            let size = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+            let kind = data[4..8].try_into().unwrap();
+            let (start, size) = match size {
+                0 => (8, data.len()),
+                1 if data.len() >= 16 => {
+                    let size = u64::from_be_bytes(data[8..16].try_into().unwrap());
+                    (16, size.min(data.len() as u64) as usize)
+                }
+                size => (8, size),
+            };
+            if size < start || size > data.len() {
+                break;
+            }
+            children.push((kind, &data[start..size]));
+            data = &data[size..];
+        }
+        children
+    }
+
+    fn child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+        children(data)
+            .into_iter()
+            .find(|(k, _)| k == kind)
+            .map(|(_, body)| body)
+    }
+
+    fn parse_moov(moov: &[u8]) -> Result<VideoInfo> {
+        let mut info = VideoInfo::default();
+        if let Some(mvhd) = child(moov, b"mvhd") {
+            info.duration = movie_duration(mvhd).unwrap_or_default();
+        }
+        for (kind, trak) in children(moov) {
+            if &kind != b"trak" {
+                continue;
+            }
+            let mdia = match child(trak, b"mdia") {
+                Some(mdia) => mdia,
+                None => continue,
+            };
+            if child(mdia, b"hdlr").and_then(|hdlr| hdlr.get(8..12)) != Some(b"vide") {
+                continue;
+            }
+            let entry = child(mdia, b"minf")
+                .and_then(|minf| child(minf, b"stbl"))
+                .and_then(|stbl| child(stbl, b"stsd"))
+                .and_then(|stsd| stsd.get(8..))
+                .context("The video track has no sample description.")?;
+            // Size and format of the first entry, followed by the visual sample entry
+            let format = entry.get(4..8).context("Invalid sample description.")?;
+            let dimensions = entry.get(32..36).context("Invalid sample description.")?;
+            info.width = u32::from(u16::from_be_bytes([dimensions[0], dimensions[1]]));
+            info.height = u32::from(u16::from_be_bytes([dimensions[2], dimensions[3]]));
+            info.codec = codec(format);
+            return Ok(info);
+        }
+        Err(anyhow!("There is no video track."))
+    }
+
+    fn movie_duration(mvhd: &[u8]) -> Option<Duration> {
+        let (timescale, duration) = match mvhd.first()? {
+            1 => (
+                u32::from_be_bytes(mvhd.get(20..24)?.try_into().ok()?),
+                u64::from_be_bytes(mvhd.get(24..32)?.try_into().ok()?),
+            ),
+            _ => (
+                u32::from_be_bytes(mvhd.get(12..16)?.try_into().ok()?),
+                u64::from(u32::from_be_bytes(mvhd.get(16..20)?.try_into().ok()?)),
+            ),
+        };
+        if timescale == 0 {
+            return None;
+        }
+        Some(Duration::from_secs_f64(
+            duration as f64 / f64::from(timescale),
+        ))
+    }
+
+    fn codec(format: &[u8]) -> String {
+        String::from(match format {
+            b"avc1" | b"avc3" => "h264",
+            b"hvc1" | b"hev1" => "hevc",
+            b"av01" => "av1",
+            b"vp09" => "vp9",
+            b"vp08" => "vp8",
+            b"mp4v" => "mpeg4",
+            b"apch" | b"apcn" | b"apcs" | b"apco" | b"ap4h" | b"ap4x" => "prores",
+            other => return String::from_utf8_lossy(other).trim().to_lowercase(),
+        })
+    }
+}
+
+/// Matroska (MKV) and WebM files, made of EBML elements
+mod matroska {
+    use super::*;
+
+    const SEGMENT: u32 = 0x1853_8067;
+    const INFO: u32 = 0x1549_a966;
+    const TIMESTAMP_SCALE: u32 = 0x2a_d7b1;
+    const DURATION: u32 = 0x4489;
+    const TRACKS: u32 = 0x1654_ae6b;
+    const TRACK_ENTRY: u32 = 0xae;
+    const TRACK_TYPE: u32 = 0x83;
+    const CODEC_ID: u32 = 0x86;
+    const VIDEO: u32 = 0xe0;
+    const PIXEL_WIDTH: u32 = 0xb0;
+    const PIXEL_HEIGHT: u32 = 0xba;
+    const CLUSTER: u32 = 0x1f43_b675;
+
+    pub fn probe(file: &mut (impl Read + Seek)) -> Result<VideoInfo> {
+        // EBML header
+        let (_, size) = header(file)?;
+        file.seek(SeekFrom::Current(size as i64))?;
+        let (id, _) = header(file)?;
+        if id != SEGMENT {
+            return Err(anyhow!("There is no segment."));
+        }
+
+        let mut info = None;
+        let mut tracks = None;
+        while info.is_none() || tracks.is_none() {
+            let (id, size) = match header(file) {
+                Ok(header) => header,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            };
+            match id {
+                INFO => info = Some(read_body(file, size)?),
+                TRACKS => tracks = Some(read_body(file, size)?),
+                // The headers come before the first cluster
+                CLUSTER => break,
+                _ => {
+                    file.seek(SeekFrom::Current(size as i64))?;
+                }
+            }
+        }
+
+        let mut video = parse_tracks(&tracks.context("There are no tracks.")?)?;
+        if let Some(info) = info {
+            video.duration = parse_duration(&info).unwrap_or_default();
+        }
+        Ok(video)
+    }
+
+    /// ID and size of the next element
+    fn header(file: &mut impl Read) -> io::Result<(u32, u64)> {
+        let mut first = [0; 1];
+        file.read_exact(&mut first)?;
+        let length = first[0].leading_zeros() as usize + 1;
+        let mut id = u32::from(first[0]);
+        for _ in 1..length.min(4) {
+            file.read_exact(&mut first)?;
+            id = (id << 8) | u32::from(first[0]);
+        }
+        file.read_exact(&mut first)?;
+        let length = first[0].leading_zeros() as usize + 1;
+        if length > 8 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid size."));
+        }
+        let mut size = u64::from(first[0]) & (0xff >> length);
+        let mut unknown = size == (0xff >> length);
+        for _ in 1..length {
+            file.read_exact(&mut first)?;
+            size = (size << 8) | u64::from(first[0]);
+            unknown &= first[0] == 0xff;
+        }
+        // Elements of unknown size are only used for segments and clusters
+        Ok((id, if unknown { 0 } else { size }))
+    }
+
+    /// Elements inside of an element that has been read into memory
+    fn children(mut data: &[u8]) -> Vec<(u32, &[u8])> {
+        let mut children = Vec::new();
+        while !data.is_empty() {
+            let (id, size) = match header(&mut data) {
+                Ok((id, size)) if size as usize <= data.len() => (id, size as usize),
+                _ => break,
+            };
+            children.push((id, &data[..size]));
+            data = &data[size..];
+        }
+        children
+    }
+
+    fn uint(data: &[u8]) -> u64 {
+        data.iter()
+            .fold(0, |value, byte| (value << 8) | u64::from(*byte))
+    }
+
+    fn parse_tracks(tracks: &[u8]) -> Result<VideoInfo> {
+        for (_, entry) in children(tracks)
+            .into_iter()
+            .filter(|(id, _)| *id == TRACK_ENTRY)
+        {
+            let entry = children(entry);
+            let element = |id| entry.iter().find(|(i, _)| *i == id).map(|(_, body)| *body);
+            if element(TRACK_TYPE).map(uint) != Some(1) {
+                continue;
+            }
+            let video = children(element(VIDEO).context("The video track has no video settings.")?);
+            let dimension = |id| {
+                video
+                    .iter()
+                    .find(|(i, _)| *i == id)
+                    .map_or(0, |(_, body)| uint(body) as u32)
+            };
+            return Ok(VideoInfo {
+                width: dimension(PIXEL_WIDTH),
+                height: dimension(PIXEL_HEIGHT),
+                duration: Duration::default(),
+                codec: codec(&String::from_utf8_lossy(
+                    element(CODEC_ID).unwrap_or_default(),
+                )),
+            });
+        }
+        Err(anyhow!("There is no video track."))
+    }
+
+    fn parse_duration(info: &[u8]) -> Option<Duration> {
+        let info = children(info);
+        let scale = info
+            .iter()
+            .find(|(id, _)| *id == TIMESTAMP_SCALE)
+            .map_or(1_000_000, |(_, body)| uint(body));
+        let duration = match info.iter().find(|(id, _)| *id == DURATION)?.1 {
+            body if body.len() == 4 => f64::from(f32::from_be_bytes(body.try_into().ok()?)),
+            body => f64::from_be_bytes(body.try_into().ok()?),
+        };
+        Some(Duration::from_secs_f64(duration * scale as f64 / 1e9))
+    }
+
+    fn codec(id: &str) -> String {
+        String::from(match id.trim_end_matches('\0') {
+            "V_MPEG4/ISO/AVC" => "h264",
+            "V_MPEGH/ISO/HEVC" => "hevc",
+            "V_AV1" => "av1",
+            "V_VP9" => "vp9",
+            "V_VP8" => "vp8",
+            id if id.starts_with("V_MPEG4/ISO/") => "mpeg4",
+            id => return id.trim_start_matches("V_").to_lowercase(),
+        })
+    }
+}
@@ -0,0 +1,171 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// A removable medium whose files are sorted whenever it is mounted, e.g. the SD card of a
+/// camera
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VolumeSettings {
+    /// Label of the filesystem
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+
+    /// UUID of the filesystem as listed in `/dev/disk/by-uuid`. Linux only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+
+    /// Folder on the medium whose files are sorted, e.g. `DCIM`. Defaults to all of it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+}
+
+impl VolumeSettings {
+    /// How the volume is called in the log
+    pub fn name(&self) -> &str {
+        self.label
+            .as_deref()
+            .or(self.uuid.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// The folder whose files are sorted, if the volume is mounted
+    fn dir(&self) -> Option<PathBuf> {
+        let mount_point = mount_point(self)?;
+        Some(match &self.path {
+            Some(path) => mount_point.join(path),
+            None => mount_point,
+        })
+    }
+}
+
+/// What happened to a configured volume since the last check
+pub(crate) enum Change {
+    /// Mounted, its files should be sorted now
+    Attached(String, PathBuf),
+    /// Unmounted or no longer configured
+    Detached(String, PathBuf),
+}
+
+/// Keeps track of the configured volumes that are mounted
+#[derive(Default)]
+pub(crate) struct Volumes {
+    attached: Vec<(String, PathBuf)>,
+    /// Attached volumes whose files are still being sorted
+    importing: Vec<(String, PathBuf)>,
+    last_check: Option<Instant>,
+}
+
+impl Volumes {
+    /// Looks for volumes that were mounted or unmounted, at most once a second
+    pub fn check(&mut self, settings: &[VolumeSettings]) -> Vec<Change> {
+        if self
+            .last_check
+            .is_some_and(|last| last.elapsed() < Duration::from_secs(1))
+        {
+            return Vec::new();
+        }
+        self.last_check = Some(Instant::now());
+
+        let mounted: Vec<(String, PathBuf)> = settings
+            .iter()
+            .filter_map(|volume| Some((volume.name().to_string(), volume.dir()?)))
+            .filter(|(_, dir)| dir.is_dir())
+            .collect();
+        let mut changes = Vec::new();
+        for (name, dir) in self.attached.iter() {
+            if !mounted.iter().any(|(_, mounted)| mounted == dir) {
+                changes.push(Change::Detached(name.clone(), dir.clone()));
+            }
+        }
+        for (name, dir) in mounted.iter() {
+            if !self.attached.iter().any(|(_, attached)| attached == dir) {
+                changes.push(Change::Attached(name.clone(), dir.clone()));
+                self.importing.push((name.clone(), dir.clone()));
+            }
+        }
+        self.importing
+            .retain(|(_, dir)| mounted.iter().any(|(_, mounted)| mounted == dir));
+        self.attached = mounted;
+        changes
+    }
+
+    /// Names of the volumes whose files have all been sorted since they were mounted
+    pub fn imported(&mut self, is_busy: impl Fn(&Path) -> bool) -> Vec<String> {
+        let (busy, done) = self.importing.drain(..).partition(|(_, dir)| is_busy(dir));
+        self.importing = busy;
+        done.into_iter().map(|(name, _)| name).collect()
+    }
+
+    /// The folder of the attached volume that contains the path
+    pub fn containing(&self, path: &Path) -> Option<&Path> {
+        self.attached
+            .iter()
+            .map(|(_, dir)| dir.as_path())
+            .find(|dir| path.starts_with(dir))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn mount_point(volume: &VolumeSettings) -> Option<PathBuf> {
+    // Maintained by udev, with special characters escaped like `\x20`
+    let link = match (&volume.uuid, &volume.label) {
+        (Some(uuid), _) => Path::new("/dev/disk/by-uuid").join(uuid),
+        (None, Some(label)) => Path::new("/dev/disk/by-label").join(
+            label
+                .replace('\\', "\\x5c")
+                .replace(' ', "\\x20")
+                .replace('/', "\\x2f"),
+        ),
+        (None, None) => return None,
+    };
+    let device = fs::canonicalize(link).ok()?;
+    let mounts = fs::read_to_string("/proc/self/mountinfo").ok()?;
+    mounts.lines().find_map(|line| {
+        let (mount, filesystem) = line.split_once(" - ")?;
+        let source = unescape(filesystem.split(' ').nth(1)?);
+        let mount_point = unescape(mount.split(' ').nth(4)?);
+        (source.starts_with("/dev/") && fs::canonicalize(&source).ok()? == device)
+            .then(|| PathBuf::from(mount_point))
+    })
+}
+
+/// Undoes the octal escapes of `/proc/self/mountinfo`, e.g. `\040` for a space
+#[cfg(target_os = "linux")]
+fn unescape(field: &str) -> String {
+    let mut bytes = Vec::with_capacity(field.len());
+    let mut rest = field.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let code = tail
+            .get(..3)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match code {
+            Some(code) if byte == b'\\' => {
+                bytes.push(code);
+                rest = &tail[3..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(target_os = "macos")]
+fn mount_point(volume: &VolumeSettings) -> Option<PathBuf> {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = Path::new("/Volumes").join(volume.label.as_ref()?);
+    // Unmounted volumes can leave an empty folder behind
+    let mounted = fs::metadata(&dir).ok()?.dev() != fs::metadata("/Volumes").ok()?.dev();
+    mounted.then_some(dir)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn mount_point(_volume: &VolumeSettings) -> Option<PathBuf> {
+    None
+}
@@ -0,0 +1,134 @@
+use std::fs;
+use std::path::Path;
+
+use notify::ErrorKind;
+
+/// Where Linux keeps the number of inotify watches that one user may hold
+#[cfg(target_os = "linux")]
+const MAX_USER_WATCHES: &str = "/proc/sys/fs/inotify/max_user_watches";
+
+/// Whether watching failed because the operating system allows no more watches
+pub fn is_exhausted(e: &notify::Error) -> bool {
+    matches!(e.kind, ErrorKind::MaxFilesWatch)
+}
+
+/// Explains how many watches the directory needs and how many are allowed. Raises the limit
+/// if `raise` is set and returns whether it did.
+pub fn report(dir: &Path, recursive: bool, raise: bool) -> bool {
+    let needed = needed(dir, recursive);
+    let allowed = match allowed() {
+        Some(allowed) => allowed,
+        None => {
+            error!(
+                "The operating system allows no more file watches. {:?} needs {}, one for \
+                 every folder.",
+                dir, needed
+            );
+            return false;
+        }
+    };
+    let in_use = in_use();
+    error!(
+        "{:?} needs {} inotify watches, one for every folder. {} of the {} that \
+         fs.inotify.max_user_watches allows are already in use by this user.",
+        dir, needed, in_use, allowed
+    );
+    // Leave room for folders that are created later
+    let limit = (in_use + needed + needed / 4)
+        .next_power_of_two()
+        .max(allowed * 2);
+    if raise {
+        match set_allowed(limit) {
+            Ok(()) => {
+                info!("Raised fs.inotify.max_user_watches to {}.", limit);
+                return true;
+            }
+            Err(e) => error!(
+                "Could not raise fs.inotify.max_user_watches: {}. Only root may change it.",
+                e
+            ),
+        }
+    } else {
+        error!(
+            "Raise the limit with `sudo sysctl fs.inotify.max_user_watches={}` or start \
+             wurmloch with --raise-watch-limit as root. Add `fs.inotify.max_user_watches={}` \
+             to /etc/sysctl.d/40-wurmloch.conf to keep it after a reboot.",
+            limit, limit
+        );
+    }
+    false
+}
+
+/// Watches that inotify needs for the directory: one for every folder
+fn needed(dir: &Path, recursive: bool) -> usize {
+    if !recursive {
+        return 1;
+    }
+    let subdirs: usize = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+        .map(|entry| needed(&entry.path(), true))
+        .sum();
+    1 + subdirs
+}
+
+#[cfg(target_os = "linux")]
+fn allowed() -> Option<usize> {
+    fs::read_to_string(MAX_USER_WATCHES)
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn allowed() -> Option<usize> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn set_allowed(limit: usize) -> std::io::Result<()> {
+    fs::write(MAX_USER_WATCHES, limit.to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_allowed(_limit: usize) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Watches held by the processes of this user, counted in `/proc/<pid>/fdinfo`
+#[cfg(target_os = "linux")]
+fn in_use() -> usize {
+    use std::os::unix::fs::MetadataExt;
+
+    let uid = unsafe { libc::getuid() };
+    let processes = fs::read_dir("/proc").into_iter().flatten().flatten();
+    processes
+        // Skips `self` and `thread-self`, which would count this process again
+        .filter(|process| process.file_name().to_string_lossy().parse::<u32>().is_ok())
+        .filter(|process| process.metadata().is_ok_and(|m| m.uid() == uid))
+        .flat_map(|process| {
+            let fds = fs::read_dir(process.path().join("fd"));
+            fds.into_iter().flatten().flatten().filter_map(move |fd| {
+                let target = fs::read_link(fd.path()).ok()?;
+                if target != Path::new("anon_inode:inotify") {
+                    return None;
+                }
+                let info =
+                    fs::read_to_string(process.path().join("fdinfo").join(fd.file_name())).ok()?;
+                Some(
+                    info.lines()
+                        .filter(|l| l.starts_with("inotify wd:"))
+                        .count(),
+                )
+            })
+        })
+        .sum()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn in_use() -> usize {
+    0
+}
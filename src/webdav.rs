@@ -0,0 +1,575 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context as _, Result};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+pub use crate::action::OnConflict;
+use crate::action::{self, Action, ActionSettings, Context, Outcome};
+use crate::journal::Event;
+use crate::quarantine::{Blocked, Check};
+use crate::s3::uri_encode;
+use crate::template::Template;
+use crate::throttle::{Limit, Paced};
+use crate::{logging, APP_NAME};
+
+/// Targets on a WebDAV server over HTTPS, e.g. a Nextcloud folder
+pub const URL_PREFIX: &str = "davs://";
+
+/// Targets on a WebDAV server over plain HTTP
+pub const INSECURE_URL_PREFIX: &str = "dav://";
+
+/// Where Nextcloud keeps the files of a user, followed by the user ID
+const NEXTCLOUD_FILES_PATH: &str = "/remote.php/dav/files/";
+
+/// Nextcloud rejects smaller chunks, except for the last one
+const MIN_CHUNK_SIZE: u64 = 5 << 20;
+
+/// Upload settings of `davs://` and `dav://` targets
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebDavSettings {
+    /// What happens if the target already contains a file with the same name
+    #[serde(default)]
+    pub on_conflict: OnConflict,
+
+    /// Larger files are uploaded to Nextcloud in chunks of this size
+    #[serde(default = "default_chunk_size", with = "crate::units::size")]
+    pub chunk_size: u64,
+
+    /// Used for all hosts instead of the environment or the keyring, e.g. `{secrets.dav_user}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+
+    /// Password of `user`, e.g. `{secrets.nextcloud}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+impl Default for WebDavSettings {
+    fn default() -> Self {
+        WebDavSettings {
+            on_conflict: OnConflict::default(),
+            chunk_size: default_chunk_size(),
+            user: None,
+            password: None,
+        }
+    }
+}
+
+fn default_chunk_size() -> u64 {
+    10 << 20
+}
+
+pub fn is_url(target: &str) -> bool {
+    target.starts_with(URL_PREFIX) || target.starts_with(INSECURE_URL_PREFIX)
+}
+
+/// Uploads files to a WebDAV server and deletes them once the upload has been verified, unless
+/// it is a copy. Created by the `move` and `copy` actions for `davs://` and `dav://` targets.
+#[derive(Debug)]
+pub struct Upload {
+    /// `davs://<host>/<path>`
+    pub target: Template,
+    pub create_target: bool,
+    /// New name of the remote file
+    pub rename: Option<Template>,
+    pub delete_original: bool,
+    pub settings: WebDavSettings,
+    /// Read once per host
+    credentials: Mutex<Vec<(String, Credentials)>>,
+}
+
+impl Upload {
+    pub fn create(settings: ActionSettings, delete_original: bool) -> Result<Box<dyn Action>> {
+        let target = settings.target.context("A target is required.")?;
+        if settings.webdav.chunk_size < MIN_CHUNK_SIZE {
+            return Err(anyhow!("The chunk size has to be at least 5 MiB."));
+        }
+        let mut webdav = settings.webdav;
+        // The rule knows better than the defaults of all WebDAV targets
+        if let Some(on_conflict) = settings.on_conflict {
+            webdav.on_conflict = on_conflict;
+        }
+        Ok(Box::new(Upload {
+            target,
+            create_target: settings.create_target,
+            rename: settings.rename,
+            delete_original,
+            settings: webdav,
+            credentials: Mutex::new(Vec::new()),
+        }))
+    }
+
+    /// Target directory and name of the remote file
+    fn location(&self, path: &Path) -> Result<(Location, String)> {
+        let target = self.target.expand(path)?;
+        let name = action::new_name(self.rename.as_ref(), path)?;
+        Ok((
+            Location::parse(&target)?,
+            name.to_string_lossy().into_owned(),
+        ))
+    }
+
+    fn client(&self, host: &str) -> Result<Client> {
+        let mut cache = self.credentials.lock().unwrap_or_else(|e| e.into_inner());
+        let credentials = match cache.iter().find(|(h, _)| h == host) {
+            Some((_, credentials)) => credentials.clone(),
+            None => {
+                let credentials = Credentials::load(&self.settings, host)?;
+                cache.push((host.to_string(), credentials.clone()));
+                credentials
+            }
+        };
+        Ok(Client { credentials })
+    }
+}
+
+impl Action for Upload {
+    fn name(&self) -> &str {
+        if self.delete_original {
+            "move"
+        } else {
+            "copy"
+        }
+    }
+
+    fn describe(&self, path: &Path) -> Result<String> {
+        let (dir, name) = self.location(path)?;
+        Ok(format!("upload to {}", dir.join(&name).target().display()))
+    }
+
+    fn apply(&self, path: &Path, context: &Context) -> Result<Outcome> {
+        if !path.is_file() {
+            return Err(anyhow!("Only files can be uploaded to {:?}.", self.target));
+        }
+        let (dir, name) = self.location(path)?;
+        let client = self.client(&dir.host)?;
+        if self.create_target {
+            client.create_dir(&dir)?;
+        }
+
+        let on_conflict = match self.settings.on_conflict {
+            OnConflict::Replace if context.safe => OnConflict::Rename,
+            on_conflict => on_conflict,
+        };
+        let mut file = dir.join(&name);
+        let replaced = client.exists(&file)?;
+        if replaced {
+            match on_conflict {
+                OnConflict::Replace => {}
+                OnConflict::Skip => {
+                    info!(
+                        "{} exists already. {:?} is left alone.",
+                        file.target().display(),
+                        path
+                    );
+                    return Ok(Outcome::default());
+                }
+                OnConflict::Rename => {
+                    file = free_name(&client, &dir, &name)?;
+                }
+                OnConflict::Quarantine => {
+                    return Err(Blocked::new(
+                        Check::Overwrite,
+                        format!("{} exists already.", file.target().display()),
+                    )
+                    .into());
+                }
+            }
+        }
+        let destination = file.target();
+        logging::target(&destination);
+
+        let size = fs::metadata(path)?.len();
+        let limit = context.limit(&destination);
+        match file.nextcloud_uploads() {
+            Some(uploads) if size > self.settings.chunk_size => client.put_chunked(
+                &file,
+                &uploads,
+                path,
+                size,
+                self.settings.chunk_size,
+                limit.as_ref(),
+            )?,
+            _ => client.put(&file, path, size, limit.as_ref())?,
+        }
+        let uploaded = client
+            .size(&file)
+            .context(format!("Could not verify the upload of {:?}.", path))?;
+        if uploaded != size {
+            return Err(Blocked::new(
+                Check::Mismatch,
+                format!(
+                    "{} has {} bytes instead of {}.",
+                    destination.display(),
+                    uploaded,
+                    size
+                ),
+            )
+            .into());
+        }
+        debug!("Uploaded {:?} to {:?}.", path, &destination);
+
+        let from = path.to_path_buf();
+        let rule = context.rule.to_string();
+        let event = if self.delete_original {
+            fs::remove_file(path).context(format!("Could not delete {:?}.", path))?;
+            Event::Move {
+                rule,
+                from,
+                to: destination.clone(),
+                replaced: None,
+            }
+        } else {
+            Event::Copy {
+                rule,
+                from,
+                to: destination.clone(),
+                replaced: None,
+                size: None,
+                modified: None,
+            }
+        };
+        Ok(Outcome {
+            destination: Some(destination),
+            replaced: replaced && on_conflict == OnConflict::Replace,
+            event: Some(event),
+        })
+    }
+}
+
+/// `name (1).ext`, `name (2).ext` and so on, whichever is not taken yet
+fn free_name(client: &Client, dir: &Location, name: &str) -> Result<Location> {
+    for number in 1.. {
+        let file = dir.join(&action::numbered_name(name, number));
+        if !client.exists(&file)? {
+            return Ok(file);
+        }
+    }
+    unreachable!()
+}
+
+/// A file or directory on a WebDAV server
+#[derive(Debug, Clone)]
+struct Location {
+    secure: bool,
+    /// Including the port, if any
+    host: String,
+    /// Decoded path, starting with a slash and without a trailing one
+    path: String,
+}
+
+impl Location {
+    fn parse(url: &str) -> Result<Location> {
+        let (secure, rest) = match url.strip_prefix(URL_PREFIX) {
+            Some(rest) => (true, rest),
+            None => (
+                false,
+                url.strip_prefix(INSECURE_URL_PREFIX)
+                    .context(format!("{:?} is no WebDAV URL.", url))?,
+            ),
+        };
+        let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+        if host.is_empty() {
+            return Err(anyhow!("{:?} does not name a host.", url));
+        }
+        Ok(Location {
+            secure,
+            host: host.to_string(),
+            path: format!("/{}", path.trim_matches('/')),
+        })
+    }
+
+    fn join(&self, name: &str) -> Location {
+        Location {
+            path: format!("{}/{}", self.path.trim_end_matches('/'), name),
+            ..self.clone()
+        }
+    }
+
+    fn parent(&self) -> Option<Location> {
+        let (parent, _) = self.path.rsplit_once('/')?;
+        Some(Location {
+            path: parent.to_string(),
+            ..self.clone()
+        })
+        .filter(|parent| !parent.path.is_empty())
+    }
+
+    /// `davs://` URL as shown in logs and recorded in the journal
+    fn target(&self) -> PathBuf {
+        let prefix = if self.secure {
+            URL_PREFIX
+        } else {
+            INSECURE_URL_PREFIX
+        };
+        PathBuf::from(format!("{}{}{}", prefix, self.host, self.path))
+    }
+
+    fn url(&self) -> String {
+        let scheme = if self.secure { "https" } else { "http" };
+        format!(
+            "{}://{}{}",
+            scheme,
+            self.host,
+            uri_encode(&self.path, false)
+        )
+    }
+
+    /// Where chunks of uploads to this file are collected, if it is in a Nextcloud
+    fn nextcloud_uploads(&self) -> Option<Location> {
+        let start = self.path.find(NEXTCLOUD_FILES_PATH)?;
+        let user = self.path[start + NEXTCLOUD_FILES_PATH.len()..]
+            .split('/')
+            .next()?;
+        Some(Location {
+            path: format!("{}/remote.php/dav/uploads/{}", &self.path[..start], user),
+            ..self.clone()
+        })
+    }
+}
+
+#[derive(Clone)]
+struct Credentials {
+    user: String,
+    password: String,
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("user", &self.user)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Credentials {
+    /// From the settings, the environment or, if not set there, from the keyring
+    fn load(settings: &WebDavSettings, host: &str) -> Result<Credentials> {
+        if let (Some(user), Some(password)) = (&settings.user, &settings.password) {
+            return Ok(Credentials {
+                user: user.clone(),
+                password: password.clone(),
+            });
+        }
+        if let (Ok(user), Ok(password)) = (
+            env::var("WURMLOCH_WEBDAV_USER"),
+            env::var("WURMLOCH_WEBDAV_PASSWORD"),
+        ) {
+            return Ok(Credentials { user, password });
+        }
+        let stored = keyring_entry(host)?.get_password().map_err(|e| {
+            anyhow!(
+                "No credentials for {}. Set WURMLOCH_WEBDAV_USER and WURMLOCH_WEBDAV_PASSWORD \
+                 or run `{} webdav-login {}`. Reason: {}",
+                host,
+                APP_NAME,
+                host,
+                e
+            )
+        })?;
+        let (user, password) = stored
+            .split_once(':')
+            .context(format!("Invalid credentials for {} in the keyring.", host))?;
+        Ok(Credentials {
+            user: user.to_string(),
+            password: password.to_string(),
+        })
+    }
+}
+
+fn keyring_entry(host: &str) -> Result<keyring::Entry> {
+    keyring::Entry::new(APP_NAME, &format!("webdav:{}", host))
+        .context("Could not access the keyring.")
+}
+
+/// Stores the user and password, e.g. a Nextcloud app password, for a host in the keyring of
+/// the system
+pub fn store_credentials(host: &str, user: &str, password: &str) -> Result<()> {
+    if user.is_empty() || password.is_empty() {
+        return Err(anyhow!("User and password are required."));
+    }
+    keyring_entry(host)?
+        .set_password(&format!("{}:{}", user, password))
+        .context(format!("Could not store the credentials of {}.", host))
+}
+
+/// Authenticated requests to one server
+struct Client {
+    credentials: Credentials,
+}
+
+impl Client {
+    fn request(&self, method: &str, location: &Location) -> ureq::Request {
+        let token = base64::engine::general_purpose::STANDARD.encode(format!(
+            "{}:{}",
+            self.credentials.user, self.credentials.password
+        ));
+        ureq::request(method, &location.url()).set("Authorization", &format!("Basic {}", token))
+    }
+
+    /// Size of a remote file
+    fn size(&self, file: &Location) -> Result<u64> {
+        let response = self.request("HEAD", file).call().map_err(error)?;
+        response
+            .header("Content-Length")
+            .and_then(|length| length.parse().ok())
+            .context("The response does not contain the size.")
+    }
+
+    fn exists(&self, file: &Location) -> Result<bool> {
+        match self.request("HEAD", file).call() {
+            Ok(_) => Ok(true),
+            Err(ureq::Error::Status(404, _)) => Ok(false),
+            Err(e) => Err(error(e)).context(format!("Could not check {}.", file.url())),
+        }
+    }
+
+    /// Creates the directory and its missing parents
+    fn create_dir(&self, dir: &Location) -> Result<()> {
+        match self.request("MKCOL", dir).call() {
+            Ok(_) => {
+                info!("Created target directory {:?}.", dir.target());
+                Ok(())
+            }
+            // The directory exists already
+            Err(ureq::Error::Status(405, _)) => Ok(()),
+            // The parent is missing
+            Err(ureq::Error::Status(409, _)) => match dir.parent() {
+                Some(parent) => {
+                    self.create_dir(&parent)?;
+                    self.create_dir(dir)
+                }
+                None => Err(anyhow!("Could not create {}.", dir.url())),
+            },
+            Err(e) => Err(error(e)).context(format!("Could not create {}.", dir.url())),
+        }
+    }
+
+    fn put(&self, file: &Location, path: &Path, size: u64, limit: Option<&Limit>) -> Result<()> {
+        let content = File::open(path).context(format!("Could not open {:?}.", path))?;
+        let mut request = self
+            .request("PUT", file)
+            .set("Content-Length", &size.to_string());
+        if let Some(modified) = modified(path) {
+            request = request.set("X-OC-Mtime", &modified);
+        }
+        match limit {
+            Some(limit) => request.send(Paced::new(content, limit)),
+            None => request.send(content),
+        }
+        .map_err(error)
+        .context(format!("Could not upload {:?}.", path))?;
+        Ok(())
+    }
+
+    /// Uploads the file in chunks with the chunking protocol of Nextcloud. Incomplete uploads
+    /// are removed from the server.
+    fn put_chunked(
+        &self,
+        file: &Location,
+        uploads: &Location,
+        path: &Path,
+        size: u64,
+        chunk_size: u64,
+        limit: Option<&Limit>,
+    ) -> Result<()> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let upload = uploads.join(&format!("{}-{}-{}", APP_NAME, process::id(), nanos));
+        let destination = file.url();
+        self.request("MKCOL", &upload)
+            .set("Destination", &destination)
+            .call()
+            .map_err(error)
+            .context(format!("Could not start the upload of {:?}.", path))?;
+
+        let result = self.put_chunks(file, &upload, path, size, chunk_size, limit);
+        if result.is_err() {
+            if let Err(e) = self.request("DELETE", &upload).call() {
+                warn!("Could not remove the chunks of {:?}: {}", path, error(e));
+            }
+        }
+        result
+    }
+
+    fn put_chunks(
+        &self,
+        file: &Location,
+        upload: &Location,
+        path: &Path,
+        size: u64,
+        chunk_size: u64,
+        limit: Option<&Limit>,
+    ) -> Result<()> {
+        let destination = file.url();
+        let total = size.to_string();
+        let mut content = File::open(path).context(format!("Could not open {:?}.", path))?;
+        let mut buffer = Vec::new();
+        for number in 1.. {
+            buffer.clear();
+            (&mut content).take(chunk_size).read_to_end(&mut buffer)?;
+            if buffer.is_empty() {
+                break;
+            }
+            if let Some(limit) = limit {
+                limit.consume(buffer.len() as u64);
+            }
+            self.request("PUT", &upload.join(&format!("{:05}", number)))
+                .set("Destination", &destination)
+                .set("OC-Total-Length", &total)
+                .send_bytes(&buffer)
+                .map_err(error)
+                .context(format!("Could not upload chunk {} of {:?}.", number, path))?;
+            debug!("Uploaded chunk {} of {:?}.", number, path);
+        }
+        let mut request = self
+            .request("MOVE", &upload.join(".file"))
+            .set("Destination", &destination)
+            .set("OC-Total-Length", &total)
+            .set("Overwrite", "T");
+        if let Some(modified) = modified(path) {
+            request = request.set("X-OC-Mtime", &modified);
+        }
+        request
+            .call()
+            .map_err(error)
+            .context(format!("Could not assemble the chunks of {:?}.", path))?;
+        Ok(())
+    }
+}
+
+/// Modification time in seconds, which Nextcloud keeps for uploaded files
+fn modified(path: &Path) -> Option<String> {
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    let seconds = modified.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    Some(seconds.to_string())
+}
+
+/// The message that the server sent with an error status
+fn error(error: ureq::Error) -> anyhow::Error {
+    match error {
+        ureq::Error::Status(status, response) => {
+            let body = response.into_string().unwrap_or_default();
+            // Nextcloud and other sabre/dav servers explain errors in <s:message>
+            let message = body
+                .split_once("<s:message>")
+                .and_then(|(_, rest)| rest.split_once("</s:message>"))
+                .map(|(message, _)| message.trim())
+                .filter(|message| !message.is_empty());
+            match message {
+                Some(message) => anyhow!("Status {}: {}", status, message),
+                None => anyhow!("Status {}.", status),
+            }
+        }
+        error => anyhow!(error),
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookSettings {
+    pub url: String,
+
+    /// Body of the request. `{event}`, `{file}`, `{rule}`, `{destination}` and `{outcome}` are
+    /// replaced with JSON-escaped values. Defaults to a JSON object with all of them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<String>,
+
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+
+    /// Attempts per request, including the first one
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+/// Something that happened to a file
+#[derive(Debug, Serialize)]
+pub struct WebhookEvent {
+    /// `sorted` or `failed`
+    pub event: &'static str,
+    pub file: String,
+    pub rule: String,
+    pub destination: Option<String>,
+    /// `moved`, `deleted` or the reason of a failure
+    pub outcome: String,
+}
+
+impl WebhookEvent {
+    pub(crate) fn render(&self, template: Option<&str>) -> String {
+        let template = match template {
+            Some(template) => template,
+            None => return serde_json::to_string(self).unwrap(),
+        };
+        let escape = |value: &str| {
+            let quoted = serde_json::to_string(value).unwrap();
+            quoted[1..quoted.len() - 1].to_string()
+        };
+        // One pass, so that placeholders in the values themselves, e.g. in a file name, stay
+        // as they are
+        let mut rendered = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            rendered.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let placeholder = rest.find('}').and_then(|end| {
+                let value = match &rest[1..end] {
+                    "event" => self.event,
+                    "file" => &self.file,
+                    "rule" => &self.rule,
+                    "destination" => self.destination.as_deref().unwrap_or_default(),
+                    "outcome" => &self.outcome,
+                    _ => return None,
+                };
+                Some((value, end))
+            });
+            match placeholder {
+                Some((value, end)) => {
+                    rendered.push_str(&escape(value));
+                    rest = &rest[end + 1..];
+                }
+                None => {
+                    rendered.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        rendered.push_str(rest);
+        rendered
+    }
+}
+
+/// Posts events to a URL in the background, so that a slow endpoint does not hold up sorting
+pub struct Webhook {
+    tx: Sender<String>,
+    template: Option<String>,
+}
+
+impl Webhook {
+    pub fn start(settings: WebhookSettings) -> Webhook {
+        let (tx, rx) = channel::<String>();
+        let template = settings.payload.clone();
+        thread::spawn(move || {
+            for body in rx {
+                post(&settings, &body);
+            }
+        });
+        Webhook { tx, template }
+    }
+
+    pub fn send(&self, event: &WebhookEvent) {
+        self.tx.send(event.render(self.template.as_deref())).ok();
+    }
+}
+
+/// Retries with exponential backoff
+fn post(settings: &WebhookSettings, body: &str) {
+    let mut delay = Duration::from_secs(1);
+    for attempt in 1..=settings.max_attempts {
+        let mut request = ureq::post(&settings.url).set("Content-Type", "application/json");
+        for (name, value) in settings.headers.iter() {
+            request = request.set(name, value);
+        }
+        match request.send_string(body) {
+            Ok(_) => return,
+            Err(e) if attempt < settings.max_attempts => {
+                debug!("Webhook attempt {} failed: {}", attempt, e);
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            Err(e) => warn!(
+                "Could not call webhook {} after {} attempts: {}",
+                &settings.url, attempt, e
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(file: &str) -> WebhookEvent {
+        WebhookEvent {
+            event: "sorted",
+            file: file.to_string(),
+            rule: String::from("*.pdf"),
+            destination: None,
+            outcome: String::from("moved"),
+        }
+    }
+
+    #[test]
+    fn placeholders_are_filled_in_once() {
+        let event = event("{rule}\"x.pdf");
+        assert_eq!(
+            event.render(Some(r#"{"text": "{file} ({rule}) {destination}"}"#)),
+            r#"{"text": "{rule}\"x.pdf (*.pdf) "}"#
+        );
+    }
+
+    #[test]
+    fn unknown_placeholders_are_kept() {
+        let event = event("report.pdf");
+        assert_eq!(
+            event.render(Some("{unknown} {file} {event")),
+            "{unknown} report.pdf {event"
+        );
+        assert_eq!(
+            event.render(None),
+            r#"{"event":"sorted","file":"report.pdf","rule":"*.pdf","destination":null,"outcome":"moved"}"#
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveTime, TimeZone, Timelike, Weekday};
+use serde::{Deserialize, Serialize};
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// Days and hours during which something is active, e.g. `Mon-Fri 18:00-08:00`, `Sat,Sun` or
+/// `22:00-06:00`. Hours that end before they start continue on the next day.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeWindow {
+    text: String,
+    /// Indexed by the days since Monday
+    days: [bool; 7],
+    /// Minutes since midnight
+    start: u32,
+    end: u32,
+}
+
+impl TimeWindow {
+    pub fn parse(text: &str) -> Result<TimeWindow> {
+        let mut days = None;
+        let mut hours = None;
+        for part in text.split_whitespace() {
+            if part.contains(':') && hours.is_none() {
+                hours = Some(parse_hours(part)?);
+            } else if days.is_none() {
+                days = Some(parse_days(part)?);
+            } else {
+                return Err(anyhow!("Unexpected {:?} in time window {:?}.", part, text));
+            }
+        }
+        if days.is_none() && hours.is_none() {
+            return Err(anyhow!("The time window is empty."));
+        }
+        let (start, end) = hours.unwrap_or((0, MINUTES_PER_DAY));
+        Ok(TimeWindow {
+            text: text.to_string(),
+            days: days.unwrap_or([true; 7]),
+            start,
+            end,
+        })
+    }
+
+    fn contains(&self, time: DateTime<Local>) -> bool {
+        let day = time.weekday().num_days_from_monday() as usize;
+        let minute = time.hour() * 60 + time.minute();
+        if self.start < self.end {
+            self.days[day] && (self.start..self.end).contains(&minute)
+        } else {
+            // Began yesterday or begins today and ends tomorrow
+            (self.days[day] && minute >= self.start)
+                || (self.days[(day + 6) % 7] && minute < self.end)
+        }
+    }
+
+    /// The first time after `after` at which the window begins
+    fn next_start(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        let start = NaiveTime::from_hms_opt(self.start / 60 % 24, self.start % 60, 0)?;
+        (0..=7)
+            .map(|offset| after.date_naive() + Duration::days(offset))
+            .filter(|date| self.days[date.weekday().num_days_from_monday() as usize])
+            .filter_map(|date| Local.from_local_datetime(&date.and_time(start)).earliest())
+            .find(|start| *start > after)
+    }
+}
+
+/// Whether one of the windows contains the time. No windows means always.
+pub fn is_active(windows: &[TimeWindow], time: DateTime<Local>) -> bool {
+    windows.is_empty() || windows.iter().any(|window| window.contains(time))
+}
+
+/// When the first of the windows begins after `after`
+pub fn next_active(windows: &[TimeWindow], after: DateTime<Local>) -> Option<DateTime<Local>> {
+    windows.iter().filter_map(|w| w.next_start(after)).min()
+}
+
+/// The windows for the log, e.g. `Mon-Fri 18:00-08:00 or Sat,Sun`
+pub fn describe(windows: &[TimeWindow]) -> String {
+    windows
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(" or ")
+}
+
+/// Days like `Mon-Fri`, `Sat,Sun` or `Fri-Mon`
+fn parse_days(text: &str) -> Result<[bool; 7]> {
+    let mut days = [false; 7];
+    for part in text.split(',') {
+        let (first, last) = part.split_once('-').unwrap_or((part, part));
+        let first = parse_day(first)?;
+        let last = parse_day(last)?;
+        let mut day = first;
+        loop {
+            days[day] = true;
+            if day == last {
+                break;
+            }
+            day = (day + 1) % 7;
+        }
+    }
+    Ok(days)
+}
+
+fn parse_day(text: &str) -> Result<usize> {
+    let day = Weekday::from_str(text)
+        .ok()
+        .context(format!("Unknown day {:?}. Use Mon, Tue, ...", text))?;
+    Ok(day.num_days_from_monday() as usize)
+}
+
+/// Hours like `18:00-08:00`, up to `24:00`
+fn parse_hours(text: &str) -> Result<(u32, u32)> {
+    let (start, end) = text.split_once('-').context(format!(
+        "{:?} is not a range of hours like 18:00-08:00.",
+        text
+    ))?;
+    Ok((parse_time(start)?, parse_time(end)?))
+}
+
+fn parse_time(text: &str) -> Result<u32> {
+    let invalid = || format!("{:?} is not a time like 08:30.", text);
+    let (hours, minutes) = text.split_once(':').with_context(invalid)?;
+    let hours: u32 = hours.parse().ok().with_context(invalid)?;
+    let minutes: u32 = minutes.parse().ok().with_context(invalid)?;
+    if minutes >= 60 || hours * 60 + minutes > MINUTES_PER_DAY {
+        return Err(anyhow!(invalid()));
+    }
+    Ok(hours * 60 + minutes)
+}
+
+impl fmt::Display for TimeWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl TryFrom<String> for TimeWindow {
+    type Error = anyhow::Error;
+
+    fn try_from(text: String) -> Result<TimeWindow> {
+        TimeWindow::parse(&text)
+    }
+}
+
+impl From<TimeWindow> for String {
+    fn from(window: TimeWindow) -> String {
+        window.text
+    }
+}
@@ -0,0 +1,244 @@
+use std::any::Any;
+use std::collections::{HashSet, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::SystemTime;
+
+/// The work to do and how to report it as failed if it panics
+struct Job<T> {
+    run: Box<dyn FnOnce() -> T + Send>,
+    failed: Box<dyn FnOnce(String) -> T + Send>,
+}
+
+/// Runs jobs on a fixed number of threads, so that a large copy does not hold up small files.
+/// Jobs for the same file, for a directory and the files inside it or for files with the same
+/// name, which may end up at the same target, never run at the same time.
+///
+/// Files wait in a queue of limited size until a worker is free. Files that do not fit are
+/// turned away, and the caller is asked to look for them again once there is room.
+pub struct Pool<T> {
+    jobs: Option<Sender<(PathBuf, Job<T>)>>,
+    finished: Receiver<(PathBuf, T)>,
+    threads: Vec<JoinHandle<()>>,
+    workers: usize,
+    running: Vec<PathBuf>,
+    /// Files that wait for a worker, oldest first
+    queue: VecDeque<PathBuf>,
+    queued: HashSet<PathBuf>,
+    capacity: usize,
+    /// When the first file was turned away since the last rescan
+    overflowed: Option<SystemTime>,
+    /// Events for files that were already queued
+    pub coalesced: u64,
+    /// Files that were turned away because the queue was full
+    pub turned_away: u64,
+}
+
+impl<T: Send + 'static> Pool<T> {
+    pub fn new(workers: usize, capacity: usize) -> Pool<T> {
+        let workers = workers.max(1);
+        let (jobs, queue) = channel::<(PathBuf, Job<T>)>();
+        let (done, finished) = channel();
+        let queue = Arc::new(Mutex::new(queue));
+        let threads = (0..workers)
+            .map(|number| {
+                let queue = Arc::clone(&queue);
+                let done = Sender::clone(&done);
+                thread::Builder::new()
+                    .name(format!("worker-{}", number))
+                    .spawn(move || loop {
+                        let job = queue.lock().unwrap_or_else(|e| e.into_inner()).recv();
+                        match job {
+                            Ok((path, job)) => {
+                                // A panicking job must not take its worker down, or its file
+                                // would count as running forever
+                                let result = match panic::catch_unwind(AssertUnwindSafe(job.run)) {
+                                    Ok(result) => result,
+                                    Err(panic) => (job.failed)(panic_message(panic)),
+                                };
+                                if done.send((path, result)).is_err() {
+                                    break;
+                                }
+                            }
+                            // The pool has been shut down
+                            Err(_) => break,
+                        }
+                    })
+                    .expect("Could not start worker thread.")
+            })
+            .collect();
+        Pool {
+            jobs: Some(jobs),
+            finished,
+            threads,
+            workers,
+            running: Vec::new(),
+            queue: VecDeque::new(),
+            queued: HashSet::new(),
+            capacity: capacity.max(1),
+            overflowed: None,
+            coalesced: 0,
+            turned_away: 0,
+        }
+    }
+
+    /// Queues a file unless it is already waiting or the queue is full
+    pub fn push(&mut self, path: &Path) {
+        if self.queued.contains(path) {
+            trace!("{:?} is already queued.", path);
+            self.coalesced += 1;
+        } else if self.queue.len() >= self.capacity {
+            if self.overflowed.is_none() {
+                warn!(
+                    "More than {} files are waiting. New files are picked up later.",
+                    self.capacity
+                );
+                self.overflowed = Some(SystemTime::now());
+            }
+            self.turned_away += 1;
+        } else {
+            self.queue.push_back(path.to_path_buf());
+            self.queued.insert(path.to_path_buf());
+        }
+    }
+
+    /// The oldest queued file that can be started now, if a worker is free
+    pub fn next(&mut self) -> Option<PathBuf> {
+        if self.running.len() >= self.workers {
+            return None;
+        }
+        let position = self
+            .queue
+            .iter()
+            .position(|path| !self.is_busy_with(path))?;
+        let path = self.queue.remove(position)?;
+        self.queued.remove(&path);
+        Some(path)
+    }
+
+    /// Whether a job that is running could interfere with a job for `path`
+    fn is_busy_with(&self, path: &Path) -> bool {
+        self.running.iter().any(|running| {
+            running.starts_with(path)
+                || path.starts_with(running)
+                || running.file_name() == path.file_name()
+        })
+    }
+
+    pub fn is_running(&self, path: &Path) -> bool {
+        self.running.iter().any(|running| running == path)
+    }
+
+    /// Runs the job on a free worker. If it panics, the result is `failed` with the message of
+    /// the panic.
+    pub fn submit(
+        &mut self,
+        path: PathBuf,
+        job: impl FnOnce() -> T + Send + 'static,
+        failed: impl FnOnce(String) -> T + Send + 'static,
+    ) {
+        if let Some(jobs) = &self.jobs {
+            self.running.push(path.clone());
+            let job = Job {
+                run: Box::new(job),
+                failed: Box::new(failed),
+            };
+            // Workers only stop when the sender is dropped
+            let _ = jobs.send((path, job));
+        }
+    }
+
+    /// Results of the jobs that have finished since the last call
+    pub fn finished(&mut self) -> Vec<T> {
+        let finished: Vec<(PathBuf, T)> = self.finished.try_iter().collect();
+        finished
+            .into_iter()
+            .map(|(path, result)| self.complete(&path, result))
+            .collect()
+    }
+
+    /// Waits until at least one job has finished and returns the results of all finished jobs
+    pub fn wait_for_finished(&mut self) -> Vec<T> {
+        if self.running.is_empty() {
+            return Vec::new();
+        }
+        match self.finished.recv() {
+            Ok((path, result)) => {
+                let mut finished = vec![self.complete(&path, result)];
+                finished.append(&mut self.finished());
+                finished
+            }
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// If files have been turned away and the queue is at most half full again, when the first
+    /// of them was turned away. Files that changed since then should be queued again.
+    pub fn take_overflow(&mut self) -> Option<SystemTime> {
+        if self.queue.len() > self.capacity / 2 {
+            return None;
+        }
+        self.overflowed.take()
+    }
+
+    /// Keeps looking for the files turned away since `since` if the rescan did not fit either
+    pub fn rescanned(&mut self, since: SystemTime) {
+        if let Some(overflowed) = &mut self.overflowed {
+            *overflowed = since.min(*overflowed);
+        }
+    }
+
+    /// Whether files inside the directory are queued or being sorted
+    pub fn is_busy_below(&self, dir: &Path) -> bool {
+        self.running
+            .iter()
+            .chain(self.queue.iter())
+            .any(|path| path.starts_with(dir))
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.running.is_empty() && self.queue.is_empty()
+    }
+
+    /// Number of files that are being sorted
+    pub fn running(&self) -> usize {
+        self.running.len()
+    }
+
+    /// Number of files that wait for a worker
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Lets running jobs finish and returns their results. Queued files are left alone.
+    pub fn shutdown(mut self) -> Vec<T> {
+        if !self.running.is_empty() {
+            info!("Waiting for {} files to be sorted ...", self.running.len());
+        }
+        self.jobs = None;
+        for thread in std::mem::take(&mut self.threads) {
+            let _ = thread.join();
+        }
+        self.finished()
+    }
+
+    fn complete(&mut self, path: &Path, result: T) -> T {
+        if let Some(position) = self.running.iter().position(|running| running == path) {
+            self.running.remove(position);
+        }
+        result
+    }
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => match panic.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => String::from("unknown panic"),
+        },
+    }
+}